@@ -0,0 +1,56 @@
+//! With the `embedded` feature, lists every file of assets/ for src/embedded.rs to build into
+//! the executable. Without it, there is nothing to do.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBEDDED").is_none() {
+        return;
+    }
+
+    let assets = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("assets");
+    println!("cargo:rerun-if-changed={}", assets.display());
+    let mut files = Vec::new();
+    list_files(&assets, &mut files);
+    files.sort();
+
+    let entries: String = files
+        .iter()
+        .map(|file| {
+            // Asset paths always use forward slashes, whatever the platform
+            let path = file
+                .strip_prefix(&assets)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            format!(
+                "    ({path:?}, include_bytes!({:?})),\n",
+                file.display().to_string()
+            )
+        })
+        .collect();
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    fs::write(
+        out,
+        format!("pub const EMBEDDED_ASSETS: &[(&str, &[u8])] = &[\n{entries}];\n"),
+    )
+    .unwrap();
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        println!("cargo:rerun-if-changed={}", path.display());
+        if path.is_dir() {
+            list_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
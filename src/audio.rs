@@ -0,0 +1,408 @@
+//! Sound effects and music. Everything is played through `AudioChannels`, at the volume the
+//! settings give its channel. Most sound effects answer the events gameplay sends, like a player
+//! jumping or an enemy getting flipped. The music follows the state of the game, crossfading from
+//! one track to the next.
+//!
+//! Browsers don't let a page make any sound before the player has pressed something, so with the
+//! `wasm` feature everything stays quiet until the first key, click, touch or button press.
+
+use bevy::{
+    audio::{PlaybackMode, Volume},
+    ecs::system::SystemParam,
+    prelude::*,
+};
+
+use crate::{
+    boss::{Boss, BossDefeated, BossHit},
+    enemy::{EnemyEnraged, EnemyFlipped, EnemyKicked, Enraged},
+    hurry::{HurriedUp, HurryUp},
+    level::{PhaseCleared, PlatformBumped, Splashed},
+    player::{ComboExtended, ExtraLifeAwarded, PlayerDied, PlayerJumped, PlayerLanded},
+    powerup::HammerSwung,
+    settings::Settings,
+    status::{StatusEffects, StatusKind},
+    GameState,
+};
+
+// Turns all sound off and back on, whatever screen is showing
+const MUTE_KEY: KeyCode = KeyCode::KeyM;
+const CROSSFADE_SECONDS: f32 = 1.0;
+// Every link of a combo plays the coin sound this many semitones higher than the last
+const COMBO_PITCH_SEMITONES: f32 = 2.0;
+
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, load_sounds)
+            .init_resource::<Music>()
+            .init_resource::<AudioUnlocked>()
+            .add_systems(PreUpdate, unlock_audio)
+            .add_systems(
+                Update,
+                (
+                    play_player_sounds,
+                    play_enemy_sounds,
+                    play_combo_sounds,
+                    play_boss_sounds,
+                    play_arena_sounds,
+                    toggle_mute,
+                    choose_music,
+                ),
+            )
+            .add_systems(Update, crossfade_music.after(choose_music));
+    }
+}
+
+// Each channel has its own volume, on top of the master volume
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Music,
+    Sfx,
+}
+
+#[derive(SystemParam)]
+pub struct AudioChannels<'w, 's> {
+    commands: Commands<'w, 's>,
+    settings: Res<'w, Settings>,
+    unlocked: Res<'w, AudioUnlocked>,
+}
+
+// Whether anything may be played yet
+#[derive(Resource)]
+pub struct AudioUnlocked(pub bool);
+
+impl Default for AudioUnlocked {
+    fn default() -> Self {
+        AudioUnlocked(!cfg!(feature = "wasm"))
+    }
+}
+
+// The music for each part of the game
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Track {
+    Menu,
+    Gameplay,
+    // Faster, for when time is running out
+    HurryUp,
+    // While a player has a star
+    Star,
+    // While a boss is around
+    Boss,
+    // Played once when the game ends
+    GameOver,
+}
+
+impl Track {
+    fn path(self) -> &'static str {
+        match self {
+            Track::Menu => "music/menu.ogg",
+            Track::Gameplay => "music/gameplay.ogg",
+            Track::HurryUp => "music/hurry_up.ogg",
+            Track::Star => "music/star.ogg",
+            Track::Boss => "music/boss.ogg",
+            Track::GameOver => "music/game_over.ogg",
+        }
+    }
+}
+
+// The track that is playing and how far it has faded in, and the ones still fading out. Each is
+// played by its own entity.
+#[derive(Resource, Default)]
+struct Music {
+    current: Option<(Track, Entity, f32)>,
+    fading_out: Vec<(Entity, f32)>,
+}
+
+impl AudioChannels<'_, '_> {
+    pub fn play(&mut self, channel: Channel, sound: &Handle<AudioSource>) {
+        self.play_at_speed(channel, sound, 1.0);
+    }
+
+    // Faster is higher, twice as fast being an octave up
+    pub fn play_at_speed(&mut self, channel: Channel, sound: &Handle<AudioSource>, speed: f32) {
+        let volume = self.settings.volume(channel);
+        if volume > 0.0 && self.unlocked.0 {
+            self.commands.spawn((
+                AudioPlayer::new(sound.clone()),
+                PlaybackSettings::DESPAWN
+                    .with_volume(Volume::Linear(volume))
+                    .with_speed(speed),
+            ));
+        }
+    }
+}
+
+fn unlock_audio(
+    mut unlocked: ResMut<AudioUnlocked>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<&Gamepad>,
+    touches: Res<Touches>,
+) {
+    if !unlocked.0
+        && (keyboard_input.get_just_pressed().next().is_some()
+            || mouse_input.get_just_pressed().next().is_some()
+            || gamepads
+                .iter()
+                .any(|gamepad| gamepad.get_just_pressed().next().is_some())
+            || touches.iter_just_pressed().next().is_some())
+    {
+        unlocked.0 = true;
+    }
+}
+
+fn choose_music(
+    state: Res<State<GameState>>,
+    (enraged_query, boss_query, status_query, hurry_up): (
+        Query<(), With<Enraged>>,
+        Query<(), With<Boss>>,
+        Query<&StatusEffects>,
+        Res<HurryUp>,
+    ),
+    asset_server: Res<AssetServer>,
+    mut audio: AudioChannels,
+    mut music: ResMut<Music>,
+) {
+    if !audio.unlocked.0 {
+        return;
+    }
+    let track = match state.get() {
+        GameState::Menu
+        | GameState::Options
+        | GameState::Controls
+        | GameState::HighScores
+        | GameState::LevelSelect
+        | GameState::CustomGame
+        | GameState::CharacterSelect => Some(Track::Menu),
+        #[cfg(feature = "online")]
+        GameState::Lobby => Some(Track::Menu),
+        GameState::Playing
+            if status_query
+                .iter()
+                .any(|effects| effects.has(StatusKind::Star)) =>
+        {
+            Some(Track::Star)
+        }
+        GameState::Playing if !boss_query.is_empty() => Some(Track::Boss),
+        // The last enemy getting angry, or the phase dragging on, is the cue to hurry up
+        GameState::Playing if !enraged_query.is_empty() || hurry_up.hurried() => {
+            Some(Track::HurryUp)
+        }
+        GameState::Playing => Some(Track::Gameplay),
+        GameState::EnterInitials | GameState::GameOver => Some(Track::GameOver),
+        // Quiet while editing, and while the music is still loading
+        GameState::Loading | GameState::Editor => None,
+    };
+    if music.current.as_ref().map(|(playing, _, _)| *playing) == track {
+        return;
+    }
+
+    if let Some((_, sink, level)) = music.current.take() {
+        music.fading_out.push((sink, level));
+    }
+    music.current = track.map(|track| {
+        let player = audio.commands.spawn((
+            AudioPlayer::new(asset_server.load(track.path())),
+            PlaybackSettings {
+                mode: if track == Track::GameOver {
+                    PlaybackMode::Once
+                } else {
+                    PlaybackMode::Loop
+                },
+                // Faded in by `crossfade_music`
+                volume: Volume::SILENT,
+                ..default()
+            },
+        ));
+        (track, player.id(), 0.0)
+    });
+}
+
+// Fades the current track in and the old ones out, following the music volume as it changes
+// A track starts playing a frame or so after its entity is spawned, until then it has no sink.
+// A faded out track is stopped by despawning it.
+fn crossfade_music(
+    mut commands: Commands,
+    time: Res<Time>,
+    settings: Res<Settings>,
+    mut sinks: Query<&mut AudioSink>,
+    mut music: ResMut<Music>,
+) {
+    let step = time.delta_secs() / CROSSFADE_SECONDS;
+    let volume = settings.volume(Channel::Music);
+    let music = &mut *music;
+    if let Some((_, player, level)) = &mut music.current {
+        *level = (*level + step).min(1.0);
+        if let Ok(mut sink) = sinks.get_mut(*player) {
+            sink.set_volume(Volume::Linear(*level * volume));
+        }
+    }
+    music.fading_out.retain_mut(|(player, level)| {
+        *level -= step;
+        if *level > 0.0 {
+            if let Ok(mut sink) = sinks.get_mut(*player) {
+                sink.set_volume(Volume::Linear(*level * volume));
+            }
+            true
+        } else {
+            commands.entity(*player).despawn();
+            false
+        }
+    });
+}
+
+fn toggle_mute(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut settings: ResMut<Settings>,
+) {
+    // The key might be getting bound to an action
+    if *state.get() == GameState::Controls {
+        return;
+    }
+    if keyboard_input.just_pressed(MUTE_KEY) {
+        settings.muted = !settings.muted;
+        settings.save();
+    }
+}
+
+fn load_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SoundEffects {
+        jump: asset_server.load("sounds/jump.ogg"),
+        land: asset_server.load("sounds/land.ogg"),
+        die: asset_server.load("sounds/die.ogg"),
+        bump: asset_server.load("sounds/bump.ogg"),
+        flip: asset_server.load("sounds/flip.ogg"),
+        kick: asset_server.load("sounds/kick.ogg"),
+        coin: asset_server.load("sounds/coin.ogg"),
+        hammer: asset_server.load("sounds/hammer.ogg"),
+        boss_hit: asset_server.load("sounds/boss_hit.ogg"),
+        boss_defeated: asset_server.load("sounds/boss_defeated.ogg"),
+        hurry_up: asset_server.load("sounds/hurry_up.ogg"),
+        rage: asset_server.load("sounds/last_enemy.ogg"),
+        extra_life: asset_server.load("sounds/extra_life.ogg"),
+        splash: asset_server.load("sounds/splash.ogg"),
+        phase_clear: asset_server.load("sounds/phase_clear.ogg"),
+    });
+}
+
+// The sounds played for gameplay events
+#[derive(Resource)]
+struct SoundEffects {
+    jump: Handle<AudioSource>,
+    land: Handle<AudioSource>,
+    die: Handle<AudioSource>,
+    bump: Handle<AudioSource>,
+    flip: Handle<AudioSource>,
+    kick: Handle<AudioSource>,
+    coin: Handle<AudioSource>,
+    hammer: Handle<AudioSource>,
+    boss_hit: Handle<AudioSource>,
+    boss_defeated: Handle<AudioSource>,
+    hurry_up: Handle<AudioSource>,
+    // Warning jingle played when the last enemy of a phase gets angry
+    rage: Handle<AudioSource>,
+    extra_life: Handle<AudioSource>,
+    // Something fell into a lava or water floor
+    splash: Handle<AudioSource>,
+    phase_clear: Handle<AudioSource>,
+}
+
+// Each sound plays once per frame at most, however many of its events there were. Both players
+// jumping together sound like one jump, not one twice as loud.
+fn play_player_sounds(
+    mut jumped_events: MessageReader<PlayerJumped>,
+    mut landed_events: MessageReader<PlayerLanded>,
+    mut died_events: MessageReader<PlayerDied>,
+    mut swung_events: MessageReader<HammerSwung>,
+    mut extra_life_events: MessageReader<ExtraLifeAwarded>,
+    mut audio: AudioChannels,
+    sounds: Res<SoundEffects>,
+) {
+    if jumped_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.jump);
+    }
+    if landed_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.land);
+    }
+    if died_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.die);
+    }
+    if swung_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.hammer);
+    }
+    if extra_life_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.extra_life);
+    }
+}
+
+// Bumps come from the players too, but they are heard on the platform, with the enemies
+fn play_enemy_sounds(
+    mut bumped_events: MessageReader<PlatformBumped>,
+    mut flipped_events: MessageReader<EnemyFlipped>,
+    mut kicked_events: MessageReader<EnemyKicked>,
+    mut enraged_events: MessageReader<EnemyEnraged>,
+    mut audio: AudioChannels,
+    sounds: Res<SoundEffects>,
+) {
+    if enraged_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.rage);
+    }
+    // A flip already says the bump hit something
+    if flipped_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.flip);
+        bumped_events.clear();
+    } else if bumped_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.bump);
+    }
+    if kicked_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.kick);
+    }
+}
+
+fn play_arena_sounds(
+    mut cleared_events: MessageReader<PhaseCleared>,
+    mut splashed_events: MessageReader<Splashed>,
+    mut hurried_events: MessageReader<HurriedUp>,
+    mut audio: AudioChannels,
+    sounds: Res<SoundEffects>,
+) {
+    if cleared_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.phase_clear);
+    }
+    if splashed_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.splash);
+    }
+    if hurried_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.hurry_up);
+    }
+}
+
+fn play_boss_sounds(
+    mut hit_events: MessageReader<BossHit>,
+    mut defeated_events: MessageReader<BossDefeated>,
+    mut audio: AudioChannels,
+    sounds: Res<SoundEffects>,
+) {
+    // The last hit only sounds like the boss going down
+    let hit = hit_events.read().count() > 0;
+    if defeated_events.read().count() > 0 {
+        audio.play(Channel::Sfx, &sounds.boss_defeated);
+    } else if hit {
+        audio.play(Channel::Sfx, &sounds.boss_hit);
+    }
+}
+
+// Every coin and kick rings the coin sound, a little higher for each link of the chain. Only the
+// highest note of a frame is played.
+fn play_combo_sounds(
+    mut combo_events: MessageReader<ComboExtended>,
+    mut audio: AudioChannels,
+    sounds: Res<SoundEffects>,
+) {
+    if let Some(chain) = combo_events.read().map(|combo| combo.chain).max() {
+        let semitones = (chain - 1) as f32 * COMBO_PITCH_SEMITONES;
+        audio.play_at_speed(Channel::Sfx, &sounds.coin, 2f32.powf(semitones / 12.0));
+    }
+}
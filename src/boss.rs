@@ -0,0 +1,531 @@
+//! The boss: every tenth phase a giant crab takes the place of the usual enemies. Kicks and
+//! hammers don't hurt it; only hitting the POW block while it stands on the ground, or bumping the
+//! platform right under its feet, does. Each hit knocks it down for a moment, and makes it angrier.
+//!
+//! Between its walks it picks one of a few attacks: charging across the arena, leaping at the
+//! nearest player and staggering everyone on the ground as it lands, calling more enemies out of
+//! the pipes, or spitting a fireball. Once it is beaten, it blinks away with whatever it brought
+//! along, the player who landed the last hit gets a big bonus, and the next phase starts as usual.
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{
+    enemy::{
+        destroy_bumped_hazards, spawn_enemy, spawn_fireball, Enemy, EnemyCount, EnemyDefeated,
+        Hazard,
+    },
+    generator::Rng,
+    level::{
+        Collider, Grounded, LevelDef, Levels, Phase, PlatformBumped, PowBlock, RestartPhase,
+        Velocity, WrapsHorizontally, GRAVITY_ACCEL,
+    },
+    player::{Dying, Player},
+    status::{StatusEffects, StatusKind},
+    ui::{SCORE_COLOR, TEXT_COLOR},
+    GameState, PauseState, StepSet, BLOCK_SIZE, TIME_STEP,
+};
+
+// Every this many phases, a boss comes instead of the usual enemies
+const BOSS_EVERY: usize = 10;
+const BOSS_SIZE: Vec3 = Vec3::new(BLOCK_SIZE * 6.0, BLOCK_SIZE * 4.0, 0.0);
+// It comes in on the floor, away from where the players start
+const BOSS_START_X: f32 = BLOCK_SIZE * -10.0;
+const BOSS_COLOR: Color = Color::srgb(0.9, 0.35, 0.2);
+const STUNNED_BOSS_COLOR: Color = Color::srgb(0.9, 0.9, 0.2);
+const BOSS_HEALTH: usize = 6;
+// Each hit it took makes it this much faster
+const BOSS_RAGE_PER_HIT: f32 = 0.15;
+const BOSS_WALK_SPEED: f32 = 60.0;
+const BOSS_WALK_SECONDS: f32 = 2.5;
+const BOSS_CHARGE_SPEED: f32 = 260.0;
+const BOSS_CHARGE_SECONDS: f32 = 1.5;
+// A leap goes this much higher than the player it is aimed at, and no faster sideways than this
+const BOSS_LEAP_CLEARANCE: f32 = BLOCK_SIZE * 3.0;
+const BOSS_LEAP_MAX_XSPEED: f32 = 400.0;
+// Landing staggers the players standing on something
+const BOSS_QUAKE_SECONDS: f32 = 1.0;
+// Summoning and spitting, it stands still for this long first
+const BOSS_WINDUP_SECONDS: f32 = 1.0;
+const BOSS_SUMMONS: usize = 2;
+// No more summoning while this many enemies and hazards are around
+const BOSS_MAX_MINIONS: usize = 4;
+const BOSS_STUN_SECONDS: f32 = 3.0;
+const BOSS_BUMP_SPEED: f32 = 300.0;
+pub const BOSS_HIT_POINTS: usize = 1000;
+const BOSS_BONUS: usize = 30_000;
+const BOSS_VICTORY_SECONDS: f32 = 3.0;
+const BOSS_BLINK_SECONDS: f32 = 0.1;
+// Mixed into the number of every attack, to pick the next one
+const BOSS_SEED: u64 = 0xB055_C4AB;
+const BOSS_NAME: &str = "GIANT CRAB";
+const HEALTH_BAR_FONT_SIZE: f32 = 24.0;
+const HEALTH_BAR_WIDTH: Val = Val::Px(300.0);
+const HEALTH_BAR_HEIGHT: Val = Val::Px(14.0);
+const HEALTH_BAR_BACKGROUND: Color = Color::srgb(0.2, 0.2, 0.2);
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<BossHit>()
+            .add_message::<BossDefeated>()
+            // The boss goes by its hits, so it runs with the rest of the gameplay rather than the AI
+            .add_systems(
+                FixedUpdate,
+                (
+                    hurt_boss.before(destroy_bumped_hazards),
+                    run_boss.after(hurt_boss),
+                )
+                    .in_set(StepSet::Gameplay),
+            )
+            .add_systems(
+                Update,
+                (
+                    spawn_health_bar,
+                    update_health_bar.after(spawn_health_bar),
+                    clear_boss,
+                )
+                    .run_if(in_state(PauseState::Running)),
+            );
+    }
+}
+
+// Everything of the boss's that the gameplay step changes, for an online game to put back when
+// it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<Boss>();
+}
+
+pub fn is_boss_phase(phase: usize) -> bool {
+    phase > 0 && phase.is_multiple_of(BOSS_EVERY)
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default)]
+enum BossAttack {
+    // Towards the nearest player, until it picks an attack
+    #[default]
+    Walk,
+    Charge,
+    Leap,
+    Summon,
+    Spit,
+    // Beaten, blinking away
+    Defeated,
+}
+
+const ATTACKS: [BossAttack; 4] = [
+    BossAttack::Charge,
+    BossAttack::Leap,
+    BossAttack::Summon,
+    BossAttack::Spit,
+];
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Boss {
+    health: usize,
+    attack: BossAttack,
+    // Since the attack started
+    seconds: f32,
+    // How many attacks it picked so far
+    attacks: usize,
+    direction: f32,
+    speed_scale: f32,
+    // Who gets the bonus
+    last_hit_by: usize,
+}
+
+impl Boss {
+    fn start(&mut self, attack: BossAttack) {
+        self.attack = attack;
+        self.seconds = 0.0;
+    }
+
+    fn speed(&self) -> f32 {
+        self.speed_scale * (1.0 + (BOSS_HEALTH - self.health) as f32 * BOSS_RAGE_PER_HIT)
+    }
+}
+
+// Sent whenever the boss takes a hit, the last one included
+#[derive(Message)]
+pub struct BossHit {
+    pub player: usize,
+    pub position: Vec3,
+}
+
+// Sent when the boss takes its last hit, as the victory sequence starts
+#[derive(Message)]
+pub struct BossDefeated;
+
+// On the floor of the layout, or where it would be on layouts with a lava or water floor
+fn floor_top(level: &LevelDef) -> f32 {
+    (level.origin.y + 1.0) * BLOCK_SIZE
+}
+
+// Down on the floor, or sunk into the lava or water where there is no floor to stand on
+fn on_floor(level: &LevelDef, transform: &Transform) -> bool {
+    transform.translation.y - transform.scale.y / 2.0 <= floor_top(level)
+}
+
+pub fn start_position(level: &LevelDef) -> Vec3 {
+    Vec3::new(BOSS_START_X, floor_top(level) + BOSS_SIZE.y / 2.0, 1.0)
+}
+
+// Counted like any enemy, so the phase doesn't end before it is beaten
+pub fn spawn_boss(
+    commands: &mut Commands,
+    enemy_count: &mut EnemyCount,
+    position: Vec3,
+    speed_scale: f32,
+) {
+    enemy_count.0 += 1;
+    commands.spawn((
+        Sprite {
+            color: BOSS_COLOR,
+            ..default()
+        },
+        Transform::from_translation(position).with_scale(BOSS_SIZE),
+        Boss {
+            health: BOSS_HEALTH,
+            direction: 1.0,
+            speed_scale,
+            ..default()
+        },
+        StatusEffects::default(),
+        Grounded::default(),
+        Velocity::default(),
+        WrapsHorizontally,
+        DespawnOnExit(GameState::Playing),
+    ));
+}
+
+// A POW hit only counts while the boss is on the ground. A bump has to be under its feet, which
+// are wider than an enemy's.
+pub fn hurt_boss(
+    mut bump_events: MessageReader<PlatformBumped>,
+    platform_query: Query<(&Transform, Option<&PowBlock>), With<Collider>>,
+    mut boss_query: Query<(
+        &mut Boss,
+        &Transform,
+        &mut Velocity,
+        &Grounded,
+        &mut StatusEffects,
+    )>,
+    (mut hit_events, mut defeated_events): (MessageWriter<BossHit>, MessageWriter<BossDefeated>),
+    (phase, levels, level_assets): (Res<Phase>, Res<Levels>, Res<Assets<LevelDef>>),
+) {
+    let level = levels.for_phase(phase.0, &level_assets);
+    for bump in bump_events.read() {
+        let Ok((platform, pow)) = platform_query.get(bump.platform) else {
+            continue;
+        };
+        for (mut boss, transform, mut velocity, grounded, mut effects) in &mut boss_query {
+            if boss.attack == BossAttack::Defeated || effects.has(StatusKind::Stunned) {
+                continue;
+            }
+            let platform_top = platform.translation.y + platform.scale.y / 2.0;
+            let feet = transform.translation.y - transform.scale.y / 2.0;
+            let under_feet = (feet - platform_top).abs() < BLOCK_SIZE / 2.0
+                && (transform.translation.x - bump.x).abs() <= transform.scale.x / 2.0;
+            let hit = if pow.is_some() {
+                grounded.0.is_some() || on_floor(level, transform)
+            } else {
+                under_feet
+            };
+            if !hit {
+                continue;
+            }
+
+            boss.health -= 1;
+            boss.last_hit_by = bump.player;
+            velocity.x = 0.0;
+            velocity.y = BOSS_BUMP_SPEED;
+            hit_events.write(BossHit {
+                player: bump.player,
+                position: transform.translation,
+            });
+            if boss.health == 0 {
+                boss.start(BossAttack::Defeated);
+                effects.apply(StatusKind::Stunned, BOSS_VICTORY_SECONDS);
+                defeated_events.write(BossDefeated);
+            } else {
+                boss.start(BossAttack::Walk);
+                effects.apply(StatusKind::Stunned, BOSS_STUN_SECONDS);
+            }
+        }
+    }
+}
+
+// Straight up and over to `to`, high enough to clear it
+fn leap_velocity(from: Vec3, to: Vec3) -> Vec2 {
+    let rise = (to.y - from.y).max(0.0) + BOSS_LEAP_CLEARANCE;
+    let up_speed = (2.0 * GRAVITY_ACCEL * rise).sqrt();
+    let fall = rise - (to.y - from.y);
+    let air_seconds = up_speed / GRAVITY_ACCEL + (2.0 * fall.max(0.0) / GRAVITY_ACCEL).sqrt();
+    let x_speed =
+        ((to.x - from.x) / air_seconds).clamp(-BOSS_LEAP_MAX_XSPEED, BOSS_LEAP_MAX_XSPEED);
+    Vec2::new(x_speed, up_speed)
+}
+
+fn run_boss(
+    mut commands: Commands,
+    mut enemy_count: ResMut<EnemyCount>,
+    (phase, levels, level_assets): (Res<Phase>, Res<Levels>, Res<Assets<LevelDef>>),
+    mut boss_query: Query<(
+        Entity,
+        &mut Boss,
+        &mut Transform,
+        &mut Velocity,
+        &Grounded,
+        &StatusEffects,
+        (&mut Sprite, &mut Visibility),
+    )>,
+    mut player_query: Query<
+        (&Transform, &Grounded, &mut StatusEffects),
+        (With<Player>, Without<Dying>, Without<Boss>),
+    >,
+    minion_query: Query<Entity, Or<(With<Enemy>, With<Hazard>)>>,
+    mut defeated_events: MessageWriter<EnemyDefeated>,
+) {
+    let level = levels.for_phase(phase.0, &level_assets);
+    for (
+        entity,
+        mut boss,
+        mut transform,
+        mut velocity,
+        grounded,
+        effects,
+        (mut sprite, mut visibility),
+    ) in &mut boss_query
+    {
+        if boss.attack == BossAttack::Defeated {
+            // Whatever it brought along goes with it
+            if boss.seconds == 0.0 {
+                for minion in &minion_query {
+                    commands.entity(minion).despawn();
+                }
+                enemy_count.0 = 1;
+            }
+            boss.seconds += TIME_STEP;
+            velocity.x = 0.0;
+            *visibility = if ((boss.seconds / BOSS_BLINK_SECONDS) as usize).is_multiple_of(2) {
+                Visibility::Inherited
+            } else {
+                Visibility::Hidden
+            };
+            if boss.seconds >= BOSS_VICTORY_SECONDS {
+                commands.entity(entity).despawn();
+                enemy_count.0 = 0;
+                defeated_events.write(EnemyDefeated {
+                    player: boss.last_hit_by,
+                    position: transform.translation,
+                    base_points: 0,
+                    bonus: BOSS_BONUS,
+                });
+            }
+            continue;
+        }
+        boss.seconds += TIME_STEP;
+
+        // Nothing to stand on down there, it leaps back out of the lava or water instead
+        let sunk = on_floor(level, &transform) && grounded.0.is_none();
+        if sunk {
+            transform.translation.y = floor_top(level) + transform.scale.y / 2.0;
+            velocity.y = velocity.y.max(0.0);
+        }
+        let on_ground = grounded.0.is_some() || sunk;
+
+        if effects.has(StatusKind::Stunned) {
+            sprite.color = STUNNED_BOSS_COLOR;
+            velocity.x = 0.0;
+            continue;
+        }
+        sprite.color = BOSS_COLOR;
+
+        // Platforms turn it around, like any other enemy
+        if velocity.x != 0.0 {
+            boss.direction = velocity.x.signum();
+        }
+        let position = transform.translation;
+        let target = player_query
+            .iter()
+            .map(|(transform, _, _)| transform.translation)
+            .min_by(|a, b| {
+                a.distance_squared(position)
+                    .total_cmp(&b.distance_squared(position))
+            });
+        let speed = boss.speed();
+
+        match boss.attack {
+            BossAttack::Walk => {
+                if let Some(target) = target {
+                    boss.direction = if target.x < position.x { -1.0 } else { 1.0 };
+                }
+                velocity.x = boss.direction * BOSS_WALK_SPEED * speed;
+                if boss.seconds < BOSS_WALK_SECONDS / speed || !on_ground {
+                    continue;
+                }
+                let mut rng = Rng::new(BOSS_SEED ^ boss.attacks as u64);
+                boss.attacks += 1;
+                let attack = ATTACKS[rng.range(0, ATTACKS.len() - 1)];
+                boss.start(attack);
+                if attack == BossAttack::Leap {
+                    let to = target.unwrap_or(position);
+                    velocity.0 = leap_velocity(position, to);
+                }
+            }
+            BossAttack::Charge => {
+                velocity.x = boss.direction * BOSS_CHARGE_SPEED * speed;
+                if boss.seconds >= BOSS_CHARGE_SECONDS {
+                    boss.start(BossAttack::Walk);
+                }
+            }
+            BossAttack::Leap => {
+                // Out of the lava, the leap starts over
+                if sunk {
+                    velocity.0 = leap_velocity(position, target.unwrap_or(position));
+                    continue;
+                }
+                if !on_ground || velocity.y > 0.0 {
+                    continue;
+                }
+                for (_, player_grounded, mut player_effects) in &mut player_query {
+                    if player_grounded.0.is_some() && !player_effects.invincible() {
+                        player_effects.apply(StatusKind::Staggered, BOSS_QUAKE_SECONDS);
+                    }
+                }
+                velocity.x = 0.0;
+                boss.start(BossAttack::Walk);
+            }
+            BossAttack::Summon => {
+                velocity.x = 0.0;
+                if boss.seconds < BOSS_WINDUP_SECONDS {
+                    continue;
+                }
+                let minions = minion_query.iter().count();
+                for pipe in 0..BOSS_SUMMONS.min(BOSS_MAX_MINIONS.saturating_sub(minions)) {
+                    let position = level.pipe(boss.attacks + pipe);
+                    spawn_enemy(&mut commands, &mut enemy_count, position, 1.0);
+                }
+                boss.start(BossAttack::Walk);
+            }
+            BossAttack::Spit => {
+                velocity.x = 0.0;
+                if boss.seconds < BOSS_WINDUP_SECONDS {
+                    continue;
+                }
+                // Red ones, once it is down to half its health
+                let mouth = position + Vec3::Y * transform.scale.y / 2.0;
+                spawn_fireball(&mut commands, mouth, boss.health <= BOSS_HEALTH / 2);
+                boss.start(BossAttack::Walk);
+            }
+            BossAttack::Defeated => {}
+        }
+    }
+}
+
+// Across the bottom of the screen while the boss is around
+#[derive(Component)]
+struct HealthBar;
+
+#[derive(Component)]
+struct HealthBarFill;
+
+#[derive(Component)]
+struct HealthBarLabel;
+
+fn spawn_health_bar(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    boss_query: Query<(), Added<Boss>>,
+    bar_query: Query<(), With<HealthBar>>,
+) {
+    if boss_query.is_empty() || !bar_query.is_empty() {
+        return;
+    }
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(HEALTH_BAR_FONT_SIZE),
+            width: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        HealthBar,
+        DespawnOnExit(GameState::Playing),
+        children![
+            (
+                Text::new(BOSS_NAME),
+                TextFont::from_font_size(HEALTH_BAR_FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+                TextColor(TEXT_COLOR),
+                HealthBarLabel,
+            ),
+            (
+                Node {
+                    width: HEALTH_BAR_WIDTH,
+                    height: HEALTH_BAR_HEIGHT,
+                    ..default()
+                },
+                BackgroundColor(HEALTH_BAR_BACKGROUND),
+                children![(
+                    Node {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    BackgroundColor(BOSS_COLOR),
+                    HealthBarFill,
+                )],
+            ),
+        ],
+    ));
+}
+
+// Once the boss is beaten the bar announces the bonus, and goes with the boss
+fn update_health_bar(
+    mut commands: Commands,
+    boss_query: Query<&Boss, Changed<Boss>>,
+    all_boss_query: Query<(), With<Boss>>,
+    bar_query: Query<Entity, With<HealthBar>>,
+    mut fill_query: Query<&mut Node, With<HealthBarFill>>,
+    mut label_query: Query<(&mut Text, &mut TextColor), With<HealthBarLabel>>,
+) {
+    if all_boss_query.is_empty() {
+        for bar in &bar_query {
+            commands.entity(bar).despawn();
+        }
+        return;
+    }
+    for boss in &boss_query {
+        for mut node in &mut fill_query {
+            let health = boss.health as f32 / BOSS_HEALTH as f32;
+            node.width = Val::Percent(health * 100.0);
+        }
+        if boss.attack != BossAttack::Defeated {
+            continue;
+        }
+        for (mut text, mut color) in &mut label_query {
+            text.0 = format!("{BOSS_NAME} DEFEATED! +{BOSS_BONUS}");
+            color.0 = SCORE_COLOR;
+        }
+    }
+}
+
+// A restarted boss phase brings in a boss at full health, the bar goes with the old one
+fn clear_boss(
+    mut commands: Commands,
+    mut restart_events: MessageReader<RestartPhase>,
+    boss_query: Query<Entity, With<Boss>>,
+) {
+    if restart_events.read().count() == 0 {
+        return;
+    }
+    for boss in &boss_query {
+        commands.entity(boss).despawn();
+    }
+}
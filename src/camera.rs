@@ -0,0 +1,320 @@
+//! The camera: how the arena fits the window, screen shake, and the parallax layers behind the
+//! arena.
+//!
+//! The arena is drawn at a fixed resolution, big enough for it, and scaled up by a whole number of
+//! screen pixels per unit so every sprite pixel stays the same size. Whatever is left of the window
+//! is filled with bars.
+//!
+//! Hard hits add trauma, which wears off over time, and the camera is knocked around by the square
+//! of it. The shake is an offset put on the camera's transform for drawing only, and taken off
+//! again at the start of the next frame, so everything else that moves the camera never sees it.
+//!
+//! The parallax layers are placed relative to wherever the camera ends up each frame, shake
+//! included. Each one repeats its picture side by side, like the arena wraps around. A layer whose
+//! picture is missing is left out, rather than covering the back of the arena in stand-ins.
+
+use bevy::{
+    asset::LoadState, camera::Viewport, prelude::*, transform::TransformSystems,
+    window::PrimaryWindow,
+};
+
+use crate::{
+    enemy::FreezieExploded,
+    level::{ArenaBounds, PlatformBumped, PowBlock},
+    player::PlayerDied,
+    skins::{SkinImages, SpriteId},
+    GameState, BACKGROUND_COLOR, BLOCK_SIZE,
+};
+
+// The smallest resolution the arena is drawn at, in world units. Bigger arenas get more.
+const RENDER_SIZE: Vec2 = Vec2::new(640.0, 480.0);
+// Fills the arena's part of the window, behind everything else
+const BACKDROP_Z: f32 = -0.095;
+
+// How far the camera moves at full trauma
+const MAX_SHAKE_OFFSET: f32 = BLOCK_SIZE * 0.75;
+// Full trauma is gone after 1 / this many seconds
+const TRAUMA_DECAY_PER_SECOND: f32 = 1.5;
+const POW_TRAUMA: f32 = 0.6;
+const DEATH_TRAUMA: f32 = 0.4;
+const FREEZIE_TRAUMA: f32 = 0.3;
+// Unrelated frequencies, so the shake never settles into a visible pattern
+const SHAKE_FREQUENCIES: Vec2 = Vec2::new(37.0, 53.0);
+// Far to near
+const PARALLAX_LAYERS: [LayerDef; 3] = [
+    LayerDef {
+        sprite: SpriteId::Skyline,
+        size: Vec2::new(512.0, 256.0),
+        y: BLOCK_SIZE * 4.0,
+        factor: 0.15,
+    },
+    LayerDef {
+        sprite: SpriteId::Bricks,
+        size: Vec2::new(256.0, 512.0),
+        y: 0.0,
+        factor: 0.35,
+    },
+    LayerDef {
+        sprite: SpriteId::Pipes,
+        size: Vec2::new(256.0, 128.0),
+        y: -BLOCK_SIZE * 8.0,
+        factor: 0.6,
+    },
+];
+// Each layer's picture is repeated over at least this width, wider than any window
+const PARALLAX_SPAN: f32 = 4096.0;
+// Behind the background tiles of the arena, but still in front of the far plane of the camera
+const PARALLAX_Z: f32 = -0.09;
+const PARALLAX_Z_STEP: f32 = 0.01;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ScreenShake>()
+            .add_systems(Startup, spawn_camera)
+            .add_systems(
+                Update,
+                (
+                    frame_arena,
+                    shake_on_pow,
+                    shake_on_death,
+                    shake_on_freezie_explosion,
+                ),
+            )
+            .add_systems(OnEnter(GameState::Playing), spawn_parallax_layers)
+            .add_systems(First, unshake_camera)
+            .add_systems(PostUpdate, shake_camera.before(TransformSystems::Propagate))
+            .add_systems(
+                PostUpdate,
+                position_parallax_layers
+                    .after(shake_camera)
+                    .before(TransformSystems::Propagate),
+            );
+    }
+}
+
+// The backdrop moves along with the camera, so it needs the camera to be visible to be drawn
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn((
+        Camera2d,
+        Visibility::default(),
+        children![(
+            Sprite {
+                color: BACKGROUND_COLOR,
+                // Larger than any viewport, which cuts it off
+                custom_size: Some(Vec2::splat(PARALLAX_SPAN)),
+                ..default()
+            },
+            // The camera sits at 0, so this is where the backdrop ends up too
+            Transform::from_xyz(0.0, 0.0, BACKDROP_Z),
+        )],
+    ));
+}
+
+// Centers the camera on the arena, and scales the arena up by as many whole pixels per unit as
+// the window has room for. The camera stays level with the middle of the window, where the menus
+// are. A window too small for even one pixel per unit gets the arena shrunk to fit.
+fn frame_arena(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    bounds: Res<ArenaBounds>,
+    mut camera_query: Query<(&mut Camera, &mut Transform, &mut Projection)>,
+) {
+    let Ok(window) = window_query.single() else {
+        return;
+    };
+    let window_size = Vec2::new(
+        window.physical_width() as f32,
+        window.physical_height() as f32,
+    );
+    // Minimized
+    if window_size.min_element() < 1.0 {
+        return;
+    }
+
+    let needed = Vec2::new(
+        bounds.max.x - bounds.min.x,
+        2.0 * bounds.min.y.abs().max(bounds.max.y.abs()),
+    );
+    let resolution = RENDER_SIZE.max(needed);
+    let fit = (window_size / resolution).min_element();
+    let pixels_per_unit = if fit >= 1.0 { fit.floor() } else { fit };
+    let size = (resolution * pixels_per_unit).floor().max(Vec2::ONE);
+    let position = ((window_size - size) / 2.0).floor();
+
+    for (mut camera, mut transform, mut projection) in &mut camera_query {
+        let center_x = (bounds.min.x + bounds.max.x) / 2.0;
+        if transform.translation.x != center_x {
+            transform.translation.x = center_x;
+        }
+
+        let unchanged = camera.viewport.as_ref().is_some_and(|viewport| {
+            viewport.physical_position == position.as_uvec2()
+                && viewport.physical_size == size.as_uvec2()
+        });
+        if !unchanged {
+            camera.viewport = Some(Viewport {
+                physical_position: position.as_uvec2(),
+                physical_size: size.as_uvec2(),
+                ..default()
+            });
+        }
+        // The projection is in logical pixels of the viewport
+        let scale = window.scale_factor() / pixels_per_unit;
+        if let Projection::Orthographic(projection) = projection.as_mut() {
+            if projection.scale != scale {
+                projection.scale = scale;
+            }
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ScreenShake {
+    // From 0 to 1
+    trauma: f32,
+    // What was added to the camera's position this frame, to take off again
+    offset: Vec2,
+}
+
+impl ScreenShake {
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+}
+
+struct LayerDef {
+    sprite: SpriteId,
+    // Of one copy of the picture
+    size: Vec2,
+    // Height of the middle of the layer, with the camera level with the middle of the arena
+    y: f32,
+    factor: f32,
+}
+
+// A background that scrolls by `factor` of what the camera moves: 0 stays put on the screen,
+// 1 moves along with the arena
+#[derive(Component)]
+pub struct ParallaxLayer {
+    pub factor: f32,
+    // Width of one copy of the picture, after which it repeats
+    width: f32,
+    y: f32,
+}
+
+fn spawn_parallax_layers(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    skin_images: Res<SkinImages>,
+) {
+    for (depth, layer) in PARALLAX_LAYERS.iter().enumerate() {
+        let texture = skin_images.get(layer.sprite);
+        if matches!(
+            asset_server.get_load_state(&texture),
+            Some(LoadState::Failed(_))
+        ) {
+            continue;
+        }
+        let copies = (PARALLAX_SPAN / layer.size.x).ceil() as usize + 1;
+        let z = PARALLAX_Z + depth as f32 * PARALLAX_Z_STEP;
+        commands
+            .spawn((
+                Transform::from_xyz(0.0, layer.y, z),
+                Visibility::default(),
+                ParallaxLayer {
+                    factor: layer.factor,
+                    width: layer.size.x,
+                    y: layer.y,
+                },
+                DespawnOnExit(GameState::Playing),
+            ))
+            .with_children(|parent| {
+                for copy in 0..copies {
+                    let x = (copy as f32 - copies as f32 / 2.0) * layer.size.x;
+                    parent.spawn((
+                        Sprite {
+                            image: texture.clone(),
+                            custom_size: Some(layer.size),
+                            ..default()
+                        },
+                        Transform::from_xyz(x, 0.0, 0.0),
+                    ));
+                }
+            });
+    }
+}
+
+// Follows the camera, minus how far the layer has scrolled. Scrolling by a whole copy of the
+// picture looks the same as not scrolling at all, so the layer never runs out of copies.
+fn position_parallax_layers(
+    camera_query: Query<&Transform, (With<Camera>, Without<ParallaxLayer>)>,
+    mut layer_query: Query<(&ParallaxLayer, &mut Transform)>,
+) {
+    let Some(camera) = camera_query.iter().next() else {
+        return;
+    };
+    for (layer, mut transform) in &mut layer_query {
+        let scrolled = (camera.translation.x * layer.factor).rem_euclid(layer.width);
+        transform.translation.x = camera.translation.x - scrolled;
+        transform.translation.y = layer.y + camera.translation.y * (1.0 - layer.factor);
+    }
+}
+
+fn shake_on_pow(
+    mut bump_events: MessageReader<PlatformBumped>,
+    pow_query: Query<(), With<PowBlock>>,
+    mut shake: ResMut<ScreenShake>,
+) {
+    if bump_events
+        .read()
+        .any(|bump| pow_query.contains(bump.platform))
+    {
+        shake.add_trauma(POW_TRAUMA);
+    }
+}
+
+fn shake_on_death(mut died_events: MessageReader<PlayerDied>, mut shake: ResMut<ScreenShake>) {
+    for _ in died_events.read() {
+        shake.add_trauma(DEATH_TRAUMA);
+    }
+}
+
+fn shake_on_freezie_explosion(
+    mut exploded_events: MessageReader<FreezieExploded>,
+    mut shake: ResMut<ScreenShake>,
+) {
+    for _ in exploded_events.read() {
+        shake.add_trauma(FREEZIE_TRAUMA);
+    }
+}
+
+fn unshake_camera(
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<Camera>>,
+) {
+    if shake.offset == Vec2::ZERO {
+        return;
+    }
+    for mut transform in &mut camera_query {
+        transform.translation -= shake.offset.extend(0.0);
+    }
+    shake.offset = Vec2::ZERO;
+}
+
+fn shake_camera(
+    time: Res<Time>,
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<Camera>>,
+) {
+    if shake.trauma <= 0.0 {
+        return;
+    }
+
+    let strength = shake.trauma * shake.trauma;
+    let phase = SHAKE_FREQUENCIES * time.elapsed_secs();
+    shake.offset = Vec2::new(phase.x.sin(), phase.y.sin()) * MAX_SHAKE_OFFSET * strength;
+    for mut transform in &mut camera_query {
+        transform.translation += shake.offset.extend(0.0);
+    }
+    shake.trauma = (shake.trauma - TRAUMA_DECAY_PER_SECOND * time.delta_secs()).max(0.0);
+}
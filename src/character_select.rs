@@ -0,0 +1,201 @@
+//! The character select screen, between picking a game on the menus and playing it. Every player
+//! of the game picks who they play as with their own controls: Left and Right go through the
+//! characters, and Jump says they are ready. The game starts once everybody is. A player who
+//! changes their mind after that just picks again.
+//!
+//! The picks are kept in the settings, so the next game starts with the same characters picked,
+//! and the players are spawned with them. Where each player starts doesn't depend on the pick,
+//! so replays and online games play out the same whoever everybody is.
+
+use bevy::prelude::*;
+
+use crate::{
+    change_state,
+    controls::{Action, PlayerActions},
+    palette::Palette,
+    settings::Settings,
+    skins::{SkinImages, SpriteId},
+    ui::{
+        centered_screen_node, spawn_hint_text, spawn_title_text, MenuControls, MenuInput,
+        MENU_FONT_SIZE, SELECTED_TEXT_COLOR, TEXT_COLOR,
+    },
+    GameMode, GameState, MAX_PLAYERS,
+};
+
+const PORTRAIT_SIZE: f32 = 84.0;
+// Between the players' columns
+const COLUMN_GAP: f32 = 60.0;
+
+pub struct CharacterSelectPlugin;
+
+impl Plugin for CharacterSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::CharacterSelect),
+            spawn_character_select_screen,
+        )
+        .add_systems(
+            Update,
+            (
+                leave_character_select,
+                pick_characters,
+                update_character_cards.after(pick_characters),
+            )
+                .run_if(in_state(GameState::CharacterSelect)),
+        );
+    }
+}
+
+// Which players have said they are ready
+#[derive(Resource, Default)]
+struct CharacterSelection {
+    ready: [bool; MAX_PLAYERS],
+}
+
+// A player's column, with their character's picture and name
+#[derive(Component)]
+struct CharacterPortrait(usize);
+
+#[derive(Component)]
+struct CharacterName(usize);
+
+fn spawn_character_select_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    skin_images: Res<SkinImages>,
+    game_mode: Res<GameMode>,
+) {
+    commands.insert_resource(CharacterSelection::default());
+
+    let font = TextFont::from_font_size(MENU_FONT_SIZE)
+        .with_font(asset_server.load("fonts/FiraSans-Bold.ttf"));
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::CharacterSelect)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "CHOOSE YOUR CHARACTER");
+            parent
+                .spawn(Node {
+                    margin: UiRect::vertical(Val::Px(COLUMN_GAP / 2.0)),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for player in 0..game_mode.player_count() {
+                        parent.spawn((
+                            Node {
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                margin: UiRect::horizontal(Val::Px(COLUMN_GAP / 2.0)),
+                                ..default()
+                            },
+                            children![
+                                (
+                                    Text::new(format!("Player {}", player + 1)),
+                                    font.clone(),
+                                    TextColor(TEXT_COLOR),
+                                ),
+                                (
+                                    ImageNode::new(skin_images.get(SpriteId::LifeIcon)),
+                                    Node {
+                                        width: Val::Px(PORTRAIT_SIZE),
+                                        height: Val::Px(PORTRAIT_SIZE),
+                                        ..default()
+                                    },
+                                    CharacterPortrait(player),
+                                ),
+                                // Filled in by `update_character_cards`
+                                (
+                                    Text::default(),
+                                    font.clone(),
+                                    TextColor(TEXT_COLOR),
+                                    TextLayout::justify(Justify::Center),
+                                    CharacterName(player),
+                                ),
+                            ],
+                        ));
+                    }
+                });
+            spawn_hint_text(
+                parent,
+                &asset_server,
+                "Left/Right: change   Jump: ready   Esc: back",
+            );
+        });
+}
+
+// Back to the title menu, from any player's device
+fn leave_character_select(
+    mut controls: MenuControls,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if controls.pressed(MenuInput::Back) {
+        controls.reset(MenuInput::Back);
+        change_state(&mut next_state, GameState::Menu);
+    }
+}
+
+fn pick_characters(
+    mut actions: PlayerActions,
+    mut selection: ResMut<CharacterSelection>,
+    mut settings: ResMut<Settings>,
+    game_mode: Res<GameMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let players = game_mode.player_count();
+    for player in 0..players {
+        let step = if actions.just_pressed(player, Action::MoveLeft) {
+            Palette::ALL.len() - 1
+        } else if actions.just_pressed(player, Action::MoveRight) {
+            1
+        } else {
+            0
+        };
+        if step != 0 {
+            let palette = &mut settings.player_palettes[player];
+            let current = Palette::ALL
+                .iter()
+                .position(|other| *other == *palette)
+                .unwrap_or(0);
+            *palette = Palette::ALL[(current + step) % Palette::ALL.len()];
+            selection.ready[player] = false;
+        }
+        if actions.just_pressed(player, Action::Jump) {
+            selection.ready[player] = true;
+        }
+    }
+
+    if selection.ready[..players].iter().all(|&ready| ready) {
+        // The last press of Jump shouldn't make anyone jump as the game starts
+        for player in 0..players {
+            actions.reset(player, Action::Jump);
+        }
+        settings.save();
+        change_state(&mut next_state, GameState::Playing);
+    }
+}
+
+fn update_character_cards(
+    selection: Res<CharacterSelection>,
+    settings: Res<Settings>,
+    mut portrait_query: Query<(&CharacterPortrait, &mut ImageNode)>,
+    mut name_query: Query<(&CharacterName, &mut Text, &mut TextColor)>,
+) {
+    if !selection.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    for (portrait, mut image) in &mut portrait_query {
+        image.color = settings.player_palettes[portrait.0].tint();
+    }
+    for (name, mut text, mut color) in &mut name_query {
+        let palette = settings.player_palettes[name.0];
+        if selection.ready[name.0] {
+            text.0 = format!("{}\nReady!", palette.name());
+            color.0 = SELECTED_TEXT_COLOR;
+        } else {
+            text.0 = format!("< {} >\n", palette.name());
+            color.0 = TEXT_COLOR;
+        }
+    }
+}
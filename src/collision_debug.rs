@@ -0,0 +1,135 @@
+//! Draws what the collision code sees over the running game, toggled with F3: the box of every
+//! collider, one-way platform and sensor, the probe under each character's feet, and the normals
+//! of the contacts the players made in the last physics step. Each kind has a color of its own.
+//!
+//! The lines are gizmos of their own group, which F3 turns on and off.
+
+use bevy::prelude::*;
+
+use crate::{
+    level::{ground_probe, Collider, Collision, CollisionEvent, Grounded, OneWayPlatform, Sensor},
+    StepSet,
+};
+
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+const LINE_WIDTH: f32 = 1.0;
+const NORMAL_LENGTH: f32 = 12.0;
+const COLLIDER_COLOR: Color = Color::srgb(0.2, 1.0, 0.2);
+const ONE_WAY_COLOR: Color = Color::srgb(0.2, 0.8, 1.0);
+const SENSOR_COLOR: Color = Color::srgb(1.0, 0.9, 0.1);
+// A probe that found ground, and one that didn't
+const GROUNDED_PROBE_COLOR: Color = Color::srgb(1.0, 0.2, 1.0);
+const AIRBORNE_PROBE_COLOR: Color = Color::srgb(0.5, 0.3, 0.5);
+const NORMAL_COLOR: Color = Color::srgb(1.0, 0.2, 0.2);
+
+pub struct CollisionDebugPlugin;
+
+impl Plugin for CollisionDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CollisionContacts>()
+            .insert_gizmo_config(
+                CollisionGizmos,
+                GizmoConfig {
+                    enabled: false,
+                    line: GizmoLineConfig {
+                        width: LINE_WIDTH,
+                        ..default()
+                    },
+                    ..default()
+                },
+            )
+            .add_systems(Update, toggle_collision_debug)
+            .add_systems(FixedUpdate, remember_contacts.in_set(StepSet::Presentation))
+            // Wherever there are colliders, paused games and the editor included
+            .add_systems(Update, draw_collision_shapes.after(toggle_collision_debug));
+    }
+}
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct CollisionGizmos;
+
+// Where the players touched something in the last physics step, and the outward normal of what
+// they touched
+#[derive(Resource, Default)]
+struct CollisionContacts(Vec<(Vec2, Vec2)>);
+
+fn toggle_collision_debug(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut config_store: ResMut<GizmoConfigStore>,
+) {
+    if keyboard_input.just_pressed(TOGGLE_KEY) {
+        let (config, _) = config_store.config_mut::<CollisionGizmos>();
+        config.enabled = !config.enabled;
+    }
+}
+
+// Only kept while it is on, and never read by the gameplay, so replays and online games don't
+// notice
+fn remember_contacts(
+    mut collision_events: MessageReader<CollisionEvent>,
+    config_store: Res<GizmoConfigStore>,
+    mut contacts: ResMut<CollisionContacts>,
+) {
+    let touched = collision_events
+        .read()
+        .filter_map(|collision| {
+            // `side` is the side of the collider that was hit
+            let normal = match collision.side {
+                Collision::Top => Vec2::Y,
+                Collision::Bottom => Vec2::NEG_Y,
+                Collision::Left => Vec2::NEG_X,
+                Collision::Right => Vec2::X,
+                Collision::Inside => return None,
+            };
+            Some((collision.point, normal))
+        })
+        .collect();
+    let (config, _) = config_store.config::<CollisionGizmos>();
+    if config.enabled {
+        contacts.0 = touched;
+    }
+}
+
+fn draw_collision_shapes(
+    mut gizmos: Gizmos<CollisionGizmos>,
+    contacts: Res<CollisionContacts>,
+    collider_query: Query<(&Transform, Option<&OneWayPlatform>), With<Collider>>,
+    sensor_query: Query<&Transform, With<Sensor>>,
+    probe_query: Query<(&Transform, &Grounded)>,
+) {
+    if !gizmos.config.enabled {
+        return;
+    }
+
+    for (transform, one_way) in &collider_query {
+        let color = if one_way.is_some() {
+            ONE_WAY_COLOR
+        } else {
+            COLLIDER_COLOR
+        };
+        gizmos.rect_2d(
+            transform.translation.truncate(),
+            transform.scale.truncate(),
+            color,
+        );
+    }
+    for transform in &sensor_query {
+        gizmos.rect_2d(
+            transform.translation.truncate(),
+            transform.scale.truncate(),
+            SENSOR_COLOR,
+        );
+    }
+    for (transform, grounded) in &probe_query {
+        let (position, size) = ground_probe(transform);
+        let color = if grounded.0.is_some() {
+            GROUNDED_PROBE_COLOR
+        } else {
+            AIRBORNE_PROBE_COLOR
+        };
+        gizmos.rect_2d(position.truncate(), size, color);
+    }
+    for &(point, normal) in &contacts.0 {
+        gizmos.arrow_2d(point, point + normal * NORMAL_LENGTH, NORMAL_COLOR);
+    }
+}
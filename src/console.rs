@@ -0,0 +1,259 @@
+//! The debug console, opened and closed with the key left of 1 (` or ~). Each line typed into it
+//! is a command and its arguments, split on spaces, like `spawn shellcreeper 3` or `phase 7`.
+//! `help` lists every command there is, and `clear` empties the console.
+//!
+//! The console itself only knows those two. The rest are added by the plugins whose part of the
+//! game they change, with `add_console_command`, and run on the world directly. They only run
+//! while playing, and never in a replay being watched or an online game, which both have to play
+//! out the way they were played. A recording stops at the first one, much like restarting.
+//!
+//! While it is open, the keyboard is the console's: the game doesn't see any of it.
+
+use std::collections::BTreeMap;
+
+use bevy::{
+    input::{keyboard::KeyboardInput, InputSystems},
+    prelude::*,
+};
+
+use crate::{replay::ReplayPlayback, PauseState, StepDriver};
+
+const TOGGLE_KEY: KeyCode = KeyCode::Backquote;
+const FONT_SIZE: f32 = 18.0;
+// How many lines of what was typed and answered are kept on screen
+const MAX_LOG_LINES: usize = 12;
+const MAX_INPUT_LENGTH: usize = 80;
+const BACKGROUND_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.8);
+const TEXT_COLOR: Color = Color::srgb(0.85, 0.85, 0.85);
+
+// Runs a command, given its arguments, and answers with what it did or why it couldn't
+pub type ConsoleCommandFn = fn(&mut World, &[&str]) -> Result<String, String>;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Console>()
+            .init_resource::<ConsoleCommands>()
+            .add_message::<ConsoleCommandRun>()
+            // Before anything else looks at the keyboard
+            .add_systems(
+                PreUpdate,
+                (
+                    type_in_console.after(InputSystems),
+                    run_console_commands.after(type_in_console),
+                ),
+            )
+            .add_systems(Update, show_console);
+    }
+}
+
+pub trait AddConsoleCommand {
+    // `usage` is how `help` lists it, like "phase <number>"
+    fn add_console_command(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        run: ConsoleCommandFn,
+    ) -> &mut Self;
+}
+
+impl AddConsoleCommand for App {
+    fn add_console_command(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        run: ConsoleCommandFn,
+    ) -> &mut Self {
+        // Plugins can add theirs before or after the `ConsolePlugin` is added
+        self.init_resource::<ConsoleCommands>();
+        self.world_mut()
+            .resource_mut::<ConsoleCommands>()
+            .0
+            .insert(name, ConsoleCommand { usage, run });
+        self
+    }
+}
+
+// Sent whenever a command changed the game
+#[derive(Message)]
+pub struct ConsoleCommandRun;
+
+struct ConsoleCommand {
+    usage: &'static str,
+    run: ConsoleCommandFn,
+}
+
+// Every command by its name, in alphabetical order for `help`
+#[derive(Resource, Default)]
+struct ConsoleCommands(BTreeMap<&'static str, ConsoleCommand>);
+
+#[derive(Resource, Default)]
+struct Console {
+    open: bool,
+    input: String,
+    log: Vec<String>,
+    // The line just entered, for `run_console_commands`
+    entered: Option<String>,
+}
+
+impl Console {
+    fn print(&mut self, text: &str) {
+        self.log.extend(text.lines().map(str::to_owned));
+        let extra = self.log.len().saturating_sub(MAX_LOG_LINES);
+        self.log.drain(..extra);
+    }
+}
+
+// The console's background, and its text
+#[derive(Component)]
+struct ConsoleOverlay;
+
+#[derive(Component)]
+struct ConsoleText;
+
+fn type_in_console(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut key_events: MessageReader<KeyboardInput>,
+    mut console: ResMut<Console>,
+) {
+    let typed: Vec<char> = key_events
+        .read()
+        .filter(|event| event.state.is_pressed())
+        .filter_map(|event| event.text.as_ref())
+        .flat_map(|text| text.chars())
+        .collect();
+    if keyboard_input.just_pressed(TOGGLE_KEY) {
+        console.open = !console.open;
+        console.input.clear();
+    } else if !console.open {
+        return;
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        console.open = false;
+        console.input.clear();
+    } else if keyboard_input.just_pressed(KeyCode::Enter) {
+        let line = std::mem::take(&mut console.input);
+        console.entered = Some(line);
+    } else {
+        if keyboard_input.just_pressed(KeyCode::Backspace) {
+            console.input.pop();
+        }
+        // The toggle key types a character too
+        for typed in typed {
+            let allowed = !typed.is_control() && typed != '`' && typed != '~';
+            if allowed && console.input.len() < MAX_INPUT_LENGTH {
+                console.input.push(typed);
+            }
+        }
+    }
+    keyboard_input.reset_all();
+}
+
+fn run_console_commands(world: &mut World) {
+    let Some(line) = world.resource_mut::<Console>().entered.take() else {
+        return;
+    };
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((&name, args)) = words.split_first() else {
+        return;
+    };
+    if name == "clear" {
+        world.resource_mut::<Console>().log.clear();
+        return;
+    }
+
+    let reply = if name == "help" {
+        Ok(help(world.resource::<ConsoleCommands>()))
+    } else {
+        run_command(world, name, args)
+    };
+    let mut console = world.resource_mut::<Console>();
+    console.print(&format!("> {line}"));
+    match reply {
+        Ok(reply) => console.print(&reply),
+        Err(error) => console.print(&error),
+    }
+}
+
+fn help(commands: &ConsoleCommands) -> String {
+    ["help", "clear"]
+        .into_iter()
+        .chain(commands.0.values().map(|command| command.usage))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn run_command(world: &mut World, name: &str, args: &[&str]) -> Result<String, String> {
+    let Some(command) = world.resource::<ConsoleCommands>().0.get(name) else {
+        return Err(format!("There is no command \"{name}\", try help"));
+    };
+    let (usage, run) = (command.usage, command.run);
+    if world
+        .get_resource::<State<PauseState>>()
+        .is_none_or(|pause| *pause.get() != PauseState::Running)
+    {
+        return Err("Commands only run while playing".to_string());
+    }
+    if *world.resource::<StepDriver>() == StepDriver::Session
+        || world.contains_resource::<ReplayPlayback>()
+    {
+        return Err("Not in a replay or an online game".to_string());
+    }
+
+    let reply = run(world, args).map_err(|error| format!("{error}\nUsage: {usage}"))?;
+    world
+        .resource_mut::<Messages<ConsoleCommandRun>>()
+        .write(ConsoleCommandRun);
+    Ok(reply)
+}
+
+fn show_console(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    console: Res<Console>,
+    overlay_query: Query<Entity, With<ConsoleOverlay>>,
+    mut text_query: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.is_changed() {
+        return;
+    }
+    if !console.open {
+        for overlay in &overlay_query {
+            commands.entity(overlay).despawn();
+        }
+        return;
+    }
+
+    let text = console
+        .log
+        .iter()
+        .map(String::as_str)
+        .chain([format!("> {}_", console.input).as_str()])
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Ok(mut shown) = text_query.single_mut() {
+        shown.0 = text;
+        return;
+    }
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.0),
+                width: Val::Percent(100.0),
+                padding: UiRect::all(Val::Px(FONT_SIZE / 2.0)),
+                ..default()
+            },
+            BackgroundColor(BACKGROUND_COLOR),
+            ConsoleOverlay,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(text),
+                TextFont::from_font_size(FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+                TextColor(TEXT_COLOR),
+                ConsoleText,
+            ));
+        });
+}
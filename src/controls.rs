@@ -0,0 +1,365 @@
+//! What each player does, from their keys or their gamepad. Gameplay asks about actions rather
+//! than keys or buttons, through `PlayerActions`, and the `Bindings` in the settings say which
+//! ones trigger each action. Bindings are changed on the controls screen.
+//!
+//! The gameplay step doesn't ask `PlayerActions` itself: what each player holds is copied into
+//! `StepInputs` first, so the step plays the same whether it comes from this machine's keys or
+//! from somewhere else, like the other machine of an online game.
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::{gamepad::GamepadConnectionEvent, InputSystems},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::Settings, MAX_PLAYERS};
+
+// How far the left stick has to be pushed to count as moving
+const STICK_DEADZONE: f32 = 0.5;
+// Hotkeys that work while playing, whatever the bindings: restarting (R, Ctrl+R for the whole
+// run), muting, the collision shapes and the console. None of them can be bound to an action.
+const RESERVED_KEYS: [KeyCode; 6] = [
+    KeyCode::KeyR,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::KeyM,
+    KeyCode::F3,
+    KeyCode::Backquote,
+];
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GamepadAssignment>()
+            .init_resource::<StepInputs>()
+            .add_systems(
+                PreUpdate,
+                (
+                    assign_gamepads.after(InputSystems),
+                    read_local_inputs.after(assign_gamepads),
+                ),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Pause,
+    // Swings the hammer, while the player has one
+    Attack,
+}
+
+impl Action {
+    // Actions added later come last, so the bindings of older settings files still line up
+    pub const ALL: [Action; 5] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Pause,
+        Action::Attack,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Left",
+            Action::MoveRight => "Right",
+            Action::Jump => "Jump",
+            Action::Pause => "Pause",
+            Action::Attack => "Hammer",
+        }
+    }
+
+    // Every player pauses the same game, so they can share a key for it
+    fn shareable(self) -> bool {
+        self == Action::Pause
+    }
+}
+
+// Returned when a key can't be bound because a hotkey has it
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReservedKey;
+
+// The key and the gamepad button of each action, in the order of `Action::ALL`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlayerBindings {
+    keys: [KeyCode; Action::ALL.len()],
+    buttons: [GamepadButton; Action::ALL.len()],
+}
+
+impl PlayerBindings {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys[action as usize]
+    }
+
+    pub fn button(&self, action: Action) -> GamepadButton {
+        self.buttons[action as usize]
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "SavedBindings")]
+pub struct Bindings(pub [PlayerBindings; MAX_PLAYERS]);
+
+// Bindings as saved in the settings file, which may be from before some actions were added
+#[derive(Deserialize)]
+struct SavedBindings([SavedPlayerBindings; MAX_PLAYERS]);
+
+#[derive(Deserialize)]
+struct SavedPlayerBindings {
+    keys: Vec<KeyCode>,
+    buttons: Vec<GamepadButton>,
+}
+
+// Actions missing from the file get their default key and button
+impl From<SavedBindings> for Bindings {
+    fn from(saved: SavedBindings) -> Self {
+        let mut bindings = Bindings::default();
+        for (player, saved) in bindings.0.iter_mut().zip(saved.0) {
+            for (key, saved) in player.keys.iter_mut().zip(saved.keys) {
+                *key = saved;
+            }
+            for (button, saved) in player.buttons.iter_mut().zip(saved.buttons) {
+                *button = saved;
+            }
+        }
+        bindings
+    }
+}
+
+impl Bindings {
+    // Binds a key to one of a player's actions. Whatever the key was already bound to gets the
+    // action's old key instead, so no key does two things at once. Returns the player and the
+    // action that were moved, if any.
+    pub fn bind_key(
+        &mut self,
+        player: usize,
+        action: Action,
+        key: KeyCode,
+    ) -> Result<Option<(usize, Action)>, ReservedKey> {
+        if RESERVED_KEYS.contains(&key) {
+            return Err(ReservedKey);
+        }
+        let old = self.0[player].key(action);
+        let clash = (0..MAX_PLAYERS)
+            .flat_map(|other| Action::ALL.map(|other_action| (other, other_action)))
+            .filter(|&(other, other_action)| (other, other_action) != (player, action))
+            .filter(|&(other, other_action)| {
+                other == player || !(other_action == action && action.shareable())
+            })
+            .find(|&(other, other_action)| self.0[other].key(other_action) == key);
+        if let Some((other, other_action)) = clash {
+            self.0[other].keys[other_action as usize] = old;
+        }
+        self.0[player].keys[action as usize] = key;
+        Ok(clash)
+    }
+
+    // Like `bind_key`, but every player has a gamepad of their own, so a button can only clash
+    // with another action of the same player
+    pub fn bind_button(
+        &mut self,
+        player: usize,
+        action: Action,
+        button: GamepadButton,
+    ) -> Option<Action> {
+        let bindings = &mut self.0[player];
+        let old = bindings.button(action);
+        let clash = Action::ALL
+            .into_iter()
+            .find(|&other| other != action && bindings.button(other) == button);
+        if let Some(other) = clash {
+            bindings.buttons[other as usize] = old;
+        }
+        bindings.buttons[action as usize] = button;
+        clash
+    }
+}
+
+impl Default for Bindings {
+    // The arrow keys for the first player and WASD for the second, so both fit on one keyboard
+    fn default() -> Self {
+        use GamepadButton::*;
+        let buttons = [DPadLeft, DPadRight, South, Start, West];
+        Bindings([
+            PlayerBindings {
+                keys: [
+                    KeyCode::ArrowLeft,
+                    KeyCode::ArrowRight,
+                    KeyCode::ArrowUp,
+                    KeyCode::Escape,
+                    KeyCode::ShiftRight,
+                ],
+                buttons,
+            },
+            PlayerBindings {
+                keys: [
+                    KeyCode::KeyA,
+                    KeyCode::KeyD,
+                    KeyCode::KeyW,
+                    KeyCode::Escape,
+                    KeyCode::ShiftLeft,
+                ],
+                buttons,
+            },
+        ])
+    }
+}
+
+// The first gamepad connected belongs to the first player, the next one to the second. A player
+// whose gamepad is unplugged gets the next one connected.
+#[derive(Resource, Default)]
+pub struct GamepadAssignment([Option<Entity>; MAX_PLAYERS]);
+
+fn assign_gamepads(
+    mut gamepad_events: MessageReader<GamepadConnectionEvent>,
+    mut assignment: ResMut<GamepadAssignment>,
+) {
+    for event in gamepad_events.read() {
+        if event.connected() {
+            if assignment.0.contains(&Some(event.gamepad)) {
+                continue;
+            }
+            if let Some(slot) = assignment.0.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(event.gamepad);
+            }
+        } else {
+            for slot in &mut assignment.0 {
+                if *slot == Some(event.gamepad) {
+                    *slot = None;
+                }
+            }
+        }
+    }
+}
+
+// The actions of every player, from their bound keys or buttons. The keyboard keeps working
+// with a gamepad plugged in.
+#[derive(SystemParam)]
+pub struct PlayerActions<'w, 's> {
+    keyboard_input: ResMut<'w, ButtonInput<KeyCode>>,
+    gamepads: Query<'w, 's, &'static mut Gamepad>,
+    settings: Res<'w, Settings>,
+    assignment: Res<'w, GamepadAssignment>,
+}
+
+impl PlayerActions<'_, '_> {
+    fn gamepad(&self, player: usize) -> Option<&Gamepad> {
+        self.assignment.0[player].and_then(|entity| self.gamepads.get(entity).ok())
+    }
+
+    pub fn held(&self, player: usize, action: Action) -> bool {
+        let bindings = &self.settings.bindings.0[player];
+        if self.keyboard_input.pressed(bindings.key(action)) {
+            return true;
+        }
+        let Some(gamepad) = self.gamepad(player) else {
+            return false;
+        };
+        if gamepad.pressed(bindings.button(action)) {
+            return true;
+        }
+        // The left stick moves too, whatever the buttons are bound to
+        let stick_x = gamepad.get(GamepadAxis::LeftStickX).unwrap_or(0.0);
+        match action {
+            Action::MoveLeft => stick_x < -STICK_DEADZONE,
+            Action::MoveRight => stick_x > STICK_DEADZONE,
+            _ => false,
+        }
+    }
+
+    pub fn just_pressed(&self, player: usize, action: Action) -> bool {
+        let bindings = &self.settings.bindings.0[player];
+        self.keyboard_input.just_pressed(bindings.key(action))
+            || self
+                .gamepad(player)
+                .is_some_and(|gamepad| gamepad.just_pressed(bindings.button(action)))
+    }
+
+    // Forgets a press, so it isn't seen again by the screen it leads to
+    pub fn reset(&mut self, player: usize, action: Action) {
+        let key = self.settings.bindings.0[player].key(action);
+        let button = self.settings.bindings.0[player].button(action);
+        self.keyboard_input.reset(key);
+        if let Some(mut gamepad) =
+            self.assignment.0[player].and_then(|entity| self.gamepads.get_mut(entity).ok())
+        {
+            gamepad.digital_mut().reset(button);
+        }
+    }
+}
+
+// The actions a player holds during one gameplay step, one bit each. Small enough to send over
+// the network every step, or to keep for every step of a replay.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerInput(u8);
+
+impl PlayerInput {
+    // Pausing isn't part of the gameplay step
+    const STEP_ACTIONS: [Action; 4] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Attack,
+    ];
+
+    pub fn from_bits(bits: u8) -> PlayerInput {
+        PlayerInput(bits)
+    }
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn held(self, action: Action) -> bool {
+        self.0 & (1 << action as u8) != 0
+    }
+
+    // What the player holds on this machine right now
+    pub fn read(actions: &PlayerActions, player: usize) -> PlayerInput {
+        let bits = Self::STEP_ACTIONS
+            .into_iter()
+            .filter(|&action| actions.held(player, action))
+            .fold(0, |bits, action| bits | 1 << action as u8);
+        PlayerInput(bits)
+    }
+}
+
+// What each player holds during the next gameplay step
+#[derive(Resource, Default)]
+pub struct StepInputs(pub [PlayerInput; MAX_PLAYERS]);
+
+fn read_local_inputs(actions: PlayerActions, mut inputs: ResMut<StepInputs>) {
+    for (player, input) in inputs.0.iter_mut().enumerate() {
+        *input = PlayerInput::read(&actions, player);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hotkeys_cant_be_bound() {
+        let mut bindings = Bindings::default();
+        for key in RESERVED_KEYS {
+            assert_eq!(bindings.bind_key(0, Action::Jump, key), Err(ReservedKey));
+        }
+        assert_eq!(bindings.0[0].key(Action::Jump), KeyCode::ArrowUp);
+    }
+
+    #[test]
+    fn taken_key_swaps_with_the_old_one() {
+        let mut bindings = Bindings::default();
+        assert_eq!(
+            bindings.bind_key(0, Action::Jump, KeyCode::ArrowLeft),
+            Ok(Some((0, Action::MoveLeft)))
+        );
+        assert_eq!(bindings.0[0].key(Action::Jump), KeyCode::ArrowLeft);
+        assert_eq!(bindings.0[0].key(Action::MoveLeft), KeyCode::ArrowUp);
+    }
+}
@@ -0,0 +1,191 @@
+//! The daily challenge: endless mode arenas from a seed made from the date, so everyone playing
+//! on the same day gets the same phases. The best score of each day is kept apart from the high
+//! score table, which is for games everyone can start however they like.
+//!
+//! The date also turns on a few mutators, or none, the same ones for everyone.
+
+#[cfg(not(feature = "wasm"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_state,
+    level::{LevelChoice, LevelDef, Levels, StartPhase},
+    mutators::{MutatorDefs, MutatorList, Mutators},
+    player::Scoreboard,
+    replay::ReplayPlayback,
+    storage::{self, Location},
+    GameMode, GameState,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// Mixed into the day, so the daily seeds aren't just the day numbers
+const DAILY_SALT: u64 = 0xDA11_C4A1_1E46_E5EE;
+// Mixed into the daily seed to pick the mutators, so they don't follow from the arenas
+const MUTATOR_SALT: u64 = 0x3D7A_70E5;
+// Older days are forgotten
+const DAYS_KEPT: usize = 30;
+
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DailyScores::load())
+            .init_resource::<DailyRun>()
+            .add_message::<PlayDaily>()
+            .add_systems(Update, start_daily.run_if(in_state(GameState::Menu)))
+            .add_systems(OnExit(GameState::Playing), record_daily_score);
+    }
+}
+
+// Sent by the title menu to play today's challenge
+#[derive(Message)]
+pub struct PlayDaily;
+
+// Days since 1970-01-01, in UTC
+#[cfg(not(feature = "wasm"))]
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() / SECONDS_PER_DAY)
+}
+
+// The browser has no system clock, but the page knows when it was opened
+#[cfg(feature = "wasm")]
+fn today() -> u64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0, |performance| {
+            ((performance.time_origin() + performance.now()) / 1000.0) as u64 / SECONDS_PER_DAY
+        })
+}
+
+fn seed(day: u64) -> u64 {
+    day.wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ DAILY_SALT
+}
+
+// Like 2024-03-17, from days since 1970-01-01
+fn format_day(day: u64) -> String {
+    // Howard Hinnant's civil_from_days, with days counted from 0000-03-01 so leap days come last
+    let days = day + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{year}-{month:02}-{day_of_month:02}")
+}
+
+// The day of the challenge being played, and its mutators
+#[derive(Resource, Default)]
+pub struct DailyRun {
+    day: u64,
+    mutators: String,
+}
+
+// The best score of each day's challenge, saved in the platform's config directory
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct DailyScores {
+    // Latest day last
+    days: Vec<(u64, usize)>,
+}
+
+impl DailyScores {
+    const FILE_NAME: &str = "daily_scores.ron";
+
+    // A missing or unreadable file just means no challenge was played yet
+    fn load() -> DailyScores {
+        storage::read(Location::Config, Self::FILE_NAME)
+            .and_then(|contents| ron::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Config, Self::FILE_NAME, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the daily scores to {}: {err}",
+                storage::describe(Location::Config, Self::FILE_NAME)
+            );
+        }
+    }
+
+    fn best(&self, day: u64) -> Option<usize> {
+        self.days
+            .iter()
+            .find(|(other, _)| *other == day)
+            .map(|(_, score)| *score)
+    }
+
+    fn insert(&mut self, day: u64, score: usize) {
+        match self.days.iter_mut().find(|(other, _)| *other == day) {
+            Some((_, best)) => *best = (*best).max(score),
+            None => {
+                self.days.push((day, score));
+                self.days.sort_by_key(|(day, _)| *day);
+            }
+        }
+        let forgotten = self.days.len().saturating_sub(DAYS_KEPT);
+        self.days.drain(..forgotten);
+    }
+
+    // For the game over screen, in place of the high score table
+    pub fn summary(&self, run: &DailyRun) -> String {
+        let mut text = format!("Daily challenge of {}\n", format_day(run.day));
+        if !run.mutators.is_empty() {
+            text += &format!("{}\n", run.mutators);
+        }
+        text + &format!("Best that day: {}\n", self.best(run.day).unwrap_or(0))
+    }
+}
+
+fn start_daily(
+    mut events: MessageReader<PlayDaily>,
+    mut run: ResMut<DailyRun>,
+    mut next_state: ResMut<NextState<GameState>>,
+    (mut game_mode, mut start_phase): (ResMut<GameMode>, ResMut<StartPhase>),
+    (mut levels, level_assets): (ResMut<Levels>, Res<Assets<LevelDef>>),
+    (mut mutators, defs, lists): (ResMut<Mutators>, Res<MutatorDefs>, Res<Assets<MutatorList>>),
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    // Everyone has to get the same mutators, so not without knowing which there are
+    let Some(list) = lists.get(&defs.0) else {
+        return;
+    };
+    run.day = today();
+    let seed = seed(run.day);
+    start_phase.0 = levels.choose(&LevelChoice::Endless { seed }, &level_assets);
+    mutators.0 = list.pick(seed ^ MUTATOR_SALT);
+    run.mutators = list.labels(&mutators.0);
+    *game_mode = GameMode::Daily;
+    change_state(&mut next_state, GameState::Playing);
+}
+
+// Kept under the day the challenge was started on, even if it was finished after midnight
+fn record_daily_score(
+    mut scores: ResMut<DailyScores>,
+    run: Res<DailyRun>,
+    game_mode: Res<GameMode>,
+    scoreboard: Res<Scoreboard>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    if *game_mode != GameMode::Daily || playback.is_some() {
+        return;
+    }
+    scores.insert(run.day, scoreboard.score(0));
+    scores.save();
+}
@@ -0,0 +1,24 @@
+//! Tools for working on the game, with the `dev-tools` feature: a world inspector over the
+//! running game, listing every entity and resource. What the gameplay modules register can be
+//! changed in it as the game runs, like a player's `Velocity`, whether they are `Grounded`, the
+//! timers of their status effects, or the `JumpConfig` while tuning how jumps feel.
+//!
+//! Without the feature none of this is built, and the inspector isn't even a dependency.
+
+use bevy::prelude::*;
+use bevy_inspector_egui::{bevy_egui::EguiPlugin, quick::WorldInspectorPlugin};
+
+use crate::{enemy, hurry, level, player, status};
+
+pub struct DevToolsPlugin;
+
+impl Plugin for DevToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((EguiPlugin::default(), WorldInspectorPlugin::new()));
+        level::register_inspectable(app);
+        player::register_inspectable(app);
+        enemy::register_inspectable(app);
+        status::register_inspectable(app);
+        hurry::register_inspectable(app);
+    }
+}
@@ -0,0 +1,450 @@
+//! The spawn director: what comes out of the pipes during a phase, and when. Each layout can
+//! describe its own in the `spawns` table of its level file, layouts that don't get the arcade's:
+//! an enemy out of every pipe as the phase starts, then Freezies and fireballs every so often.
+//!
+//! An entry of the table comes out `count` times, the first at `start` seconds into the phase and
+//! then every `interval` seconds, so "3 enemies at 20 seconds" is an entry of its own. How many,
+//! how often and how fast can follow the phase number, as `Curve`s. Whatever is due waits while
+//! there are already `max_alive` enemies and hazards around.
+//!
+//! Boss phases leave the table of their layout aside: the boss comes alone, and brings its own.
+
+use bevy::{ecs::system::SystemState, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boss::{self, spawn_boss},
+    console::AddConsoleCommand,
+    enemy::{spawn_enemy, spawn_fireball, spawn_freezie, Enemy, EnemyCount, Hazard, ScoreKind},
+    level::{LevelDef, Levels, Phase},
+    StepSet, TIME_STEP,
+};
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+// The arcade's table, for layouts without one
+const DEFAULT_MAX_ALIVE: usize = 8;
+const FREEZIE_FIRST_PHASE: usize = 2;
+const FREEZIE_SPAWN_SECONDS: f32 = 10.0;
+const FIREBALL_SPAWN_SECONDS: f32 = 15.0;
+const RED_FIREBALL_FIRST_PHASE: usize = 3;
+
+pub struct DirectorPlugin;
+
+impl Plugin for DirectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpawnDirector>()
+            .register_type::<Scheduled>()
+            .add_systems(FixedUpdate, direct_spawns.in_set(StepSet::Ai))
+            .add_console_command(
+                "spawn",
+                "spawn <shellcreeper|freezie|fireball|red_fireball|boss> [count]",
+                spawn_command,
+            );
+    }
+}
+
+// Everything of the director's that the gameplay step changes, for an online game to put back
+// when it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_resource_with_reflect::<SpawnDirector>();
+}
+
+// What can come out of a pipe
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Spawn {
+    #[default]
+    Enemy,
+    Freezie,
+    GreenFireball,
+    RedFireball,
+    // Walks in on the floor rather than out of a pipe
+    Boss,
+}
+
+impl Spawn {
+    // Out of the pipe at `position`, going `speed_scale` times as fast as usual. Fireballs always
+    // fly at the same speed.
+    pub fn spawn(
+        self,
+        commands: &mut Commands,
+        enemy_count: &mut EnemyCount,
+        position: Vec3,
+        speed_scale: f32,
+    ) {
+        match self {
+            Spawn::Enemy => spawn_enemy(commands, enemy_count, position, speed_scale),
+            Spawn::Freezie => spawn_freezie(commands, position, speed_scale),
+            Spawn::GreenFireball => spawn_fireball(commands, position, false),
+            Spawn::RedFireball => spawn_fireball(commands, position, true),
+            Spawn::Boss => spawn_boss(commands, enemy_count, position, speed_scale),
+        }
+    }
+
+    // The bosses are worth nothing until they are beaten, and never come one at a time
+    fn score_kind(self) -> Option<ScoreKind> {
+        match self {
+            Spawn::Enemy => Some(ScoreKind::Enemy),
+            Spawn::Freezie => Some(ScoreKind::Freezie),
+            Spawn::GreenFireball => Some(ScoreKind::GreenFireball),
+            Spawn::RedFireball => Some(ScoreKind::RedFireball),
+            Spawn::Boss => None,
+        }
+    }
+
+    // Whether the phase waits for it to be defeated
+    fn holds_phase(self) -> bool {
+        matches!(self, Spawn::Enemy | Spawn::Boss)
+    }
+
+    // What the console calls it
+    fn from_name(name: &str) -> Option<Spawn> {
+        match name {
+            "shellcreeper" | "enemy" => Some(Spawn::Enemy),
+            "freezie" => Some(Spawn::Freezie),
+            "fireball" | "green_fireball" => Some(Spawn::GreenFireball),
+            "red_fireball" => Some(Spawn::RedFireball),
+            "boss" => Some(Spawn::Boss),
+            _ => None,
+        }
+    }
+}
+
+// `(x, value)` points, joined by straight lines and level before the first and after the last
+// one. Without any points it is 0 everywhere.
+#[derive(Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Curve(Vec<(usize, f32)>);
+
+impl Curve {
+    fn one() -> Curve {
+        Curve(vec![(1, 1.0)])
+    }
+
+    pub fn at(&self, x: usize) -> f32 {
+        let points = &self.0;
+        let Some(&(first_x, first)) = points.first() else {
+            return 0.0;
+        };
+        if x <= first_x {
+            return first;
+        }
+        points.windows(2).find(|pair| x < pair[1].0).map_or_else(
+            || points[points.len() - 1].1,
+            |pair| {
+                let ((from_x, from), (to_x, to)) = (pair[0], pair[1]);
+                let along = (x - from_x) as f32 / (to_x - from_x) as f32;
+                from + (to - from) * along
+            },
+        )
+    }
+
+    // The values of its points, between which every other value lies
+    pub fn values(&self) -> impl Iterator<Item = f32> + '_ {
+        self.0.iter().map(|&(_, value)| value)
+    }
+}
+
+// Which pipe a spawn comes out of
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Pipe {
+    // The pipes take turns, over all the entries of the table
+    #[default]
+    Next,
+    // One out of every pipe at once
+    Each,
+    Only(usize),
+}
+
+// One line of a spawn table. The curves go by the phase number.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnEntry {
+    spawn: Spawn,
+    // How many times it comes out over the phase
+    #[serde(default = "Curve::one")]
+    count: Curve,
+    // Seconds into the phase of the first one, and between the others
+    #[serde(default)]
+    start: f32,
+    #[serde(default)]
+    interval: Curve,
+    // A multiple of the usual speed
+    #[serde(default = "Curve::one")]
+    speed: Curve,
+    #[serde(default)]
+    pipe: Pipe,
+    // Only from this phase on
+    #[serde(default)]
+    first_phase: usize,
+    // Comes out for as long as the phase lasts, whatever the count. Enemies that keep coming
+    // don't hold up the end of the phase.
+    #[serde(default)]
+    keeps_coming: bool,
+    // Doesn't count down while another of its kind is around
+    #[serde(default)]
+    one_at_a_time: bool,
+}
+
+impl SpawnEntry {
+    fn new(spawn: Spawn) -> SpawnEntry {
+        SpawnEntry {
+            spawn,
+            count: Curve::one(),
+            start: 0.0,
+            interval: Curve::default(),
+            speed: Curve::one(),
+            pipe: Pipe::Next,
+            first_phase: 0,
+            keeps_coming: false,
+            one_at_a_time: false,
+        }
+    }
+
+    // The arcade's hazards, a new one every so often once the last one is gone
+    fn hazard(spawn: Spawn, first_phase: usize, seconds: f32) -> SpawnEntry {
+        SpawnEntry {
+            start: seconds,
+            interval: Curve(vec![(first_phase, seconds)]),
+            first_phase,
+            keeps_coming: true,
+            one_at_a_time: true,
+            ..SpawnEntry::new(spawn)
+        }
+    }
+}
+
+// What comes out of the pipes during each phase of a layout
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpawnTable {
+    max_alive: usize,
+    entries: Vec<SpawnEntry>,
+}
+
+impl SpawnTable {
+    pub fn is_default(&self) -> bool {
+        *self == SpawnTable::default()
+    }
+
+    fn boss() -> SpawnTable {
+        SpawnTable {
+            max_alive: DEFAULT_MAX_ALIVE,
+            entries: vec![SpawnEntry::new(Spawn::Boss)],
+        }
+    }
+}
+
+impl Default for SpawnTable {
+    fn default() -> Self {
+        let mut green_fireballs =
+            SpawnEntry::hazard(Spawn::GreenFireball, 0, FIREBALL_SPAWN_SECONDS);
+        // Once red fireballs come too, the two take turns
+        green_fireballs.interval = Curve(vec![
+            (RED_FIREBALL_FIRST_PHASE - 1, FIREBALL_SPAWN_SECONDS),
+            (RED_FIREBALL_FIRST_PHASE, FIREBALL_SPAWN_SECONDS * 2.0),
+        ]);
+        let red_fireballs = SpawnEntry::hazard(
+            Spawn::RedFireball,
+            RED_FIREBALL_FIRST_PHASE,
+            FIREBALL_SPAWN_SECONDS * 2.0,
+        );
+        Self {
+            max_alive: DEFAULT_MAX_ALIVE,
+            entries: vec![
+                SpawnEntry {
+                    pipe: Pipe::Each,
+                    ..SpawnEntry::new(Spawn::Enemy)
+                },
+                SpawnEntry::hazard(Spawn::Freezie, FREEZIE_FIRST_PHASE, FREEZIE_SPAWN_SECONDS),
+                green_fireballs,
+                red_fireballs,
+            ],
+        }
+    }
+}
+
+// An entry of the current phase's table, with its curves worked out for the phase
+#[derive(Reflect, Default)]
+struct Scheduled {
+    spawn: Spawn,
+    pipe: Pipe,
+    left: usize,
+    keeps_coming: bool,
+    one_at_a_time: bool,
+    interval: f32,
+    speed_scale: f32,
+    // Counts down to the next one
+    seconds: f32,
+}
+
+// What is still to come out of the pipes this phase
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct SpawnDirector {
+    scheduled: Vec<Scheduled>,
+    max_alive: usize,
+    enemies_to_come: usize,
+    // For the pipes to take turns
+    spawned: usize,
+}
+
+impl SpawnDirector {
+    // Every phase starts over from the table of its layout
+    pub fn start(&mut self, phase: usize, level: &LevelDef) {
+        let boss_table;
+        let table = if boss::is_boss_phase(phase) {
+            boss_table = SpawnTable::boss();
+            &boss_table
+        } else {
+            &level.spawns
+        };
+        self.scheduled = table
+            .entries
+            .iter()
+            .filter(|entry| entry.first_phase <= phase)
+            .map(|entry| Scheduled {
+                spawn: entry.spawn,
+                pipe: entry.pipe,
+                left: entry.count.at(phase).round() as usize,
+                keeps_coming: entry.keeps_coming,
+                one_at_a_time: entry.one_at_a_time,
+                interval: entry.interval.at(phase),
+                speed_scale: entry.speed.at(phase),
+                seconds: entry.start,
+            })
+            .collect();
+        self.max_alive = table.max_alive;
+        self.enemies_to_come = self
+            .scheduled
+            .iter()
+            .filter(|scheduled| scheduled.spawn.holds_phase() && !scheduled.keeps_coming)
+            .map(|scheduled| match scheduled.pipe {
+                // Bosses don't come out of pipes
+                Pipe::Each if scheduled.spawn != Spawn::Boss => scheduled.left * level.pipes.len(),
+                Pipe::Each | Pipe::Next | Pipe::Only(_) => scheduled.left,
+            })
+            .sum();
+        self.spawned = 0;
+    }
+
+    // The phase isn't over while more enemies are on their way
+    pub fn enemies_to_come(&self) -> usize {
+        self.enemies_to_come
+    }
+
+    pub fn phase_cleared(&self, enemy_count: &EnemyCount) -> bool {
+        enemy_count.0 == 0 && self.enemies_to_come == 0
+    }
+}
+
+// The `spawn` console command: what it names comes out of the pipes in turn, on top of whatever
+// the table sends out. Enemies hold the phase like any others.
+fn spawn_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let (name, count) = match args {
+        [name] => (*name, 1),
+        [name, count] => match count.parse() {
+            Ok(count) if count >= 1 => (*name, count),
+            _ => return Err(format!("\"{count}\" isn't a count")),
+        },
+        _ => return Err("Spawn what?".to_string()),
+    };
+    let Some(spawn) = Spawn::from_name(name) else {
+        return Err(format!("Nothing called \"{name}\" comes out of the pipes"));
+    };
+
+    let mut state: SystemState<(
+        Commands,
+        ResMut<SpawnDirector>,
+        ResMut<EnemyCount>,
+        (Res<Phase>, Res<Levels>, Res<Assets<LevelDef>>),
+    )> = SystemState::new(world);
+    let (mut commands, mut director, mut enemy_count, (phase, levels, level_assets)) =
+        state.get_mut(world).map_err(|err| err.to_string())?;
+    let level = levels.for_phase(phase.0, &level_assets);
+    if spawn != Spawn::Boss && level.pipes.is_empty() {
+        return Err("This layout has no pipes".to_string());
+    }
+    for _ in 0..count {
+        let position = if spawn == Spawn::Boss {
+            boss::start_position(level)
+        } else {
+            director.spawned += 1;
+            level.pipe(director.spawned - 1)
+        };
+        spawn.spawn(&mut commands, &mut enemy_count, position, 1.0);
+    }
+    state.apply(world);
+    Ok(format!("{count} x {name}"))
+}
+
+fn direct_spawns(
+    mut commands: Commands,
+    mut director: ResMut<SpawnDirector>,
+    mut enemy_count: ResMut<EnemyCount>,
+    (phase, levels, level_assets): (Res<Phase>, Res<Levels>, Res<Assets<LevelDef>>),
+    alive_query: Query<&ScoreKind, Or<(With<Enemy>, With<Hazard>)>>,
+) {
+    let level = levels.for_phase(phase.0, &level_assets);
+    let mut alive = alive_query.iter().count();
+    let SpawnDirector {
+        scheduled,
+        max_alive,
+        enemies_to_come,
+        spawned,
+    } = &mut *director;
+
+    for scheduled in scheduled {
+        if scheduled.left == 0 && !scheduled.keeps_coming {
+            continue;
+        }
+        let kind = scheduled.spawn.score_kind();
+        if scheduled.one_at_a_time && alive_query.iter().any(|other| Some(*other) == kind) {
+            continue;
+        }
+        scheduled.seconds -= TIME_STEP;
+        if scheduled.seconds > 0.0 || alive >= *max_alive {
+            continue;
+        }
+        if scheduled.spawn == Spawn::Boss {
+            let position = boss::start_position(level);
+            scheduled.spawn.spawn(
+                &mut commands,
+                &mut enemy_count,
+                position,
+                scheduled.speed_scale,
+            );
+            *enemies_to_come = enemies_to_come.saturating_sub(1);
+            scheduled.left = scheduled.left.saturating_sub(1);
+            scheduled.seconds = scheduled.interval;
+            continue;
+        }
+        if level.pipes.is_empty() {
+            continue;
+        }
+
+        let pipes = match scheduled.pipe {
+            Pipe::Next => {
+                *spawned += 1;
+                *spawned - 1..*spawned
+            }
+            Pipe::Each => 0..level.pipes.len(),
+            Pipe::Only(pipe) => pipe..pipe + 1,
+        };
+        for pipe in pipes {
+            let position = level.pipe(pipe);
+            scheduled.spawn.spawn(
+                &mut commands,
+                &mut enemy_count,
+                position,
+                scheduled.speed_scale,
+            );
+            if scheduled.spawn.holds_phase() && !scheduled.keeps_coming {
+                *enemies_to_come = enemies_to_come.saturating_sub(1);
+            }
+            alive += 1;
+        }
+        scheduled.left = scheduled.left.saturating_sub(1);
+        scheduled.seconds = scheduled.interval;
+    }
+}
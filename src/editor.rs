@@ -0,0 +1,429 @@
+//! The level editor: paint the tiles of an arena with the mouse, place its pipes and POW block,
+//! play-test it and save it as a level file.
+
+#[cfg(not(feature = "wasm"))]
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+
+#[cfg(feature = "wasm")]
+use crate::storage::{self, Location};
+use crate::{
+    change_state,
+    level::{rebuild_arena, Background, HazardFloor, LevelDef, Levels, Platform, PowBlock, Tile},
+    GameState, PauseState, BLOCK_SIZE,
+};
+
+// Saved layouts without a path of their own end up here, relative to the assets folder
+const NEW_LEVEL_PATH: &str = "levels/edited.level.ron";
+const PIPE_MARKER_COLOR: Color = Color::srgb(0.2, 0.8, 0.2);
+// Right clicking this close to a pipe removes it
+const PIPE_PICK_RANGE: f32 = 2.0;
+const HELP_FONT_SIZE: f32 = 20.0;
+const HELP_TEXT_COLOR: Color = Color::srgb(0.5, 0.5, 1.0);
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorTool>()
+            .add_systems(OnEnter(GameState::Menu), close_editor)
+            .add_systems(Update, open_editor.run_if(in_state(GameState::Menu)))
+            .add_systems(OnEnter(GameState::Editor), spawn_editor_screen)
+            .add_systems(
+                Update,
+                (
+                    select_tool,
+                    edit_level.after(select_tool),
+                    update_help_text.after(select_tool),
+                    show_edited_level.after(edit_level),
+                    save_level,
+                    leave_editor,
+                )
+                    .run_if(in_state(GameState::Editor)),
+            )
+            .add_systems(Update, stop_play_test.run_if(in_state(PauseState::Running)));
+    }
+}
+
+#[derive(Component)]
+struct PipeMarker;
+
+#[derive(Component)]
+struct HelpText;
+
+// The layout being edited, which starts out as the first phase's one
+#[derive(Resource)]
+struct EditedLevel {
+    level: LevelDef,
+    // Where it is saved, relative to the assets folder
+    path: PathBuf,
+}
+
+// What clicking does, picked with the number keys
+#[derive(Resource, Clone, Copy, PartialEq, Default)]
+enum EditorTool {
+    #[default]
+    Solid,
+    Ice,
+    OneWay,
+    ConveyorLeft,
+    ConveyorRight,
+    Crumbling,
+    Pipe,
+    PowBlock,
+}
+
+impl EditorTool {
+    const ALL: [(KeyCode, EditorTool); 8] = [
+        (KeyCode::Digit1, EditorTool::Solid),
+        (KeyCode::Digit2, EditorTool::Ice),
+        (KeyCode::Digit3, EditorTool::OneWay),
+        (KeyCode::Digit4, EditorTool::ConveyorLeft),
+        (KeyCode::Digit5, EditorTool::ConveyorRight),
+        (KeyCode::Digit6, EditorTool::Crumbling),
+        (KeyCode::Digit7, EditorTool::Pipe),
+        (KeyCode::Digit8, EditorTool::PowBlock),
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            EditorTool::Solid => "platform",
+            EditorTool::Ice => "ice",
+            EditorTool::OneWay => "one-way platform",
+            EditorTool::ConveyorLeft => "conveyor going left",
+            EditorTool::ConveyorRight => "conveyor going right",
+            EditorTool::Crumbling => "crumbling platform",
+            EditorTool::Pipe => "pipe",
+            EditorTool::PowBlock => "POW block",
+        }
+    }
+
+    fn tile(&self) -> Option<Tile> {
+        match self {
+            EditorTool::Solid => Some(Tile::Solid),
+            EditorTool::Ice => Some(Tile::Ice),
+            EditorTool::OneWay => Some(Tile::OneWay),
+            EditorTool::ConveyorLeft => Some(Tile::ConveyorLeft),
+            EditorTool::ConveyorRight => Some(Tile::ConveyorRight),
+            EditorTool::Crumbling => Some(Tile::Crumbling),
+            EditorTool::Pipe | EditorTool::PowBlock => None,
+        }
+    }
+}
+
+impl EditedLevel {
+    // The row and column of the tile under a point in level coordinates, counting rows from the
+    // top like the level files do
+    fn tile_at(&self, point: Vec2) -> Option<(usize, usize)> {
+        let cell = (point - self.level.origin).floor();
+        let height = self.level.tiles.len() as f32;
+        if cell.x < 0.0 || cell.y < 0.0 || cell.y >= height {
+            return None;
+        }
+        let row = (height - 1.0 - cell.y) as usize;
+        let column = cell.x as usize;
+        (column < self.level.tiles[row].chars().count()).then_some((row, column))
+    }
+
+    fn tile(&self, row: usize, column: usize) -> Tile {
+        Tile::from_char(self.level.tiles[row].chars().nth(column).unwrap_or('.'))
+    }
+
+    fn set_tile(&mut self, row: usize, column: usize, tile: Tile) {
+        self.level.tiles[row] = self.level.tiles[row]
+            .chars()
+            .enumerate()
+            .map(|(index, character)| {
+                if index == column {
+                    tile.to_char()
+                } else {
+                    character
+                }
+            })
+            .collect();
+    }
+
+    // Pipes and the POW block sit on the corners of the tile grid
+    fn snap(&self, point: Vec2) -> Vec2 {
+        self.level.origin + (point - self.level.origin).round()
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn save(&self) {
+        let path = PathBuf::from("assets").join(&self.path);
+        let result = ron::ser::to_string_pretty(&self.level, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+                }
+                fs::write(&path, contents).map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(()) => info!("Saved the level to {}", path.display()),
+            Err(err) => warn!("Could not save the level to {}: {err}", path.display()),
+        }
+    }
+
+    // The browser can't write to the assets folder, so the level is kept with the players' own
+    // levels instead, and shows up on the level select screen after a reload
+    #[cfg(feature = "wasm")]
+    fn save(&self) {
+        let Some(file_name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        let name = format!("levels/{file_name}");
+        let result = ron::ser::to_string_pretty(&self.level, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Data, &name, &contents));
+        let described = storage::describe(Location::Data, &name);
+        match result {
+            Ok(()) => info!("Saved the level to {described}"),
+            Err(err) => warn!("Could not save the level to {described}: {err}"),
+        }
+    }
+}
+
+// E on the title menu opens the editor on the first phase's layout
+fn open_editor(
+    mut commands: Commands,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::KeyE) || !levels.loaded(&asset_server) {
+        return;
+    }
+
+    let handle = levels.handle_for_phase(1, &level_assets);
+    // Maps from other editors are saved as level files next to the original
+    let path = match asset_server.get_path(handle) {
+        Some(asset_path) if asset_path.path().to_string_lossy().ends_with(".level.ron") => {
+            asset_path.path().to_owned()
+        }
+        Some(asset_path) => asset_path.path().with_extension("level.ron"),
+        None => PathBuf::from(NEW_LEVEL_PATH),
+    };
+    commands.insert_resource(EditedLevel {
+        level: level_assets.get(handle).unwrap().clone(),
+        path,
+    });
+    change_state(&mut next_state, GameState::Editor);
+    keyboard_input.reset(KeyCode::KeyE);
+}
+
+fn close_editor(mut commands: Commands) {
+    commands.remove_resource::<EditedLevel>();
+}
+
+fn spawn_editor_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut edited: ResMut<EditedLevel>,
+) {
+    commands.spawn((
+        Text::default(),
+        TextFont::from_font_size(HELP_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+        TextColor(HELP_TEXT_COLOR),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        HelpText,
+        DespawnOnExit(GameState::Editor),
+    ));
+    // The arena was cleaned up when the play-test ended, so it has to be shown again
+    edited.set_changed();
+}
+
+fn select_tool(keyboard_input: Res<ButtonInput<KeyCode>>, mut tool: ResMut<EditorTool>) {
+    for (key, key_tool) in EditorTool::ALL {
+        if keyboard_input.just_pressed(key) {
+            *tool = key_tool;
+        }
+    }
+}
+
+fn update_help_text(
+    tool: Res<EditorTool>,
+    edited: Res<EditedLevel>,
+    mut query: Query<&mut Text, With<HelpText>>,
+    new_text_query: Query<(), Added<HelpText>>,
+) {
+    if !tool.is_changed() && !edited.is_changed() && new_text_query.is_empty() {
+        return;
+    }
+    for mut text in &mut query {
+        text.0 = format!(
+            "LEVEL EDITOR - {}\n1: platform  2: ice  3: one-way  4: pipe  5: POW block\nLeft click: place ({})  Right click: remove\nTab: play-test  S: save  Esc: back to the menu",
+            edited.path.display(),
+            tool.label(),
+        );
+    }
+}
+
+// The left mouse button paints with the current tool, the right one erases
+fn edit_level(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    tool: Res<EditorTool>,
+    mut edited: ResMut<EditedLevel>,
+) {
+    let Some(cursor) = window_query
+        .single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+    else {
+        return;
+    };
+    let Some(point) = camera_query.iter().find_map(|(camera, transform)| {
+        camera
+            .viewport_to_world_2d(transform, cursor)
+            .ok()
+            .map(|point| point / BLOCK_SIZE)
+    }) else {
+        return;
+    };
+
+    match tool.tile() {
+        // Tiles are painted while the button is held
+        Some(tile) => {
+            let paint = if mouse_input.pressed(MouseButton::Left) {
+                tile
+            } else if mouse_input.pressed(MouseButton::Right) {
+                Tile::Empty
+            } else {
+                return;
+            };
+            // Only touch the resource when a tile actually changes, so the arena isn't
+            // rebuilt every frame
+            if let Some((row, column)) = edited.tile_at(point) {
+                if edited.tile(row, column) != paint {
+                    edited.set_tile(row, column, paint);
+                }
+            }
+        }
+        None if mouse_input.just_pressed(MouseButton::Left) => {
+            let position = edited.snap(point);
+            if *tool == EditorTool::Pipe {
+                edited.level.pipes.push(position);
+            } else {
+                edited.level.pow_block = Some(position);
+            }
+        }
+        None if mouse_input.just_pressed(MouseButton::Right) => {
+            if *tool == EditorTool::PowBlock {
+                edited.level.pow_block = None;
+                return;
+            }
+            // Enemies need at least one pipe to come out of
+            if edited.level.pipes.len() < 2 {
+                return;
+            }
+            let nearest = edited
+                .level
+                .pipes
+                .iter()
+                .enumerate()
+                .map(|(index, pipe)| (index, pipe.distance(point)))
+                .filter(|(_, distance)| *distance <= PIPE_PICK_RANGE)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+            if let Some((index, _)) = nearest {
+                edited.level.pipes.remove(index);
+            }
+        }
+        None => {}
+    }
+}
+
+// The arena is rebuilt from scratch after every edit, just like when a level file is reloaded
+fn show_edited_level(
+    mut commands: Commands,
+    edited: Res<EditedLevel>,
+    arena_query: Query<
+        Entity,
+        Or<(
+            With<Platform>,
+            With<PowBlock>,
+            With<Background>,
+            With<HazardFloor>,
+        )>,
+    >,
+    marker_query: Query<Entity, With<PipeMarker>>,
+) {
+    if !edited.is_changed() {
+        return;
+    }
+
+    rebuild_arena(
+        &mut commands,
+        &arena_query,
+        &edited.level,
+        GameState::Editor,
+    );
+    for entity in &marker_query {
+        commands.entity(entity).despawn();
+    }
+    for pipe in &edited.level.pipes {
+        commands.spawn((
+            Sprite {
+                color: PIPE_MARKER_COLOR,
+                ..default()
+            },
+            Transform {
+                translation: (*pipe * BLOCK_SIZE).extend(1.0),
+                scale: Vec3::new(BLOCK_SIZE, BLOCK_SIZE, 1.0),
+                ..default()
+            },
+            PipeMarker,
+            DespawnOnExit(GameState::Editor),
+        ));
+    }
+}
+
+fn save_level(keyboard_input: Res<ButtonInput<KeyCode>>, edited: Res<EditedLevel>) {
+    if keyboard_input.just_pressed(KeyCode::KeyS) {
+        edited.save();
+    }
+}
+
+// Tab plays the edited layout, Esc goes back to the menu
+fn leave_editor(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut levels: ResMut<Levels>,
+    mut level_assets: ResMut<Assets<LevelDef>>,
+    edited: Res<EditedLevel>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Tab) {
+        levels.custom = Some(level_assets.add(edited.level.clone()));
+        change_state(&mut next_state, GameState::Playing);
+        keyboard_input.reset(KeyCode::Tab);
+    } else if keyboard_input.just_pressed(KeyCode::Escape) {
+        change_state(&mut next_state, GameState::Menu);
+        // Esc on the menu quits the game
+        keyboard_input.reset(KeyCode::Escape);
+    }
+}
+
+// Tab during a play-test goes straight back to editing
+fn stop_play_test(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut levels: ResMut<Levels>,
+    edited: Option<Res<EditedLevel>>,
+) {
+    if edited.is_some() && keyboard_input.just_pressed(KeyCode::Tab) {
+        levels.custom = None;
+        change_state(&mut next_state, GameState::Editor);
+        keyboard_input.reset(KeyCode::Tab);
+    }
+}
@@ -0,0 +1,33 @@
+//! Assets built into the executable, with the `embedded` feature. build.rs lists every file of
+//! assets/ with `include_bytes!`, and the asset server reads them from there instead of from the
+//! folder, so the game can be handed out as a single file. Saving levels from the editor still
+//! writes to assets/levels, but the game won't see them until it is built again.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::io::{
+        memory::{Dir, MemoryAssetReader},
+        AssetSourceBuilder, AssetSourceId,
+    },
+    prelude::*,
+};
+
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+// Added before bevy's `AssetPlugin`, which then reads the default asset source from here
+pub struct EmbeddedAssetsPlugin;
+
+impl Plugin for EmbeddedAssetsPlugin {
+    fn build(&self, app: &mut App) {
+        // Nothing built in ever changes, so there is nothing to watch either
+        let root = Dir::new(PathBuf::new());
+        for (file, bytes) in EMBEDDED_ASSETS {
+            root.insert_asset(Path::new(file), *bytes);
+        }
+        app.register_asset_source(
+            AssetSourceId::Default,
+            AssetSourceBuilder::new(move || Box::new(MemoryAssetReader { root: root.clone() })),
+        );
+    }
+}
@@ -0,0 +1,940 @@
+//! Enemies, hazards and coins, and how they move and get knocked out. When they come out of the
+//! pipes is up to the spawn director.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{
+    boss::Boss,
+    console::AddConsoleCommand,
+    director::SpawnDirector,
+    level::{
+        collide, hit_by_bump, penetration, reflection, Collider, Collision, Crushed, GravityScale,
+        Grounded, HazardFloor, Ice, OneWayPlatform, Platform, PlatformBumped, PowBlock,
+        RestartPhase, Sensor, SpatialHash, TileMap, TriggerEnter, Velocity, WrapsHorizontally,
+        ICE_COLOR, TOP_WALL,
+    },
+    mutators::CoopRules,
+    player::{Dying, Facing, Player},
+    powerup::HammerSwung,
+    GameMode, GameState, PauseState, StepSet, BLOCK_SIZE, TIME_STEP,
+};
+
+const ENEMY_SIZE: Vec3 = Vec3::new(BLOCK_SIZE * 2.0, BLOCK_SIZE * 1.5, 0.0);
+const ENEMY_SPEED: f32 = 100.0;
+// How long a bumped enemy stays on its back before getting up again
+const ENEMY_FLIP_SECONDS: f32 = 5.0;
+// Upward kick given to an enemy when the platform under it is bumped
+const ENEMY_BUMP_SPEED: f32 = 400.0;
+// The last enemy of a phase gets angry and runs this much faster
+const RAGE_SPEED_MULTIPLIER: f32 = 2.0;
+const COIN_SIZE: Vec3 = Vec3::new(BLOCK_SIZE, BLOCK_SIZE, 0.0);
+const COIN_XSPEED: f32 = 120.0;
+const COIN_POP_SPEED: f32 = 600.0;
+// Fraction of the landing speed a coin keeps on its single bounce
+const COIN_BOUNCE: f32 = 0.5;
+// Versus mode: kicking an enemy the other player flipped is worth extra
+const STOLEN_KICK_BONUS: usize = 800;
+// Co-op games where kicks hit the partner: how a kicked enemy flies off, and for how long
+const KICKED_ENEMY_SPEED: f32 = 500.0;
+const KICKED_ENEMY_POP_SPEED: f32 = 300.0;
+const KICKED_ENEMY_SECONDS: f32 = 1.5;
+const FREEZIE_SIZE: Vec3 = Vec3::new(BLOCK_SIZE * 1.5, BLOCK_SIZE * 1.5, 0.0);
+const FREEZIE_SPEED: f32 = 150.0;
+// How long a Freezie has to be standing on a platform before it freezes it
+const FREEZIE_FUSE_SECONDS: f32 = 4.0;
+const FIREBALL_SIZE: Vec3 = Vec3::new(BLOCK_SIZE, BLOCK_SIZE, 0.0);
+// Fireballs fly diagonally, this fast along each axis
+const FIREBALL_SPEED: f32 = 120.0;
+// Fireballs burn out on their own after a while
+const FIREBALL_LIFETIME_SECONDS: f32 = 8.0;
+const RED_FIREBALL_SPEED: f32 = 140.0;
+// How fast (radians per second) a red fireball can turn towards the nearest player
+const RED_FIREBALL_TURN_RATE: f32 = 1.5;
+// Points for everything a player can get rid of or pick up
+const SCORE_TABLE: [(ScoreKind, usize); 5] = [
+    (ScoreKind::Enemy, 800),
+    (ScoreKind::Coin, 800),
+    (ScoreKind::Freezie, 500),
+    (ScoreKind::GreenFireball, 1000),
+    (ScoreKind::RedFireball, 1000),
+];
+const ENEMY_COLOR: Color = Color::srgb(0.2, 0.8, 0.3);
+const FLIPPED_ENEMY_COLOR: Color = Color::srgb(0.9, 0.9, 0.2);
+const ENRAGED_ENEMY_COLOR: Color = Color::srgb(0.9, 0.2, 0.2);
+const FREEZIE_COLOR: Color = Color::srgb(0.7, 0.95, 1.0);
+const GREEN_FIREBALL_COLOR: Color = Color::srgb(0.3, 1.0, 0.2);
+const RED_FIREBALL_COLOR: Color = Color::srgb(1.0, 0.25, 0.1);
+const COIN_COLOR: Color = Color::srgb(1.0, 0.8, 0.1);
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EnemyCount(0))
+            .add_message::<EnemyKicked>()
+            .add_message::<EnemyDefeated>()
+            .add_message::<EnemyFlipped>()
+            .add_message::<HazardDestroyed>()
+            .add_message::<CoinCollected>()
+            .add_message::<FreezieExploded>()
+            .add_message::<EnemyEnraged>()
+            .add_systems(Update, clear_enemies.run_if(in_state(PauseState::Running)))
+            .add_systems(FixedUpdate, steer_tracking_fireballs.in_set(StepSet::Ai))
+            .add_systems(
+                FixedUpdate,
+                (check_for_body_collisions, bounce_fireballs).in_set(StepSet::CollisionResolve),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    flip_bumped_enemies,
+                    recover_flipped_enemies.after(flip_bumped_enemies),
+                    kick_flipped_enemies.after(flip_bumped_enemies),
+                    flip_hammered_enemies.after(kick_flipped_enemies),
+                    crush_enemies,
+                    drop_coins.after(kick_flipped_enemies).after(crush_enemies),
+                    collect_coins,
+                    sink_enemies,
+                    count_kicked_enemies
+                        .after(kick_flipped_enemies)
+                        .after(crush_enemies)
+                        .after(sink_enemies),
+                    enrage_last_enemy.after(count_kicked_enemies),
+                    destroy_bumped_hazards,
+                    explode_freezies,
+                    burn_out_fireballs,
+                    expire_kicked_enemies,
+                )
+                    .in_set(StepSet::Gameplay),
+            )
+            .add_console_command("killall", "killall", kill_all_command);
+    }
+}
+
+// What of the enemies' and hazards' the dev tools' inspector can show and change
+#[cfg(feature = "dev-tools")]
+pub fn register_inspectable(app: &mut App) {
+    app.register_type::<Enemy>()
+        .register_type::<Flipped>()
+        .register_type::<Freezie>()
+        .register_type::<Fireball>()
+        .register_type::<Coin>()
+        .register_type::<KickedEnemy>()
+        .register_type::<EnemyCount>();
+}
+
+// Everything of the enemies' and hazards' that the gameplay step changes, for an online game to
+// put back when it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<Enemy>()
+        .rollback_component_with_reflect::<Enraged>()
+        .rollback_component_with_reflect::<Flipped>()
+        .rollback_component_with_reflect::<Hazard>()
+        .rollback_component_with_reflect::<Freezie>()
+        .rollback_component_with_reflect::<Fireball>()
+        .rollback_component_with_reflect::<Tracking>()
+        .rollback_component_with_reflect::<Coin>()
+        .rollback_component_with_reflect::<KickedEnemy>()
+        .rollback_component_with_reflect::<ScoreKind>()
+        .rollback_resource_with_reflect::<EnemyCount>();
+}
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Enemy;
+
+// The last enemy of a phase, which is faster than the others
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Enraged;
+
+// Present while an enemy is lying on its back after the platform under it was bumped.
+// Remembers how fast the enemy was walking so it can carry on when it gets up,
+// and which player flipped it.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Flipped {
+    timer: Timer,
+    walk_speed: f32,
+    by: usize,
+}
+
+// Touching a hazard costs a life. Unlike enemies, hazards can't be flipped,
+// bumping the platform under them destroys them outright.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Hazard;
+
+// A hazard that slides along the platforms and freezes the one it is standing on
+// once its fuse runs out
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Freezie {
+    fuse: Timer,
+}
+
+// A hazard that flies diagonally around the arena, bouncing off everything it hits,
+// until it burns out
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Fireball {
+    lifetime: Timer,
+}
+
+// Keeps turning towards the nearest player, at most `turn_rate` radians per second
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Tracking {
+    turn_rate: f32,
+}
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Coin {
+    bounced: bool,
+}
+
+// What is left of a kicked enemy in a co-op game where kicks hit the partner. It flies off
+// through everything until its time is up, and the player who didn't kick it can get hit.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct KickedEnemy {
+    pub by: usize,
+    lifetime: Timer,
+}
+
+// What a player gets points for, see SCORE_TABLE
+#[derive(Component, Reflect, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(Component)]
+pub enum ScoreKind {
+    #[default]
+    Enemy,
+    Coin,
+    Freezie,
+    GreenFireball,
+    RedFireball,
+}
+
+impl ScoreKind {
+    pub fn points(self) -> usize {
+        SCORE_TABLE
+            .iter()
+            .find(|(kind, _)| *kind == self)
+            .map_or(0, |(_, points)| *points)
+    }
+}
+
+// Sent when a player gets rid of an enemy. The combo multiplies `base_points`,
+// `bonus` is added on top as it is.
+#[derive(Message)]
+pub struct EnemyDefeated {
+    pub player: usize,
+    pub position: Vec3,
+    pub base_points: usize,
+    pub bonus: usize,
+}
+
+// Sent when a bump or a POW hit gets rid of a hazard. Unlike an enemy, it is worth its points
+// as they are, without adding to the combo.
+#[derive(Message)]
+pub struct HazardDestroyed {
+    pub player: usize,
+    pub position: Vec3,
+    pub points: usize,
+}
+
+// Sent when Mario kicks a flipped enemy off the stage
+#[derive(Message)]
+pub struct EnemyKicked {
+    pub position: Vec3,
+    pub direction: f32,
+}
+
+// Sent when a bump turns a walking enemy over. Bumping it back onto its feet doesn't count.
+// Only the sound effects listen so far, which don't care who or where.
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct EnemyFlipped {
+    pub by: usize,
+    pub position: Vec3,
+}
+
+#[derive(Message)]
+pub struct FreezieExploded;
+
+// Scored by whoever picked it up, and heard and seen as it goes
+#[derive(Message)]
+pub struct CoinCollected {
+    pub player: usize,
+    pub position: Vec3,
+    pub points: usize,
+}
+
+// Sent when the last enemy of a phase gets angry
+#[derive(Message)]
+pub struct EnemyEnraged;
+
+// How many enemies of the current phase are still around. Kept up to date from
+// spawns and kicks, so nothing has to count the enemies every frame.
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct EnemyCount(pub usize);
+
+// An enemy coming out of the pipe at `position`, walking `speed_scale` times as fast as usual
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    enemy_count: &mut EnemyCount,
+    position: Vec3,
+    speed_scale: f32,
+) {
+    enemy_count.0 += 1;
+    // Walk towards the middle of the arena
+    let direction = -position.x.signum();
+    commands.spawn((
+        Sprite {
+            color: ENEMY_COLOR,
+            ..default()
+        },
+        Transform::from_translation(position).with_scale(ENEMY_SIZE),
+        Enemy,
+        ScoreKind::Enemy,
+        Facing::default(),
+        Grounded::default(),
+        Velocity(Vec2::new(direction * ENEMY_SPEED * speed_scale, 0.0)),
+        WrapsHorizontally,
+        DespawnOnExit(GameState::Playing),
+    ));
+}
+
+// Enemies and coins land on platforms too, but never stop Mario the way walls do
+fn check_for_body_collisions(
+    mut body_query: Query<
+        (&mut Velocity, &mut Transform, Option<&mut Coin>),
+        (
+            Without<Player>,
+            Without<Fireball>,
+            Without<KickedEnemy>,
+            Without<Collider>,
+        ),
+    >,
+    spatial_hash: Res<SpatialHash>,
+    collider_query: Query<(&Transform, Option<&OneWayPlatform>), With<Collider>>,
+) {
+    for (mut velocity, mut body_transform, mut maybe_coin) in &mut body_query {
+        let body_size = body_transform.scale.truncate();
+        let nearby = spatial_hash.colliders_near(body_transform.translation.truncate(), body_size);
+        for (transform, one_way) in collider_query.iter_many(nearby) {
+            let collision = collide(
+                body_transform.translation,
+                body_size,
+                transform.translation,
+                transform.scale.truncate(),
+            );
+            match collision {
+                Some(Collision::Top) if velocity.y < 0.0 => {
+                    match maybe_coin.as_deref_mut() {
+                        // Coins bounce once before settling down
+                        Some(coin) if !coin.bounced => {
+                            coin.bounced = true;
+                            velocity.y = -velocity.y * COIN_BOUNCE;
+                        }
+                        Some(_) => velocity.0 = Vec2::ZERO,
+                        None => velocity.y = 0.0,
+                    }
+                    // Land on top of the platform rather than a little way into it
+                    if let Some(push) =
+                        penetration(body_transform.translation.truncate(), body_size, transform)
+                    {
+                        body_transform.translation.y += push.y;
+                    }
+                }
+                // One-way platforms only stop things landing on them
+                _ if one_way.is_some() => {}
+                Some(Collision::Bottom) if velocity.y > 0.0 => velocity.y = 0.0,
+                // Walk back the other way after running into the side of a platform
+                Some(Collision::Left) if velocity.x > 0.0 => velocity.x = -velocity.x,
+                Some(Collision::Right) if velocity.x < 0.0 => velocity.x = -velocity.x,
+                _ => {}
+            }
+        }
+    }
+}
+
+fn flip_bumped_enemies(
+    mut commands: Commands,
+    mut bump_events: MessageReader<PlatformBumped>,
+    mut flipped_events: MessageWriter<EnemyFlipped>,
+    platform_query: Query<&Transform, With<Collider>>,
+    mut enemy_query: Query<
+        (
+            Entity,
+            &Transform,
+            &mut Velocity,
+            &mut Sprite,
+            Option<&Flipped>,
+            Option<&Enraged>,
+        ),
+        With<Enemy>,
+    >,
+) {
+    for bump in bump_events.read() {
+        let Ok(platform_transform) = platform_query.get(bump.platform) else {
+            continue;
+        };
+
+        for (enemy, transform, mut velocity, mut sprite, maybe_flipped, enraged) in &mut enemy_query
+        {
+            // Only enemies standing on the bumped platform, right above the bump, are affected
+            if !hit_by_bump(bump, platform_transform, transform) {
+                continue;
+            }
+
+            velocity.y = ENEMY_BUMP_SPEED;
+            match maybe_flipped {
+                // Bumping a flipped enemy again puts it back on its feet
+                Some(flipped) => {
+                    velocity.x = flipped.walk_speed;
+                    sprite.color = walking_enemy_color(enraged.is_some());
+                    commands.entity(enemy).remove::<Flipped>();
+                }
+                None => {
+                    flip_enemy(
+                        &mut commands,
+                        enemy,
+                        &mut velocity,
+                        &mut sprite,
+                        bump.player,
+                    );
+                    flipped_events.write(EnemyFlipped {
+                        by: bump.player,
+                        position: transform.translation,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// Turns a walking enemy onto its back, for anyone to kick
+fn flip_enemy(
+    commands: &mut Commands,
+    enemy: Entity,
+    velocity: &mut Velocity,
+    sprite: &mut Sprite,
+    by: usize,
+) {
+    commands.entity(enemy).insert(Flipped {
+        timer: Timer::from_seconds(ENEMY_FLIP_SECONDS, TimerMode::Once),
+        walk_speed: velocity.x,
+        by,
+    });
+    velocity.x = 0.0;
+    sprite.color = FLIPPED_ENEMY_COLOR;
+}
+
+// A hammer flips the walking enemies it hits on the spot. Flipped ones are kicked by
+// `kick_flipped_enemies` instead.
+fn flip_hammered_enemies(
+    mut commands: Commands,
+    mut swung_events: MessageReader<HammerSwung>,
+    mut enemy_query: Query<
+        (Entity, &Transform, &mut Velocity, &mut Sprite),
+        (With<Enemy>, Without<Flipped>),
+    >,
+    mut flipped_events: MessageWriter<EnemyFlipped>,
+) {
+    let mut hit = Vec::new();
+    for swing in swung_events.read() {
+        for (enemy, transform, mut velocity, mut sprite) in &mut enemy_query {
+            // Both players can hit the same enemy in the same step
+            if hit.contains(&enemy) || !swing.hits(transform) {
+                continue;
+            }
+            hit.push(enemy);
+            flip_enemy(
+                &mut commands,
+                enemy,
+                &mut velocity,
+                &mut sprite,
+                swing.player,
+            );
+            flipped_events.write(EnemyFlipped {
+                by: swing.player,
+                position: transform.translation,
+            });
+        }
+    }
+}
+
+fn recover_flipped_enemies(
+    mut commands: Commands,
+    mut query: Query<
+        (
+            Entity,
+            &mut Flipped,
+            &mut Velocity,
+            &mut Sprite,
+            Option<&Enraged>,
+        ),
+        With<Enemy>,
+    >,
+) {
+    for (enemy, mut flipped, mut velocity, mut sprite, enraged) in &mut query {
+        flipped.timer.tick(Duration::from_secs_f32(TIME_STEP));
+        if flipped.timer.is_finished() {
+            velocity.x = flipped.walk_speed;
+            sprite.color = walking_enemy_color(enraged.is_some());
+            commands.entity(enemy).remove::<Flipped>();
+        }
+    }
+}
+
+pub fn kick_flipped_enemies(
+    mut commands: Commands,
+    (game_mode, coop_rules): (Res<GameMode>, Res<CoopRules>),
+    player_query: Query<(&Player, &Transform), Without<Dying>>,
+    enemy_query: Query<(Entity, &Transform, &Flipped, &ScoreKind), With<Enemy>>,
+    mut swung_events: MessageReader<HammerSwung>,
+    mut kick_events: MessageWriter<EnemyKicked>,
+    mut defeated_events: MessageWriter<EnemyDefeated>,
+) {
+    let swings: Vec<_> = swung_events.read().collect();
+    for (enemy, transform, flipped, score_kind) in &enemy_query {
+        // Whoever touches a flipped enemy first gets to kick it, or hits it with a hammer
+        let kicker = player_query
+            .iter()
+            .find(|(_, player_transform)| {
+                collide(
+                    player_transform.translation,
+                    player_transform.scale.truncate(),
+                    transform.translation,
+                    transform.scale.truncate(),
+                )
+                .is_some()
+            })
+            .map(|(player, player_transform)| (player.index, player_transform.translation.x))
+            .or_else(|| {
+                swings
+                    .iter()
+                    .find(|swing| swing.hits(transform))
+                    .map(|swing| (swing.player, swing.center.x))
+            });
+        if let Some((player, from_x)) = kicker {
+            // Stealing the other player's kill is rewarded in versus mode
+            let stolen = *game_mode == GameMode::Versus && flipped.by != player;
+            defeated_events.write(EnemyDefeated {
+                player,
+                position: transform.translation,
+                base_points: score_kind.points(),
+                bonus: if stolen { STOLEN_KICK_BONUS } else { 0 },
+            });
+            commands.entity(enemy).despawn();
+            let direction = (transform.translation.x - from_x).signum();
+            kick_events.write(EnemyKicked {
+                position: transform.translation,
+                direction,
+            });
+            if coop_rules.kicks_hit_partner {
+                commands.spawn((
+                    Sprite {
+                        color: FLIPPED_ENEMY_COLOR,
+                        ..default()
+                    },
+                    *transform,
+                    KickedEnemy {
+                        by: player,
+                        lifetime: Timer::from_seconds(KICKED_ENEMY_SECONDS, TimerMode::Once),
+                    },
+                    Velocity(Vec2::new(
+                        direction * KICKED_ENEMY_SPEED,
+                        KICKED_ENEMY_POP_SPEED,
+                    )),
+                    DespawnOnExit(GameState::Playing),
+                ));
+            }
+        }
+    }
+}
+
+// Enemies squeezed by an elevator are gone for good, and count as kicked
+fn crush_enemies(
+    mut commands: Commands,
+    mut crushed_events: MessageReader<Crushed>,
+    enemy_query: Query<(&Transform, Option<&Enemy>), Or<(With<Enemy>, With<Hazard>)>>,
+    mut kick_events: MessageWriter<EnemyKicked>,
+) {
+    for crushed in crushed_events.read() {
+        let Ok((transform, enemy)) = enemy_query.get(crushed.entity) else {
+            continue;
+        };
+        commands.entity(crushed.entity).despawn();
+        if enemy.is_some() {
+            kick_events.write(EnemyKicked {
+                position: transform.translation,
+                direction: 0.0,
+            });
+        }
+    }
+}
+
+// Enemies and hazards that fall into a lava or water floor are gone, without leaving a coin.
+// Coins are sensors themselves, which sensors don't notice, so they go once they drop below the
+// surface.
+fn sink_enemies(
+    mut commands: Commands,
+    mut enter_events: MessageReader<TriggerEnter>,
+    mut enemy_count: ResMut<EnemyCount>,
+    floor_query: Query<&Transform, With<HazardFloor>>,
+    enemy_query: Query<Option<&Enemy>, Or<(With<Enemy>, With<Hazard>)>>,
+    coin_query: Query<(Entity, &Transform), With<Coin>>,
+) {
+    for enter in enter_events.read() {
+        if !floor_query.contains(enter.sensor) {
+            continue;
+        }
+        let Ok(enemy) = enemy_query.get(enter.entity) else {
+            continue;
+        };
+        commands.entity(enter.entity).despawn();
+        if enemy.is_some() {
+            enemy_count.0 = enemy_count.0.saturating_sub(1);
+        }
+    }
+
+    for floor in &floor_query {
+        let surface = floor.translation.y + floor.scale.y / 2.0;
+        for (coin, transform) in &coin_query {
+            if transform.translation.y < surface {
+                commands.entity(coin).despawn();
+            }
+        }
+    }
+}
+
+pub fn count_kicked_enemies(
+    mut kick_events: MessageReader<EnemyKicked>,
+    mut enemy_count: ResMut<EnemyCount>,
+) {
+    for _ in kick_events.read() {
+        enemy_count.0 = enemy_count.0.saturating_sub(1);
+    }
+}
+
+// Like in the arcade, the last enemy standing turns red and speeds up
+fn enrage_last_enemy(
+    mut commands: Commands,
+    enemy_count: Res<EnemyCount>,
+    director: Res<SpawnDirector>,
+    mut enraged_events: MessageWriter<EnemyEnraged>,
+    mut enemy_query: Query<
+        (Entity, &mut Velocity, &mut Sprite, Option<&mut Flipped>),
+        (With<Enemy>, Without<Enraged>),
+    >,
+) {
+    if !enemy_count.is_changed() || enemy_count.0 != 1 || director.enemies_to_come() > 0 {
+        return;
+    }
+
+    for (enemy, mut velocity, mut sprite, flipped) in &mut enemy_query {
+        match flipped {
+            // It gets up angry
+            Some(mut flipped) => flipped.walk_speed *= RAGE_SPEED_MULTIPLIER,
+            None => {
+                velocity.x *= RAGE_SPEED_MULTIPLIER;
+                sprite.color = ENRAGED_ENEMY_COLOR;
+            }
+        }
+        commands.entity(enemy).insert(Enraged);
+        enraged_events.write(EnemyEnraged);
+    }
+}
+
+fn walking_enemy_color(enraged: bool) -> Color {
+    if enraged {
+        ENRAGED_ENEMY_COLOR
+    } else {
+        ENEMY_COLOR
+    }
+}
+
+fn drop_coins(mut commands: Commands, mut kick_events: MessageReader<EnemyKicked>) {
+    for kick in kick_events.read() {
+        commands.spawn((
+            Sprite {
+                color: COIN_COLOR,
+                ..default()
+            },
+            Transform::from_translation(kick.position).with_scale(COIN_SIZE),
+            Coin { bounced: false },
+            Sensor::default(),
+            ScoreKind::Coin,
+            // Coins pop out in the direction the enemy was kicked
+            Velocity(Vec2::new(kick.direction * COIN_XSPEED, COIN_POP_SPEED)),
+            WrapsHorizontally,
+            DespawnOnExit(GameState::Playing),
+        ));
+    }
+}
+
+// Coins are picked up by simply overlapping them, without affecting the player's movement
+pub fn collect_coins(
+    mut commands: Commands,
+    player_query: Query<&Player>,
+    coin_query: Query<(&Transform, &ScoreKind), With<Coin>>,
+    mut trigger_events: MessageReader<TriggerEnter>,
+    mut coin_events: MessageWriter<CoinCollected>,
+) {
+    let mut collected = Vec::new();
+    for trigger in trigger_events.read() {
+        let (Ok(player), Ok((transform, score_kind))) = (
+            player_query.get(trigger.entity),
+            coin_query.get(trigger.sensor),
+        ) else {
+            continue;
+        };
+        // Both players can reach a coin in the same step, the first one gets it
+        if collected.contains(&trigger.sensor) {
+            continue;
+        }
+        collected.push(trigger.sensor);
+
+        coin_events.write(CoinCollected {
+            player: player.index,
+            position: transform.translation,
+            points: score_kind.points(),
+        });
+        commands.entity(trigger.sensor).despawn();
+    }
+}
+
+pub fn spawn_freezie(commands: &mut Commands, position: Vec3, speed_scale: f32) {
+    let direction = -position.x.signum();
+    commands.spawn((
+        Sprite {
+            color: FREEZIE_COLOR,
+            ..default()
+        },
+        Transform::from_translation(position).with_scale(FREEZIE_SIZE),
+        Freezie {
+            fuse: Timer::from_seconds(FREEZIE_FUSE_SECONDS, TimerMode::Once),
+        },
+        ScoreKind::Freezie,
+        Hazard,
+        Grounded::default(),
+        Velocity(Vec2::new(direction * FREEZIE_SPEED * speed_scale, 0.0)),
+        WrapsHorizontally,
+        DespawnOnExit(GameState::Playing),
+    ));
+}
+
+// The fuse only burns while the Freezie stands on a platform, which turns to ice when it goes off
+fn explode_freezies(
+    mut commands: Commands,
+    mut tile_map: ResMut<TileMap>,
+    mut freezie_query: Query<(Entity, &mut Freezie, &Grounded)>,
+    mut platform_query: Query<(&mut Sprite, Option<&Ice>), (With<Platform>, Without<Freezie>)>,
+    mut exploded_events: MessageWriter<FreezieExploded>,
+) {
+    for (entity, mut freezie, grounded) in &mut freezie_query {
+        let Some(platform) = grounded.0 else {
+            continue;
+        };
+
+        freezie.fuse.tick(Duration::from_secs_f32(TIME_STEP));
+        if !freezie.fuse.is_finished() {
+            continue;
+        }
+
+        commands.entity(entity).despawn();
+        exploded_events.write(FreezieExploded);
+        // Only the platforms of the arena can freeze, not the temporary respawn ones
+        if let Ok((mut sprite, ice)) = platform_query.get_mut(platform) {
+            if ice.is_none() {
+                sprite.color = ICE_COLOR;
+                commands.entity(platform).insert(Ice);
+                tile_map.freeze(platform);
+            }
+        }
+    }
+}
+
+// Red fireballs chase the nearest player, green ones just bounce around
+pub fn spawn_fireball(commands: &mut Commands, position: Vec3, red: bool) {
+    // Head down towards the middle of the arena
+    let direction = Vec2::new(-position.x.signum(), -1.0);
+    let (color, velocity, score_kind) = if red {
+        (
+            RED_FIREBALL_COLOR,
+            direction.normalize() * RED_FIREBALL_SPEED,
+            ScoreKind::RedFireball,
+        )
+    } else {
+        (
+            GREEN_FIREBALL_COLOR,
+            direction * FIREBALL_SPEED,
+            ScoreKind::GreenFireball,
+        )
+    };
+    let mut fireball = commands.spawn((
+        Sprite { color, ..default() },
+        Transform::from_translation(position).with_scale(FIREBALL_SIZE),
+        Fireball {
+            lifetime: Timer::from_seconds(FIREBALL_LIFETIME_SECONDS, TimerMode::Once),
+        },
+        score_kind,
+        Hazard,
+        GravityScale(0.0),
+        Velocity(velocity),
+        WrapsHorizontally,
+        DespawnOnExit(GameState::Playing),
+    ));
+    if red {
+        fireball.insert(Tracking {
+            turn_rate: RED_FIREBALL_TURN_RATE,
+        });
+    }
+}
+
+// Turns tracking fireballs towards the nearest player without changing their speed
+fn steer_tracking_fireballs(
+    mut fireball_query: Query<(&Tracking, &Transform, &mut Velocity), Without<Player>>,
+    player_query: Query<&Transform, With<Player>>,
+) {
+    for (tracking, transform, mut velocity) in &mut fireball_query {
+        let position = transform.translation.truncate();
+        let nearest_player = player_query
+            .iter()
+            .map(|player| player.translation.truncate())
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)));
+        let Some(target) = nearest_player else {
+            continue;
+        };
+
+        let max_turn = tracking.turn_rate * TIME_STEP;
+        let turn = velocity.angle_to(target - position);
+        if turn.is_nan() {
+            continue;
+        }
+        velocity.0 = Vec2::from_angle(turn.clamp(-max_turn, max_turn)).rotate(velocity.0);
+    }
+}
+
+fn bounce_fireballs(
+    mut fireball_query: Query<(&mut Velocity, &Transform), With<Fireball>>,
+    spatial_hash: Res<SpatialHash>,
+    collider_query: Query<&Transform, With<Collider>>,
+) {
+    for (mut velocity, fireball_transform) in &mut fireball_query {
+        let nearby = spatial_hash.colliders_near(
+            fireball_transform.translation.truncate(),
+            fireball_transform.scale.truncate(),
+        );
+        for transform in collider_query.iter_many(nearby) {
+            let collision = collide(
+                fireball_transform.translation,
+                fireball_transform.scale.truncate(),
+                transform.translation,
+                transform.scale.truncate(),
+            );
+            if let Some(collision) = collision {
+                let (reflect_x, reflect_y) = reflection(&collision, velocity.0);
+                if reflect_x {
+                    velocity.x = -velocity.x;
+                }
+                if reflect_y {
+                    velocity.y = -velocity.y;
+                }
+            }
+        }
+
+        // There is no ceiling to bounce off, so turn around at the top of the screen
+        if fireball_transform.translation.y > TOP_WALL && velocity.y > 0.0 {
+            velocity.y = -velocity.y;
+        }
+    }
+}
+
+fn burn_out_fireballs(mut commands: Commands, mut query: Query<(Entity, &mut Fireball)>) {
+    for (entity, mut fireball) in &mut query {
+        fireball.lifetime.tick(Duration::from_secs_f32(TIME_STEP));
+        if fireball.lifetime.is_finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+fn expire_kicked_enemies(mut commands: Commands, mut query: Query<(Entity, &mut KickedEnemy)>) {
+    for (entity, mut kicked) in &mut query {
+        kicked.lifetime.tick(Duration::from_secs_f32(TIME_STEP));
+        if kicked.lifetime.is_finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// Bumping the platform under a hazard gets rid of it. A POW hit gets rid of every hazard in the
+// arena, fireballs flying through the air included.
+pub fn destroy_bumped_hazards(
+    mut commands: Commands,
+    mut bump_events: MessageReader<PlatformBumped>,
+    platform_query: Query<(&Transform, Option<&PowBlock>), With<Collider>>,
+    hazard_query: Query<(Entity, &Transform, &ScoreKind), With<Hazard>>,
+    mut destroyed_events: MessageWriter<HazardDestroyed>,
+) {
+    let mut destroyed = Vec::new();
+    for bump in bump_events.read() {
+        let Ok((platform_transform, pow)) = platform_query.get(bump.platform) else {
+            continue;
+        };
+
+        for (hazard, transform, score_kind) in &hazard_query {
+            // Only once, should a POW hit and a bump land on the same step
+            if destroyed.contains(&hazard) {
+                continue;
+            }
+            if pow.is_some() || hit_by_bump(bump, platform_transform, transform) {
+                destroyed.push(hazard);
+                destroyed_events.write(HazardDestroyed {
+                    player: bump.player,
+                    position: transform.translation,
+                    points: score_kind.points(),
+                });
+                commands.entity(hazard).despawn();
+            }
+        }
+    }
+}
+
+// The `killall` console command: every enemy, hazard and boss goes at once, without any points.
+// Whatever the phase still has to send out comes out as usual.
+fn kill_all_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    if !args.is_empty() {
+        return Err("killall takes no arguments".to_string());
+    }
+    let mut query = world.query_filtered::<Entity, Or<(With<Enemy>, With<Hazard>, With<Boss>)>>();
+    let entities: Vec<Entity> = query.iter(world).collect();
+    for &entity in &entities {
+        world.entity_mut(entity).despawn();
+    }
+    world.resource_mut::<EnemyCount>().0 = 0;
+    Ok(format!("Removed {} enemies and hazards", entities.len()))
+}
+
+// A restarted phase starts without the enemies, hazards and coins left over from the last try
+fn clear_enemies(
+    mut commands: Commands,
+    mut restart_events: MessageReader<RestartPhase>,
+    query: Query<Entity, Or<(With<Enemy>, With<Hazard>, With<Coin>)>>,
+) {
+    if restart_events.read().count() == 0 {
+        return;
+    }
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
@@ -0,0 +1,367 @@
+//! Arenas made up on the fly for the endless mode. The same seed always gives the same layouts,
+//! and every layout is checked to be playable before it is used.
+
+#[cfg(not(feature = "wasm"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{cmp::Ordering, collections::VecDeque};
+
+use bevy::prelude::*;
+
+use crate::{
+    director::SpawnTable,
+    level::{Floor, LevelDef, Tile, BOTTOM_WALL, GRAVITY_ACCEL},
+    player::{JUMP_HELD_GRAVITY_SCALE, JUMP_SPEED, MARIO_SIZE},
+    BLOCK_SIZE, TIME_STEP,
+};
+
+// Same size as the bundled arenas
+const COLUMNS: usize = 32;
+const MAX_ROWS: usize = 19;
+// A platform row, plus room for a player standing on the row below it, plus a little spare
+const MIN_TIER_RISE: usize = 5;
+// Platforms at most this many tiles wide sit in the middle of a row, and ledges stick out of
+// the sides of the arena at most this far
+const MAX_CENTER_HALF_WIDTH: usize = 8;
+const MAX_LEDGE_LENGTH: usize = 12;
+// Columns left open between a ledge and a middle platform, so players fit through
+const MIN_GAP: usize = 3;
+// Ice shows up from this phase on, more of it the longer the game goes
+const ICE_FIRST_PHASE: usize = 3;
+const MAX_ICE_CHANCE: f32 = 0.5;
+// From this phase on, waves can come out of four pipes instead of two
+const EXTRA_PIPES_FIRST_PHASE: usize = 4;
+const PIPE_COLUMN: f32 = 14.0;
+const EXTRA_PIPE_COLUMN: f32 = 6.0;
+// Pipes sit this far above the top row, and players start this far apart on the floor
+const PIPE_HEIGHT: f32 = 2.5;
+const PLAYER_SPAWN_SPACING: f32 = 3.0;
+// Layouts that fail validation are thrown away; this many tries is plenty in practice
+const MAX_ATTEMPTS: usize = 1000;
+
+// `cargo run -- --seed 1234` replays the endless mode arenas of that seed
+pub fn seed_from_args() -> u64 {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == "--seed")
+        .and_then(|index| args.get(index + 1)?.parse().ok())
+        .unwrap_or_else(time_seed)
+}
+
+#[cfg(not(feature = "wasm"))]
+pub fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+// There is no system clock in the browser, but the milliseconds since the page opened do as well
+#[cfg(feature = "wasm")]
+pub fn time_seed() -> u64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or(0, |performance| performance.now() as u64)
+}
+
+// The layout of one phase of the endless mode
+pub fn generate(seed: u64, phase: usize) -> LevelDef {
+    let mut rng = Rng::new(seed ^ (phase as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let max_rise = max_jump_rows();
+    for _ in 0..MAX_ATTEMPTS {
+        let level = generate_layout(&mut rng, phase, max_rise);
+        if is_playable(&level, max_rise) {
+            return level;
+        }
+    }
+    panic!("no playable layout found for phase {phase} of seed {seed}");
+}
+
+// How many rows higher than the one they stand on players can land, with a full jump.
+// Simulated step by step the way `apply_velocity` moves them.
+fn max_jump_rows() -> usize {
+    let mut velocity = JUMP_SPEED;
+    let mut height = 0.0;
+    while velocity > 0.0 {
+        height += velocity * TIME_STEP;
+        velocity -= GRAVITY_ACCEL * JUMP_HELD_GRAVITY_SCALE * TIME_STEP;
+    }
+    // Keep a tile of margin, so the jump doesn't have to be pixel perfect
+    (height / BLOCK_SIZE) as usize - 1
+}
+
+// Rows of platforms, each mirrored around the middle of the arena like in the original game
+fn generate_layout(rng: &mut Rng, phase: usize, max_rise: usize) -> LevelDef {
+    let ice_chance = if phase >= ICE_FIRST_PHASE {
+        (0.1 * (phase - ICE_FIRST_PHASE + 1) as f32).min(MAX_ICE_CHANCE)
+    } else {
+        0.0
+    };
+
+    let mut rows = vec![[Tile::Solid; COLUMNS]];
+    let mut row = 0;
+    loop {
+        row += rng.range(MIN_TIER_RISE, max_rise.max(MIN_TIER_RISE));
+        if row >= MAX_ROWS {
+            break;
+        }
+        rows.resize(row, [Tile::Empty; COLUMNS]);
+
+        let tile = if rng.chance(ice_chance) {
+            Tile::Ice
+        } else {
+            Tile::Solid
+        };
+        let mut tiles = [Tile::Empty; COLUMNS];
+        let (ledge, center) = match rng.range(0, 2) {
+            0 => (rng.range(4, MAX_LEDGE_LENGTH), 0),
+            1 => (0, rng.range(3, MAX_CENTER_HALF_WIDTH)),
+            _ => {
+                let ledge = rng.range(3, 5);
+                (ledge, rng.range(3, COLUMNS / 2 - ledge - MIN_GAP))
+            }
+        };
+        for column in (0..ledge).chain(COLUMNS / 2 - center..COLUMNS / 2) {
+            tiles[column] = tile;
+            tiles[COLUMNS - 1 - column] = tile;
+        }
+        rows.push(tiles);
+    }
+
+    let top = rows.len() as f32;
+    let origin = Vec2::new(-(COLUMNS as f32) / 2.0, BOTTOM_WALL / BLOCK_SIZE - 0.5);
+    let pipe_y = origin.y + top + PIPE_HEIGHT;
+    let mut pipes = vec![
+        Vec2::new(-PIPE_COLUMN, pipe_y),
+        Vec2::new(PIPE_COLUMN, pipe_y),
+    ];
+    if phase >= EXTRA_PIPES_FIRST_PHASE && rng.chance(0.5) {
+        pipes.push(Vec2::new(-EXTRA_PIPE_COLUMN, pipe_y));
+        pipes.push(Vec2::new(EXTRA_PIPE_COLUMN, pipe_y));
+    }
+
+    // Players start on the floor
+    let spawn_y = origin.y + 1.0 + MARIO_SIZE.y / BLOCK_SIZE / 2.0;
+    LevelDef {
+        first_phase: phase,
+        origin,
+        tiles: rows
+            .iter()
+            .rev()
+            .map(|row| row.iter().map(|tile| tile.to_char()).collect())
+            .collect(),
+        player_spawns: vec![
+            Vec2::new(0.0, spawn_y),
+            Vec2::new(PLAYER_SPAWN_SPACING, spawn_y),
+        ],
+        pipes,
+        pow_block: None,
+        elevators: Vec::new(),
+        floor: Floor::Solid,
+        spawns: SpawnTable::default(),
+        background: Vec::new(),
+    }
+}
+
+// Every platform can be reached from the floor, and no player starts inside a platform
+fn is_playable(level: &LevelDef, max_rise: usize) -> bool {
+    let grid = Grid::from_level(level);
+    let player_size = MARIO_SIZE.truncate() / BLOCK_SIZE;
+    let spawns_clear = level.player_spawns.iter().all(|spawn| {
+        let min = (*spawn - player_size / 2.0 - level.origin).floor();
+        let max = (*spawn + player_size / 2.0 - level.origin).ceil();
+        (min.y as usize..max.y as usize)
+            .all(|row| (min.x as usize..max.x as usize).all(|column| !grid.solid(column, row)))
+    });
+    if !spawns_clear {
+        return false;
+    }
+
+    // Search outwards from the floor, jumping up or dropping down next to the ends of platforms
+    let runs = grid.runs();
+    let mut reached = vec![false; runs.len()];
+    let mut queue = VecDeque::from([0]);
+    reached[0] = true;
+    while let Some(from) = queue.pop_front() {
+        for to in 0..runs.len() {
+            if !reached[to] && grid.can_move(&runs[from], &runs[to], max_rise) {
+                reached[to] = true;
+                queue.push_back(to);
+            }
+        }
+    }
+    reached.into_iter().all(|reached| reached)
+}
+
+// A horizontal stretch of tiles that can be stood on
+struct Run {
+    row: usize,
+    first: usize,
+    last: usize,
+}
+
+// Tiles of a level, bottom row first
+struct Grid {
+    rows: Vec<Vec<Tile>>,
+}
+
+impl Grid {
+    fn from_level(level: &LevelDef) -> Grid {
+        Grid {
+            rows: level
+                .tiles
+                .iter()
+                .rev()
+                .map(|line| line.chars().map(Tile::from_char).collect())
+                .collect(),
+        }
+    }
+
+    fn solid(&self, column: usize, row: usize) -> bool {
+        self.rows
+            .get(row)
+            .and_then(|tiles| tiles.get(column % COLUMNS))
+            .is_some_and(|tile| *tile != Tile::Empty)
+    }
+
+    // Bottom row first, so the floor is the first run
+    fn runs(&self) -> Vec<Run> {
+        let mut runs = Vec::new();
+        for row in 0..self.rows.len() {
+            let mut column = 0;
+            while column < COLUMNS {
+                if !self.solid(column, row) {
+                    column += 1;
+                    continue;
+                }
+                let first = column;
+                while column < COLUMNS && self.solid(column, row) {
+                    column += 1;
+                }
+                runs.push(Run {
+                    row,
+                    first,
+                    last: column - 1,
+                });
+            }
+        }
+        runs
+    }
+
+    // Whether a player can get from one run to the other through an open shaft right next to
+    // an end of the higher one: jumping up it, or dropping down it
+    fn can_move(&self, from: &Run, to: &Run, max_rise: usize) -> bool {
+        let (upper, lower) = match to.row.cmp(&from.row) {
+            Ordering::Greater if to.row - from.row <= max_rise => (to, from),
+            Ordering::Less => (from, to),
+            _ => return false,
+        };
+        let width = (MARIO_SIZE.x / BLOCK_SIZE) as usize;
+        let height = (MARIO_SIZE.y / BLOCK_SIZE) as usize;
+        // The columns on either side of the upper run, wrapping around the edges of the arena
+        let left = (upper.first + COLUMNS - width) % COLUMNS;
+        let right = (upper.last + 1) % COLUMNS;
+        [left, right].into_iter().any(|start| {
+            (start..start + width).all(|column| {
+                let column = column % COLUMNS;
+                (lower.first..=lower.last).contains(&column)
+                    && (lower.row + 1..=upper.row + height).all(|row| !self.solid(column, row))
+            })
+        })
+    }
+}
+
+// A small SplitMix64 generator. Written out here rather than pulled in as a dependency, so a
+// seed keeps giving the same arenas whatever versions of other crates are used.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Somewhere from `low` to `high`, both included
+    pub fn range(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next() % (high - low + 1) as u64) as usize
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        self.unit() < probability
+    }
+
+    // Somewhere from `low` up to `high`
+    pub fn float(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.unit()
+    }
+
+    // From 0 up to 1
+    fn unit(&mut self) -> f32 {
+        // The top 24 bits fit an f32 exactly
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A solid floor and the given platforms, as (row, first column, last column) with rows
+    // counted up from the floor. The players start on the floor, like in a generated layout.
+    fn layout(platforms: &[(usize, usize, usize)]) -> LevelDef {
+        let height = platforms
+            .iter()
+            .map(|&(row, _, _)| row + 1)
+            .max()
+            .unwrap_or(1);
+        let mut rows = vec![[Tile::Empty; COLUMNS]; height];
+        rows[0] = [Tile::Solid; COLUMNS];
+        for &(row, first, last) in platforms {
+            rows[row][first..=last].fill(Tile::Solid);
+        }
+        let mut level = generate(1, 1);
+        level.tiles = rows
+            .iter()
+            .rev()
+            .map(|row| row.iter().map(|tile| tile.to_char()).collect())
+            .collect();
+        level
+    }
+
+    #[test]
+    fn generated_layouts_are_playable() {
+        for phase in 1..10 {
+            assert!(is_playable(&generate(7, phase), max_jump_rows()));
+        }
+    }
+
+    #[test]
+    fn platform_within_a_jump_is_reachable() {
+        assert!(is_playable(&layout(&[(5, 10, 21)]), max_jump_rows()));
+    }
+
+    #[test]
+    fn platform_out_of_reach_is_rejected() {
+        let max_rise = max_jump_rows();
+        assert!(!is_playable(&layout(&[(max_rise + 2, 10, 21)]), max_rise));
+    }
+
+    #[test]
+    fn platform_without_an_open_shaft_is_rejected() {
+        // A row the whole width of the arena leaves no way up past it
+        assert!(!is_playable(
+            &layout(&[(5, 0, COLUMNS - 1), (10, 10, 21)]),
+            max_jump_rows()
+        ));
+    }
+
+    #[test]
+    fn player_inside_a_platform_is_rejected() {
+        assert!(!is_playable(&layout(&[(2, 0, 20)]), max_jump_rows()));
+    }
+}
@@ -0,0 +1,208 @@
+//! The ghost of the best time attack run. Where Mario was during every gameplay step of a run is
+//! kept, and the fastest finished run on each layout and starting phase plays back as a
+//! see-through Mario alongside the live one, for racing against.
+//!
+//! A ghost only keeps positions and sprites, not inputs like a replay does: it doesn't play the
+//! game again, so it still plays back the same once the game plays differently.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::{Levels, StartPhase},
+    palette::{PaletteSprite, PaletteSwap},
+    player::{Player, MARIO_FRAME_SIZE, MARIO_SHEET_COLUMNS, MARIO_SIZE},
+    replay::{ReplayLevel, ReplayPlayback},
+    settings::Settings,
+    skins::{SkinImages, SpriteId},
+    storage::{self, Location},
+    time_attack::SpeedrunTimer,
+    GameMode, GameState, StepSet,
+};
+
+const GHOST_ALPHA: f32 = 0.35;
+// Behind the live characters
+const GHOST_Z: f32 = 0.5;
+// The sprite of a step the ghost wasn't on the screen, like after a game over
+const HIDDEN: u8 = u8::MAX;
+// The sprite index takes the low bits, whether it is flipped the top one
+const FLIPPED: u8 = 0x80;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GhostRun>()
+            .add_systems(OnEnter(GameState::Playing), start_ghost)
+            .add_systems(OnExit(GameState::Playing), save_ghost)
+            .add_systems(
+                FixedUpdate,
+                (record_ghost, move_ghost.after(record_ghost)).in_set(StepSet::Presentation),
+            );
+    }
+}
+
+// Where Mario was every gameplay step of one run, one `(x, y, sprite)` each. Whole pixels are
+// close enough for a ghost, and keep the file small.
+#[derive(Serialize, Deserialize)]
+struct GhostTrack {
+    // How long the run took, in milliseconds
+    time: u64,
+    steps: Vec<(i16, i16, u8)>,
+}
+
+impl GhostTrack {
+    fn file_name(level: &ReplayLevel, start_phase: usize) -> String {
+        format!("ghosts/{}.ghost.ron", level.key(start_phase))
+    }
+
+    fn load(file_name: &str) -> Option<GhostTrack> {
+        let contents = storage::read(Location::Data, file_name)?;
+        match ron::from_str(&contents) {
+            Ok(track) => Some(track),
+            Err(err) => {
+                warn!(
+                    "Could not read the ghost {}: {err}",
+                    storage::describe(Location::Data, file_name)
+                );
+                None
+            }
+        }
+    }
+
+    fn save(&self, file_name: &str) {
+        let result = ron::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Data, file_name, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the ghost to {}: {err}",
+                storage::describe(Location::Data, file_name)
+            );
+        }
+    }
+}
+
+// The ghost of the run being played, and the track of the run itself. Only time attack runs get
+// either, and not while a replay is being watched.
+#[derive(Resource, Default)]
+struct GhostRun {
+    file_name: Option<String>,
+    best: Option<GhostTrack>,
+    recorded: Vec<(i16, i16, u8)>,
+}
+
+#[derive(Component)]
+struct Ghost;
+
+fn start_ghost(
+    mut commands: Commands,
+    mut run: ResMut<GhostRun>,
+    (game_mode, start_phase, levels): (Res<GameMode>, Res<StartPhase>, Res<Levels>),
+    playback: Option<Res<ReplayPlayback>>,
+    skin_images: Res<SkinImages>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+    settings: Res<Settings>,
+) {
+    let races = *game_mode == GameMode::TimeAttack && playback.is_none();
+    let file_name = ReplayLevel::of(&levels)
+        .filter(|_| races)
+        .map(|level| GhostTrack::file_name(&level, start_phase.0));
+    let best = file_name.as_deref().and_then(GhostTrack::load);
+    if best.is_some() {
+        let layout = layouts.add(TextureAtlasLayout::from_grid(
+            MARIO_FRAME_SIZE,
+            MARIO_SHEET_COLUMNS,
+            1,
+            None,
+            None,
+        ));
+        commands.spawn((
+            PaletteSwap {
+                palette: settings.player_palettes[0],
+                image: skin_images.get(SpriteId::MarioSheet),
+                layout,
+            },
+            PaletteSprite {
+                color: Color::srgba(1.0, 1.0, 1.0, GHOST_ALPHA),
+                ..default()
+            },
+            Transform::from_xyz(0.0, 0.0, GHOST_Z).with_scale(MARIO_SIZE),
+            Visibility::Hidden,
+            Ghost,
+            DespawnOnExit(GameState::Playing),
+        ));
+    }
+    *run = GhostRun {
+        file_name,
+        best,
+        recorded: Vec::new(),
+    };
+}
+
+fn record_ghost(
+    mut run: ResMut<GhostRun>,
+    player_query: Query<(&Player, &Transform, &PaletteSprite)>,
+) {
+    if run.file_name.is_none() {
+        return;
+    }
+    let step = player_query
+        .iter()
+        .find(|(player, ..)| player.index == 0)
+        .map_or((0, 0, HIDDEN), |(_, transform, sprite)| {
+            let flipped = if sprite.flip_x { FLIPPED } else { 0 };
+            (
+                transform.translation.x.round() as i16,
+                transform.translation.y.round() as i16,
+                sprite.index as u8 | flipped,
+            )
+        });
+    run.recorded.push(step);
+}
+
+fn move_ghost(
+    run: Res<GhostRun>,
+    mut ghost_query: Query<(&mut Transform, &mut PaletteSprite, &mut Visibility), With<Ghost>>,
+) {
+    let Some(best) = &run.best else {
+        return;
+    };
+    // Recorded first, so the step being played is the last one recorded
+    let step = run
+        .recorded
+        .len()
+        .checked_sub(1)
+        .and_then(|step| best.steps.get(step));
+    for (mut transform, mut sprite, mut visibility) in &mut ghost_query {
+        match step {
+            Some(&(x, y, bits)) if bits != HIDDEN => {
+                transform.translation.x = x as f32;
+                transform.translation.y = y as f32;
+                sprite.index = (bits & !FLIPPED) as usize;
+                sprite.flip_x = bits & FLIPPED != 0;
+                *visibility = Visibility::Inherited;
+            }
+            // The best run was already over by now
+            _ => *visibility = Visibility::Hidden,
+        }
+    }
+}
+
+// Only finished runs count
+fn save_ghost(mut run: ResMut<GhostRun>, timer: Res<SpeedrunTimer>) {
+    let Some(file_name) = run.file_name.take() else {
+        return;
+    };
+    let time = timer.times().total;
+    let beaten = run.best.as_ref().is_none_or(|best| time < best.time);
+    if timer.finished() && beaten {
+        let track = GhostTrack {
+            time,
+            steps: std::mem::take(&mut run.recorded),
+        };
+        track.save(&file_name);
+    }
+    run.best = None;
+    run.recorded.clear();
+}
@@ -0,0 +1,370 @@
+//! The heads-up display over a running game: each player's score and lives, the best score, the
+//! phase and the running combos. Every part is only rewritten when the resource behind it changes.
+//! A banner with a countdown opens every phase, and warns of the boss phases.
+
+use bevy::prelude::*;
+
+use crate::{
+    boss::is_boss_phase,
+    level::{Phase, PhaseIntro},
+    player::{ComboTracker, ExtraLifeAwarded, Lives, Scoreboard},
+    settings::Settings,
+    skins::{SkinImages, SpriteId},
+    ui::{HighScores, SCORE_COLOR, SELECTED_TEXT_COLOR, TEXT_COLOR},
+    GameMode, GameState, PauseState, PLAYER_NAMES,
+};
+
+const HUD_FONT_SIZE: f32 = 30.0;
+const HUD_PADDING: Val = Val::Px(5.0);
+const LIFE_ICON_SIZE: f32 = 20.0;
+// More lives than this are shown as a single icon and a count
+const MAX_LIFE_ICONS: usize = 5;
+// The lives blink for a moment when a 1-UP is awarded
+const EXTRA_LIFE_FLASH_SECONDS: f32 = 1.5;
+const EXTRA_LIFE_BLINK_SECONDS: f32 = 0.15;
+const BANNER_FONT_SIZE: f32 = 60.0;
+// The banner fades out over this long once the countdown is over
+const BANNER_FADE_SECONDS: f32 = 0.5;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ExtraLifeFlash>()
+            .add_systems(OnEnter(GameState::Playing), spawn_hud)
+            .add_systems(
+                Update,
+                (
+                    update_scores,
+                    update_phase,
+                    update_lives,
+                    show_player_columns,
+                    flash_extra_life.after(update_lives),
+                    update_combo_text,
+                    update_phase_banner,
+                )
+                    .run_if(in_state(PauseState::Running)),
+            );
+    }
+}
+
+// Runs while the lives are blinking after a 1-UP
+#[derive(Resource)]
+struct ExtraLifeFlash(Timer);
+
+impl Default for ExtraLifeFlash {
+    fn default() -> Self {
+        // Nothing to flash until the first 1-UP
+        let mut timer = Timer::from_seconds(EXTRA_LIFE_FLASH_SECONDS, TimerMode::Once);
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+// The score of the player with this index
+#[derive(Component)]
+struct PlayerScoreText(usize);
+
+// The best score so far, counting the scores of the game being played
+#[derive(Component)]
+struct TopScoreText;
+
+#[derive(Component)]
+struct PhaseText;
+
+// The score and lives of the player with this index, hidden while they aren't playing
+#[derive(Component)]
+struct PlayerColumn(usize);
+
+// Holds an icon per life of the player with this index. Shared lives are all shown under the
+// first player.
+#[derive(Component)]
+struct LivesIcons(usize);
+
+// Shows the running combo multipliers while they last
+#[derive(Component)]
+struct ComboText;
+
+// "PHASE 3" over the countdown, in the middle of the screen
+#[derive(Component)]
+struct PhaseBanner {
+    fade: Timer,
+}
+
+// A player's column on either side, and the best score, the phase and the combos in the middle
+fn spawn_hud(mut commands: Commands, asset_server: Res<AssetServer>, game_mode: Res<GameMode>) {
+    let label_font = TextFont::from_font_size(HUD_FONT_SIZE)
+        .with_font(asset_server.load("fonts/FiraSans-Bold.ttf"));
+    let value_font = TextFont::from_font_size(HUD_FONT_SIZE)
+        .with_font(asset_server.load("fonts/FiraMono-Medium.ttf"));
+    // Filled in by the update systems, as soon as the resources of the new game are in place
+    let labelled = |label: &str| {
+        (
+            Text::new(format!("{label} ")),
+            label_font.clone(),
+            TextColor(TEXT_COLOR),
+            children![(
+                TextSpan::default(),
+                value_font.clone(),
+                TextColor(SCORE_COLOR)
+            )],
+        )
+    };
+    let column = |align_items| Node {
+        flex_direction: FlexDirection::Column,
+        align_items,
+        ..default()
+    };
+    // Both are there from the start, for a player who drops in
+    let player_column = |parent: &mut ChildSpawnerCommands, player: usize, align_items| {
+        let visibility = if player < game_mode.player_count() {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        parent.spawn((
+            column(align_items),
+            visibility,
+            PlayerColumn(player),
+            children![
+                (labelled(PLAYER_NAMES[player]), PlayerScoreText(player)),
+                (
+                    Node {
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    LivesIcons(player),
+                ),
+            ],
+        ));
+    };
+
+    commands
+        .spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::SpaceBetween,
+                align_items: AlignItems::FlexStart,
+                padding: UiRect::all(HUD_PADDING),
+                ..default()
+            },
+            DespawnOnExit(GameState::Playing),
+        ))
+        .with_children(|parent| {
+            player_column(parent, 0, AlignItems::FlexStart);
+            parent.spawn((
+                column(AlignItems::Center),
+                children![
+                    (labelled("TOP"), TopScoreText),
+                    (labelled("PHASE"), PhaseText),
+                    (
+                        Text::default(),
+                        value_font.clone(),
+                        TextColor(SELECTED_TEXT_COLOR),
+                        ComboText,
+                    ),
+                ],
+            ));
+            player_column(parent, 1, AlignItems::FlexEnd);
+        });
+
+    let banner_font = label_font.with_font_size(BANNER_FONT_SIZE);
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        DespawnOnExit(GameState::Playing),
+        children![(
+            Text::default(),
+            banner_font.clone(),
+            TextColor(SELECTED_TEXT_COLOR),
+            TextLayout::justify(Justify::Center),
+            PhaseBanner {
+                fade: Timer::from_seconds(BANNER_FADE_SECONDS, TimerMode::Once),
+            },
+            children![(TextSpan::default(), banner_font, TextColor(SCORE_COLOR))],
+        )],
+    ));
+}
+
+fn update_scores(
+    scoreboard: Res<Scoreboard>,
+    high_scores: Res<HighScores>,
+    score_query: Query<(Entity, &PlayerScoreText)>,
+    top_query: Query<Entity, With<TopScoreText>>,
+    mut writer: TextUiWriter,
+) {
+    if !scoreboard.is_changed() && !high_scores.is_changed() {
+        return;
+    }
+
+    for (text, player) in &score_query {
+        *writer.text(text, 1) = scoreboard.score(player.0).to_string();
+    }
+    // A new best score shows as soon as it is reached, before it makes it into the table
+    let top = scoreboard.best().max(high_scores.best().unwrap_or(0));
+    for text in &top_query {
+        *writer.text(text, 1) = top.to_string();
+    }
+}
+
+fn update_phase(
+    phase: Res<Phase>,
+    query: Query<Entity, With<PhaseText>>,
+    mut writer: TextUiWriter,
+) {
+    if !phase.is_changed() {
+        return;
+    }
+
+    for text in &query {
+        *writer.text(text, 1) = phase.0.to_string();
+    }
+}
+
+fn show_player_columns(
+    game_mode: Res<GameMode>,
+    mut query: Query<(&PlayerColumn, &mut Visibility)>,
+) {
+    if !game_mode.is_changed() {
+        return;
+    }
+
+    for (column, mut visibility) in &mut query {
+        *visibility = if column.0 < game_mode.player_count() {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn update_lives(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    skin_images: Res<SkinImages>,
+    lives: Res<Lives>,
+    settings: Res<Settings>,
+    query: Query<(Entity, &LivesIcons)>,
+) {
+    if !lives.is_changed() {
+        return;
+    }
+
+    for (entity, icons) in &query {
+        let count = match (lives.shared, icons.0) {
+            (true, 0) => lives.remaining[0],
+            (true, _) => 0,
+            (false, player) => lives.remaining[player],
+        };
+        let icon = (
+            ImageNode {
+                image: skin_images.get(SpriteId::LifeIcon),
+                // Tints the image, like the player's sprite
+                color: settings.player_palettes[icons.0].tint(),
+                ..default()
+            },
+            Node {
+                width: Val::Px(LIFE_ICON_SIZE),
+                height: Val::Px(LIFE_ICON_SIZE),
+                ..default()
+            },
+        );
+        commands.entity(entity).despawn_children();
+        commands.entity(entity).with_children(|parent| {
+            if count <= MAX_LIFE_ICONS {
+                for _ in 0..count {
+                    parent.spawn(icon.clone());
+                }
+            } else {
+                parent.spawn(icon);
+                parent.spawn((
+                    Text::new(format!("x{count}")),
+                    TextFont::from_font_size(HUD_FONT_SIZE)
+                        .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+                    TextColor(SCORE_COLOR),
+                ));
+            }
+        });
+    }
+}
+
+fn flash_extra_life(
+    time: Res<Time>,
+    mut extra_life_events: MessageReader<ExtraLifeAwarded>,
+    mut flash: ResMut<ExtraLifeFlash>,
+    mut query: Query<&mut Visibility, With<LivesIcons>>,
+) {
+    if extra_life_events.read().count() > 0 {
+        flash.0.reset();
+    }
+    if flash.0.is_finished() {
+        return;
+    }
+
+    flash.0.tick(time.delta());
+    let blinks = (flash.0.elapsed_secs() / EXTRA_LIFE_BLINK_SECONDS) as usize;
+    let hidden = !flash.0.is_finished() && !blinks.is_multiple_of(2);
+    for mut visibility in &mut query {
+        *visibility = if !hidden {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn update_combo_text(
+    combo_tracker: Res<ComboTracker>,
+    game_mode: Res<GameMode>,
+    mut query: Query<&mut Text, With<ComboText>>,
+) {
+    if !combo_tracker.is_changed() {
+        return;
+    }
+
+    // Only chains of two or more are worth showing
+    let combos: Vec<String> = combo_tracker.combos[..game_mode.player_count()]
+        .iter()
+        .zip(PLAYER_NAMES)
+        .filter(|(combo, _)| combo.chain > 1)
+        .map(|(combo, name)| format!("{name} x{}", combo.chain))
+        .collect();
+    for mut text in &mut query {
+        text.0 = combos.join("  ");
+    }
+}
+
+fn update_phase_banner(
+    time: Res<Time>,
+    phase: Res<Phase>,
+    intro: Res<PhaseIntro>,
+    mut query: Query<(Entity, &mut PhaseBanner)>,
+    mut writer: TextUiWriter,
+) {
+    for (text, mut banner) in &mut query {
+        let alpha = if intro.running() {
+            banner.fade.reset();
+            *writer.text(text, 0) = if is_boss_phase(phase.0) {
+                format!("PHASE {} - BOSS!\n", phase.0)
+            } else {
+                format!("PHASE {}\n", phase.0)
+            };
+            *writer.text(text, 1) = intro.seconds_left().to_string();
+            1.0
+        } else if !banner.fade.is_finished() {
+            banner.fade.tick(time.delta());
+            *writer.text(text, 1) = "GO!".to_string();
+            banner.fade.fraction_remaining()
+        } else {
+            continue;
+        };
+        writer.for_each_color(text, |mut color| color.0.set_alpha(alpha));
+    }
+}
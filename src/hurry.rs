@@ -0,0 +1,155 @@
+//! The hurry-up timer. A phase that drags on for too long plays a jingle, tints the arena and
+//! speeds up every enemy and hazard by half again until the phase is over. The timer counts
+//! gameplay steps, so it stops for the countdown that opens every phase and for the pause menu.
+//!
+//! The survival mode never leaves its first phase, and gets faster on its own, so it doesn't
+//! hurry.
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{
+    level::{Phase, RestartPhase},
+    GameMode, GameState, PauseState, StepSet, TIME_STEP,
+};
+
+// How long a phase can last before it hurries
+const HURRY_UP_SECONDS: f32 = 60.0;
+const HURRY_UP_SPEED: f32 = 1.5;
+const HURRY_TINT_COLOR: Color = Color::srgba(1.0, 0.15, 0.0, 0.12);
+// Over the arena, under the darkness of the mutator
+const HURRY_TINT_Z: f32 = 2.4;
+// Larger than any arena
+const HURRY_TINT_SIZE: f32 = 10_000.0;
+
+pub struct HurryPlugin;
+
+impl Plugin for HurryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HurryUp>()
+            .init_resource::<SpeedModifier>()
+            .add_message::<HurriedUp>()
+            .add_systems(OnEnter(GameState::Playing), reset_hurry_up)
+            .add_systems(
+                Update,
+                (tint_arena, restart_hurry_up).run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(FixedUpdate, tick_hurry_up.in_set(StepSet::Gameplay));
+    }
+}
+
+// So the dev tools' inspector can show and change them
+#[cfg(feature = "dev-tools")]
+pub fn register_inspectable(app: &mut App) {
+    app.register_type::<HurryUp>()
+        .register_type::<SpeedModifier>();
+}
+
+// Everything of the hurry-up timer's that the gameplay step changes, for an online game to put
+// back when it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_resource_with_reflect::<HurryUp>()
+        .rollback_resource_with_reflect::<SpeedModifier>();
+}
+
+// How much faster than usual enemies and hazards move. `apply_velocity` goes by it.
+#[derive(Resource, Reflect, Clone, Copy)]
+#[reflect(Resource)]
+pub struct SpeedModifier(pub f32);
+
+impl Default for SpeedModifier {
+    fn default() -> Self {
+        SpeedModifier(1.0)
+    }
+}
+
+// The timer of the phase being played
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct HurryUp {
+    // The phase it was started for, it starts over as soon as that changes
+    phase: usize,
+    seconds: f32,
+    hurried: bool,
+}
+
+impl HurryUp {
+    pub fn hurried(&self) -> bool {
+        self.hurried
+    }
+}
+
+// Sent once the timer of a phase runs out
+#[derive(Message)]
+pub struct HurriedUp;
+
+// No phase was started yet, the first step starts the timer
+fn reset_hurry_up(mut hurry_up: ResMut<HurryUp>, mut speed: ResMut<SpeedModifier>) {
+    *hurry_up = HurryUp::default();
+    *speed = SpeedModifier::default();
+}
+
+// A restarted phase gets the whole timer again
+fn restart_hurry_up(
+    mut restart_events: MessageReader<RestartPhase>,
+    hurry_up: ResMut<HurryUp>,
+    speed: ResMut<SpeedModifier>,
+) {
+    if restart_events.read().count() > 0 {
+        reset_hurry_up(hurry_up, speed);
+    }
+}
+
+fn tick_hurry_up(
+    mut hurry_up: ResMut<HurryUp>,
+    mut speed: ResMut<SpeedModifier>,
+    phase: Res<Phase>,
+    game_mode: Res<GameMode>,
+    mut hurried_events: MessageWriter<HurriedUp>,
+) {
+    if hurry_up.phase != phase.0 {
+        *hurry_up = HurryUp {
+            phase: phase.0,
+            ..default()
+        };
+        *speed = SpeedModifier::default();
+    }
+    if hurry_up.hurried || *game_mode == GameMode::Survival {
+        return;
+    }
+    hurry_up.seconds += TIME_STEP;
+    if hurry_up.seconds >= HURRY_UP_SECONDS {
+        hurry_up.hurried = true;
+        speed.0 = HURRY_UP_SPEED;
+        hurried_events.write(HurriedUp);
+    }
+}
+
+#[derive(Component)]
+struct HurryTint;
+
+// Follows the timer rather than its event, so it also comes and goes with a rollback
+fn tint_arena(
+    mut commands: Commands,
+    hurry_up: Res<HurryUp>,
+    tint_query: Query<Entity, With<HurryTint>>,
+) {
+    if hurry_up.hurried && tint_query.is_empty() {
+        commands.spawn((
+            Sprite {
+                color: HURRY_TINT_COLOR,
+                custom_size: Some(Vec2::splat(HURRY_TINT_SIZE)),
+                ..default()
+            },
+            Transform::from_xyz(0.0, 0.0, HURRY_TINT_Z),
+            HurryTint,
+            DespawnOnExit(GameState::Playing),
+        ));
+    } else if !hurry_up.hurried {
+        for tint in &tint_query {
+            commands.entity(tint).despawn();
+        }
+    }
+}
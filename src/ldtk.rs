@@ -0,0 +1,184 @@
+//! Arenas authored in LDtk. The IntGrid layer becomes the tile grid and the entity layer
+//! the spawn points, giving the same `LevelDef` as the RON level files.
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    director::SpawnTable,
+    level::{Floor, LevelDef},
+};
+
+// IntGrid values, as set up in the LDtk project; anything else is an empty tile
+const INT_GRID_SOLID: i64 = 1;
+const INT_GRID_ICE: i64 = 2;
+const INT_GRID_ONE_WAY: i64 = 3;
+const INT_GRID_CONVEYOR_LEFT: i64 = 4;
+const INT_GRID_CONVEYOR_RIGHT: i64 = 5;
+const INT_GRID_CRUMBLING: i64 = 6;
+
+// The parts of the LDtk project format we use, see https://ldtk.io/json
+#[derive(Deserialize)]
+struct Project {
+    levels: Vec<Level>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Level {
+    identifier: String,
+    px_hei: f32,
+    // Missing when the project saves its levels in separate files
+    layer_instances: Option<Vec<Layer>>,
+    field_instances: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+struct Layer {
+    #[serde(rename = "__type")]
+    kind: String,
+    #[serde(rename = "__cWid")]
+    columns: usize,
+    #[serde(rename = "__gridSize")]
+    grid_size: f32,
+    #[serde(rename = "intGridCsv")]
+    int_grid: Vec<i64>,
+    #[serde(rename = "entityInstances")]
+    entities: Vec<EntityInstance>,
+}
+
+#[derive(Deserialize)]
+struct EntityInstance {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__pivot")]
+    pivot: Vec2,
+    // Position of the pivot, in pixels from the top left corner of the level
+    px: Vec2,
+    width: f32,
+    height: f32,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__value")]
+    value: Value,
+}
+
+#[derive(Default, TypePath)]
+pub struct LdtkLoader;
+
+impl AssetLoader for LdtkLoader {
+    type Asset = LevelDef;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<LevelDef, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let project: Project = serde_json::from_slice(&bytes)?;
+        // One arena per project file, like the RON level files
+        let level = project
+            .levels
+            .first()
+            .ok_or_else(|| BevyError::from("the LDtk project has no levels"))?;
+        convert_level(level)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ldtk"]
+    }
+}
+
+fn convert_level(level: &Level) -> Result<LevelDef, BevyError> {
+    let layers = level.layer_instances.as_ref().ok_or_else(|| {
+        BevyError::from(format!(
+            "level {} is saved in a separate file, which isn't supported",
+            level.identifier
+        ))
+    })?;
+    let int_grid = layers
+        .iter()
+        .find(|layer| layer.kind == "IntGrid")
+        .ok_or_else(|| {
+            BevyError::from(format!("level {} has no IntGrid layer", level.identifier))
+        })?;
+
+    let columns = int_grid.columns;
+    let origin = LevelDef::grid_origin(columns);
+    let tiles = int_grid
+        .int_grid
+        .chunks(columns.max(1))
+        .map(|row| {
+            row.iter()
+                .map(|&value| match value {
+                    INT_GRID_SOLID => '#',
+                    INT_GRID_ICE => '~',
+                    INT_GRID_ONE_WAY => '-',
+                    INT_GRID_CONVEYOR_LEFT => '<',
+                    INT_GRID_CONVEYOR_RIGHT => '>',
+                    INT_GRID_CRUMBLING => '=',
+                    _ => '.',
+                })
+                .collect()
+        })
+        .collect();
+
+    let first_phase = level
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == "first_phase")
+        .and_then(|field| field.value.as_u64())
+        .map_or(1, |phase| phase as usize);
+    let floor = level
+        .field_instances
+        .iter()
+        .find(|field| field.identifier == "floor")
+        .and_then(|field| field.value.as_str())
+        .map_or(Floor::Solid, Floor::from_name);
+
+    let mut level_def = LevelDef {
+        first_phase,
+        origin,
+        tiles,
+        player_spawns: Vec::new(),
+        pipes: Vec::new(),
+        pow_block: None,
+        elevators: Vec::new(),
+        floor,
+        spawns: SpawnTable::default(),
+        background: Vec::new(),
+    };
+    for entity in layers.iter().flat_map(|layer| &layer.entities) {
+        // LDtk measures down from the top left corner, levels up from the bottom left one
+        let center =
+            entity.px + (Vec2::splat(0.5) - entity.pivot) * Vec2::new(entity.width, entity.height);
+        let position = origin + Vec2::new(center.x, level.px_hei - center.y) / int_grid.grid_size;
+        match entity.identifier.as_str() {
+            "Player" => level_def.player_spawns.push(position),
+            // Enemies come out of the pipes
+            "Pipe" | "Enemy" => level_def.pipes.push(position),
+            "Pow" => level_def.pow_block = Some(position),
+            _ => {}
+        }
+    }
+
+    if level_def.player_spawns.is_empty() || level_def.pipes.is_empty() {
+        return Err(BevyError::from(format!(
+            "level {} needs at least one Player and one Pipe entity",
+            level.identifier
+        )));
+    }
+    Ok(level_def)
+}
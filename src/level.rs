@@ -0,0 +1,2425 @@
+//! The arena: walls and platforms, phases, and the physics everything in it moves by.
+
+use std::{borrow::Cow, time::Duration};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadedFolder},
+    platform::collections::HashMap,
+    prelude::*,
+    transform::TransformSystems,
+};
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    boss::Boss,
+    console::AddConsoleCommand,
+    director::{SpawnDirector, SpawnTable},
+    enemy::{count_kicked_enemies, Enemy, EnemyCount, EnemyDefeated, Hazard},
+    generator,
+    hurry::SpeedModifier,
+    mutators::{CoopRules, Modifiers},
+    player::{Dying, MovePlayers, Player},
+    storage::{self, Location},
+    GameMode, GameState, PauseState, StepDriver, StepSet, BLOCK_SIZE, TIME_STEP,
+};
+
+// In units per second squared
+pub const GRAVITY_ACCEL: f32 = 3000.0;
+// Falling never gets faster than this. It's a little under a block per physics step, so bodies that
+// are only checked for overlaps can't drop through a platform between two steps.
+const MAX_FALL_SPEED: f32 = 1000.0;
+// Anything that moved further than this in one physics step was put somewhere else rather than
+// moving there, like wrapping around the arena or respawning, and is drawn where it is instead
+const MAX_INTERPOLATED_DISTANCE: f32 = BLOCK_SIZE * 2.0;
+// Height of the box checked just below a character's feet to decide whether they stand on something
+const GROUND_PROBE_DEPTH: f32 = 2.0;
+// How far from the bump point (horizontally) an enemy is still affected
+const BUMP_RANGE: f32 = BLOCK_SIZE * 2.0;
+// y coordinates
+pub const BOTTOM_WALL: f32 = BLOCK_SIZE * -12.0;
+pub const TOP_WALL: f32 = 300.;
+// Arena layouts are read from every file with this extension in assets/levels,
+// unless a single Tiled map is played
+const LEVEL_FOLDER: &str = "levels";
+const LEVEL_EXTENSIONS: &[&str] = &["level.ron"];
+// A browser can't list the files of a folder, so it loads these from assets/levels instead
+#[cfg(feature = "wasm")]
+const BUNDLED_LEVELS: [&str; 7] = [
+    "phase01.level.ron",
+    "phase03.level.ron",
+    "phase05.level.ron",
+    "phase07.level.ron",
+    "phase09.level.ron",
+    "phase13.level.ron",
+    "phase15.level.ron",
+];
+// Players can keep level files of their own in this folder of the data directory, e.g.
+// ~/.local/share/Mario-siblings/levels
+const USER_LEVEL_FOLDER: &str = "levels";
+const WALL_COLOR: Color = Color::srgb(0.8, 0.8, 0.8);
+const ONE_WAY_COLOR: Color = Color::srgb(0.8, 0.6, 0.4);
+const POW_BLOCK_COLOR: Color = Color::srgb(0.2, 0.4, 1.0);
+const ELEVATOR_COLOR: Color = Color::srgb(0.6, 0.6, 0.9);
+const CONVEYOR_COLOR: Color = Color::srgb(0.5, 0.5, 0.5);
+const CONVEYOR_STRIPE_COLOR: Color = Color::srgb(0.9, 0.8, 0.2);
+// How fast conveyors carry what stands on them, and how their stripes are spaced out
+const CONVEYOR_SPEED: f32 = BLOCK_SIZE * 3.0;
+const CONVEYOR_STRIPE_WIDTH: f32 = BLOCK_SIZE / 4.0;
+const CONVEYOR_STRIPE_SPACING: f32 = BLOCK_SIZE;
+// From this phase on, every conveyor changes direction every few seconds
+const CONVEYOR_REVERSE_FIRST_PHASE: usize = 11;
+const CONVEYOR_REVERSE_SECONDS: f32 = 8.0;
+const CRUMBLING_COLOR: Color = Color::srgb(0.6, 0.4, 0.3);
+// Crumbling platforms shake this long once stood on, fall for this long, and stay gone this long
+const CRUMBLE_SHAKE_SECONDS: f32 = 0.5;
+const CRUMBLE_FALL_SECONDS: f32 = 1.0;
+const CRUMBLE_RESPAWN_SECONDS: f32 = 4.0;
+// How far a shaking platform moves to either side, and how fast (in radians per second)
+const CRUMBLE_SHAKE_DISTANCE: f32 = 1.5;
+const CRUMBLE_SHAKE_RATE: f32 = 60.0;
+const LAVA_COLOR: Color = Color::srgb(0.9, 0.3, 0.1);
+const WATER_COLOR: Color = Color::srgb(0.2, 0.4, 0.9);
+// In front of the players and enemies, so they sink into it rather than fall past it
+const HAZARD_FLOOR_Z: f32 = 2.0;
+// Drops thrown up when something falls into a lava or water floor
+const SPLASH_DROPS: usize = 6;
+const SPLASH_DROP_SIZE: Vec3 = Vec3::new(4.0, 4.0, 1.0);
+const SPLASH_SPEED: f32 = 300.0;
+const SPLASH_SECONDS: f32 = 0.4;
+// Boxes closer than this are touching rather than overlapping, which keeps rounding errors from
+// catching a player walking along a floor on its edge
+const CONTACT_EPSILON: f32 = 0.01;
+// Behind the platforms, but still in front of the far plane of the 2D camera at -0.1
+const BACKGROUND_Z: f32 = -0.05;
+pub const ICE_COLOR: Color = Color::srgb(0.6, 0.9, 1.0);
+// Every phase opens with a countdown, during which the game is frozen
+const PHASE_INTRO_SECONDS: f32 = 3.0;
+// Kicks and POW hits freeze the game for a few steps, to land with more weight
+const HIT_STOP_SECONDS: f32 = TIME_STEP * 3.0;
+
+pub struct LevelPlugin;
+
+impl Plugin for LevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Phase(1))
+            .insert_resource(StartPhase(1))
+            .init_resource::<LevelSource>()
+            .init_resource::<TileMap>()
+            .init_resource::<SpatialHash>()
+            .init_resource::<ArenaBounds>()
+            .insert_resource(ConveyorReversal::new())
+            .init_resource::<PhaseIntro>()
+            .init_resource::<HitStop>()
+            .init_asset::<LevelDef>()
+            .init_asset_loader::<LevelLoader>()
+            .add_message::<CollisionEvent>()
+            .add_message::<PlatformBumped>()
+            .add_message::<TriggerEnter>()
+            .add_message::<TriggerExit>()
+            .add_message::<Crushed>()
+            .add_message::<PhaseCleared>()
+            .add_message::<RestartPhase>()
+            .add_message::<Splashed>()
+            .add_systems(Startup, load_levels)
+            .add_systems(
+                Update,
+                (
+                    collect_level_folder,
+                    check_first_phases.after(collect_level_folder),
+                ),
+            )
+            .add_systems(OnEnter(GameState::Menu), forget_level_choice)
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (
+                    generate_first_endless_layout.before(spawn_arena),
+                    spawn_arena,
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    reload_levels,
+                    restart_phase,
+                    scroll_conveyors,
+                    tick_phase_intro,
+                    hit_stop,
+                )
+                    .run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(Update, add_previous_transforms)
+            .add_systems(First, restore_physics_transforms)
+            .add_systems(
+                PostUpdate,
+                interpolate_transforms.before(TransformSystems::Propagate),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    move_elevators.before(update_spatial_hash),
+                    crumble_platforms.before(update_spatial_hash),
+                    update_spatial_hash
+                        .before(apply_velocity)
+                        .before(MovePlayers),
+                    remember_previous_transforms
+                        .before(move_elevators)
+                        .before(crumble_platforms)
+                        .before(apply_velocity)
+                        .before(MovePlayers),
+                    drift_on_conveyors
+                        .after(update_spatial_hash)
+                        .before(apply_velocity)
+                        .before(MovePlayers),
+                    reverse_conveyors.before(drift_on_conveyors),
+                    apply_velocity,
+                )
+                    .in_set(StepSet::Physics),
+            )
+            .add_systems(
+                FixedUpdate,
+                (detect_ground, detect_triggers, bump_platforms).in_set(StepSet::CollisionResolve),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    splash_hazard_floors,
+                    generate_next_endless_layout
+                        .after(count_kicked_enemies)
+                        .before(advance_phase),
+                    advance_phase.after(count_kicked_enemies),
+                )
+                    .in_set(StepSet::Gameplay),
+            )
+            .add_systems(FixedUpdate, move_splash_drops.in_set(StepSet::Presentation))
+            .add_console_command("phase", "phase <number>", phase_command);
+
+        #[cfg(feature = "ldtk")]
+        app.init_asset_loader::<crate::ldtk::LdtkLoader>();
+        #[cfg(feature = "tiled")]
+        app.init_asset_loader::<crate::tiled::TiledLoader>();
+    }
+}
+
+// What of the arena's the dev tools' inspector can show and change
+#[cfg(feature = "dev-tools")]
+pub fn register_inspectable(app: &mut App) {
+    app.register_type::<Grounded>()
+        .register_type::<Velocity>()
+        .register_type::<GravityScale>()
+        .register_type::<Elevator>()
+        .register_type::<Conveyor>()
+        .register_type::<Crumbling>()
+        .register_type::<Phase>();
+}
+
+// Everything of the arena's that the gameplay step changes, for an online game to put back when
+// it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<Grounded>()
+        .rollback_component_with_reflect::<Velocity>()
+        .rollback_component_with_reflect::<WrapsHorizontally>()
+        .rollback_component_with_reflect::<GravityScale>()
+        .rollback_component_with_reflect::<Collider>()
+        .rollback_component_with_reflect::<Sensor>()
+        .rollback_component_with_reflect::<Ice>()
+        .rollback_component_with_reflect::<Elevator>()
+        .rollback_component_with_reflect::<Conveyor>()
+        .rollback_component_with_reflect::<Crumbling>()
+        .rollback_component_with_reflect::<OneWayPlatform>()
+        .rollback_component_with_reflect::<Platform>()
+        .rollback_component_with_reflect::<PowBlock>()
+        .rollback_component_with_reflect::<HazardFloor>()
+        .rollback_component_with_reflect::<SplashDrop>()
+        .rollback_resource_with_reflect::<Phase>()
+        .rollback_resource_with_reflect::<TileMap>()
+        .rollback_resource_with_reflect::<ConveyorReversal>();
+}
+
+fn load_levels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut level_assets: ResMut<Assets<LevelDef>>,
+    level_source: Res<LevelSource>,
+) {
+    let (folder, handles) = match &*level_source {
+        #[cfg(not(feature = "wasm"))]
+        LevelSource::Folder => (Some(asset_server.load_folder(LEVEL_FOLDER)), Vec::new()),
+        #[cfg(feature = "wasm")]
+        LevelSource::Folder => (
+            None,
+            BUNDLED_LEVELS
+                .iter()
+                .map(|file| asset_server.load(format!("{LEVEL_FOLDER}/{file}")))
+                .collect(),
+        ),
+        #[cfg(feature = "tiled")]
+        LevelSource::Tiled(path) => (None, vec![asset_server.load(path.clone())]),
+    };
+
+    // The player's own levels live outside the assets folder, so they are read right away
+    // instead of going through the asset server
+    let mut user_levels = Vec::new();
+    for file in storage::list(Location::Data, USER_LEVEL_FOLDER) {
+        let Some(name) = file
+            .strip_prefix(USER_LEVEL_FOLDER)
+            .and_then(|name| name.strip_prefix('/')?.strip_suffix(".level.ron"))
+        else {
+            continue;
+        };
+        let level = storage::read(Location::Data, &file)
+            .ok_or_else(|| "it could not be read".to_string())
+            .and_then(|contents| {
+                ron::from_str::<LevelDef>(&contents).map_err(|err| err.to_string())
+            })
+            .and_then(|level| level.check().map(|()| level));
+        match level {
+            Ok(level) => user_levels.push((name.to_owned(), level_assets.add(level))),
+            Err(err) => warn!(
+                "Could not load the level {}: {err}",
+                storage::describe(Location::Data, &file)
+            ),
+        }
+    }
+    user_levels.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    commands.insert_resource(Levels {
+        folder,
+        handles,
+        user_levels,
+        custom: None,
+        endless_seed: None,
+    });
+}
+
+// The layouts of the folder are only known once it has been read
+fn collect_level_folder(mut levels: ResMut<Levels>, folders: Res<Assets<LoadedFolder>>) {
+    let Some(folder) = levels
+        .folder
+        .as_ref()
+        .and_then(|folder| folders.get(folder))
+    else {
+        return;
+    };
+    levels.handles = folder
+        .handles
+        .iter()
+        .filter_map(|handle| handle.clone().try_typed().ok())
+        .collect();
+    levels.folder = None;
+}
+
+// A folder of layouts that all start after the first phase is reported once they have loaded,
+// rather than the game stopping at the first phase without one
+fn check_first_phases(
+    mut checked: Local<bool>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+) {
+    if *checked || !levels.loaded(&asset_server) {
+        return;
+    }
+    *checked = true;
+    let first = levels
+        .handles
+        .iter()
+        .filter_map(|handle| level_assets.get(handle))
+        .map(|level| level.first_phase)
+        .min();
+    if let Some(first) = first.filter(|&first| first > 1) {
+        error!(
+            "No layout starts at phase 1, the one starting at phase {first} is played until then"
+        );
+    }
+}
+
+// Starting a game from the title menu plays the regular layouts from the first phase again
+fn forget_level_choice(mut commands: Commands, mut levels: ResMut<Levels>) {
+    levels.custom = None;
+    levels.endless_seed = None;
+    commands.insert_resource(StartPhase(1));
+}
+
+// Every game starts from the chosen phase, with its wave of enemies
+fn spawn_arena(
+    mut commands: Commands,
+    start_phase: Res<StartPhase>,
+    levels: Res<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+    (mut enemy_count, mut director): (ResMut<EnemyCount>, ResMut<SpawnDirector>),
+    mut intro: ResMut<PhaseIntro>,
+) {
+    commands.insert_resource(Phase(start_phase.0));
+    commands.insert_resource(ConveyorReversal::new());
+    intro.0.reset();
+    let level = levels.for_phase(start_phase.0, &level_assets);
+    spawn_platforms(&mut commands, level, GameState::Playing);
+    enemy_count.0 = 0;
+    director.start(start_phase.0, level);
+}
+
+// Every run of tiles of the same kind in a row of the tile map becomes one platform. The arena
+// belongs to the state it is built for, the editor builds it too.
+fn spawn_platforms(commands: &mut Commands, level: &LevelDef, state: GameState) {
+    let mut tile_map = TileMap::from_level(level);
+    for run in tile_map.runs() {
+        let (position, size) = tile_map.run_bounds(&run);
+        let mut wall = commands.spawn((
+            WallBundle::new(position, size, run.tile),
+            DespawnOnExit(state),
+        ));
+        match run.tile {
+            Tile::Ice => {
+                wall.insert(Ice);
+            }
+            Tile::OneWay => {
+                wall.insert(OneWayPlatform);
+            }
+            Tile::ConveyorLeft | Tile::ConveyorRight => {
+                let speed = if run.tile == Tile::ConveyorLeft {
+                    -CONVEYOR_SPEED
+                } else {
+                    CONVEYOR_SPEED
+                };
+                wall.insert(Conveyor { speed });
+                // Stripes in the platform's own units, where it is 1 wide
+                let stripes = (size.x / CONVEYOR_STRIPE_SPACING).round().max(1.0);
+                wall.with_children(|parent| {
+                    for stripe in 0..stripes as usize {
+                        parent.spawn((
+                            Sprite {
+                                color: CONVEYOR_STRIPE_COLOR,
+                                ..default()
+                            },
+                            Transform {
+                                translation: Vec3::new(
+                                    (stripe as f32 + 0.5) / stripes - 0.5,
+                                    0.0,
+                                    0.01,
+                                ),
+                                scale: Vec3::new(CONVEYOR_STRIPE_WIDTH / size.x, 0.5, 1.0),
+                                ..default()
+                            },
+                            ConveyorStripe,
+                        ));
+                    }
+                });
+            }
+            Tile::Crumbling => {
+                wall.insert(Crumbling {
+                    home: position.extend(0.0),
+                    state: CrumbleState::Intact,
+                });
+            }
+            Tile::Empty | Tile::Solid => {}
+        }
+        tile_map.set_collider(&run, wall.id());
+    }
+    if level.floor != Floor::Solid {
+        let (position, size) = tile_map.bottom_row_bounds();
+        commands.spawn((
+            Sprite {
+                color: level.floor.color(),
+                ..default()
+            },
+            Transform {
+                translation: position.extend(HAZARD_FLOOR_Z),
+                scale: size.extend(1.0),
+                ..default()
+            },
+            HazardFloor,
+            Sensor::default(),
+            DespawnOnExit(state),
+        ));
+    }
+    commands.insert_resource(tile_map);
+    commands.insert_resource(ArenaBounds::from_level(level));
+
+    for tile in &level.background {
+        commands.spawn((
+            Sprite {
+                image: tile.image.clone(),
+                texture_atlas: Some(TextureAtlas {
+                    layout: tile.layout.clone(),
+                    index: tile.index,
+                }),
+                custom_size: Some(Vec2::splat(BLOCK_SIZE)),
+                ..default()
+            },
+            Transform::from_translation((tile.position * BLOCK_SIZE).extend(BACKGROUND_Z)),
+            Background,
+            DespawnOnExit(state),
+        ));
+    }
+
+    for elevator in &level.elevators {
+        let size = Vec2::new(elevator.width, 1.0) * BLOCK_SIZE;
+        let mut wall = WallBundle::new(elevator.from * BLOCK_SIZE, size, Tile::Solid);
+        wall.sprite.color = ELEVATOR_COLOR;
+        commands.spawn((
+            wall,
+            Elevator {
+                from: elevator.from * BLOCK_SIZE,
+                to: elevator.to * BLOCK_SIZE,
+                speed: elevator.speed * BLOCK_SIZE,
+                outward: true,
+            },
+            DespawnOnExit(state),
+        ));
+    }
+
+    if let Some(position) = level.pow_block {
+        commands.spawn((
+            Sprite {
+                color: POW_BLOCK_COLOR,
+                ..default()
+            },
+            Transform {
+                translation: (position * BLOCK_SIZE).extend(0.0),
+                scale: Vec3::new(BLOCK_SIZE * 2.0, BLOCK_SIZE * 2.0, 1.0),
+                ..default()
+            },
+            PowBlock,
+            Collider,
+            DespawnOnExit(state),
+        ));
+    }
+}
+
+// The collider the character was standing on at the end of the last physics step, if any.
+// Kept up to date by `detect_ground`, don't set it anywhere else.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Grounded(pub Option<Entity>);
+
+#[derive(Component, Reflect, Deref, DerefMut, Default)]
+#[reflect(Component)]
+pub struct Velocity(pub Vec2);
+
+// Things with this come back in on the other side of the arena when they leave it on one side,
+// the rest just carry on off the screen
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct WrapsHorizontally;
+
+// Everything with a `Velocity`, every elevator and every crumbling platform is drawn in between where it was at the end
+// of the last two physics steps, by how far the clock has got towards the next one. Without this,
+// motion judders on displays that refresh faster than the physics runs.
+#[derive(Component)]
+struct PreviousTransform {
+    // Where the entity was when the last physics step started
+    translation: Vec3,
+    // Where it really is, while the `Transform` holds where it is drawn
+    physics: Option<Vec3>,
+}
+
+// Multiplier applied to `GRAVITY_ACCEL` for this entity; entities without it fall normally
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct GravityScale(pub f32);
+
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Collider;
+
+// Only notices what overlaps it, through `TriggerEnter` and `TriggerExit`, and never stops
+// anything the way a `Collider` does. Coins are sensors.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Sensor {
+    // Everything that overlapped it at the end of the last physics step
+    touching: Vec<Entity>,
+}
+
+// Platforms with this component are slippery
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Ice;
+
+// Moves between two points, carrying whatever stands on it. Anything it pins against another
+// collider is crushed.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Elevator {
+    from: Vec2,
+    to: Vec2,
+    speed: f32,
+    // Heading for `to`, rather than back to `from`
+    outward: bool,
+}
+
+// Carries whatever stands on it sideways at `speed`, to the right when positive
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Conveyor {
+    pub speed: f32,
+}
+
+// One of the stripes scrolling along a conveyor, a child of its platform
+#[derive(Component)]
+struct ConveyorStripe;
+
+// Ticks while a phase with reversing conveyors is played
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+struct ConveyorReversal {
+    timer: Timer,
+}
+
+impl ConveyorReversal {
+    fn new() -> ConveyorReversal {
+        ConveyorReversal {
+            timer: Timer::from_seconds(CONVEYOR_REVERSE_SECONDS, TimerMode::Repeating),
+        }
+    }
+}
+
+impl Default for ConveyorReversal {
+    fn default() -> Self {
+        ConveyorReversal::new()
+    }
+}
+
+// Shakes for a moment once a player stands on it, then falls away and comes back a while later
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Crumbling {
+    // Where it sits while intact
+    home: Vec3,
+    state: CrumbleState,
+}
+
+#[derive(Reflect, Default)]
+enum CrumbleState {
+    #[default]
+    Intact,
+    // Stood on, and about to give way
+    Shaking(Timer),
+    // No longer stops anything, and drops out of the arena
+    Falling {
+        timer: Timer,
+        speed: f32,
+    },
+    // Out of sight until it is back where it was
+    Respawning(Timer),
+}
+
+// Sent when an elevator squeezes something against another collider
+#[derive(Message)]
+pub struct Crushed {
+    pub entity: Entity,
+}
+
+// Sent when the last enemy of a phase is gone, right before the next phase starts
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct PhaseCleared {
+    pub phase: usize,
+}
+
+// Sent to play the phase being played again from the start. The arena, its enemies and the
+// players go back to how the phase started; scores and lives stay as they are.
+#[derive(Message)]
+pub struct RestartPhase;
+
+// Sent when something falls into a lava or water floor
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct Splashed {
+    pub position: Vec3,
+}
+
+// Can be jumped through from below and only stops things landing on it from above. Bumping it
+// from below still bumps whatever stands on it.
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct OneWayPlatform;
+
+// The platforms of the arena, as opposed to the temporary respawn ones
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Platform;
+
+// Bumping it hits everything in the arena at once: it shakes the screen, hurts a boss on the
+// ground and gets rid of every hazard
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct PowBlock;
+
+// The lava or water taking the place of the floor, see `Floor`
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct HazardFloor;
+
+// Thrown up by something falling into a `HazardFloor`, and gone shortly after
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct SplashDrop {
+    velocity: Vec2,
+    lifetime: Timer,
+}
+
+// Decoration behind the platforms, from the tile layers of Tiled maps
+#[derive(Component)]
+pub struct Background;
+
+// Which side of `b` a box `a` touches, seen from `a`: `Left` means `a` is to the left of `b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    Left,
+    Right,
+    Top,
+    Bottom,
+    Inside,
+}
+
+// Whether two boxes overlap, and on which side. The side is the one `a` went in the least
+// deep, so a box landing on a wide platform hits its top rather than one of its ends.
+pub fn collide(a_pos: Vec3, a_size: Vec2, b_pos: Vec3, b_size: Vec2) -> Option<Collision> {
+    let a_min = a_pos.truncate() - a_size / 2.0;
+    let a_max = a_pos.truncate() + a_size / 2.0;
+    let b_min = b_pos.truncate() - b_size / 2.0;
+    let b_max = b_pos.truncate() + b_size / 2.0;
+    if a_min.x >= b_max.x || a_max.x <= b_min.x || a_min.y >= b_max.y || a_max.y <= b_min.y {
+        return None;
+    }
+
+    let (x_collision, x_depth) = if a_min.x < b_min.x && a_max.x > b_min.x && a_max.x < b_max.x {
+        (Collision::Left, b_min.x - a_max.x)
+    } else if a_min.x > b_min.x && a_min.x < b_max.x && a_max.x > b_max.x {
+        (Collision::Right, a_min.x - b_max.x)
+    } else {
+        (Collision::Inside, -f32::INFINITY)
+    };
+    let (y_collision, y_depth) = if a_min.y < b_min.y && a_max.y > b_min.y && a_max.y < b_max.y {
+        (Collision::Bottom, b_min.y - a_max.y)
+    } else if a_min.y > b_min.y && a_min.y < b_max.y && a_max.y > b_max.y {
+        (Collision::Top, a_min.y - b_max.y)
+    } else {
+        (Collision::Inside, -f32::INFINITY)
+    };
+    Some(if y_depth.abs() < x_depth.abs() {
+        y_collision
+    } else {
+        x_collision
+    })
+}
+
+// Sent when a player runs into a collider. `side` is the side of the collider they hit, the way
+// `collide` reports it, and `point` is the middle of the side of the player that touched it.
+#[derive(Message)]
+pub struct CollisionEvent {
+    pub player: Entity,
+    pub collider: Entity,
+    pub side: Collision,
+    pub point: Vec2,
+}
+
+impl CollisionEvent {
+    pub fn new(
+        player: Entity,
+        collider: Entity,
+        side: Collision,
+        center: Vec2,
+        size: Vec2,
+    ) -> CollisionEvent {
+        let offset = match side {
+            Collision::Left => Vec2::new(size.x / 2.0, 0.0),
+            Collision::Right => Vec2::new(-size.x / 2.0, 0.0),
+            Collision::Top => Vec2::new(0.0, -size.y / 2.0),
+            Collision::Bottom => Vec2::new(0.0, size.y / 2.0),
+            Collision::Inside => Vec2::ZERO,
+        };
+        CollisionEvent {
+            player,
+            collider,
+            side,
+            point: center + offset,
+        }
+    }
+}
+
+// Sent when something that moves starts overlapping a sensor
+#[derive(Message)]
+pub struct TriggerEnter {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+// Sent when it stops overlapping the sensor again, or is gone. Nothing reacts to that yet.
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct TriggerExit {
+    pub sensor: Entity,
+    pub entity: Entity,
+}
+
+// Sent when a player hits a platform from below
+#[derive(Message)]
+pub struct PlatformBumped {
+    pub player: usize,
+    pub platform: Entity,
+    pub x: f32,
+}
+
+// One arena layout, loaded from a RON file in assets/levels. Positions are in `BLOCK_SIZE`
+// units, measured from the middle of the arena.
+#[derive(Asset, TypePath, Clone, Deserialize, Serialize)]
+pub struct LevelDef {
+    // The layout is used from this phase on, until a layout with a later first phase takes over
+    pub first_phase: usize,
+    // The bottom left corner of the tile grid
+    pub origin: Vec2,
+    // The tile grid, one string per row and one character per tile, top row first.
+    // See `Tile::from_char` for the characters.
+    pub tiles: Vec<String>,
+    // Where each player starts the game
+    pub player_spawns: Vec<Vec2>,
+    // Enemies and hazards come out of these in turn
+    pub pipes: Vec<Vec2>,
+    #[serde(default)]
+    pub pow_block: Option<Vec2>,
+    #[serde(default)]
+    pub elevators: Vec<ElevatorDef>,
+    #[serde(default)]
+    pub floor: Floor,
+    // What comes out of the pipes, see the director module. Layouts without one get the arcade's.
+    #[serde(default, skip_serializing_if = "SpawnTable::is_default")]
+    pub spawns: SpawnTable,
+    // Only Tiled maps have a background, which the level files can't describe
+    #[serde(skip)]
+    pub background: Vec<BackgroundTile>,
+}
+
+// A platform going up and down (or back and forth) between two points, in `BLOCK_SIZE` units
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ElevatorDef {
+    // Where the middle of the platform starts, and where it turns back
+    pub from: Vec2,
+    pub to: Vec2,
+    pub width: f32,
+    // In blocks per second
+    pub speed: f32,
+}
+
+// What the bottom row of the arena is
+#[derive(Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Floor {
+    // Its tiles, like any other row
+    #[default]
+    Solid,
+    // A strip of lava or water, whatever the tiles of the row are. Players that fall in lose a
+    // life, and enemies are gone for good.
+    Lava,
+    Water,
+}
+
+impl Floor {
+    // As written in Tiled map properties and LDtk level fields; anything else is a solid floor
+    #[cfg(any(feature = "ldtk", feature = "tiled"))]
+    pub fn from_name(name: &str) -> Floor {
+        match name {
+            "Lava" => Floor::Lava,
+            "Water" => Floor::Water,
+            _ => Floor::Solid,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Floor::Lava => LAVA_COLOR,
+            Floor::Water => WATER_COLOR,
+            Floor::Solid => WALL_COLOR,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BackgroundTile {
+    pub image: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+    pub index: usize,
+    pub position: Vec2,
+}
+
+impl LevelDef {
+    // Imported grids are centered horizontally, with their bottom row as the floor of the arena
+    #[cfg(any(feature = "ldtk", feature = "tiled"))]
+    pub fn grid_origin(columns: usize) -> Vec2 {
+        Vec2::new(-(columns as f32) / 2.0, BOTTOM_WALL / BLOCK_SIZE - 0.5)
+    }
+
+    // Characters get a z-value of 1 so they render on top of the platforms
+    pub fn player_spawn(&self, player: usize) -> Vec3 {
+        (self.player_spawns[player % self.player_spawns.len()] * BLOCK_SIZE).extend(1.0)
+    }
+
+    pub fn pipe(&self, index: usize) -> Vec3 {
+        (self.pipes[index % self.pipes.len()] * BLOCK_SIZE).extend(1.0)
+    }
+
+    // Every layout needs somewhere for the players to start and the enemies to come out, like
+    // the LDtk and Tiled loaders ask for
+    fn check(&self) -> Result<(), String> {
+        if self.player_spawns.is_empty() || self.pipes.is_empty() {
+            return Err("the layout needs at least one player spawn and one pipe".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default, TypePath)]
+struct LevelLoader;
+
+impl AssetLoader for LevelLoader {
+    type Asset = LevelDef;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<LevelDef, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let level: LevelDef = ron::de::from_bytes(&bytes)?;
+        level.check().map_err(BevyError::from)?;
+        Ok(level)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        LEVEL_EXTENSIONS
+    }
+}
+
+// Where the arena layouts come from
+#[derive(Resource, Default)]
+pub enum LevelSource {
+    // Every level file in assets/levels
+    #[default]
+    Folder,
+    // A single Tiled map, relative to the assets folder, for every phase
+    #[cfg(feature = "tiled")]
+    Tiled(String),
+}
+
+impl LevelSource {
+    // `cargo run --features tiled -- levels/arena.tmx` plays a single Tiled map
+    pub fn from_args() -> LevelSource {
+        #[cfg(feature = "tiled")]
+        if let Some(path) = std::env::args()
+            .nth(1)
+            .filter(|path| path.ends_with(".tmx"))
+        {
+            return LevelSource::Tiled(path);
+        }
+        LevelSource::Folder
+    }
+}
+
+// Every arena layout that was loaded, in no particular order
+#[derive(Resource)]
+pub struct Levels {
+    // assets/levels, until every file in it has been found
+    folder: Option<Handle<LoadedFolder>>,
+    handles: Vec<Handle<LevelDef>>,
+    // Named after their files, which don't take part in picking the layout of a phase
+    user_levels: Vec<(String, Handle<LevelDef>)>,
+    // A single layout used for every phase instead, like one being play-tested in the level
+    // editor or picked from the player's own levels
+    pub custom: Option<Handle<LevelDef>>,
+    // In the endless mode, every phase gets a layout generated from this seed as its custom one
+    endless_seed: Option<u64>,
+}
+
+// One entry of the level select screen
+pub enum LevelChoice {
+    // A bundled layout, played from its first phase on
+    Regular {
+        name: String,
+        handle: Handle<LevelDef>,
+    },
+    // One of the player's own layouts, played on its own for every phase
+    Custom {
+        name: String,
+        handle: Handle<LevelDef>,
+    },
+    // A freshly generated layout every phase
+    Endless {
+        seed: u64,
+    },
+}
+
+impl LevelChoice {
+    pub fn label(&self, level_assets: &Assets<LevelDef>) -> String {
+        match self {
+            LevelChoice::Regular { name, handle } => {
+                let first_phase = level_assets
+                    .get(handle)
+                    .map_or(1, |level| level.first_phase);
+                format!("{name} - phase {first_phase}")
+            }
+            LevelChoice::Custom { name, .. } => format!("{name} (your level)"),
+            LevelChoice::Endless { seed } => format!("endless (seed {seed})"),
+        }
+    }
+
+    // The layout the choice starts with
+    pub fn first_layout<'a>(&self, level_assets: &'a Assets<LevelDef>) -> Cow<'a, LevelDef> {
+        match self {
+            LevelChoice::Regular { handle, .. } | LevelChoice::Custom { handle, .. } => {
+                Cow::Borrowed(level_assets.get(handle).unwrap())
+            }
+            LevelChoice::Endless { seed } => Cow::Owned(generator::generate(*seed, 1)),
+        }
+    }
+}
+
+impl Levels {
+    // The layouts load in the background, so the game can't start before this is true
+    pub fn loaded(&self, asset_server: &AssetServer) -> bool {
+        self.folder.is_none()
+            && self
+                .handles
+                .iter()
+                .all(|handle| asset_server.is_loaded_with_dependencies(handle))
+    }
+
+    // The regular layouts in the order of their first phase, followed by the player's own ones
+    // and the endless mode
+    pub fn choices(
+        &self,
+        asset_server: &AssetServer,
+        level_assets: &Assets<LevelDef>,
+    ) -> Vec<LevelChoice> {
+        let mut regular: Vec<_> = self
+            .handles
+            .iter()
+            .filter_map(|handle| Some((handle, level_assets.get(handle)?.first_phase)))
+            .collect();
+        regular.sort_by_key(|(_, first_phase)| *first_phase);
+
+        let regular = regular.into_iter().map(|(handle, first_phase)| {
+            let name = asset_server
+                .get_path(handle)
+                .and_then(|path| {
+                    let file_name = path.path().file_name()?.to_str()?;
+                    Some(file_name.split('.').next()?.to_owned())
+                })
+                .unwrap_or_else(|| format!("phase {first_phase}"));
+            LevelChoice::Regular {
+                name,
+                handle: handle.clone(),
+            }
+        });
+        let user_levels = self
+            .user_levels
+            .iter()
+            .map(|(name, handle)| LevelChoice::Custom {
+                name: name.clone(),
+                handle: handle.clone(),
+            });
+        let endless = LevelChoice::Endless {
+            seed: generator::seed_from_args(),
+        };
+        regular
+            .chain(user_levels)
+            .chain(std::iter::once(endless))
+            .collect()
+    }
+
+    // Sets up the layouts for a game started from the level select screen, returning the phase
+    // it starts from
+    pub fn choose(&mut self, choice: &LevelChoice, level_assets: &Assets<LevelDef>) -> usize {
+        self.custom = None;
+        self.endless_seed = None;
+        match choice {
+            LevelChoice::Regular { handle, .. } => level_assets.get(handle).unwrap().first_phase,
+            LevelChoice::Custom { handle, .. } => {
+                self.custom = Some(handle.clone());
+                level_assets.get(handle).unwrap().first_phase
+            }
+            LevelChoice::Endless { seed } => {
+                self.endless_seed = Some(*seed);
+                1
+            }
+        }
+    }
+
+    // The seed of the endless mode, while it is played
+    pub fn endless_seed(&self) -> Option<u64> {
+        self.endless_seed
+    }
+
+    // The name of the player's own layout being played, if it is one of theirs rather than one
+    // being play-tested
+    pub fn custom_name(&self) -> Option<&str> {
+        let custom = self.custom.as_ref()?;
+        self.user_levels
+            .iter()
+            .find(|(_, handle)| handle == custom)
+            .map(|(name, _)| name.as_str())
+    }
+
+    // The layout with the latest first phase that has already started
+    pub fn for_phase<'a>(&self, phase: usize, level_assets: &'a Assets<LevelDef>) -> &'a LevelDef {
+        level_assets
+            .get(self.handle_for_phase(phase, level_assets))
+            .expect("the layouts should have loaded")
+    }
+
+    // Phases before the first phase of every layout get the earliest one, see
+    // `check_first_phases`
+    pub fn handle_for_phase(
+        &self,
+        phase: usize,
+        level_assets: &Assets<LevelDef>,
+    ) -> &Handle<LevelDef> {
+        if let Some(handle) = &self.custom {
+            return handle;
+        }
+        let loaded = || {
+            self.handles
+                .iter()
+                .filter_map(|handle| Some((handle, level_assets.get(handle)?)))
+        };
+        loaded()
+            .filter(|(_, level)| level.first_phase <= phase)
+            .max_by_key(|(_, level)| level.first_phase)
+            .or_else(|| loaded().min_by_key(|(_, level)| level.first_phase))
+            .map(|(handle, _)| handle)
+            .expect("there should be at least one layout")
+    }
+}
+
+// What fills one `BLOCK_SIZE` square of the arena
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tile {
+    #[default]
+    Empty,
+    Solid,
+    Ice,
+    // Can be jumped through from below
+    OneWay,
+    ConveyorLeft,
+    ConveyorRight,
+    // Falls away shortly after being stood on
+    Crumbling,
+}
+
+impl Tile {
+    // Anything else in a level file is an empty tile, '.' by convention
+    pub fn from_char(character: char) -> Tile {
+        match character {
+            '#' => Tile::Solid,
+            '~' => Tile::Ice,
+            '-' => Tile::OneWay,
+            '<' => Tile::ConveyorLeft,
+            '>' => Tile::ConveyorRight,
+            '=' => Tile::Crumbling,
+            _ => Tile::Empty,
+        }
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            Tile::Empty => '.',
+            Tile::Solid => '#',
+            Tile::Ice => '~',
+            Tile::OneWay => '-',
+            Tile::ConveyorLeft => '<',
+            Tile::ConveyorRight => '>',
+            Tile::Crumbling => '=',
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Tile::Ice => ICE_COLOR,
+            Tile::OneWay => ONE_WAY_COLOR,
+            Tile::ConveyorLeft | Tile::ConveyorRight => CONVEYOR_COLOR,
+            Tile::Crumbling => CRUMBLING_COLOR,
+            Tile::Empty | Tile::Solid => WALL_COLOR,
+        }
+    }
+}
+
+// A horizontal stretch of tiles of the same kind, which gets a single collider
+pub struct TileRun {
+    row: usize,
+    column: usize,
+    length: usize,
+    tile: Tile,
+}
+
+// The tiles of the current arena, and the collider covering each of them
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct TileMap {
+    width: usize,
+    height: usize,
+    // World position of the bottom left corner of the grid
+    origin: Vec2,
+    // Row by row, bottom row first
+    tiles: Vec<Tile>,
+    colliders: Vec<Option<Entity>>,
+}
+
+impl TileMap {
+    fn from_level(level: &LevelDef) -> TileMap {
+        let width = level
+            .tiles
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let height = level.tiles.len();
+        let mut tiles = vec![Tile::Empty; width * height];
+        for (row, line) in level.tiles.iter().rev().enumerate() {
+            for (column, character) in line.chars().enumerate() {
+                tiles[row * width + column] = Tile::from_char(character);
+            }
+        }
+
+        // A lava or water floor takes the place of the bottom row
+        if level.floor != Floor::Solid {
+            tiles[..width].fill(Tile::Empty);
+        }
+
+        TileMap {
+            width,
+            height,
+            origin: level.origin * BLOCK_SIZE,
+            tiles,
+            colliders: vec![None; width * height],
+        }
+    }
+
+    fn runs(&self) -> Vec<TileRun> {
+        let mut runs = Vec::new();
+        for row in 0..self.height {
+            let mut column = 0;
+            while column < self.width {
+                let tile = self.tiles[row * self.width + column];
+                let length = self.tiles[row * self.width + column..(row + 1) * self.width]
+                    .iter()
+                    .take_while(|&&other| other == tile)
+                    .count();
+                if tile != Tile::Empty {
+                    runs.push(TileRun {
+                        row,
+                        column,
+                        length,
+                        tile,
+                    });
+                }
+                column += length;
+            }
+        }
+        runs
+    }
+
+    // World position of the middle of a run, and its size
+    fn run_bounds(&self, run: &TileRun) -> (Vec2, Vec2) {
+        let size = Vec2::new(run.length as f32, 1.0) * BLOCK_SIZE;
+        let corner = self.origin + Vec2::new(run.column as f32, run.row as f32) * BLOCK_SIZE;
+        (corner + size / 2.0, size)
+    }
+
+    // World position of the middle of the bottom row, and its size
+    fn bottom_row_bounds(&self) -> (Vec2, Vec2) {
+        let size = Vec2::new(self.width as f32, 1.0) * BLOCK_SIZE;
+        (self.origin + size / 2.0, size)
+    }
+
+    fn set_collider(&mut self, run: &TileRun, collider: Entity) {
+        let first = run.row * self.width + run.column;
+        for cell in &mut self.colliders[first..first + run.length] {
+            *cell = Some(collider);
+        }
+    }
+
+    // Turns every tile of a platform into ice
+    pub fn freeze(&mut self, collider: Entity) {
+        for (tile, cell) in self.tiles.iter_mut().zip(&self.colliders) {
+            if *cell == Some(collider) {
+                *tile = Tile::Ice;
+            }
+        }
+    }
+
+    // Whether a box overlaps any tile that isn't empty
+    fn overlaps(&self, center: Vec2, size: Vec2) -> bool {
+        let min = ((center - size / 2.0 - self.origin) / BLOCK_SIZE).floor();
+        let max = ((center + size / 2.0 - self.origin) / BLOCK_SIZE).ceil() - Vec2::ONE;
+        (min.y as isize..=max.y as isize).any(|row| {
+            (min.x as isize..=max.x as isize).any(|column| {
+                self.index(column, row)
+                    .is_some_and(|index| self.tiles[index] != Tile::Empty)
+            })
+        })
+    }
+
+    fn index(&self, column: isize, row: isize) -> Option<usize> {
+        if column < 0 || row < 0 || column as usize >= self.width || row as usize >= self.height {
+            return None;
+        }
+        Some(row as usize * self.width + column as usize)
+    }
+}
+
+// The area the tiles of the current arena cover, in world units
+#[derive(Resource)]
+pub struct ArenaBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl ArenaBounds {
+    fn from_level(level: &LevelDef) -> ArenaBounds {
+        let columns = level
+            .tiles
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        let size = Vec2::new(columns as f32, level.tiles.len() as f32);
+        ArenaBounds {
+            min: level.origin * BLOCK_SIZE,
+            max: (level.origin + size) * BLOCK_SIZE,
+        }
+    }
+
+    // Leaving the arena on one side brings you back in on the other
+    pub fn wrap(&self, translation: &mut Vec3) {
+        if translation.x > self.max.x {
+            translation.x = self.min.x
+        }
+        if translation.x < self.min.x {
+            translation.x = self.max.x
+        }
+    }
+}
+
+// The size of the bundled arenas, until the first arena is spawned
+impl Default for ArenaBounds {
+    fn default() -> Self {
+        ArenaBounds {
+            min: Vec2::new(-16.0, -12.5) * BLOCK_SIZE,
+            max: Vec2::new(16.0, 6.5) * BLOCK_SIZE,
+        }
+    }
+}
+
+// Every collider, filed under each `BLOCK_SIZE` cell it covers. Collision systems use it as their
+// broad phase, only looking at the colliders in the cells around what they move.
+#[derive(Resource, Default)]
+pub struct SpatialHash {
+    cells: HashMap<IVec2, Vec<Entity>>,
+}
+
+impl SpatialHash {
+    fn cell(position: Vec2) -> IVec2 {
+        (position / BLOCK_SIZE).floor().as_ivec2()
+    }
+
+    fn insert(&mut self, entity: Entity, center: Vec2, size: Vec2) {
+        let min = SpatialHash::cell(center - size / 2.0);
+        let max = SpatialHash::cell(center + size / 2.0);
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.cells.entry(IVec2::new(x, y)).or_default().push(entity);
+            }
+        }
+    }
+
+    // The colliders in the cells a box overlaps, or that are right next to them
+    pub fn colliders_near(&self, center: Vec2, size: Vec2) -> Vec<Entity> {
+        let min = SpatialHash::cell(center - size / 2.0) - IVec2::ONE;
+        let max = SpatialHash::cell(center + size / 2.0) + IVec2::ONE;
+        let mut colliders = Vec::new();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let Some(cell) = self.cells.get(&IVec2::new(x, y)) else {
+                    continue;
+                };
+                for collider in cell {
+                    if !colliders.contains(collider) {
+                        colliders.push(*collider);
+                    }
+                }
+            }
+        }
+        colliders
+    }
+}
+
+// This bundle is a collection of the components that define a "wall" in our game
+#[derive(Bundle)]
+struct WallBundle {
+    // You can nest bundles inside of other bundles like this
+    // Allowing you to compose their functionality
+    sprite: Sprite,
+    transform: Transform,
+    collider: Collider,
+    platform: Platform,
+}
+
+impl WallBundle {
+    // This "builder method" allows us to reuse logic across our wall entities,
+    // making our code easier to read and less prone to bugs when we change the logic
+    fn new(position: Vec2, size: Vec2, tile: Tile) -> WallBundle {
+        WallBundle {
+            sprite: Sprite {
+                color: tile.color(),
+                ..default()
+            },
+            transform: Transform {
+                // We need to convert our Vec2 into a Vec3, by giving it a z-coordinate
+                // This is used to determine the order of our sprites
+                translation: position.extend(0.0),
+                // The z-scale of 2D objects must always be 1.0,
+                // or their ordering will be affected in surprising ways.
+                // See https://github.com/bevyengine/bevy/issues/4149
+                scale: size.extend(1.0),
+                ..default()
+            },
+            collider: Collider,
+            platform: Platform,
+        }
+    }
+}
+
+// The current phase (wave of enemies), starting from 1
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct Phase(pub usize);
+
+// The phase a new game starts from, picked on the level select screen
+#[derive(Resource)]
+pub struct StartPhase(pub usize);
+
+// Counts down at the start of each phase. The gameplay step doesn't run until it's done.
+#[derive(Resource)]
+pub struct PhaseIntro(pub Timer);
+
+impl Default for PhaseIntro {
+    fn default() -> Self {
+        // Started by `spawn_arena` and `advance_phase`
+        let mut timer = Timer::from_seconds(PHASE_INTRO_SECONDS, TimerMode::Once);
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+impl PhaseIntro {
+    pub fn running(&self) -> bool {
+        !self.0.is_finished()
+    }
+
+    // Whole seconds left, for the countdown
+    pub fn seconds_left(&self) -> u32 {
+        (self.0.duration() - self.0.elapsed()).as_secs_f32().ceil() as u32
+    }
+}
+
+// Ticks in real time rather than in the gameplay step, which it holds back
+fn tick_phase_intro(time: Res<Time>, mut intro: ResMut<PhaseIntro>) {
+    if intro.running() {
+        intro.0.tick(time.delta());
+    }
+}
+
+// Holds the gameplay step back for a moment after a hard hit
+#[derive(Resource)]
+pub struct HitStop(Timer);
+
+impl Default for HitStop {
+    fn default() -> Self {
+        let mut timer = Timer::from_seconds(HIT_STOP_SECONDS, TimerMode::Once);
+        timer.tick(timer.duration());
+        Self(timer)
+    }
+}
+
+impl HitStop {
+    pub fn running(&self) -> bool {
+        !self.0.is_finished()
+    }
+}
+
+// Like the phase intro, this runs in real time. A hit during a stop starts it over.
+fn hit_stop(
+    time: Res<Time>,
+    mut defeated_events: MessageReader<EnemyDefeated>,
+    mut bump_events: MessageReader<PlatformBumped>,
+    pow_query: Query<(), With<PowBlock>>,
+    mut hit_stop: ResMut<HitStop>,
+) {
+    if hit_stop.running() {
+        hit_stop.0.tick(time.delta());
+    }
+    let kicked = defeated_events.read().count() > 0;
+    let pow_hit = bump_events
+        .read()
+        .any(|bump| pow_query.contains(bump.platform));
+    if kicked || pow_hit {
+        hit_stop.0.reset();
+    }
+}
+
+fn add_previous_transforms(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform), Or<(Added<Velocity>, Added<Elevator>, Added<Crumbling>)>>,
+) {
+    for (entity, transform) in &query {
+        commands.entity(entity).insert(PreviousTransform {
+            translation: transform.translation,
+            physics: None,
+        });
+    }
+}
+
+// Puts everything back where the physics left it, before anything else runs this frame
+fn restore_physics_transforms(mut query: Query<(&mut Transform, &mut PreviousTransform)>) {
+    for (mut transform, mut previous) in &mut query {
+        if let Some(translation) = previous.physics.take() {
+            transform.translation = translation;
+        }
+    }
+}
+
+fn remember_previous_transforms(mut query: Query<(&Transform, &mut PreviousTransform)>) {
+    for (transform, mut previous) in &mut query {
+        previous.translation = transform.translation;
+    }
+}
+
+// Only while the game runs: otherwise the physics stands still, and so should what is drawn.
+// Online games run their steps whenever the inputs for them come in rather than on the clock, so
+// there is no telling how far along the next one is, and they are drawn as they are.
+fn interpolate_transforms(
+    pause: Option<Res<State<PauseState>>>,
+    driver: Res<StepDriver>,
+    holds: (Res<PhaseIntro>, Res<HitStop>),
+    fixed_time: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &mut PreviousTransform)>,
+) {
+    if pause.is_none_or(|pause| *pause.get() == PauseState::Paused)
+        || *driver == StepDriver::Session
+        || holds.0.running()
+        || holds.1.running()
+    {
+        return;
+    }
+    let progress = fixed_time.overstep_fraction().min(1.0);
+    for (mut transform, mut previous) in &mut query {
+        let translation = transform.translation;
+        previous.physics = Some(translation);
+        if previous.translation.distance(translation) <= MAX_INTERPOLATED_DISTANCE {
+            transform.translation = previous.translation.lerp(translation, progress);
+        }
+    }
+}
+
+// Players are moved by `move_players` instead, unless they are falling off the screen.
+// Enemies and hazards go across as much faster as the `SpeedModifier` says; their falls and
+// bounces keep the usual arcs.
+pub fn apply_velocity(
+    mut query: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            Option<&GravityScale>,
+            Option<&WrapsHorizontally>,
+        ),
+        Or<(Without<Player>, With<Dying>)>,
+    >,
+    enemy_query: Query<(), Or<(With<Enemy>, With<Hazard>, With<Boss>)>>,
+    bounds: Res<ArenaBounds>,
+    (modifiers, speed): (Res<Modifiers>, Res<SpeedModifier>),
+) {
+    for (entity, mut transform, mut velocity, gravity_scale, wraps) in &mut query {
+        let speed = if enemy_query.contains(entity) {
+            speed.0
+        } else {
+            1.0
+        };
+        transform.translation.x += velocity.x * speed * TIME_STEP;
+        transform.translation.y += velocity.y * TIME_STEP;
+        if wraps.is_some() {
+            bounds.wrap(&mut transform.translation);
+        }
+        apply_gravity(&mut velocity, gravity_scale, &modifiers);
+    }
+}
+
+// One physics step worth of gravity
+pub fn apply_gravity(
+    velocity: &mut Velocity,
+    gravity_scale: Option<&GravityScale>,
+    modifiers: &Modifiers,
+) {
+    let scale = gravity_scale.map_or(1.0, |scale| scale.0) * modifiers.gravity;
+    // Weightless things like fireballs aren't held to the fall speed limit either
+    if scale == 0.0 {
+        return;
+    }
+    velocity.y = (velocity.y - GRAVITY_ACCEL * scale * TIME_STEP).max(-MAX_FALL_SPEED);
+}
+
+// How far a box has to move to stop overlapping a collider, along whichever axis is shortest,
+// or `None` when they don't overlap
+pub fn penetration(center: Vec2, size: Vec2, collider: &Transform) -> Option<Vec2> {
+    let offset = center - collider.translation.truncate();
+    let overlap = (size + collider.scale.truncate()) / 2.0 - offset.abs();
+    if overlap.x <= CONTACT_EPSILON || overlap.y <= CONTACT_EPSILON {
+        return None;
+    }
+    Some(if overlap.x < overlap.y {
+        Vec2::new(overlap.x * offset.x.signum(), 0.0)
+    } else {
+        Vec2::new(0.0, overlap.y * offset.y.signum())
+    })
+}
+
+// What is left of a velocity once the box moving with it was pushed out of a collider by `push`:
+// nothing more going into the collider on that axis, so it rests against it
+#[cfg_attr(feature = "rapier", allow(dead_code))]
+pub fn stop_against(velocity: Vec2, push: Vec2) -> Vec2 {
+    Vec2::new(
+        if velocity.x * push.x < 0.0 {
+            0.0
+        } else {
+            velocity.x
+        },
+        if velocity.y * push.y < 0.0 {
+            0.0
+        } else {
+            velocity.y
+        },
+    )
+}
+
+// The colliders a moving box ran into, on each axis, and the one-way platform it jumped into
+// from below, if any
+#[derive(Default)]
+pub struct SweepHits {
+    pub x: Option<Entity>,
+    pub y: Option<Entity>,
+    pub through: Option<Entity>,
+}
+
+// Moves a box along one axis and then the other, stopping it where it first touches a collider
+// on each. Unlike checking for overlaps after moving, this can't skip past a thin platform
+// when moving fast. Colliders the box already overlaps don't stop it, and one-way platforms only
+// stop it falling onto them.
+pub fn sweep(
+    center: Vec2,
+    size: Vec2,
+    motion: Vec2,
+    colliders: &[(Entity, &Transform, Option<&OneWayPlatform>)],
+) -> (Vec2, SweepHits) {
+    let mut center = center;
+    let mut hits = SweepHits::default();
+    let mut through = None;
+    for (axis, other, hit) in [(0, 1, &mut hits.x), (1, 0, &mut hits.y)] {
+        let delta = motion[axis];
+        if delta == 0.0 {
+            continue;
+        }
+
+        let mut allowed = delta.abs();
+        for (entity, transform, one_way) in colliders {
+            let collider_center = transform.translation.truncate();
+            let half_sizes = (size + transform.scale.truncate()) / 2.0;
+            // Only colliders in the way, not ones just touching the side we slide along
+            let offset = collider_center - center;
+            if offset[other].abs() >= half_sizes[other] - CONTACT_EPSILON
+                || offset[axis].signum() != delta.signum()
+            {
+                continue;
+            }
+            let gap = offset[axis].abs() - half_sizes[axis];
+            if gap <= -CONTACT_EPSILON || gap >= allowed {
+                continue;
+            }
+            if one_way.is_some() && !one_way_blocks(center, size, motion, transform) {
+                if delta > 0.0 && through.is_none_or(|(nearest, _)| gap < nearest) {
+                    through = Some((gap, *entity));
+                }
+                continue;
+            }
+            allowed = gap.max(0.0);
+            *hit = Some(*entity);
+        }
+        center[axis] += allowed * delta.signum();
+        // Unless something solid stopped it first
+        if axis == 1 {
+            hits.through = through
+                .filter(|(gap, _)| *gap <= allowed)
+                .map(|(_, entity)| entity);
+        }
+    }
+    (center, hits)
+}
+
+// Whether a one-way platform stops a box moving by `motion`: only when it falls onto it from above
+pub fn one_way_blocks(center: Vec2, size: Vec2, motion: Vec2, platform: &Transform) -> bool {
+    let feet = center.y - size.y / 2.0;
+    let top = platform.translation.y + platform.scale.y / 2.0;
+    motion.y < 0.0 && feet > top - CONTACT_EPSILON
+}
+
+// Which axes a velocity should be reflected on after a collision.
+// Only reflect if the velocity is going in the opposite direction of the collision.
+pub fn reflection(collision: &Collision, velocity: Vec2) -> (bool, bool) {
+    match collision {
+        Collision::Left => (velocity.x > 0.0, false),
+        Collision::Right => (velocity.x < 0.0, false),
+        Collision::Top => (false, velocity.y < 0.0),
+        Collision::Bottom => (false, velocity.y > 0.0),
+        Collision::Inside => (false, false),
+    }
+}
+
+// The thin box right under a character's feet, as its middle and size. Slightly narrower than
+// the body, so brushing against the side of a platform doesn't count as standing on it.
+pub fn ground_probe(transform: &Transform) -> (Vec3, Vec2) {
+    let size = transform.scale.truncate();
+    let feet = transform.translation.y - size.y / 2.0;
+    let position = Vec3::new(
+        transform.translation.x,
+        feet - GROUND_PROBE_DEPTH / 2.0,
+        0.0,
+    );
+    (
+        position,
+        Vec2::new(size.x - 2.0 * GROUND_PROBE_DEPTH, GROUND_PROBE_DEPTH),
+    )
+}
+
+// Probes a thin box right under each character's feet to find out if they stand on a collider.
+// Players that collide can also stand on each other.
+pub fn detect_ground(
+    mut query: Query<(Entity, &Transform, &Velocity, &mut Grounded)>,
+    spatial_hash: Res<SpatialHash>,
+    collider_query: Query<(&Transform, Option<&OneWayPlatform>), With<Collider>>,
+    coop_rules: Res<CoopRules>,
+    player_query: Query<(Entity, &Transform), (With<Player>, Without<Dying>)>,
+) {
+    for (entity, transform, velocity, mut grounded) in &mut query {
+        // Still on the way up from a jump
+        if velocity.y > 0.0 {
+            grounded.0 = None;
+            continue;
+        }
+
+        let feet = transform.translation.y - transform.scale.y / 2.0;
+        let (probe_position, probe_size) = ground_probe(transform);
+
+        grounded.0 = spatial_hash
+            .colliders_near(probe_position.truncate(), probe_size)
+            .into_iter()
+            .filter_map(|entity| Some((entity, collider_query.get(entity).ok()?)))
+            .find(|(_, (collider, one_way))| {
+                let collider_top = collider.translation.y + collider.scale.y / 2.0;
+                // Halfway through a one-way platform isn't standing on it
+                if one_way.is_some() && feet < collider_top - CONTACT_EPSILON {
+                    return false;
+                }
+                // Overlapping the lower half of a platform means we are next to or under it, not on it
+                collider_top - feet < collider.scale.y / 2.0
+                    && collide(
+                        probe_position,
+                        probe_size,
+                        collider.translation,
+                        collider.scale.truncate(),
+                    )
+                    .is_some()
+            })
+            .map(|(entity, _)| entity);
+
+        if grounded.0.is_some() || !coop_rules.players_collide || !player_query.contains(entity) {
+            continue;
+        }
+        grounded.0 = player_query
+            .iter()
+            .find(|(other, partner)| {
+                let head = partner.translation.y + partner.scale.y / 2.0;
+                *other != entity
+                    && head - feet < partner.scale.y / 2.0
+                    && collide(
+                        probe_position,
+                        probe_size,
+                        partner.translation,
+                        partner.scale.truncate(),
+                    )
+                    .is_some()
+            })
+            .map(|(other, _)| other);
+    }
+}
+
+// Compares what overlaps each sensor now with what did after the last step
+pub fn detect_triggers(
+    mut sensor_query: Query<(Entity, &Transform, &mut Sensor)>,
+    body_query: Query<(Entity, &Transform), (With<Velocity>, Without<Sensor>)>,
+    mut enter_events: MessageWriter<TriggerEnter>,
+    mut exit_events: MessageWriter<TriggerExit>,
+) {
+    for (sensor, sensor_transform, mut state) in &mut sensor_query {
+        let touching: Vec<Entity> = body_query
+            .iter()
+            .filter(|(_, transform)| {
+                collide(
+                    transform.translation,
+                    transform.scale.truncate(),
+                    sensor_transform.translation,
+                    sensor_transform.scale.truncate(),
+                )
+                .is_some()
+            })
+            .map(|(entity, _)| entity)
+            .collect();
+
+        for &entity in touching
+            .iter()
+            .filter(|entity| !state.touching.contains(entity))
+        {
+            enter_events.write(TriggerEnter { sensor, entity });
+        }
+        for &entity in state
+            .touching
+            .iter()
+            .filter(|entity| !touching.contains(entity))
+        {
+            exit_events.write(TriggerExit { sensor, entity });
+        }
+        state.touching = touching;
+    }
+}
+
+// Moves the elevators along, taking their riders with them, and pushes anything else they run into
+// out of the way. Riders are what stood on an elevator after the last step.
+pub fn move_elevators(
+    mut elevator_query: Query<(Entity, &mut Transform, &mut Elevator), With<Collider>>,
+    mut body_query: Query<
+        (Entity, &mut Transform, Option<&Grounded>),
+        (With<Velocity>, Without<Collider>, Without<Dying>),
+    >,
+    spatial_hash: Res<SpatialHash>,
+    collider_query: Query<
+        (&Transform, Option<&OneWayPlatform>),
+        (With<Collider>, Without<Elevator>),
+    >,
+    mut crushed_events: MessageWriter<Crushed>,
+) {
+    let mut moves = Vec::new();
+    for (entity, mut transform, mut elevator) in &mut elevator_query {
+        let position = transform.translation.truncate();
+        let target = if elevator.outward {
+            elevator.to
+        } else {
+            elevator.from
+        };
+        let step = elevator.speed * TIME_STEP;
+        let new_position = if position.distance(target) <= step {
+            elevator.outward = !elevator.outward;
+            target
+        } else {
+            position + (target - position).normalize() * step
+        };
+        transform.translation = new_position.extend(transform.translation.z);
+        moves.push((entity, new_position - position));
+    }
+    if moves.is_empty() {
+        return;
+    }
+
+    for (body, mut transform, grounded) in &mut body_query {
+        let size = transform.scale.truncate();
+        for (elevator, motion) in &moves {
+            if grounded.and_then(|grounded| grounded.0) == Some(*elevator) {
+                transform.translation += motion.extend(0.0);
+            } else if let Ok((_, elevator_transform, _)) = elevator_query.get(*elevator) {
+                if let Some(push) =
+                    penetration(transform.translation.truncate(), size, elevator_transform)
+                {
+                    transform.translation += push.extend(0.0);
+                }
+            }
+        }
+
+        // Nowhere left to go: stuck inside another collider, or squeezed between two elevators
+        let center = transform.translation.truncate();
+        let pinned_by_collider = collider_query
+            .iter_many(spatial_hash.colliders_near(center, size))
+            .any(|(collider, one_way)| {
+                one_way.is_none() && penetration(center, size, collider).is_some()
+            });
+        let pinned_by_elevator = elevator_query
+            .iter()
+            .any(|(_, elevator, _)| penetration(center, size, elevator).is_some());
+        if pinned_by_collider || pinned_by_elevator {
+            crushed_events.write(Crushed { entity: body });
+        }
+    }
+}
+
+// Whatever stood on a conveyor after the last step slides along with it, stopping against walls
+// like it would walking
+fn drift_on_conveyors(
+    mut body_query: Query<(&mut Transform, &Grounded), (Without<Collider>, Without<Dying>)>,
+    conveyor_query: Query<&Conveyor>,
+    spatial_hash: Res<SpatialHash>,
+    collider_query: Query<(Entity, &Transform, Option<&OneWayPlatform>), With<Collider>>,
+) {
+    for (mut transform, grounded) in &mut body_query {
+        let Some(conveyor) = grounded
+            .0
+            .and_then(|ground| conveyor_query.get(ground).ok())
+        else {
+            continue;
+        };
+        let center = transform.translation.truncate();
+        let size = transform.scale.truncate();
+        let motion = Vec2::new(conveyor.speed * TIME_STEP, 0.0);
+        let nearby = spatial_hash.colliders_near(center + motion / 2.0, size + motion.abs());
+        let colliders: Vec<_> = collider_query.iter_many(&nearby).collect();
+        let (center, _) = sweep(center, size, motion, &colliders);
+        transform.translation.x = center.x;
+    }
+}
+
+// Past `CONVEYOR_REVERSE_FIRST_PHASE`, conveyors keep switching direction on a timer
+fn reverse_conveyors(
+    phase: Res<Phase>,
+    mut reversal: ResMut<ConveyorReversal>,
+    mut conveyor_query: Query<&mut Conveyor>,
+) {
+    if phase.0 < CONVEYOR_REVERSE_FIRST_PHASE {
+        return;
+    }
+
+    reversal.timer.tick(Duration::from_secs_f32(TIME_STEP));
+    if reversal.timer.just_finished() {
+        for mut conveyor in &mut conveyor_query {
+            conveyor.speed = -conveyor.speed;
+        }
+    }
+}
+
+// Stripes move with the belt and come back in at the other end of the platform. Only drawn, so
+// this follows the frame rate rather than the physics step.
+fn scroll_conveyors(
+    time: Res<Time>,
+    conveyor_query: Query<(&Conveyor, &Transform, &Children), Without<ConveyorStripe>>,
+    mut stripe_query: Query<&mut Transform, With<ConveyorStripe>>,
+) {
+    for (conveyor, transform, children) in &conveyor_query {
+        let shift = conveyor.speed * time.delta_secs() / transform.scale.x;
+        let mut stripes = stripe_query.iter_many_mut(children);
+        while let Some(mut stripe) = stripes.fetch_next() {
+            stripe.translation.x = (stripe.translation.x + 0.5 + shift).rem_euclid(1.0) - 0.5;
+        }
+    }
+}
+
+// Takes each crumbling platform a step further through its cycle. From when it starts falling
+// until it is back it has no `Collider`, which also keeps it out of the spatial hash.
+fn crumble_platforms(
+    mut commands: Commands,
+    mut platform_query: Query<(Entity, &mut Transform, &mut Visibility, &mut Crumbling)>,
+    player_query: Query<&Grounded, With<Player>>,
+    body_query: Query<&Transform, (With<Velocity>, Without<Crumbling>)>,
+) {
+    let step = Duration::from_secs_f32(TIME_STEP);
+    for (entity, mut transform, mut visibility, mut crumbling) in &mut platform_query {
+        let home = crumbling.home;
+        match &mut crumbling.state {
+            CrumbleState::Intact => {
+                if player_query
+                    .iter()
+                    .any(|grounded| grounded.0 == Some(entity))
+                {
+                    crumbling.state = CrumbleState::Shaking(Timer::from_seconds(
+                        CRUMBLE_SHAKE_SECONDS,
+                        TimerMode::Once,
+                    ));
+                }
+            }
+            CrumbleState::Shaking(timer) => {
+                timer.tick(step);
+                transform.translation.x = home.x
+                    + (timer.elapsed_secs() * CRUMBLE_SHAKE_RATE).sin() * CRUMBLE_SHAKE_DISTANCE;
+                if timer.is_finished() {
+                    transform.translation = home;
+                    commands.entity(entity).remove::<Collider>();
+                    crumbling.state = CrumbleState::Falling {
+                        timer: Timer::from_seconds(CRUMBLE_FALL_SECONDS, TimerMode::Once),
+                        speed: 0.0,
+                    };
+                }
+            }
+            CrumbleState::Falling { timer, speed } => {
+                timer.tick(step);
+                *speed = (*speed + GRAVITY_ACCEL * TIME_STEP).min(MAX_FALL_SPEED);
+                transform.translation.y -= *speed * TIME_STEP;
+                if timer.is_finished() {
+                    transform.translation = home;
+                    *visibility = Visibility::Hidden;
+                    crumbling.state = CrumbleState::Respawning(Timer::from_seconds(
+                        CRUMBLE_RESPAWN_SECONDS,
+                        TimerMode::Once,
+                    ));
+                }
+            }
+            CrumbleState::Respawning(timer) => {
+                timer.tick(step);
+                // Waits for whatever is in the way to move on, rather than trapping it
+                let blocked = body_query.iter().any(|body| {
+                    penetration(
+                        body.translation.truncate(),
+                        body.scale.truncate(),
+                        &transform,
+                    )
+                    .is_some()
+                });
+                if timer.is_finished() && !blocked {
+                    *visibility = Visibility::Inherited;
+                    commands.entity(entity).insert(Collider);
+                    crumbling.state = CrumbleState::Intact;
+                }
+            }
+        }
+    }
+}
+
+// Whatever falls into lava or water throws up a few drops of it
+fn splash_hazard_floors(
+    mut commands: Commands,
+    mut enter_events: MessageReader<TriggerEnter>,
+    floor_query: Query<(&Transform, &Sprite), With<HazardFloor>>,
+    body_query: Query<&Transform, Without<HazardFloor>>,
+    mut splashed_events: MessageWriter<Splashed>,
+) {
+    for enter in enter_events.read() {
+        let (Ok((floor, sprite)), Ok(body)) =
+            (floor_query.get(enter.sensor), body_query.get(enter.entity))
+        else {
+            continue;
+        };
+        let surface = floor.translation.y + floor.scale.y / 2.0;
+        for drop in 0..SPLASH_DROPS {
+            // Fanned out upwards, from straight right to straight left
+            let angle = std::f32::consts::PI * drop as f32 / (SPLASH_DROPS - 1) as f32;
+            commands.spawn((
+                Sprite {
+                    color: sprite.color,
+                    ..default()
+                },
+                Transform::from_xyz(body.translation.x, surface, HAZARD_FLOOR_Z)
+                    .with_scale(SPLASH_DROP_SIZE),
+                SplashDrop {
+                    velocity: Vec2::from_angle(angle) * SPLASH_SPEED,
+                    lifetime: Timer::from_seconds(SPLASH_SECONDS, TimerMode::Once),
+                },
+                DespawnOnExit(GameState::Playing),
+            ));
+        }
+        splashed_events.write(Splashed {
+            position: Vec3::new(body.translation.x, surface, 0.0),
+        });
+    }
+}
+
+fn move_splash_drops(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Transform, &mut SplashDrop)>,
+) {
+    for (entity, mut transform, mut drop) in &mut query {
+        drop.lifetime.tick(Duration::from_secs_f32(TIME_STEP));
+        if drop.lifetime.is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        drop.velocity.y -= GRAVITY_ACCEL * TIME_STEP;
+        transform.translation += (drop.velocity * TIME_STEP).extend(0.0);
+    }
+}
+
+// Files every collider under the cells it covers, before anything moves this step. Rebuilt from
+// scratch, so colliders that moved, appeared or went away since the last step are never missed.
+fn update_spatial_hash(
+    mut spatial_hash: ResMut<SpatialHash>,
+    collider_query: Query<(Entity, &Transform), With<Collider>>,
+) {
+    spatial_hash.cells.clear();
+    for (entity, transform) in &collider_query {
+        spatial_hash.insert(
+            entity,
+            transform.translation.truncate(),
+            transform.scale.truncate(),
+        );
+    }
+}
+
+// Hitting a platform from below bumps whatever stands on it
+pub fn bump_platforms(
+    mut collision_events: MessageReader<CollisionEvent>,
+    player_query: Query<&Player>,
+    mut bump_events: MessageWriter<PlatformBumped>,
+) {
+    for collision in collision_events.read() {
+        if collision.side != Collision::Bottom {
+            continue;
+        }
+        if let Ok(player) = player_query.get(collision.player) {
+            bump_events.write(PlatformBumped {
+                player: player.index,
+                platform: collision.collider,
+                x: collision.point.x,
+            });
+        }
+    }
+}
+
+// Whether something standing on the bumped platform is close enough to the bump to feel it
+pub fn hit_by_bump(bump: &PlatformBumped, platform: &Transform, transform: &Transform) -> bool {
+    let platform_top = platform.translation.y + platform.scale.y / 2.0;
+    let feet = transform.translation.y - transform.scale.y / 2.0;
+    let standing_on_platform = (feet - platform_top).abs() < BLOCK_SIZE / 2.0;
+    standing_on_platform && (transform.translation.x - bump.x).abs() <= BUMP_RANGE
+}
+
+// Once every enemy of a phase is gone, the next phase starts with a fresh wave.
+// The arena is rebuilt from the next phase's layout, which also thaws what the Freezies froze.
+// The survival mode never leaves its first phase: more enemies just keep coming.
+pub fn advance_phase(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    (mut phase, mut cleared_events): (ResMut<Phase>, MessageWriter<PhaseCleared>),
+    mut intro: ResMut<PhaseIntro>,
+    (enemy_count, mut director): (Res<EnemyCount>, ResMut<SpawnDirector>),
+    (levels, level_assets): (Res<Levels>, Res<Assets<LevelDef>>),
+    arena_query: Query<
+        Entity,
+        Or<(
+            With<Platform>,
+            With<PowBlock>,
+            With<Background>,
+            With<HazardFloor>,
+        )>,
+    >,
+) {
+    if !director.phase_cleared(&enemy_count) || *game_mode == GameMode::Survival {
+        return;
+    }
+
+    cleared_events.write(PhaseCleared { phase: phase.0 });
+    phase.0 += 1;
+    intro.0.reset();
+    let level = levels.for_phase(phase.0, &level_assets);
+    rebuild_arena(&mut commands, &arena_query, level, GameState::Playing);
+    director.start(phase.0, level);
+}
+
+// Rebuilds the arena and starts the phase's spawns over, much like `advance_phase` without
+// advancing. The phase's modules each put back their own part.
+fn restart_phase(
+    mut commands: Commands,
+    mut restart_events: MessageReader<RestartPhase>,
+    phase: Res<Phase>,
+    mut intro: ResMut<PhaseIntro>,
+    (mut enemy_count, mut director): (ResMut<EnemyCount>, ResMut<SpawnDirector>),
+    (levels, level_assets): (Res<Levels>, Res<Assets<LevelDef>>),
+    arena_query: Query<
+        Entity,
+        Or<(
+            With<Platform>,
+            With<PowBlock>,
+            With<Background>,
+            With<HazardFloor>,
+        )>,
+    >,
+) {
+    if restart_events.read().count() == 0 {
+        return;
+    }
+
+    commands.insert_resource(ConveyorReversal::new());
+    intro.0.reset();
+    let level = levels.for_phase(phase.0, &level_assets);
+    rebuild_arena(&mut commands, &arena_query, level, GameState::Playing);
+    enemy_count.0 = 0;
+    director.start(phase.0, level);
+}
+
+// The `phase` console command: the given phase starts over from the beginning, as if the game had
+// got there by clearing the ones before it
+fn phase_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let [phase] = args else {
+        return Err("Which phase?".to_string());
+    };
+    let phase = match phase.parse() {
+        Ok(phase) if phase >= 1 => phase,
+        _ => return Err(format!("\"{phase}\" isn't a phase")),
+    };
+    if let Some(seed) = world.resource::<Levels>().endless_seed {
+        let layout = world
+            .resource_mut::<Assets<LevelDef>>()
+            .add(generator::generate(seed, phase));
+        world.resource_mut::<Levels>().custom = Some(layout);
+    }
+    world.insert_resource(Phase(phase));
+    world
+        .resource_mut::<Messages<RestartPhase>>()
+        .write(RestartPhase);
+    Ok(format!("Phase {phase}"))
+}
+
+// The endless mode makes up the layout of each phase right before it is needed
+pub fn generate_first_endless_layout(
+    start_phase: Res<StartPhase>,
+    mut levels: ResMut<Levels>,
+    mut level_assets: ResMut<Assets<LevelDef>>,
+) {
+    if let Some(seed) = levels.endless_seed {
+        levels.custom = Some(level_assets.add(generator::generate(seed, start_phase.0)));
+    }
+}
+
+fn generate_next_endless_layout(
+    game_mode: Res<GameMode>,
+    phase: Res<Phase>,
+    (enemy_count, director): (Res<EnemyCount>, Res<SpawnDirector>),
+    mut levels: ResMut<Levels>,
+    mut level_assets: ResMut<Assets<LevelDef>>,
+) {
+    if !director.phase_cleared(&enemy_count) || *game_mode == GameMode::Survival {
+        return;
+    }
+    if let Some(seed) = levels.endless_seed {
+        levels.custom = Some(level_assets.add(generator::generate(seed, phase.0 + 1)));
+    }
+}
+
+// Saving the layout in use rebuilds the arena in place. Players keep their position, unless
+// they would end up stuck inside a platform.
+fn reload_levels(
+    mut commands: Commands,
+    mut asset_events: MessageReader<AssetEvent<LevelDef>>,
+    phase: Res<Phase>,
+    levels: Res<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+    arena_query: Query<
+        Entity,
+        Or<(
+            With<Platform>,
+            With<PowBlock>,
+            With<Background>,
+            With<HazardFloor>,
+        )>,
+    >,
+    mut player_query: Query<(&Player, &mut Transform, &mut Velocity)>,
+) {
+    let level = levels.for_phase(phase.0, &level_assets);
+    let current_level_changed = asset_events.read().any(|event| match event {
+        AssetEvent::Modified { id } => level_assets
+            .get(*id)
+            .is_some_and(|changed| std::ptr::eq(changed, level)),
+        _ => false,
+    });
+    if !current_level_changed {
+        return;
+    }
+
+    rebuild_arena(&mut commands, &arena_query, level, GameState::Playing);
+    let tile_map = TileMap::from_level(level);
+    for (player, mut transform, mut velocity) in &mut player_query {
+        if tile_map.overlaps(transform.translation.truncate(), transform.scale.truncate()) {
+            transform.translation = level.player_spawn(player.index);
+            velocity.0 = Vec2::ZERO;
+        }
+    }
+}
+
+pub fn rebuild_arena(
+    commands: &mut Commands,
+    arena_query: &Query<
+        Entity,
+        Or<(
+            With<Platform>,
+            With<PowBlock>,
+            With<Background>,
+            With<HazardFloor>,
+        )>,
+    >,
+    level: &LevelDef,
+    state: GameState,
+) {
+    for entity in arena_query {
+        commands.entity(entity).despawn();
+    }
+    spawn_platforms(commands, level, state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wall(position: Vec2, size: Vec2) -> Transform {
+        Transform::from_translation(position.extend(0.0)).with_scale(size.extend(1.0))
+    }
+
+    #[test]
+    fn fast_body_stops_at_a_thin_wall() {
+        let wall = wall(Vec2::new(100.0, 0.0), Vec2::new(2.0, 40.0));
+        let entity = Entity::from_raw_u32(1).unwrap();
+        // Far more than the wall is thick in a single step
+        let (center, hits) = sweep(
+            Vec2::ZERO,
+            Vec2::splat(10.0),
+            Vec2::new(500.0, 0.0),
+            &[(entity, &wall, None)],
+        );
+        assert_eq!(center, Vec2::new(94.0, 0.0));
+        assert_eq!(hits.x, Some(entity));
+        assert_eq!(hits.y, None);
+    }
+
+    #[test]
+    fn fast_fall_lands_on_a_thin_platform() {
+        let platform = wall(Vec2::new(0.0, -100.0), Vec2::new(40.0, 2.0));
+        let entity = Entity::from_raw_u32(1).unwrap();
+        let (center, hits) = sweep(
+            Vec2::ZERO,
+            Vec2::splat(10.0),
+            Vec2::new(0.0, -500.0),
+            &[(entity, &platform, None)],
+        );
+        assert_eq!(center, Vec2::new(0.0, -94.0));
+        assert_eq!(hits.y, Some(entity));
+    }
+
+    #[test]
+    fn jumping_through_a_one_way_platform_only_reports_it() {
+        let platform = wall(Vec2::new(0.0, 20.0), Vec2::new(40.0, 2.0));
+        let entity = Entity::from_raw_u32(1).unwrap();
+        let (center, hits) = sweep(
+            Vec2::ZERO,
+            Vec2::splat(10.0),
+            Vec2::new(0.0, 50.0),
+            &[(entity, &platform, Some(&OneWayPlatform))],
+        );
+        assert_eq!(center, Vec2::new(0.0, 50.0));
+        assert_eq!(hits.y, None);
+        assert_eq!(hits.through, Some(entity));
+    }
+
+    #[test]
+    fn resting_contact_is_not_a_penetration() {
+        let floor = wall(Vec2::ZERO, Vec2::new(40.0, 2.0));
+        let entity = Entity::from_raw_u32(1).unwrap();
+        // Feet exactly on the floor's top
+        let center = Vec2::new(0.0, 6.0);
+        assert_eq!(penetration(center, Vec2::splat(10.0), &floor), None);
+
+        let (moved, hits) = sweep(
+            center,
+            Vec2::splat(10.0),
+            Vec2::new(0.0, -5.0),
+            &[(entity, &floor, None)],
+        );
+        assert_eq!(moved, center);
+        assert_eq!(hits.y, Some(entity));
+    }
+
+    #[test]
+    fn pushing_out_zeroes_only_the_velocity_going_in() {
+        let floor = wall(Vec2::ZERO, Vec2::new(40.0, 2.0));
+        let push = penetration(Vec2::new(0.0, 4.0), Vec2::splat(10.0), &floor);
+        assert_eq!(push, Some(Vec2::new(0.0, 2.0)));
+
+        let push = push.unwrap();
+        assert_eq!(
+            stop_against(Vec2::new(30.0, -200.0), push),
+            Vec2::new(30.0, 0.0)
+        );
+        assert_eq!(
+            stop_against(Vec2::new(30.0, 100.0), push),
+            Vec2::new(30.0, 100.0)
+        );
+    }
+
+    #[test]
+    fn hash_finds_colliders_in_the_next_cell_only() {
+        let mut hash = SpatialHash::default();
+        let entity = Entity::from_raw_u32(1).unwrap();
+        // Covers cells 2 and 3
+        hash.insert(entity, Vec2::new(50.0, 0.0), Vec2::splat(20.0));
+
+        assert!(hash
+            .colliders_near(Vec2::new(25.0, 0.0), Vec2::splat(10.0))
+            .contains(&entity));
+        assert!(hash
+            .colliders_near(Vec2::new(10.0, 0.0), Vec2::splat(10.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn hash_cells_below_zero_round_down() {
+        let mut hash = SpatialHash::default();
+        let entity = Entity::from_raw_u32(1).unwrap();
+        // Just left of 0, in cell -1
+        hash.insert(entity, Vec2::new(-5.0, 0.0), Vec2::splat(2.0));
+
+        assert!(hash
+            .colliders_near(Vec2::new(15.0, 0.0), Vec2::splat(2.0))
+            .contains(&entity));
+        assert!(hash
+            .colliders_near(Vec2::new(45.0, 0.0), Vec2::splat(2.0))
+            .is_empty());
+    }
+
+    #[test]
+    fn hash_lists_a_wide_collider_once() {
+        let mut hash = SpatialHash::default();
+        let entity = Entity::from_raw_u32(1).unwrap();
+        hash.insert(entity, Vec2::ZERO, Vec2::new(200.0, 20.0));
+        assert_eq!(
+            hash.colliders_near(Vec2::ZERO, Vec2::splat(100.0)),
+            vec![entity]
+        );
+    }
+
+    #[test]
+    fn hash_query_after_wrapping_finds_the_other_side() {
+        let bounds = ArenaBounds::default();
+        let mut hash = SpatialHash::default();
+        let (left, right) = (
+            Entity::from_raw_u32(1).unwrap(),
+            Entity::from_raw_u32(2).unwrap(),
+        );
+        hash.insert(left, Vec2::new(bounds.min.x + 10.0, 0.0), Vec2::splat(20.0));
+        hash.insert(
+            right,
+            Vec2::new(bounds.max.x - 10.0, 0.0),
+            Vec2::splat(20.0),
+        );
+
+        let mut translation = Vec3::new(bounds.max.x + 5.0, 0.0, 0.0);
+        bounds.wrap(&mut translation);
+        assert_eq!(translation.x, bounds.min.x);
+        let nearby = hash.colliders_near(translation.truncate(), Vec2::splat(20.0));
+        assert!(nearby.contains(&left));
+        assert!(!nearby.contains(&right));
+    }
+}
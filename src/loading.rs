@@ -0,0 +1,254 @@
+//! The loading screen. The game starts in the `Loading` state, which loads every picture, font,
+//! sound and piece of music up front with a progress bar, so the menu doesn't come up with
+//! untextured sprites and the first sound played doesn't stutter. The handles are kept for as long
+//! as the game runs, so whatever loads the same file later gets it right away.
+//!
+//! The game still runs with files missing from the assets folder. A missing picture is replaced
+//! by a placeholder and a missing font by one built into the game, while a missing sound just
+//! stays quiet. The title menu lists whatever is missing.
+
+use bevy::{
+    asset::{LoadState, RenderAssetUsages},
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    text::Font,
+};
+
+use crate::{
+    change_state,
+    ui::{centered_screen_node, spawn_title_text, TEXT_COLOR},
+    GameState,
+};
+
+// Every file the game loads by its path, rather than through a level or a settings file
+const PRELOADED: &[&str] = &[
+    "mario.png",
+    "mario_sheet.png",
+    "backgrounds/skyline.png",
+    "backgrounds/bricks.png",
+    "backgrounds/pipes.png",
+    "fonts/FiraSans-Bold.ttf",
+    "fonts/FiraMono-Medium.ttf",
+    "sounds/jump.ogg",
+    "sounds/land.ogg",
+    "sounds/die.ogg",
+    "sounds/bump.ogg",
+    "sounds/flip.ogg",
+    "sounds/kick.ogg",
+    "sounds/coin.ogg",
+    "sounds/hammer.ogg",
+    "sounds/boss_hit.ogg",
+    "sounds/boss_defeated.ogg",
+    "sounds/hurry_up.ogg",
+    "sounds/last_enemy.ogg",
+    "sounds/extra_life.ogg",
+    "sounds/splash.ogg",
+    "sounds/phase_clear.ogg",
+    "music/menu.ogg",
+    "music/gameplay.ogg",
+    "music/hurry_up.ogg",
+    "music/star.ogg",
+    "music/boss.ogg",
+    "music/game_over.ogg",
+];
+const PROGRESS_BAR_WIDTH: Val = Val::Px(400.0);
+const PROGRESS_BAR_HEIGHT: Val = Val::Px(16.0);
+const PROGRESS_BAR_BACKGROUND: Color = Color::srgb(0.2, 0.2, 0.2);
+const MISSING_FONT_SIZE: f32 = 16.0;
+const MISSING_TEXT_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
+// Stands in for any missing font
+const FALLBACK_FONT: &[u8] = include_bytes!("../fallback/DejaVuSans-Bold.ttf");
+// Stands in for any missing picture: a checkerboard of these two colors, loud enough to notice
+const PLACEHOLDER_COLORS: [[u8; 4]; 2] = [[255, 0, 255, 255], [40, 0, 40, 255]];
+const PLACEHOLDER_SQUARES: u32 = 8;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Loading),
+            (start_loading, spawn_loading_screen),
+        )
+        .add_systems(
+            Update,
+            (
+                track_loading,
+                replace_missing_files.after(track_loading),
+                leave_loading_screen.after(track_loading),
+            )
+                .run_if(in_state(GameState::Loading)),
+        )
+        .add_systems(OnEnter(GameState::Menu), spawn_missing_files_warning);
+    }
+}
+
+// The handles of `PRELOADED`, in the same order
+#[derive(Resource)]
+struct PreloadedAssets(Vec<UntypedHandle>);
+
+// How far the loading has got. `done` is set once every file has either loaded or failed.
+#[derive(Resource, Default)]
+struct LoadingProgress {
+    loaded: usize,
+    missing: Vec<&'static str>,
+    done: bool,
+}
+
+#[derive(Component)]
+struct ProgressBarFill;
+
+fn start_loading(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // Loaded as what the game loads them as later, so that gets the same handles
+    let handles = PRELOADED
+        .iter()
+        .map(|&path| {
+            if path.ends_with(".png") {
+                asset_server.load::<Image>(path).untyped()
+            } else if path.ends_with(".ttf") {
+                asset_server.load::<Font>(path).untyped()
+            } else {
+                asset_server.load::<AudioSource>(path).untyped()
+            }
+        })
+        .collect();
+    commands.insert_resource(PreloadedAssets(handles));
+    commands.insert_resource(LoadingProgress::default());
+}
+
+fn spawn_loading_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::Loading)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "LOADING");
+            parent.spawn((
+                Node {
+                    width: PROGRESS_BAR_WIDTH,
+                    height: PROGRESS_BAR_HEIGHT,
+                    ..default()
+                },
+                BackgroundColor(PROGRESS_BAR_BACKGROUND),
+                children![(
+                    Node {
+                        width: Val::Percent(0.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    BackgroundColor(TEXT_COLOR),
+                    ProgressBarFill,
+                )],
+            ));
+        });
+}
+
+fn track_loading(
+    asset_server: Res<AssetServer>,
+    preloaded: Res<PreloadedAssets>,
+    mut progress: ResMut<LoadingProgress>,
+    mut fill_query: Query<&mut Node, With<ProgressBarFill>>,
+) {
+    if progress.done {
+        return;
+    }
+    let mut loaded = 0;
+    let mut missing = Vec::new();
+    for (path, handle) in PRELOADED.iter().zip(&preloaded.0) {
+        match asset_server.get_load_state(handle) {
+            Some(LoadState::Loaded) => loaded += 1,
+            Some(LoadState::Failed(_)) => missing.push(*path),
+            _ => {}
+        }
+    }
+    progress.loaded = loaded;
+    progress.done = loaded + missing.len() == PRELOADED.len();
+    progress.missing = missing;
+
+    // Failed files count towards the bar, they won't be getting any further
+    let finished = (progress.loaded + progress.missing.len()) as f32 / PRELOADED.len() as f32;
+    for mut node in &mut fill_query {
+        node.width = Val::Percent(finished * 100.0);
+    }
+}
+
+// Every missing picture and font gets its stand-in under the handle the file would have had, so
+// whatever loads it by its path gets the stand-in instead. The loading screen's own text comes up
+// as soon as its font is known to be missing.
+fn replace_missing_files(
+    progress: Res<LoadingProgress>,
+    preloaded: Res<PreloadedAssets>,
+    mut images: ResMut<Assets<Image>>,
+    mut fonts: ResMut<Assets<Font>>,
+) {
+    for (path, handle) in PRELOADED.iter().zip(&preloaded.0) {
+        if !progress.missing.contains(path) {
+            continue;
+        }
+        let id = handle.id();
+        if path.ends_with(".png") && images.get(id.typed::<Image>()).is_none() {
+            images
+                .insert(id.typed::<Image>(), placeholder_image())
+                .expect("the handle is kept");
+        } else if path.ends_with(".ttf") && fonts.get(id.typed::<Font>()).is_none() {
+            fonts
+                .insert(id.typed::<Font>(), Font::from_bytes(FALLBACK_FONT.to_vec()))
+                .expect("the handle is kept");
+        }
+    }
+}
+
+fn placeholder_image() -> Image {
+    let size = PLACEHOLDER_SQUARES;
+    let data = (0..size * size)
+        .flat_map(|pixel| PLACEHOLDER_COLORS[((pixel % size + pixel / size) % 2) as usize])
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+// Down the left of the title menu, for as long as anything is missing
+fn spawn_missing_files_warning(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    progress: Res<LoadingProgress>,
+) {
+    if progress.missing.is_empty() {
+        return;
+    }
+    let text = std::iter::once("Missing from the assets folder:".to_string())
+        .chain(progress.missing.iter().map(|path| format!("  {path}")))
+        .collect::<Vec<_>>()
+        .join("\n");
+    commands.spawn((
+        Text::new(text),
+        TextFont::from_font_size(MISSING_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+        TextColor(MISSING_TEXT_COLOR),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(MISSING_FONT_SIZE),
+            bottom: Val::Px(MISSING_FONT_SIZE),
+            ..default()
+        },
+        DespawnOnExit(GameState::Menu),
+    ));
+}
+
+fn leave_loading_screen(
+    progress: Res<LoadingProgress>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if progress.done {
+        change_state(&mut next_state, GameState::Menu);
+    }
+}
@@ -0,0 +1,549 @@
+//! The lobby of online games, opened from the title menu. One player hosts a new room on a
+//! matchbox signalling server and tells the other its code, which they type in to join it. Either
+//! of them can run the server: `matchbox_server` on the host's machine is reached from the local
+//! network by its IP address, like 192.168.1.20:3536.
+//!
+//! The machines in a room keep telling each other who they play and whether they are ready, and
+//! time how long that takes to be answered. Once everyone is ready as a different player, the host
+//! starts the game, and the other machine starts along with it. Anyone else in the room can join
+//! as a spectator, and watches the game from the host.
+
+use bevy::{input::keyboard::KeyboardInput, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_state,
+    generator::{self, Rng},
+    online::{NetSession, Peer, Role},
+    ui::{
+        centered_screen_node, spawn_hint_text, spawn_menu_entry, spawn_title_text, MenuControls,
+        MenuInput, HINT_FONT_SIZE, SELECTED_TEXT_COLOR, TEXT_COLOR,
+    },
+    GameMode, GameState, MAX_PLAYERS, PLAYER_NAMES,
+};
+
+// Where `matchbox_server` listens when it is run on this machine
+const DEFAULT_SERVER: &str = "localhost:3536";
+const MAX_SERVER_LENGTH: usize = 64;
+const ROOM_CODE_LENGTH: usize = 4;
+// Leaves out the letters that are easily mistaken for digits
+const ROOM_CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+// How often every machine tells the others where it is at
+const STATUS_SECONDS: f64 = 0.5;
+// A machine that hasn't been heard from in this long has left the room
+const PEER_TIMEOUT_SECONDS: f64 = 5.0;
+
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LobbyForm>()
+            .add_systems(OnEnter(GameState::Lobby), spawn_lobby_screen)
+            .add_systems(
+                Update,
+                (
+                    receive_lobby_messages,
+                    send_lobby_status.after(receive_lobby_messages),
+                    navigate_lobby.after(receive_lobby_messages),
+                    type_in_lobby.after(navigate_lobby),
+                    start_online_game.after(navigate_lobby),
+                    update_lobby_text.after(type_in_lobby),
+                )
+                    .run_if(in_state(GameState::Lobby)),
+            );
+    }
+}
+
+// Who is in the room, and what is holding up the game
+#[derive(Component)]
+struct LobbyInfoText;
+
+// Entries of the lobby, in the order they are listed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum LobbyEntry {
+    Server,
+    Room,
+    Host,
+    Join,
+    Character,
+    Ready,
+    Leave,
+}
+
+impl LobbyEntry {
+    const ALL: [LobbyEntry; 7] = [
+        LobbyEntry::Server,
+        LobbyEntry::Room,
+        LobbyEntry::Host,
+        LobbyEntry::Join,
+        LobbyEntry::Character,
+        LobbyEntry::Ready,
+        LobbyEntry::Leave,
+    ];
+
+    fn label(&self, form: &LobbyForm, net: Option<&NetSession>) -> String {
+        match self {
+            LobbyEntry::Server => format!("Server: {}", form.server),
+            LobbyEntry::Room => format!("Room: {}", form.room),
+            LobbyEntry::Host => "Host a new room".to_string(),
+            LobbyEntry::Join => "Join the room".to_string(),
+            LobbyEntry::Character => format!("Join as: < {} >", form.role.label()),
+            LobbyEntry::Ready if net.is_some_and(|net| net.host) => "Start the game".to_string(),
+            LobbyEntry::Ready if form.ready => "Ready: yes".to_string(),
+            LobbyEntry::Ready => "Ready: no".to_string(),
+            LobbyEntry::Leave => "Leave".to_string(),
+        }
+    }
+
+    // Typed into, rather than picked
+    fn is_text_field(&self) -> bool {
+        matches!(self, LobbyEntry::Server | LobbyEntry::Room)
+    }
+}
+
+// What the lobby is set to, kept from one visit to the next apart from being ready
+#[derive(Resource)]
+struct LobbyForm {
+    // Index into `LobbyEntry::ALL` of the highlighted entry
+    selected: usize,
+    server: String,
+    room: String,
+    role: Role,
+    ready: bool,
+    // When this machine last told the others where it is at, on its own clock
+    last_status: f64,
+    // Set once the game is to start, by the host or by word from it
+    starting: bool,
+    // Why the last thing picked didn't work
+    message: String,
+}
+
+impl Default for LobbyForm {
+    fn default() -> Self {
+        LobbyForm {
+            selected: 0,
+            server: DEFAULT_SERVER.to_string(),
+            room: String::new(),
+            role: Role::Player(0),
+            ready: false,
+            last_status: 0.0,
+            starting: false,
+            message: String::new(),
+        }
+    }
+}
+
+// What the machines in a room tell each other
+#[derive(Serialize, Deserialize)]
+enum LobbyMessage {
+    // Sent every `STATUS_SECONDS`, with when it was sent on the sender's clock
+    Status {
+        role: Role,
+        ready: bool,
+        host: bool,
+        sent_at: f64,
+    },
+    // Sent straight back for every status, for its sender to time the round trip
+    Reply {
+        sent_at: f64,
+    },
+    // From the host, once everyone is ready
+    Start,
+}
+
+fn spawn_lobby_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut form: ResMut<LobbyForm>,
+) {
+    // Not on a text field, which would get whatever was typed to open the lobby
+    form.selected = LobbyEntry::ALL
+        .iter()
+        .position(|entry| *entry == LobbyEntry::Host)
+        .unwrap();
+    form.ready = false;
+    form.starting = false;
+    form.message.clear();
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::Lobby)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "ONLINE");
+            // Filled in by `update_lobby_text`
+            for entry in LobbyEntry::ALL {
+                spawn_menu_entry(parent, &asset_server, "", entry);
+            }
+            parent.spawn((
+                Text::default(),
+                TextFont::from_font_size(HINT_FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+                TextColor(TEXT_COLOR),
+                TextLayout::justify(Justify::Center),
+                Node {
+                    margin: UiRect::vertical(Val::Px(HINT_FONT_SIZE)),
+                    ..default()
+                },
+                LobbyInfoText,
+            ));
+            spawn_hint_text(
+                parent,
+                &asset_server,
+                "Type in the server or the room   Left/Right: player   Esc: leave",
+            );
+        });
+}
+
+// Keeps track of who is in the room, answers their statuses and times the answers to ours
+fn receive_lobby_messages(
+    net: Option<ResMut<NetSession>>,
+    mut form: ResMut<LobbyForm>,
+    time: Res<Time>,
+) {
+    let Some(mut net) = net else {
+        return;
+    };
+    let net = &mut *net;
+    let now = time.elapsed_secs_f64();
+    for id in net.socket.accept_new_connections() {
+        net.peers.push(Peer {
+            id,
+            role: None,
+            ready: false,
+            host: false,
+            ping: None,
+            last_heard: now,
+        });
+    }
+
+    for (id, message) in net.socket.receive_lobby() {
+        let Some(peer) = net.peers.iter_mut().find(|peer| peer.id == id) else {
+            continue;
+        };
+        peer.last_heard = now;
+        match message {
+            LobbyMessage::Status {
+                role,
+                ready,
+                host,
+                sent_at,
+            } => {
+                peer.role = Some(role);
+                peer.ready = ready;
+                peer.host = host;
+                net.socket.send_lobby(&LobbyMessage::Reply { sent_at }, id);
+            }
+            LobbyMessage::Reply { sent_at } => peer.ping = Some(now - sent_at),
+            LobbyMessage::Start if peer.host => form.starting = true,
+            LobbyMessage::Start => {}
+        }
+    }
+
+    net.peers.retain(|peer| {
+        let gone = now - peer.last_heard > PEER_TIMEOUT_SECONDS;
+        if gone {
+            info!("{} left the online room", peer.id);
+        }
+        !gone
+    });
+    // The host's word to start can get lost on the way, but its game talking to this machine
+    // says as much
+    if !net.host
+        && net
+            .peers
+            .iter()
+            .any(|peer| peer.host && net.socket.session_started_by(peer.id))
+    {
+        form.starting = true;
+    }
+}
+
+fn send_lobby_status(net: Option<Res<NetSession>>, mut form: ResMut<LobbyForm>, time: Res<Time>) {
+    let Some(net) = net else {
+        return;
+    };
+    let now = time.elapsed_secs_f64();
+    if now - form.last_status < STATUS_SECONDS {
+        return;
+    }
+    form.last_status = now;
+
+    let status = LobbyMessage::Status {
+        role: form.role,
+        ready: form.ready,
+        host: net.host,
+        sent_at: now,
+    };
+    for peer in &net.peers {
+        net.socket.send_lobby(&status, peer.id);
+    }
+}
+
+fn navigate_lobby(
+    mut controls: MenuControls,
+    mut form: ResMut<LobbyForm>,
+    net: Option<Res<NetSession>>,
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    time: Res<Time>,
+) {
+    let entries = LobbyEntry::ALL.len();
+    if controls.pressed(MenuInput::Up) {
+        form.selected = (form.selected + entries - 1) % entries;
+    }
+    if controls.pressed(MenuInput::Down) {
+        form.selected = (form.selected + 1) % entries;
+    }
+
+    let entry = LobbyEntry::ALL[form.selected];
+    let step = if controls.pressed(MenuInput::Left) {
+        Role::COUNT - 1
+    } else if controls.pressed(MenuInput::Right)
+        || entry == LobbyEntry::Character && controls.pressed(MenuInput::Confirm)
+    {
+        1
+    } else {
+        0
+    };
+    if entry == LobbyEntry::Character && step != 0 {
+        form.role = Role::from_index((form.role.index() + step) % Role::COUNT);
+        // Ready as someone else is not ready as this one
+        form.ready = false;
+    }
+
+    let leave = controls.pressed(MenuInput::Back)
+        || entry == LobbyEntry::Leave && controls.pressed(MenuInput::Confirm);
+    if leave {
+        commands.remove_resource::<NetSession>();
+        form.starting = false;
+        // Escape would quit from the title menu too
+        controls.reset(MenuInput::Back);
+        controls.reset(MenuInput::Confirm);
+        change_state(&mut next_state, GameState::Menu);
+        return;
+    }
+    if !controls.pressed(MenuInput::Confirm) {
+        return;
+    }
+    form.message.clear();
+
+    match entry {
+        LobbyEntry::Host => {
+            form.room = new_room_code(&time);
+            form.ready = false;
+            let room_url = room_url(&form.server, &form.room);
+            commands.insert_resource(NetSession::join(room_url, true));
+        }
+        LobbyEntry::Join if form.room.is_empty() => {
+            form.message = "Type in the code of the room to join first".to_string();
+        }
+        LobbyEntry::Join => {
+            form.ready = false;
+            let room_url = room_url(&form.server, &form.room);
+            commands.insert_resource(NetSession::join(room_url, false));
+        }
+        LobbyEntry::Ready => match net.as_deref() {
+            None => form.message = "Host or join a room first".to_string(),
+            Some(net) if net.host => match check_ready(&form, net) {
+                Ok(()) => {
+                    for peer in &net.peers {
+                        net.socket.send_lobby(&LobbyMessage::Start, peer.id);
+                    }
+                    form.starting = true;
+                }
+                Err(message) => form.message = message,
+            },
+            Some(_) => form.ready = !form.ready,
+        },
+        LobbyEntry::Server | LobbyEntry::Room | LobbyEntry::Character | LobbyEntry::Leave => {}
+    }
+}
+
+// Letters and digits for the room code, anything printable for the server
+fn type_in_lobby(
+    mut key_events: MessageReader<KeyboardInput>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut form: ResMut<LobbyForm>,
+) {
+    let typed: Vec<char> = key_events
+        .read()
+        .filter(|event| event.state.is_pressed())
+        .filter_map(|event| event.text.as_ref())
+        .flat_map(|text| text.chars())
+        .collect();
+    let erase = keyboard_input.just_pressed(KeyCode::Backspace);
+    if typed.is_empty() && !erase {
+        return;
+    }
+
+    let form = &mut *form;
+    let (field, max_length, room) = match LobbyEntry::ALL[form.selected] {
+        LobbyEntry::Server => (&mut form.server, MAX_SERVER_LENGTH, false),
+        LobbyEntry::Room => (&mut form.room, ROOM_CODE_LENGTH, true),
+        _ => return,
+    };
+    if erase {
+        field.pop();
+    }
+    for typed in typed {
+        let allowed = if room {
+            typed.is_ascii_alphanumeric()
+        } else {
+            typed.is_ascii_graphic()
+        };
+        if allowed && field.len() < max_length {
+            field.push(if room {
+                typed.to_ascii_uppercase()
+            } else {
+                typed
+            });
+        }
+    }
+}
+
+// The game needs every player, each played by a different machine, and all of them ready.
+// Spectators don't have to be ready, they watch whatever is on.
+fn check_ready(form: &LobbyForm, net: &NetSession) -> Result<(), String> {
+    let Role::Player(player) = form.role else {
+        return Err("The host plays, rather than watches".to_string());
+    };
+    let mut taken = [false; MAX_PLAYERS];
+    taken[player] = true;
+    for peer in &net.peers {
+        match peer.role {
+            None => return Err("Waiting to hear from everyone in the room".to_string()),
+            Some(Role::Spectator) => {}
+            Some(Role::Player(player)) if taken[player] => {
+                return Err(format!("Two players picked {}", PLAYER_NAMES[player]));
+            }
+            Some(Role::Player(player)) if !peer.ready => {
+                return Err(format!("{} isn't ready yet", PLAYER_NAMES[player]));
+            }
+            Some(Role::Player(player)) => taken[player] = true,
+        }
+    }
+    if taken.contains(&false) {
+        return Err("Waiting for another player to join".to_string());
+    }
+    Ok(())
+}
+
+fn start_online_game(
+    mut commands: Commands,
+    mut form: ResMut<LobbyForm>,
+    net: Option<Res<NetSession>>,
+    mut game_mode: ResMut<GameMode>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !form.starting {
+        return;
+    }
+    form.starting = false;
+    let Some(net) = net else {
+        return;
+    };
+
+    match net.start(form.role) {
+        Ok(session) => {
+            info!("Starting the online game as {}", form.role.label());
+            commands.insert_resource(session);
+            *game_mode = GameMode::Coop;
+            change_state(&mut next_state, GameState::Playing);
+        }
+        Err(err) => form.message = format!("Could not start the game: {err}"),
+    }
+}
+
+fn update_lobby_text(
+    form: Res<LobbyForm>,
+    net: Option<Res<NetSession>>,
+    mut entry_query: Query<(&LobbyEntry, &mut Text, &mut TextColor)>,
+    mut info_query: Query<&mut Text, (With<LobbyInfoText>, Without<LobbyEntry>)>,
+) {
+    // Pings change all the time, so only what actually changed is set, to keep the layout
+    for (entry, mut text, mut text_color) in &mut entry_query {
+        let selected = *entry == LobbyEntry::ALL[form.selected];
+        let mut label = entry.label(&form, net.as_deref());
+        if selected && entry.is_text_field() {
+            label.push('_');
+        }
+        let color = if selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+        if text.0 != label {
+            text.0 = label;
+        }
+        if text_color.0 != color {
+            text_color.0 = color;
+        }
+    }
+
+    let info = lobby_info(&form, net.as_deref());
+    for mut text in &mut info_query {
+        if text.0 != info {
+            text.0 = info.clone();
+        }
+    }
+}
+
+// A line for every player in the room, then whatever went wrong
+fn lobby_info(form: &LobbyForm, net: Option<&NetSession>) -> String {
+    let mut lines = Vec::new();
+    match net {
+        None => lines.push("Not in a room".to_string()),
+        Some(net) => {
+            lines.push(format!("In {}", net.room_url));
+            let you = status(net.host, form.role, form.ready);
+            lines.push(format!("{}: you, {you}", form.role.label()));
+            for peer in &net.peers {
+                let name = peer.role.map_or("?", Role::label);
+                let status = peer
+                    .role
+                    .map_or("", |role| status(peer.host, role, peer.ready));
+                let ping = peer.ping.map_or_else(
+                    || "no ping yet".to_string(),
+                    |ping| format!("{:.0} ms", ping * 1000.0),
+                );
+                lines.push(format!("{name}: someone else, {status}, {ping}"));
+            }
+            if net.peers.is_empty() {
+                lines.push("Waiting for another player to join".to_string());
+            }
+        }
+    }
+    if !form.message.is_empty() {
+        lines.push(form.message.clone());
+    }
+    lines.join("\n")
+}
+
+fn status(host: bool, role: Role, ready: bool) -> &'static str {
+    if host {
+        "host"
+    } else if role == Role::Spectator {
+        "watching"
+    } else if ready {
+        "ready"
+    } else {
+        "not ready"
+    }
+}
+
+fn new_room_code(time: &Time) -> String {
+    let mut rng = Rng::new(generator::time_seed() ^ time.elapsed().as_nanos() as u64);
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| char::from(ROOM_CODE_LETTERS[rng.range(0, ROOM_CODE_LETTERS.len() - 1)]))
+        .collect()
+}
+
+// The server can be typed in with or without its ws:// or wss://
+fn room_url(server: &str, room: &str) -> String {
+    if server.contains("://") {
+        format!("{server}/{room}")
+    } else {
+        format!("ws://{server}/{room}")
+    }
+}
@@ -0,0 +1,539 @@
+//! Mutators: twists on the rules like low gravity, icy floors or the lights going out. They are
+//! turned on for a game from the custom game screen, or handed out by the daily challenge.
+//!
+//! The mutators are listed in assets/modes/standard.mutators.ron, each with the modifiers it
+//! sets. The mutators of a game are combined into one `Modifiers` when it starts, and gameplay
+//! only ever reads those, never which mutators are on.
+//!
+//! The custom game screen also sets the `CoopRules`, which change how a co-op game is played
+//! rather than the arena.
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_state,
+    console::AddConsoleCommand,
+    controls::Action,
+    enemy::{Enemy, Freezie},
+    generator::Rng,
+    level::{Ice, Platform, Velocity, GRAVITY_ACCEL, ICE_COLOR},
+    ui::{
+        centered_screen_node, spawn_hint_text, spawn_menu_entry, spawn_title_text, MenuControls,
+        MenuInput, SELECTED_TEXT_COLOR, TEXT_COLOR,
+    },
+    GameMode, GameState, StepSet,
+};
+
+const MUTATORS_FILE: &str = "modes/standard.mutators.ron";
+const MUTATORS_EXTENSIONS: &[&str] = &["mutators.ron"];
+// The daily challenge turns on up to this many
+const MAX_DAILY_MUTATORS: usize = 2;
+// Over the characters and the lava, under the particles
+const DARKNESS_Z: f32 = 2.5;
+// Covers the arena however the camera moves
+const DARKNESS_SIZE: f32 = 10_000.0;
+// The modes a custom game can be played in. Time attack keeps best times and ghosts, which
+// wouldn't be fair to race with mutators on.
+const CUSTOM_MODES: [(GameMode, &str); 4] = [
+    (GameMode::SinglePlayer, "1 player"),
+    (GameMode::Coop, "Co-op"),
+    (GameMode::Versus, "Versus"),
+    (GameMode::Survival, "Survival"),
+];
+
+pub struct MutatorsPlugin;
+
+impl Plugin for MutatorsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MutatorList>()
+            .init_asset_loader::<MutatorLoader>()
+            .init_resource::<Mutators>()
+            .init_resource::<Modifiers>()
+            .init_resource::<CoopRules>()
+            .init_resource::<CustomGameForm>()
+            .add_systems(Startup, load_mutators)
+            .add_systems(OnEnter(GameState::Menu), forget_mutators)
+            .add_systems(OnEnter(GameState::Playing), (apply_mutators, spawn_darkness.after(apply_mutators)))
+            .add_systems(OnEnter(GameState::CustomGame), spawn_custom_game_screen)
+            .add_systems(Update, (navigate_custom_game, update_custom_game_text.after(navigate_custom_game)).run_if(in_state(GameState::CustomGame)))
+            .add_systems(FixedUpdate, (freeze_platforms, speed_up_enemies).in_set(StepSet::Gameplay))
+            .add_console_command(
+                "set",
+                "set <gravity|enemy_speed|icy_platforms|inverted_controls> <value> (gravity is normally 3000)",
+                set_command,
+            );
+    }
+}
+
+// What the mutators of a game change, all of them combined
+#[derive(Resource, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Modifiers {
+    // A multiple of the usual gravity
+    pub gravity: f32,
+    // A multiple of how fast enemies and Freezies usually go
+    pub enemy_speed: f32,
+    pub icy_platforms: bool,
+    // Left moves right and right moves left
+    pub inverted_controls: bool,
+    // How much of the arena is hidden in the dark, from 0 to 1
+    pub darkness: f32,
+}
+
+impl Default for Modifiers {
+    fn default() -> Self {
+        Modifiers {
+            gravity: 1.0,
+            enemy_speed: 1.0,
+            icy_platforms: false,
+            inverted_controls: false,
+            darkness: 0.0,
+        }
+    }
+}
+
+impl Modifiers {
+    fn combine(self, other: &Modifiers) -> Modifiers {
+        Modifiers {
+            gravity: self.gravity * other.gravity,
+            enemy_speed: self.enemy_speed * other.enemy_speed,
+            icy_platforms: self.icy_platforms || other.icy_platforms,
+            inverted_controls: self.inverted_controls || other.inverted_controls,
+            darkness: self.darkness.max(other.darkness),
+        }
+    }
+
+    // What a held action does in this game
+    pub fn remap(&self, action: Action) -> Action {
+        match action {
+            Action::MoveLeft if self.inverted_controls => Action::MoveRight,
+            Action::MoveRight if self.inverted_controls => Action::MoveLeft,
+            action => action,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MutatorDef {
+    // Kept in replays, so it shouldn't change once a mutator is out
+    name: String,
+    label: String,
+    modifiers: Modifiers,
+}
+
+// Every mutator there is, in the order the custom game screen lists them
+#[derive(Asset, TypePath, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MutatorList(Vec<MutatorDef>);
+
+impl MutatorList {
+    fn get(&self, name: &str) -> Option<&MutatorDef> {
+        self.0.iter().find(|def| def.name == name)
+    }
+
+    // The labels of these mutators, like "Low gravity, Icy floors"
+    pub fn labels(&self, names: &[String]) -> String {
+        names
+            .iter()
+            .filter_map(|name| self.get(name))
+            .map(|def| def.label.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // A few mutators picked by a seed, always the same ones for the same seed
+    pub fn pick(&self, seed: u64) -> Vec<String> {
+        let mut rng = Rng::new(seed);
+        let mut left: Vec<usize> = (0..self.0.len()).collect();
+        let count = rng.range(0, MAX_DAILY_MUTATORS.min(left.len()));
+        let mut picked: Vec<usize> = (0..count)
+            .map(|_| left.remove(rng.range(0, left.len() - 1)))
+            .collect();
+        // In the order of the list, like the ones turned on by hand
+        picked.sort_unstable();
+        picked
+            .into_iter()
+            .map(|index| self.0[index].name.clone())
+            .collect()
+    }
+}
+
+#[derive(Default, TypePath)]
+struct MutatorLoader;
+
+impl AssetLoader for MutatorLoader {
+    type Asset = MutatorList;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<MutatorList, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        MUTATORS_EXTENSIONS
+    }
+}
+
+#[derive(Resource)]
+pub struct MutatorDefs(pub Handle<MutatorList>);
+
+// The names of the mutators of the next game, set before it starts
+#[derive(Resource, Default)]
+pub struct Mutators(pub Vec<String>);
+
+// How the players of a co-op game get along, set before it starts. Other games play by the
+// defaults. Kept in replays along with the mutators.
+#[derive(Resource, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CoopRules {
+    // Each player has lives of their own instead of drawing on the same ones. One who runs out
+    // waits until the other clears the phase or earns a 1-UP.
+    pub separate_lives: bool,
+    // The players push each other aside and can stand on each other's head, instead of going
+    // through each other
+    pub players_collide: bool,
+    // Bumping the platform under the other player knocks them off balance, like in versus mode
+    pub bump_partner: bool,
+    // A kicked enemy flies off the stage, knocking the other player off balance on the way
+    pub kicks_hit_partner: bool,
+}
+
+// The co-op rules as the custom game screen lists them
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CoopRule {
+    SeparateLives,
+    PlayersCollide,
+    BumpPartner,
+    KicksHitPartner,
+}
+
+impl CoopRule {
+    const ALL: [CoopRule; 4] = [
+        CoopRule::SeparateLives,
+        CoopRule::PlayersCollide,
+        CoopRule::BumpPartner,
+        CoopRule::KicksHitPartner,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            CoopRule::SeparateLives => "Separate lives (co-op)",
+            CoopRule::PlayersCollide => "Players collide (co-op)",
+            CoopRule::BumpPartner => "Bumps hit partner (co-op)",
+            CoopRule::KicksHitPartner => "Kicks hit partner (co-op)",
+        }
+    }
+
+    fn is_on(self, rules: &CoopRules) -> bool {
+        match self {
+            CoopRule::SeparateLives => rules.separate_lives,
+            CoopRule::PlayersCollide => rules.players_collide,
+            CoopRule::BumpPartner => rules.bump_partner,
+            CoopRule::KicksHitPartner => rules.kicks_hit_partner,
+        }
+    }
+
+    fn toggle(self, rules: &mut CoopRules) {
+        let flag = match self {
+            CoopRule::SeparateLives => &mut rules.separate_lives,
+            CoopRule::PlayersCollide => &mut rules.players_collide,
+            CoopRule::BumpPartner => &mut rules.bump_partner,
+            CoopRule::KicksHitPartner => &mut rules.kicks_hit_partner,
+        };
+        *flag = !*flag;
+    }
+}
+
+fn load_mutators(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MutatorDefs(asset_server.load(MUTATORS_FILE)));
+}
+
+// Games started from the title menu have none and play by the usual rules, unless they are
+// picked again
+fn forget_mutators(mut mutators: ResMut<Mutators>, mut coop_rules: ResMut<CoopRules>) {
+    mutators.0.clear();
+    *coop_rules = CoopRules::default();
+}
+
+fn apply_mutators(
+    mutators: Res<Mutators>,
+    mut modifiers: ResMut<Modifiers>,
+    (defs, lists): (Res<MutatorDefs>, Res<Assets<MutatorList>>),
+) {
+    let list = lists.get(&defs.0);
+    *modifiers = mutators
+        .0
+        .iter()
+        .filter_map(|name| {
+            let def = list.and_then(|list| list.get(name));
+            if def.is_none() {
+                warn!("There is no mutator called {name}");
+            }
+            def
+        })
+        .fold(Modifiers::default(), |combined, def| {
+            combined.combine(&def.modifiers)
+        });
+}
+
+// The `set` console command changes one of the modifiers for the rest of the game. Like the
+// mutators, enemy speed is a multiple of the usual. Gravity is given in units per second per
+// second, like `GRAVITY_ACCEL`, and kept as a multiple of that. Enemies change speed as they come
+// out of the pipes, and platforms turn to ice as they are put up, at the next phase.
+fn set_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let [name, value] = args else {
+        return Err("Set what, to what?".to_string());
+    };
+    let number = || {
+        value
+            .parse::<f32>()
+            .ok()
+            .filter(|number| number.is_finite() && *number >= 0.0)
+            .ok_or_else(|| format!("\"{value}\" isn't a number"))
+    };
+    let on = || match *value {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(format!("\"{value}\" isn't on or off")),
+    };
+    let mut modifiers = world.resource_mut::<Modifiers>();
+    match *name {
+        "gravity" => modifiers.gravity = number()? / GRAVITY_ACCEL,
+        "enemy_speed" => modifiers.enemy_speed = number()?,
+        "icy_platforms" => modifiers.icy_platforms = on()?,
+        "inverted_controls" => modifiers.inverted_controls = on()?,
+        _ => return Err(format!("There is no \"{name}\" to set")),
+    }
+    Ok(format!("{name} is now {value}"))
+}
+
+fn spawn_darkness(mut commands: Commands, modifiers: Res<Modifiers>) {
+    if modifiers.darkness <= 0.0 {
+        return;
+    }
+    commands.spawn((
+        Sprite {
+            color: Color::srgba(0.0, 0.0, 0.0, modifiers.darkness.min(1.0)),
+            custom_size: Some(Vec2::splat(DARKNESS_SIZE)),
+            ..default()
+        },
+        Transform::from_xyz(0.0, 0.0, DARKNESS_Z),
+        DespawnOnExit(GameState::Playing),
+    ));
+}
+
+// Every platform of the arena turns to ice as it is put up
+fn freeze_platforms(
+    mut commands: Commands,
+    modifiers: Res<Modifiers>,
+    mut query: Query<(Entity, &mut Sprite), Added<Platform>>,
+) {
+    if !modifiers.icy_platforms {
+        return;
+    }
+    for (entity, mut sprite) in &mut query {
+        sprite.color = ICE_COLOR;
+        commands.entity(entity).insert(Ice);
+    }
+}
+
+// Enemies keep the speed they come out of the pipes with, turning and rage aside
+fn speed_up_enemies(
+    modifiers: Res<Modifiers>,
+    mut query: Query<&mut Velocity, Or<(Added<Enemy>, Added<Freezie>)>>,
+) {
+    for mut velocity in &mut query {
+        velocity.x *= modifiers.enemy_speed;
+    }
+}
+
+// Entries of the custom game screen, in the order they are listed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum CustomGameEntry {
+    Mode,
+    CoopRule(CoopRule),
+    // By index into the `MutatorList`
+    Mutator(usize),
+    Start,
+    Back,
+}
+
+impl CustomGameEntry {
+    fn all(list: Option<&MutatorList>) -> Vec<CustomGameEntry> {
+        let mutators = list.map_or(0, |list| list.0.len());
+        std::iter::once(CustomGameEntry::Mode)
+            .chain(CoopRule::ALL.map(CustomGameEntry::CoopRule))
+            .chain((0..mutators).map(CustomGameEntry::Mutator))
+            .chain([CustomGameEntry::Start, CustomGameEntry::Back])
+            .collect()
+    }
+
+    fn label(&self, form: &CustomGameForm, list: Option<&MutatorList>) -> String {
+        match self {
+            CustomGameEntry::Mode => format!("Mode: < {} >", CUSTOM_MODES[form.mode].1),
+            CustomGameEntry::CoopRule(rule) => {
+                let on = if rule.is_on(&form.coop_rules) {
+                    "On"
+                } else {
+                    "Off"
+                };
+                format!("{}: {on}", rule.label())
+            }
+            CustomGameEntry::Mutator(index) => {
+                let Some(def) = list.and_then(|list| list.0.get(*index)) else {
+                    return String::new();
+                };
+                let on = if form.chosen.contains(&def.name) {
+                    "On"
+                } else {
+                    "Off"
+                };
+                format!("{}: {on}", def.label)
+            }
+            CustomGameEntry::Start => "Start".to_string(),
+            CustomGameEntry::Back => "Back".to_string(),
+        }
+    }
+}
+
+// What the custom game screen is set to, kept from one visit to the next
+#[derive(Resource, Default)]
+struct CustomGameForm {
+    selected: usize,
+    // Index into `CUSTOM_MODES`
+    mode: usize,
+    // Names of the mutators turned on
+    chosen: Vec<String>,
+    coop_rules: CoopRules,
+}
+
+fn spawn_custom_game_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    (defs, lists): (Res<MutatorDefs>, Res<Assets<MutatorList>>),
+) {
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::CustomGame)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "CUSTOM GAME");
+            // Filled in by `update_custom_game_text`
+            for entry in CustomGameEntry::all(lists.get(&defs.0)) {
+                spawn_menu_entry(parent, &asset_server, "", entry);
+            }
+            spawn_hint_text(
+                parent,
+                &asset_server,
+                "Enter: turn on or off   Left/Right: mode   Esc: back",
+            );
+        });
+}
+
+fn navigate_custom_game(
+    mut controls: MenuControls,
+    mut form: ResMut<CustomGameForm>,
+    mut next_state: ResMut<NextState<GameState>>,
+    (mut mutators, mut coop_rules, mut game_mode): (
+        ResMut<Mutators>,
+        ResMut<CoopRules>,
+        ResMut<GameMode>,
+    ),
+    (defs, lists): (Res<MutatorDefs>, Res<Assets<MutatorList>>),
+) {
+    let list = lists.get(&defs.0);
+    let entries = CustomGameEntry::all(list);
+    if controls.pressed(MenuInput::Up) {
+        form.selected = (form.selected + entries.len() - 1) % entries.len();
+    }
+    if controls.pressed(MenuInput::Down) {
+        form.selected = (form.selected + 1) % entries.len();
+    }
+    // The list may have loaded in since the screen was opened
+    form.selected = form.selected.min(entries.len() - 1);
+
+    let entry = entries[form.selected];
+    if entry == CustomGameEntry::Mode {
+        if controls.pressed(MenuInput::Left) {
+            form.mode = (form.mode + CUSTOM_MODES.len() - 1) % CUSTOM_MODES.len();
+        }
+        if controls.pressed(MenuInput::Right) || controls.pressed(MenuInput::Confirm) {
+            form.mode = (form.mode + 1) % CUSTOM_MODES.len();
+        }
+    }
+
+    if controls.pressed(MenuInput::Back)
+        || entry == CustomGameEntry::Back && controls.pressed(MenuInput::Confirm)
+    {
+        // Escape would quit from the title menu too
+        controls.reset(MenuInput::Back);
+        controls.reset(MenuInput::Confirm);
+        change_state(&mut next_state, GameState::Menu);
+        return;
+    }
+    if !controls.pressed(MenuInput::Confirm) {
+        return;
+    }
+    match entry {
+        CustomGameEntry::Mutator(index) => {
+            let Some(name) = list.and_then(|list| list.0.get(index)).map(|def| &def.name) else {
+                return;
+            };
+            match form.chosen.iter().position(|chosen| chosen == name) {
+                Some(position) => {
+                    form.chosen.remove(position);
+                }
+                None => form.chosen.push(name.clone()),
+            }
+        }
+        CustomGameEntry::CoopRule(rule) => rule.toggle(&mut form.coop_rules),
+        CustomGameEntry::Start => {
+            controls.reset(MenuInput::Confirm);
+            // In the order of the list, however they were turned on
+            mutators.0 = list.map_or_else(Vec::new, |list| {
+                list.0
+                    .iter()
+                    .filter(|def| form.chosen.contains(&def.name))
+                    .map(|def| def.name.clone())
+                    .collect()
+            });
+            *game_mode = CUSTOM_MODES[form.mode].0;
+            *coop_rules = if *game_mode == GameMode::Coop {
+                form.coop_rules
+            } else {
+                CoopRules::default()
+            };
+            change_state(&mut next_state, GameState::CharacterSelect);
+        }
+        CustomGameEntry::Mode | CustomGameEntry::Back => {}
+    }
+}
+
+fn update_custom_game_text(
+    form: Res<CustomGameForm>,
+    (defs, lists): (Res<MutatorDefs>, Res<Assets<MutatorList>>),
+    mut query: Query<(&CustomGameEntry, &mut Text, &mut TextColor)>,
+) {
+    let list = lists.get(&defs.0);
+    let selected = CustomGameEntry::all(list)[form.selected];
+    for (entry, mut text, mut color) in &mut query {
+        text.0 = entry.label(&form, list);
+        color.0 = if *entry == selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    }
+}
@@ -0,0 +1,421 @@
+//! Co-op with a player on another machine. Both machines join the same room of a matchbox
+//! signalling server from the lobby (see lobby.rs), which starts the game on both of them at once.
+//!
+//! Both machines run the same gameplay step on the same inputs, so they play out the same game.
+//! Each one only knows its own player's input straight away, and guesses the other player still
+//! holds whatever they held last. When the real input comes in and the guess was wrong, the game
+//! is put back the way it was at the last step both machines agreed on, and the steps since are
+//! played again. Everything of a running game is tagged for that as it is spawned, and every module
+//! registers the components and resources the step changes, in its `register_rollback`.
+//!
+//! More machines can join the room to watch. The host passes them the inputs once both players'
+//! machines have agreed on them, so a spectator never has to guess, or play anything again.
+
+use std::sync::{Arc, Mutex};
+
+use bevy::{app::FixedMain, prelude::*, tasks::IoTaskPool};
+use bevy_ggrs::{
+    ggrs::{self, GgrsError, GgrsEvent, Message, NonBlockingSocket, PlayerType, SessionBuilder},
+    GgrsPlugin, GgrsSchedule, LocalInputs, LocalPlayers, PlayerInputs, ReadInputs, Rollback,
+    RollbackApp, RollbackFrameRate, Session,
+};
+use matchbox_socket::{PeerId, PeerState, WebRtcSocket};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    boss, change_state,
+    controls::{PlayerActions, PlayerInput, StepInputs},
+    director, enemy, hurry, level,
+    palette::{PaletteSprite, PaletteSwap},
+    player, powerup, status,
+    ui::TEXT_COLOR,
+    GameState, StepDriver, MAX_PLAYERS, PLAYER_NAMES, TIME_STEP,
+};
+
+// Steps this machine runs ahead on its own input, so the other machine's has time to arrive.
+// Fewer steps to play again when a guess is wrong, at the cost of a little lag on the controls.
+const INPUT_DELAY: usize = 2;
+const SPECTATING_FONT_SIZE: f32 = 40.0;
+
+pub struct OnlinePlugin;
+
+impl Plugin for OnlinePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(GgrsPlugin::<GgrsConfig>::default())
+            .insert_resource(RollbackFrameRate((1.0 / TIME_STEP).round() as usize))
+            .rollback_component_with_clone::<DespawnOnExit<GameState>>()
+            .rollback_component_with_reflect::<Transform>()
+            .rollback_component_with_copy::<GlobalTransform>()
+            .rollback_component_with_copy::<Visibility>()
+            .rollback_component_with_clone::<Sprite>()
+            .rollback_component_with_copy::<PaletteSprite>()
+            .rollback_component_with_clone::<PaletteSwap>();
+        level::register_rollback(app);
+        player::register_rollback(app);
+        enemy::register_rollback(app);
+        powerup::register_rollback(app);
+        status::register_rollback(app);
+        director::register_rollback(app);
+        boss::register_rollback(app);
+        hurry::register_rollback(app);
+
+        app.add_systems(ReadInputs, read_local_input)
+            .add_systems(GgrsSchedule, run_online_step)
+            .add_systems(PreUpdate, tag_rollback_entities)
+            .add_systems(Update, watch_session)
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (
+                    begin_online_game,
+                    spawn_spectating_text.after(begin_online_game),
+                ),
+            )
+            .add_systems(OnExit(GameState::Playing), end_online_game);
+    }
+}
+
+pub struct GgrsConfig;
+
+impl ggrs::Config for GgrsConfig {
+    // The bits of a `PlayerInput`
+    type Input = u8;
+    // What the other machine holds is guessed to be what it held last
+    type InputPredictor = ggrs::PredictRepeatLast;
+    // The game is saved by `bevy_ggrs` itself, rather than through ggrs
+    type State = u8;
+    // Matchbox's id of the other machine
+    type Address = PeerId;
+}
+
+// The first byte of every packet says which of the two it is for
+const LOBBY_PACKET: u8 = 0;
+const SESSION_PACKET: u8 = 1;
+
+// The connection to a room. The lobby talks over it until the game starts, and the session
+// after that, each of them only getting the packets meant for it.
+#[derive(Clone)]
+pub struct SharedSocket(Arc<Mutex<SocketState>>);
+
+struct SocketState {
+    socket: WebRtcSocket,
+    // The session's first packets can come in while this machine is still in the lobby. The
+    // lobby's are only of use to the lobby, and are dropped once the session has taken over.
+    session_packets: Vec<(PeerId, Box<[u8]>)>,
+}
+
+impl SharedSocket {
+    fn join(room_url: &str) -> SharedSocket {
+        let (socket, message_loop) = WebRtcSocket::new_reliable(room_url);
+        IoTaskPool::get().spawn(message_loop).detach();
+        SharedSocket(Arc::new(Mutex::new(SocketState {
+            socket,
+            session_packets: Vec::new(),
+        })))
+    }
+
+    // The machines that joined the room since the last time this was asked
+    pub fn accept_new_connections(&self) -> Vec<PeerId> {
+        let mut state = self.0.lock().unwrap();
+        let peers = state.socket.update_peers();
+        peers
+            .into_iter()
+            .filter(|(_, peer_state)| *peer_state == PeerState::Connected)
+            .map(|(peer, _)| peer)
+            .collect()
+    }
+
+    pub fn send_lobby<T: Serialize>(&self, message: &T, peer: PeerId) {
+        self.send(LOBBY_PACKET, message, peer);
+    }
+
+    pub fn receive_lobby<T: DeserializeOwned>(&self) -> Vec<(PeerId, T)> {
+        let mut state = self.0.lock().unwrap();
+        let mut messages = Vec::new();
+        for (peer, packet) in state.socket.channel_mut(0).receive() {
+            match packet.split_first() {
+                Some((&LOBBY_PACKET, message)) => {
+                    messages.extend(bincode::deserialize(message).ok().map(|it| (peer, it)));
+                }
+                Some((&SESSION_PACKET, _)) => state.session_packets.push((peer, packet)),
+                _ => {}
+            }
+        }
+        messages
+    }
+
+    // A session already sending to this machine means its game has started on the other one
+    pub fn session_started_by(&self, peer: PeerId) -> bool {
+        let state = self.0.lock().unwrap();
+        state.session_packets.iter().any(|(from, _)| *from == peer)
+    }
+
+    fn send<T: Serialize>(&self, kind: u8, message: &T, peer: PeerId) {
+        let mut packet = vec![kind];
+        if bincode::serialize_into(&mut packet, message).is_ok() {
+            self.0
+                .lock()
+                .unwrap()
+                .socket
+                .channel_mut(0)
+                .send(packet.into_boxed_slice(), peer);
+        }
+    }
+}
+
+impl NonBlockingSocket<PeerId> for SharedSocket {
+    fn send_to(&mut self, message: &Message, peer: &PeerId) {
+        self.send(SESSION_PACKET, message, *peer);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(PeerId, Message)> {
+        let mut state = self.0.lock().unwrap();
+        let received = state.socket.channel_mut(0).receive();
+        std::mem::take(&mut state.session_packets)
+            .into_iter()
+            .chain(received)
+            .filter_map(|(peer, packet)| match packet.split_first() {
+                Some((&SESSION_PACKET, message)) => {
+                    Some((peer, bincode::deserialize(message).ok()?))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// What a machine does in an online game
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    // Plays the player with this index into `PLAYER_NAMES`
+    Player(usize),
+    // Only watches the game of the host
+    Spectator,
+}
+
+impl Role {
+    // Every player, then watching
+    pub const COUNT: usize = MAX_PLAYERS + 1;
+
+    pub fn from_index(index: usize) -> Role {
+        if index < MAX_PLAYERS {
+            Role::Player(index)
+        } else {
+            Role::Spectator
+        }
+    }
+
+    pub fn index(self) -> usize {
+        match self {
+            Role::Player(player) => player,
+            Role::Spectator => MAX_PLAYERS,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Player(player) => PLAYER_NAMES[player],
+            Role::Spectator => "Spectator",
+        }
+    }
+}
+
+// Another machine in the room, as far as this one has heard from it
+pub struct Peer {
+    pub id: PeerId,
+    // Once it has said
+    pub role: Option<Role>,
+    pub ready: bool,
+    pub host: bool,
+    // How long a message takes there and back, in seconds
+    pub ping: Option<f64>,
+    // When this machine last got a message from it, to tell when it has gone
+    pub last_heard: f64,
+}
+
+// This machine's place in an online game, from joining a room in the lobby until the game ends.
+// The lobby fills in the other machines as they introduce themselves, and the session is started
+// from what it found out.
+#[derive(Resource)]
+pub struct NetSession {
+    pub socket: SharedSocket,
+    pub room_url: String,
+    // The host decides when the game starts
+    pub host: bool,
+    pub peers: Vec<Peer>,
+}
+
+impl NetSession {
+    pub fn join(room_url: String, host: bool) -> NetSession {
+        info!("Joining the online room {room_url}");
+        NetSession {
+            socket: SharedSocket::join(&room_url),
+            room_url,
+            host,
+            peers: Vec::new(),
+        }
+    }
+
+    // Every machine takes the role it picked in the lobby. Only the host sends the game on to
+    // the spectators.
+    pub fn start(&self, role: Role) -> Result<PendingSession, GgrsError> {
+        let builder = SessionBuilder::<GgrsConfig>::new()
+            .with_num_players(MAX_PLAYERS)?
+            .with_input_delay(INPUT_DELAY);
+        let Role::Player(handle) = role else {
+            let host = self.peers.iter().find(|peer| peer.host).ok_or_else(|| {
+                GgrsError::InvalidRequest {
+                    info: "there is no host to watch".to_string(),
+                }
+            })?;
+            let session = builder.start_spectator_session(host.id, self.socket.clone());
+            return Ok(PendingSession(Session::Spectator(session)));
+        };
+
+        let mut builder = builder.add_player(PlayerType::Local, handle)?;
+        // Spectators are numbered after the players
+        let mut spectator_handle = MAX_PLAYERS;
+        for peer in &self.peers {
+            match peer.role {
+                Some(Role::Player(handle)) => {
+                    builder = builder.add_player(PlayerType::Remote(peer.id), handle)?;
+                }
+                Some(Role::Spectator) if self.host => {
+                    let spectator = PlayerType::Spectator(peer.id);
+                    builder = builder.add_player(spectator, spectator_handle)?;
+                    spectator_handle += 1;
+                }
+                Some(Role::Spectator) | None => {}
+            }
+        }
+        let session = builder.start_p2p_session(self.socket.clone())?;
+        Ok(PendingSession(Session::P2P(session)))
+    }
+}
+
+// Started, and waiting for the game it is for to be set up
+#[derive(Resource)]
+pub struct PendingSession(Session<GgrsConfig>);
+
+// Runs one gameplay step on the inputs the session has for it, whether they were guessed or not
+fn run_online_step(world: &mut World) {
+    let mut step_inputs = StepInputs::default();
+    for (slot, (input, _)) in step_inputs
+        .0
+        .iter_mut()
+        .zip(world.resource::<PlayerInputs<GgrsConfig>>().iter())
+    {
+        *slot = PlayerInput::from_bits(*input);
+    }
+    world.insert_resource(step_inputs);
+    // The step sees the fixed timestep's time, like it does when it runs on the clock
+    let mut fixed_time = world.resource_mut::<Time<Fixed>>();
+    let timestep = fixed_time.timestep();
+    fixed_time.advance_by(timestep);
+    let fixed_time = fixed_time.as_generic();
+    *world.resource_mut::<Time>() = fixed_time;
+    world.run_schedule(FixedMain);
+    let virtual_time = world.resource::<Time<Virtual>>().as_generic();
+    *world.resource_mut::<Time>() = virtual_time;
+    // Whatever the step spawned has to be put back or taken away again by the next rollback
+    tag_rollback_entities(world);
+}
+
+// Each machine plays with its own first player's controls, whichever player that is in the
+// online game
+fn read_local_input(
+    mut commands: Commands,
+    local_players: Res<LocalPlayers>,
+    actions: PlayerActions,
+) {
+    let input = PlayerInput::read(&actions, 0).bits();
+    let inputs = local_players.0.iter().map(|&handle| (handle, input));
+    commands.insert_resource(LocalInputs::<GgrsConfig>(inputs.collect()));
+}
+
+fn tag_rollback_entities(world: &mut World) {
+    let untagged: Vec<Entity> = world
+        .query_filtered::<(Entity, &DespawnOnExit<GameState>), Without<Rollback>>()
+        .iter(world)
+        .filter(|(_, despawn)| despawn.0 == GameState::Playing)
+        .map(|(entity, _)| entity)
+        .collect();
+    for entity in untagged {
+        world.entity_mut(entity).insert(Rollback);
+    }
+}
+
+// The session only takes over the gameplay step once the old game, if there was one, is gone
+fn begin_online_game(world: &mut World) {
+    if let Some(PendingSession(session)) = world.remove_resource::<PendingSession>() {
+        world.insert_resource(session);
+        *world.resource_mut::<StepDriver>() = StepDriver::Session;
+    }
+}
+
+// Across the bottom of the screen, for as long as the game is only being watched
+fn spawn_spectating_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    session: Option<Res<Session<GgrsConfig>>>,
+) {
+    if !matches!(session.as_deref(), Some(Session::Spectator(_))) {
+        return;
+    }
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(SPECTATING_FONT_SIZE / 2.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        DespawnOnExit(GameState::Playing),
+        children![(
+            Text::new("SPECTATING"),
+            TextFont::from_font_size(SPECTATING_FONT_SIZE)
+                .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+            TextColor(TEXT_COLOR),
+        )],
+    ));
+}
+
+fn watch_session(
+    session: Option<ResMut<Session<GgrsConfig>>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(mut session) = session else {
+        return;
+    };
+    let events: Vec<_> = match &mut *session {
+        Session::P2P(session) => session.events().collect(),
+        Session::Spectator(session) => session.events().collect(),
+        Session::SyncTest(_) => return,
+    };
+    for event in events {
+        match event {
+            GgrsEvent::Disconnected { .. } => {
+                warn!("Another machine left the online game");
+                change_state(&mut next_state, GameState::Menu);
+            }
+            GgrsEvent::NetworkInterrupted { .. } => warn!("Lost touch with another machine"),
+            GgrsEvent::NetworkResumed { .. } => info!("Back in touch with another machine"),
+            _ => {}
+        }
+    }
+}
+
+// Leaving the game in any way, even restarting it from the pause menu, leaves the online game
+// and its room too. This machine is back to playing alone, until another game is set up in the
+// lobby.
+fn end_online_game(
+    mut commands: Commands,
+    session: Option<Res<Session<GgrsConfig>>>,
+    mut driver: ResMut<StepDriver>,
+) {
+    if session.is_none() {
+        return;
+    }
+    commands.remove_resource::<Session<GgrsConfig>>();
+    commands.remove_resource::<NetSession>();
+    *driver = StepDriver::Clock;
+}
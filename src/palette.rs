@@ -0,0 +1,204 @@
+//! Palette swaps: Luigi is Mario's picture with Mario's red turned green as it is drawn, instead
+//! of a picture of his own. Each player picks theirs on the character select screen, and it is
+//! kept in the settings.
+//!
+//! An entity with a `PaletteSwap` is drawn by a shader of its own rather than as a sprite. Its
+//! `PaletteSprite` picks the frame, the flip and the tint like a sprite's texture atlas would, and
+//! its `Visibility` still hides it, so animating it works the same as for any sprite sheet. It is drawn one unit square,
+//! scaled by its transform, like a sprite with a `custom_size` of 1 by 1.
+
+use bevy::{
+    asset::{embedded_asset, embedded_path, AssetPath},
+    prelude::*,
+    render::render_resource::AsBindGroup,
+    shader::ShaderRef,
+    sprite_render::{AlphaMode2d, Material2d, Material2dPlugin},
+};
+use serde::{Deserialize, Serialize};
+// How many colors a palette can swap. Has to match palette_swap.wgsl.
+const PALETTE_SIZE: usize = 4;
+// Mario's hat and shirt, as they are in mario_sheet.png
+const MARIO_RED: Color = Color::srgb(225.0 / 255.0, 40.0 / 255.0, 0.0);
+const LUIGI_GREEN: Color = Color::srgb(40.0 / 255.0, 170.0 / 255.0, 40.0 / 255.0);
+// Where the shader can't be used, like the HUD's lives, Luigi is Mario's picture tinted green
+const LUIGI_TINT: Color = Color::srgb(0.4, 1.0, 0.4);
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "palette_swap.wgsl");
+        app.add_plugins(Material2dPlugin::<PaletteMaterial>::default())
+            .init_resource::<PaletteQuad>()
+            .add_systems(PostUpdate, add_palette_materials)
+            .add_systems(
+                PostUpdate,
+                update_palette_materials.after(add_palette_materials),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Palette {
+    #[default]
+    Mario,
+    Luigi,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 2] = [Palette::Mario, Palette::Luigi];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Palette::Mario => "Mario",
+            Palette::Luigi => "Luigi",
+        }
+    }
+
+    // Which of Mario's colors change, and what to
+    fn swaps(self) -> &'static [(Color, Color)] {
+        match self {
+            Palette::Mario => &[],
+            Palette::Luigi => &[(MARIO_RED, LUIGI_GREEN)],
+        }
+    }
+
+    // Tints Mario's picture where it can't be swapped
+    pub fn tint(self) -> Color {
+        match self {
+            Palette::Mario => Color::WHITE,
+            Palette::Luigi => LUIGI_TINT,
+        }
+    }
+}
+
+// Drawn with a frame of `image`, cut up by `layout`, through the palette swap instead of as a
+// sprite
+#[derive(Component, Clone, Default)]
+#[require(PaletteSprite, Transform, Visibility)]
+pub struct PaletteSwap {
+    pub palette: Palette,
+    pub image: Handle<Image>,
+    pub layout: Handle<TextureAtlasLayout>,
+}
+
+// The frame of a palette swap, and how it is flipped and tinted, like a sprite's
+#[derive(Component, Clone, Copy)]
+pub struct PaletteSprite {
+    pub index: usize,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub color: Color,
+}
+
+impl Default for PaletteSprite {
+    fn default() -> Self {
+        PaletteSprite {
+            index: 0,
+            flip_x: false,
+            flip_y: false,
+            color: Color::WHITE,
+        }
+    }
+}
+
+// Every entity with a palette swap gets one, changed whenever its frame, flip or tint does.
+// `frame` is the corners of the frame in the picture, top left and bottom right, swapped around
+// when flipped. Unused slots of the palette turn transparent black into itself.
+#[derive(Asset, TypePath, AsBindGroup, Clone, PartialEq, Default)]
+pub struct PaletteMaterial {
+    #[uniform(0)]
+    frame: Vec4,
+    #[uniform(0)]
+    tint: LinearRgba,
+    #[uniform(0)]
+    swap_from: [LinearRgba; PALETTE_SIZE],
+    #[uniform(0)]
+    swap_to: [LinearRgba; PALETTE_SIZE],
+    #[texture(1)]
+    #[sampler(2)]
+    texture: Handle<Image>,
+}
+
+impl Material2d for PaletteMaterial {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Path(
+            AssetPath::from_path_buf(embedded_path!("palette_swap.wgsl")).with_source("embedded"),
+        )
+    }
+
+    // Sprites have transparent edges
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+}
+
+// The unit square every palette swap is drawn on
+#[derive(Resource)]
+struct PaletteQuad(Handle<Mesh>);
+
+impl FromWorld for PaletteQuad {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.resource_mut::<Assets<Mesh>>();
+        PaletteQuad(meshes.add(Rectangle::from_size(Vec2::ONE)))
+    }
+}
+
+fn add_palette_materials(
+    mut commands: Commands,
+    quad: Res<PaletteQuad>,
+    mut materials: ResMut<Assets<PaletteMaterial>>,
+    query: Query<Entity, Added<PaletteSwap>>,
+) {
+    for entity in &query {
+        commands.entity(entity).insert((
+            Mesh2d(quad.0.clone()),
+            MeshMaterial2d(materials.add(PaletteMaterial::default())),
+        ));
+    }
+}
+
+// Only materials that would come out different are touched, so the rest aren't sent to the GPU
+// again every frame
+fn update_palette_materials(
+    layouts: Res<Assets<TextureAtlasLayout>>,
+    mut materials: ResMut<Assets<PaletteMaterial>>,
+    query: Query<(
+        &PaletteSwap,
+        &PaletteSprite,
+        &MeshMaterial2d<PaletteMaterial>,
+    )>,
+) {
+    for (swap, sprite, handle) in &query {
+        let Some(layout) = layouts.get(&swap.layout) else {
+            continue;
+        };
+        let Some(rect) = layout.textures.get(sprite.index) else {
+            continue;
+        };
+        let size = layout.size.as_vec2();
+        let (mut min, mut max) = (rect.min.as_vec2() / size, rect.max.as_vec2() / size);
+        if sprite.flip_x {
+            std::mem::swap(&mut min.x, &mut max.x);
+        }
+        if sprite.flip_y {
+            std::mem::swap(&mut min.y, &mut max.y);
+        }
+        let mut material = PaletteMaterial {
+            frame: Vec4::new(min.x, min.y, max.x, max.y),
+            tint: sprite.color.to_linear(),
+            swap_from: [LinearRgba::NONE; PALETTE_SIZE],
+            swap_to: [LinearRgba::NONE; PALETTE_SIZE],
+            texture: swap.image.clone(),
+        };
+        for (slot, &(from, to)) in swap.palette.swaps().iter().enumerate() {
+            material.swap_from[slot] = from.to_linear();
+            material.swap_to[slot] = to.to_linear();
+        }
+        if materials.get(handle) != Some(&material) {
+            if let Some(mut current) = materials.get_mut(handle) {
+                *current = material;
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+
+// How many colors a palette can swap. Has to match PALETTE_SIZE in palette.rs.
+const PALETTE_SIZE: i32 = 4;
+// Colors this close count as the same, which is close enough for 8 bits per channel
+const SAME_COLOR: f32 = 0.01;
+
+struct PaletteMaterial {
+    frame: vec4<f32>,
+    tint: vec4<f32>,
+    swap_from: array<vec4<f32>, 4>,
+    swap_to: array<vec4<f32>, 4>,
+};
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(0)
+var<uniform> material: PaletteMaterial;
+@group(#{MATERIAL_BIND_GROUP}) @binding(1)
+var texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(2)
+var texture_sampler: sampler;
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = mix(material.frame.xy, material.frame.zw, in.uv);
+    var color = textureSample(texture, texture_sampler, uv);
+    for (var i: i32 = 0; i < PALETTE_SIZE; i = i + 1) {
+        if (distance(color, material.swap_from[i]) < SAME_COLOR) {
+            color = material.swap_to[i];
+            break;
+        }
+    }
+    return color * material.tint;
+}
@@ -0,0 +1,210 @@
+//! Small bursts of particles: dust under a player's feet when they land, sparks off a platform
+//! bumped from below, and a sparkle where a coin was collected. A fixed pool of sprites is spawned
+//! once and handed out over and over, the oldest particle making way when all of them are in use.
+
+use std::{f32::consts::PI, time::Duration};
+
+use bevy::prelude::*;
+
+use crate::{
+    enemy::CoinCollected,
+    generator::Rng,
+    level::{Collider, PlatformBumped, GRAVITY_ACCEL},
+    player::PlayerLanded,
+    GameState, StepSet, TIME_STEP,
+};
+
+const POOL_SIZE: usize = 128;
+// In front of the arena and the players
+const PARTICLE_Z: f32 = 3.0;
+
+const DUST: Burst = Burst {
+    count: 6,
+    color: Color::srgb(0.7, 0.65, 0.6),
+    size: 3.0,
+    // Kicked up and out to both sides
+    direction: PI / 2.0,
+    spread: PI * 0.45,
+    speed: (20.0, 60.0),
+    gravity: 0.2,
+    seconds: 0.35,
+};
+const SPARKS: Burst = Burst {
+    count: 8,
+    color: Color::srgb(1.0, 0.9, 0.4),
+    size: 3.0,
+    // Showering down from the underside of the platform
+    direction: -PI / 2.0,
+    spread: PI * 0.4,
+    speed: (80.0, 200.0),
+    gravity: 1.0,
+    seconds: 0.4,
+};
+const SPARKLE: Burst = Burst {
+    count: 10,
+    color: Color::srgb(1.0, 0.95, 0.6),
+    size: 4.0,
+    direction: 0.0,
+    spread: PI,
+    speed: (30.0, 90.0),
+    gravity: 0.0,
+    seconds: 0.5,
+};
+
+pub struct ParticlesPlugin;
+
+impl Plugin for ParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, spawn_particle_pool)
+            .add_systems(Update, emit_particles)
+            .add_systems(FixedUpdate, update_particles.in_set(StepSet::Presentation))
+            .add_systems(OnExit(GameState::Playing), clear_particles);
+    }
+}
+
+// What a burst of particles looks like
+struct Burst {
+    count: usize,
+    color: Color,
+    // Width and height of each particle
+    size: f32,
+    // The particles fly off within `spread` radians either side of `direction`
+    direction: f32,
+    spread: f32,
+    // Each particle gets a speed between these two
+    speed: (f32, f32),
+    // How strongly the particles fall, as a fraction of the usual gravity
+    gravity: f32,
+    // Particles fade out over their lifetime
+    seconds: f32,
+}
+
+// Every particle entity, in the order they are handed out
+#[derive(Resource)]
+struct ParticlePool {
+    entities: Vec<Entity>,
+    next: usize,
+    rng: Rng,
+}
+
+// A particle is in use while its lifetime runs, and hidden once it is over
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    gravity: f32,
+    lifetime: Timer,
+    alpha: f32,
+}
+
+fn spawn_particle_pool(mut commands: Commands) {
+    let entities = (0..POOL_SIZE)
+        .map(|_| {
+            // Already over, so the particle starts out unused
+            let mut lifetime = Timer::from_seconds(0.0, TimerMode::Once);
+            lifetime.tick(Duration::ZERO);
+            commands
+                .spawn((
+                    Sprite::default(),
+                    Visibility::Hidden,
+                    Particle {
+                        velocity: Vec2::ZERO,
+                        gravity: 0.0,
+                        lifetime,
+                        alpha: 0.0,
+                    },
+                ))
+                .id()
+        })
+        .collect();
+    commands.insert_resource(ParticlePool {
+        entities,
+        next: 0,
+        rng: Rng::new(0),
+    });
+}
+
+fn emit_particles(
+    mut landed_events: MessageReader<PlayerLanded>,
+    mut bump_events: MessageReader<PlatformBumped>,
+    mut coin_events: MessageReader<CoinCollected>,
+    platform_query: Query<&Transform, (With<Collider>, Without<Particle>)>,
+    mut pool: ResMut<ParticlePool>,
+    mut particle_query: Query<(&mut Particle, &mut Transform, &mut Sprite, &mut Visibility)>,
+) {
+    for landed in landed_events.read() {
+        emit(&mut pool, &mut particle_query, &DUST, landed.feet);
+    }
+    for bump in bump_events.read() {
+        let Ok(platform) = platform_query.get(bump.platform) else {
+            continue;
+        };
+        let underside = platform.translation.y - platform.scale.y / 2.0;
+        emit(
+            &mut pool,
+            &mut particle_query,
+            &SPARKS,
+            Vec3::new(bump.x, underside, 0.0),
+        );
+    }
+    for coin in coin_events.read() {
+        emit(&mut pool, &mut particle_query, &SPARKLE, coin.position);
+    }
+}
+
+fn emit(
+    pool: &mut ParticlePool,
+    particle_query: &mut Query<(&mut Particle, &mut Transform, &mut Sprite, &mut Visibility)>,
+    burst: &Burst,
+    position: Vec3,
+) {
+    for _ in 0..burst.count {
+        let entity = pool.entities[pool.next];
+        pool.next = (pool.next + 1) % pool.entities.len();
+        let angle = burst.direction + pool.rng.float(-burst.spread, burst.spread);
+        let speed = pool.rng.float(burst.speed.0, burst.speed.1);
+        let Ok((mut particle, mut transform, mut sprite, mut visibility)) =
+            particle_query.get_mut(entity)
+        else {
+            continue;
+        };
+        *particle = Particle {
+            velocity: Vec2::from_angle(angle) * speed,
+            gravity: burst.gravity,
+            lifetime: Timer::from_seconds(burst.seconds, TimerMode::Once),
+            alpha: burst.color.alpha(),
+        };
+        *transform = Transform::from_translation(position.truncate().extend(PARTICLE_Z))
+            .with_scale(Vec3::new(burst.size, burst.size, 1.0));
+        sprite.color = burst.color;
+        *visibility = Visibility::Inherited;
+    }
+}
+
+fn update_particles(
+    mut query: Query<(&mut Particle, &mut Transform, &mut Sprite, &mut Visibility)>,
+) {
+    for (mut particle, mut transform, mut sprite, mut visibility) in &mut query {
+        if particle.lifetime.is_finished() {
+            continue;
+        }
+        particle.lifetime.tick(Duration::from_secs_f32(TIME_STEP));
+        if particle.lifetime.is_finished() {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        particle.velocity.y -= GRAVITY_ACCEL * particle.gravity * TIME_STEP;
+        transform.translation += (particle.velocity * TIME_STEP).extend(0.0);
+        sprite
+            .color
+            .set_alpha(particle.alpha * particle.lifetime.fraction_remaining());
+    }
+}
+
+// Particles left over from a game that ended would otherwise hang in the air over the next screen
+fn clear_particles(mut query: Query<(&mut Particle, &mut Visibility)>) {
+    for (mut particle, mut visibility) in &mut query {
+        let duration = particle.lifetime.duration();
+        particle.lifetime.tick(duration);
+        *visibility = Visibility::Hidden;
+    }
+}
@@ -0,0 +1,1687 @@
+//! Mario and Luigi: movement, jumping, animation, dying, and their scores and lives.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{
+    boss::{hurt_boss, Boss, BossHit, BOSS_HIT_POINTS},
+    change_state,
+    console::AddConsoleCommand,
+    controls::{Action, StepInputs},
+    enemy::{
+        collect_coins, destroy_bumped_hazards, kick_flipped_enemies, CoinCollected, Enemy,
+        EnemyDefeated, Flipped, Hazard, HazardDestroyed, KickedEnemy,
+    },
+    level::{
+        advance_phase, apply_gravity, collide, generate_first_endless_layout, hit_by_bump,
+        penetration, stop_against, sweep, ArenaBounds, Collider, Collision, CollisionEvent,
+        Crushed, GravityScale, Grounded, HazardFloor, Ice, LevelDef, Levels, OneWayPlatform, Phase,
+        PhaseCleared, PlatformBumped, RestartPhase, SpatialHash, StartPhase, TriggerEnter,
+        Velocity, WrapsHorizontally, BOTTOM_WALL,
+    },
+    mutators::{CoopRules, Modifiers},
+    palette::{PaletteSprite, PaletteSwap},
+    powerup::Hammer,
+    settings::Settings,
+    skins::{SkinImages, SpriteId},
+    status::{tick_status_effects, StatusEffects, StatusExpired, StatusKind, StatusTicked},
+    ui::ScorePopup,
+    GameMode, GameState, PauseState, StepSet, BLOCK_SIZE, MAX_PLAYERS, TIME_STEP,
+};
+
+pub const MARIO_SIZE: Vec3 = Vec3::new(BLOCK_SIZE * 2.0, BLOCK_SIZE * 3.0, 0.0);
+const MARIO_XSPEED: f32 = 300.0;
+pub const JUMP_SPEED: f32 = 800.0;
+// Defaults for `JumpConfig`
+pub const JUMP_HELD_GRAVITY_SCALE: f32 = 0.65;
+const JUMP_RELEASE_VELOCITY_SCALE: f32 = 0.4;
+const JUMP_COYOTE_SECONDS: f32 = 0.1;
+const JUMP_BUFFER_SECONDS: f32 = 0.1;
+// Defaults for `MovementConfig`, in units per second squared
+const GROUND_ACCELERATION: f32 = 1200.0;
+const GROUND_DECELERATION: f32 = 1500.0;
+const GROUND_TURN_DECELERATION: f32 = 2400.0;
+const AIR_ACCELERATION: f32 = 800.0;
+const AIR_DECELERATION: f32 = 300.0;
+const AIR_TURN_DECELERATION: f32 = 1200.0;
+// Reversing direction faster than this on the ground makes the player skid
+const SKID_SPEED: f32 = 150.0;
+// Ice barely slows anything down, so players slide after letting go of the keys
+const ICE_ACCELERATION: f32 = 500.0;
+const ICE_DECELERATION: f32 = 150.0;
+const ICE_TURN_DECELERATION: f32 = 400.0;
+// mario_sheet.png is a single row of frames, see AnimationState::frames for what is where
+pub const MARIO_FRAME_SIZE: UVec2 = UVec2::new(16, 21);
+pub const MARIO_SHEET_COLUMNS: u32 = 10;
+// Animations run on the frame clock, not the physics step
+const ANIMATION_FRAME_SECONDS: f32 = 0.1;
+// Below this horizontal speed a grounded Mario counts as standing still
+const RUN_ANIMATION_MIN_SPEED: f32 = 10.0;
+// In two player games Luigi respawns next to Mario
+const PLAYER_SPACING: f32 = BLOCK_SIZE * 3.0;
+const STARTING_LIVES: usize = 3;
+// A 1-UP for every this many points
+const EXTRA_LIFE_POINTS: usize = 20_000;
+// After losing a life Mario reappears at the top of the arena, standing on a small platform
+// that goes away after a while
+const RESPAWN_POSITION: Vec3 = Vec3::new(0.0, BLOCK_SIZE * 12.0, 1.0);
+const RESPAWN_PLATFORM_SIZE: Vec2 = Vec2::new(BLOCK_SIZE * 3.0, BLOCK_SIZE / 2.0);
+const RESPAWN_PLATFORM_SECONDS: f32 = 3.0;
+// A dying Mario pops up, then falls off the bottom of the screen
+const DEATH_POP_SPEED: f32 = 600.0;
+const DEATH_FALL_Y: f32 = BOTTOM_WALL - BLOCK_SIZE * 8.0;
+// Right after respawning Mario blinks and can't be hurt for a while
+const INVINCIBLE_SECONDS: f32 = 2.0;
+// A star makes Mario cycle through these colors
+const STAR_COLORS: [Color; 4] = [
+    Color::srgb(1.0, 0.3, 0.3),
+    Color::srgb(1.0, 0.9, 0.3),
+    Color::srgb(0.3, 1.0, 0.4),
+    Color::srgb(0.4, 0.6, 1.0),
+];
+// Kicking another enemy or collecting a coin within this long of the last one continues the
+// combo, multiplying the points of kicks by the length of the chain up to MAX_COMBO
+const COMBO_WINDOW_SECONDS: f32 = 1.5;
+const MAX_COMBO: usize = 4;
+// Versus mode: a player whose platform gets bumped from below can't move for a moment
+const STAGGER_SECONDS: f32 = 1.0;
+const STAGGER_BUMP_SPEED: f32 = 300.0;
+const RESPAWN_PLATFORM_COLOR: Color = Color::srgb(0.9, 0.4, 0.4);
+// Touching the ground after less time in the air than this isn't a landing, like stepping off
+// the edge of a platform onto the one right below
+const LANDING_MIN_AIR_SECONDS: f32 = 0.1;
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Scoreboard::new())
+            .init_resource::<Lives>()
+            .init_resource::<ComboTracker>()
+            .init_resource::<JumpConfig>()
+            .init_resource::<MovementConfig>()
+            .add_message::<PlayerJumped>()
+            .add_message::<PlayerLanded>()
+            .add_message::<PlayerDied>()
+            .add_message::<ComboExtended>()
+            .add_message::<ExtraLifeAwarded>()
+            .add_message::<PlayerJoined>()
+            .add_message::<PlayerLeft>()
+            .add_systems(
+                OnEnter(GameState::Playing),
+                spawn_players.after(generate_first_endless_layout),
+            )
+            .add_systems(
+                FixedUpdate,
+                move_mario_input
+                    .in_set(MoveMarioInput)
+                    .after(tick_status_effects)
+                    .in_set(StepSet::Input),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    stagger_bumped_players,
+                    stagger_kicked_players,
+                    check_for_enemy_contact.after(kick_flipped_enemies),
+                    crush_players,
+                    sink_players,
+                    decay_combos.before(score_defeated_enemies),
+                    score_defeated_enemies.after(kick_flipped_enemies),
+                    score_coins
+                        .after(collect_coins)
+                        .after(decay_combos)
+                        .before(score_defeated_enemies),
+                    score_destroyed_hazards.after(destroy_bumped_hazards),
+                    score_boss_hits.after(hurt_boss),
+                    award_extra_lives
+                        .after(score_defeated_enemies)
+                        .after(score_coins)
+                        .after(score_destroyed_hazards)
+                        .after(score_boss_hits),
+                    expire_respawn_platforms,
+                    bring_back_players.after(advance_phase),
+                    finish_dying.after(bring_back_players),
+                    announce_deaths,
+                )
+                    .in_set(StepSet::Gameplay),
+            )
+            .add_systems(
+                FixedUpdate,
+                animate_status_effects.in_set(StepSet::Presentation),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_mario_animation,
+                    animate_sprites.after(update_mario_animation),
+                    update_facing,
+                    flip_sprites.after(update_facing),
+                    restart_players,
+                    drop_in_players,
+                    drop_out_players,
+                )
+                    .run_if(in_state(PauseState::Running)),
+            )
+            .add_console_command("give_life", "give_life [player]", give_life_command);
+
+        // The rapier backend runs its own version, in the same set
+        #[cfg(not(feature = "rapier"))]
+        app.add_systems(
+            FixedUpdate,
+            move_players.in_set(MovePlayers).in_set(StepSet::Physics),
+        );
+        #[cfg(feature = "rapier")]
+        app.add_plugins(crate::rapier::RapierBackendPlugin);
+        // After either backend's version
+        app.add_systems(
+            FixedUpdate,
+            separate_players.after(MovePlayers).in_set(StepSet::Physics),
+        );
+    }
+}
+
+// What of the players' the dev tools' inspector can show and change, the tuning values included
+#[cfg(feature = "dev-tools")]
+pub fn register_inspectable(app: &mut App) {
+    app.register_type::<Player>()
+        .register_type::<Skidding>()
+        .register_type::<AnimationState>()
+        .register_type::<AnimationTimer>()
+        .register_type::<Facing>()
+        .register_type::<JumpState>()
+        .register_type::<Dying>()
+        .register_type::<RespawnPlatform>()
+        .register_type::<ComboTracker>()
+        .register_type::<Scoreboard>()
+        .register_type::<Lives>()
+        .register_type::<JumpConfig>()
+        .register_type::<MovementConfig>();
+}
+
+// Everything of the players' that the gameplay step changes, for an online game to put back when
+// it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<Player>()
+        .rollback_component_with_reflect::<Skidding>()
+        .rollback_component_with_reflect::<AnimationState>()
+        .rollback_component_with_reflect::<AnimationTimer>()
+        .rollback_component_with_reflect::<Facing>()
+        .rollback_component_with_reflect::<JumpState>()
+        .rollback_component_with_reflect::<Dying>()
+        .rollback_component_with_reflect::<RespawnPlatform>()
+        .rollback_resource_with_reflect::<ComboTracker>()
+        .rollback_resource_with_reflect::<Scoreboard>()
+        .rollback_resource_with_reflect::<Lives>();
+}
+
+// Every game starts with fresh scores and lives, and the players at their starting positions
+fn spawn_players(
+    mut commands: Commands,
+    skin_images: Res<SkinImages>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+    (game_mode, coop_rules): (Res<GameMode>, Res<CoopRules>),
+    settings: Res<Settings>,
+    start_phase: Res<StartPhase>,
+    levels: (Res<Levels>, Res<Assets<LevelDef>>),
+) {
+    let (levels, level_assets) = levels;
+    commands.insert_resource(Scoreboard::new());
+    commands.insert_resource(Lives::new(*game_mode, *coop_rules));
+    commands.insert_resource(ComboTracker::default());
+    let level = levels.for_phase(start_phase.0, &level_assets);
+
+    // Mario (and Luigi)
+    let image = skin_images.get(SpriteId::MarioSheet);
+    let layout = layouts.add(TextureAtlasLayout::from_grid(
+        MARIO_FRAME_SIZE,
+        MARIO_SHEET_COLUMNS,
+        1,
+        None,
+        None,
+    ));
+    for (index, &palette) in settings
+        .player_palettes
+        .iter()
+        .enumerate()
+        .take(game_mode.player_count())
+    {
+        let transform = Transform::from_translation(level.player_spawn(index));
+        spawn_player(
+            &mut commands,
+            index,
+            PaletteSwap {
+                palette,
+                image: image.clone(),
+                layout: layout.clone(),
+            },
+            transform,
+        );
+    }
+}
+
+fn spawn_player(
+    commands: &mut Commands,
+    index: usize,
+    swap: PaletteSwap,
+    transform: Transform,
+) -> Entity {
+    commands
+        .spawn((
+            swap,
+            transform.with_scale(MARIO_SIZE),
+            Player { index },
+            Grounded::default(),
+            Skidding::default(),
+            AnimationState::default(),
+            AnimationTimer::default(),
+            Facing::default(),
+            JumpState::default(),
+            GravityScale(1.0),
+            StatusEffects::default(),
+            Velocity::default(),
+            WrapsHorizontally,
+            DespawnOnExit(GameState::Playing),
+        ))
+        .id()
+}
+
+// Sent when another player drops in on a single-player game, with the index they play as
+#[derive(Message)]
+pub struct PlayerJoined(pub usize);
+
+// Sent when a player drops out of a co-op game again, leaving the other one to play on alone
+#[derive(Message)]
+pub struct PlayerLeft(pub usize);
+
+// Makes it a co-op game on the shared lives. The new player drops in from the top, the way
+// players come back after losing a life.
+fn drop_in_players(
+    mut commands: Commands,
+    mut joined_events: MessageReader<PlayerJoined>,
+    mut game_mode: ResMut<GameMode>,
+    settings: Res<Settings>,
+    player_query: Query<(&Player, &PaletteSwap)>,
+) {
+    for joined in joined_events.read() {
+        if player_query
+            .iter()
+            .any(|(player, _)| player.index == joined.0)
+        {
+            continue;
+        }
+        // Everyone shares the same sheet
+        let Some((_, swap)) = player_query.iter().next() else {
+            continue;
+        };
+        *game_mode = GameMode::Coop;
+
+        let player = Player { index: joined.0 };
+        let mut transform = Transform::default();
+        let mut velocity = Velocity::default();
+        let mut grounded = Grounded::default();
+        respawn_player(
+            &mut commands,
+            &player,
+            &mut transform,
+            &mut velocity,
+            &mut grounded,
+        );
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusKind::Invincible, INVINCIBLE_SECONDS);
+        let entity = spawn_player(
+            &mut commands,
+            player.index,
+            PaletteSwap {
+                palette: settings.player_palettes[player.index],
+                ..swap.clone()
+            },
+            transform,
+        );
+        commands.entity(entity).insert(effects);
+    }
+}
+
+// Their score stays on the scoreboard, in case they drop in again
+fn drop_out_players(
+    mut commands: Commands,
+    mut left_events: MessageReader<PlayerLeft>,
+    mut game_mode: ResMut<GameMode>,
+    player_query: Query<(Entity, &Player)>,
+) {
+    for left in left_events.read() {
+        for (entity, player) in &player_query {
+            if player.index == left.0 {
+                commands.entity(entity).despawn();
+            }
+        }
+        *game_mode = GameMode::SinglePlayer;
+    }
+}
+
+// Where the players' inputs of a gameplay step are turned into movement. Whatever changes
+// `StepInputs` during the step, like a replay being watched, runs before it.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MoveMarioInput;
+
+// Where the players are moved and stopped against the arena, by either physics backend
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MovePlayers;
+
+// The character of one of the players
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Player {
+    // Of the player controlling it. Whether it is Mario or Luigi is up to their palette.
+    pub index: usize,
+}
+
+// Set while the player is sliding to a stop after reversing direction at speed
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Skidding(bool);
+
+// The animation a character is playing. Mario's follows how he is moving,
+// except for the death animation which stays until something else changes it.
+#[derive(Component, Reflect, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(Component)]
+enum AnimationState {
+    #[default]
+    Idle,
+    Run,
+    Jump,
+    Fall,
+    Skid,
+    Death,
+    // Swinging a hammer, whatever else the player is doing
+    Swing,
+}
+
+impl AnimationState {
+    // Index of the first frame in the sprite sheet, and how many frames there are
+    fn frames(self) -> (usize, usize) {
+        match self {
+            AnimationState::Idle => (0, 1),
+            AnimationState::Run => (1, 3),
+            AnimationState::Jump => (4, 1),
+            AnimationState::Fall => (5, 1),
+            AnimationState::Skid => (6, 1),
+            AnimationState::Death => (7, 1),
+            AnimationState::Swing => (8, 2),
+        }
+    }
+}
+
+// Steps through the frames of the current animation
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct AnimationTimer {
+    timer: Timer,
+    playing: AnimationState,
+    frame: usize,
+}
+
+impl Default for AnimationTimer {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(ANIMATION_FRAME_SECONDS, TimerMode::Repeating),
+            playing: AnimationState::default(),
+            frame: 0,
+        }
+    }
+}
+
+// Which way a character looks. Only changes while it is moving sideways,
+// so it keeps looking the same way after stopping.
+#[derive(Component, Reflect, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(Component)]
+pub enum Facing {
+    Left,
+    #[default]
+    Right,
+}
+
+// Per player bookkeeping for jumps
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct JumpState {
+    // Still holding the jump key during the rising part of a jump
+    holding: bool,
+    // Seconds since the player last stood on the ground, for coyote time
+    since_grounded: f32,
+    // Seconds since the jump key was last pressed, for jump buffering
+    since_jump_pressed: f32,
+    // Whether the jump key was down on the previous step, to tell new presses from held keys
+    jump_was_down: bool,
+}
+
+impl Default for JumpState {
+    fn default() -> Self {
+        JumpState {
+            holding: false,
+            since_grounded: f32::INFINITY,
+            since_jump_pressed: f32::INFINITY,
+            jump_was_down: false,
+        }
+    }
+}
+
+// Nothing needs to know which player these are about yet, or where they jumped or died
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct PlayerJumped {
+    pub player: usize,
+    pub position: Vec3,
+}
+
+// Sent when a player touches the ground again after a jump or a fall
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct PlayerLanded {
+    pub player: usize,
+    // Where their feet touched the ground
+    pub feet: Vec3,
+}
+
+// Sent when a player loses a life, as the death animation starts
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct PlayerDied {
+    pub player: usize,
+    pub position: Vec3,
+}
+
+// Sent for every life a player earns with their score
+#[allow(dead_code)]
+#[derive(Message)]
+pub struct ExtraLifeAwarded {
+    pub player: usize,
+}
+
+// Present while a player plays the death animation, ignoring controls and walls
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Dying;
+
+// Temporary platform Mario stands on after respawning
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct RespawnPlatform(Timer);
+
+// Tuning values for how jumps feel. Holding the jump key keeps gravity low on the way up,
+// releasing it early cuts the jump short, so both short hops and full jumps are possible.
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+struct JumpConfig {
+    // Upward speed a jump starts with
+    speed: f32,
+    // Multiplier on gravity while rising with the jump key held
+    held_gravity_scale: f32,
+    // Fraction of the upward speed kept when the jump key is released early
+    release_velocity_scale: f32,
+    // Grace period after walking off a platform during which a jump still works
+    coyote_time: f32,
+    // How long a jump press made in the air is remembered, so it fires on landing
+    buffer_time: f32,
+}
+
+impl Default for JumpConfig {
+    fn default() -> Self {
+        JumpConfig {
+            speed: JUMP_SPEED,
+            held_gravity_scale: JUMP_HELD_GRAVITY_SCALE,
+            release_velocity_scale: JUMP_RELEASE_VELOCITY_SCALE,
+            coyote_time: JUMP_COYOTE_SECONDS,
+            buffer_time: JUMP_BUFFER_SECONDS,
+        }
+    }
+}
+
+// How quickly horizontal speed changes on a given kind of surface
+#[derive(Reflect)]
+struct SurfaceMovement {
+    // Speeding up in the pressed direction
+    acceleration: f32,
+    // Slowing down with no direction pressed
+    deceleration: f32,
+    // Slowing down while pressing against the current movement
+    turn_deceleration: f32,
+}
+
+// Tuning values for horizontal movement
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+struct MovementConfig {
+    max_speed: f32,
+    skid_speed: f32,
+    ground: SurfaceMovement,
+    ice: SurfaceMovement,
+    air: SurfaceMovement,
+}
+
+impl Default for MovementConfig {
+    fn default() -> Self {
+        MovementConfig {
+            max_speed: MARIO_XSPEED,
+            skid_speed: SKID_SPEED,
+            ground: SurfaceMovement {
+                acceleration: GROUND_ACCELERATION,
+                deceleration: GROUND_DECELERATION,
+                turn_deceleration: GROUND_TURN_DECELERATION,
+            },
+            ice: SurfaceMovement {
+                acceleration: ICE_ACCELERATION,
+                deceleration: ICE_DECELERATION,
+                turn_deceleration: ICE_TURN_DECELERATION,
+            },
+            air: SurfaceMovement {
+                acceleration: AIR_ACCELERATION,
+                deceleration: AIR_DECELERATION,
+                turn_deceleration: AIR_TURN_DECELERATION,
+            },
+        }
+    }
+}
+
+// Each player's current chain of kicks and coins
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct ComboTracker {
+    pub combos: [Combo; MAX_PLAYERS],
+}
+
+#[derive(Reflect)]
+pub struct Combo {
+    pub chain: usize,
+    decay: Timer,
+}
+
+impl Combo {
+    // Adds a link to the chain and gives it another full window
+    fn extend(&mut self) {
+        self.chain = (self.chain + 1).min(MAX_COMBO);
+        self.decay.reset();
+    }
+}
+
+// Sent whenever a kick or a coin adds to a player's chain
+#[derive(Message)]
+pub struct ComboExtended {
+    pub chain: usize,
+}
+
+impl Default for ComboTracker {
+    fn default() -> Self {
+        Self {
+            combos: std::array::from_fn(|_| Combo {
+                chain: 0,
+                decay: Timer::from_seconds(COMBO_WINDOW_SECONDS, TimerMode::Once),
+            }),
+        }
+    }
+}
+
+// This resource tracks the score of each player, and the score at which they get
+// their next extra life. Everything is kept by player index.
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Scoreboard {
+    scores: [usize; MAX_PLAYERS],
+    // Enemies each player has kicked off, for the game over screen
+    defeated: [usize; MAX_PLAYERS],
+    next_extra_life: [usize; MAX_PLAYERS],
+}
+
+impl Scoreboard {
+    fn new() -> Scoreboard {
+        Scoreboard {
+            scores: [0; MAX_PLAYERS],
+            defeated: [0; MAX_PLAYERS],
+            next_extra_life: [EXTRA_LIFE_POINTS; MAX_PLAYERS],
+        }
+    }
+
+    pub fn score(&self, player: usize) -> usize {
+        self.scores[player]
+    }
+
+    // Every player's, including the ones not playing
+    pub fn scores(&self) -> [usize; MAX_PLAYERS] {
+        self.scores
+    }
+
+    pub fn best(&self) -> usize {
+        self.scores.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, player: usize, points: usize) {
+        self.scores[player] += points;
+    }
+
+    pub fn defeated(&self, player: usize) -> usize {
+        self.defeated[player]
+    }
+
+    pub fn count_defeated(&mut self, player: usize) {
+        self.defeated[player] += 1;
+    }
+
+    fn extra_life_due(&self, player: usize) -> bool {
+        self.scores[player] >= self.next_extra_life[player]
+    }
+
+    // How many extra lives the player's score is worth that weren't awarded yet. Thresholds
+    // are passed one by one, so a kick that jumps past one still awards it exactly once.
+    fn take_extra_lives(&mut self, player: usize) -> usize {
+        let mut earned = 0;
+        while self.scores[player] >= self.next_extra_life[player] {
+            self.next_extra_life[player] += EXTRA_LIFE_POINTS;
+            earned += 1;
+        }
+        earned
+    }
+}
+
+impl Default for Scoreboard {
+    fn default() -> Self {
+        Scoreboard::new()
+    }
+}
+
+// This resource tracks how many more times the players can get hit before the game is over
+#[derive(Resource, Reflect)]
+#[reflect(Resource)]
+pub struct Lives {
+    // One count per player, or a single shared pool in the first slot
+    pub remaining: [usize; MAX_PLAYERS],
+    pub shared: bool,
+    // Co-op on separate lives: a player out of lives waits to be brought back by the other, and
+    // the game only ends once both are out
+    pub revivable: bool,
+}
+
+impl Lives {
+    fn new(game_mode: GameMode, coop_rules: CoopRules) -> Lives {
+        let separate = match game_mode {
+            GameMode::Versus => true,
+            GameMode::Coop => coop_rules.separate_lives,
+            _ => false,
+        };
+        Lives {
+            remaining: [STARTING_LIVES; MAX_PLAYERS],
+            shared: !separate,
+            revivable: separate && game_mode == GameMode::Coop,
+        }
+    }
+
+    fn pool(&self, player: usize) -> usize {
+        if self.shared {
+            0
+        } else {
+            player
+        }
+    }
+
+    // A 1-UP goes to a partner who is out of lives first, bringing them back
+    fn gain(&mut self, player: usize, player_count: usize) {
+        let pool = (0..player_count)
+            .find(|&other| other != player && self.waiting(other))
+            .unwrap_or_else(|| self.pool(player));
+        self.remaining[pool] += 1;
+    }
+
+    // Takes a life from the given player
+    fn lose(&mut self, player: usize) {
+        let pool = self.pool(player);
+        self.remaining[pool] = self.remaining[pool].saturating_sub(1);
+    }
+
+    fn game_over(&self, player_count: usize) -> bool {
+        let pools = if self.shared { 1 } else { player_count };
+        if self.revivable {
+            self.remaining[..pools].iter().all(|&left| left == 0)
+        } else {
+            self.remaining[..pools].contains(&0)
+        }
+    }
+
+    // Whether the given player is out of lives but can still be brought back
+    fn waiting(&self, player: usize) -> bool {
+        self.revivable && self.remaining[player] == 0
+    }
+
+    // Everyone waiting comes back with a single life
+    fn bring_back(&mut self, player_count: usize) {
+        for player in 0..player_count {
+            if self.waiting(player) {
+                self.remaining[player] = 1;
+            }
+        }
+    }
+}
+
+impl Default for Lives {
+    fn default() -> Self {
+        Lives::new(GameMode::SinglePlayer, CoopRules::default())
+    }
+}
+
+// The `give_life` console command: a life for the given player, the first one unless it says
+// otherwise, just like a 1-UP
+fn give_life_command(world: &mut World, args: &[&str]) -> Result<String, String> {
+    let player_count = world.resource::<GameMode>().player_count();
+    let player = match args {
+        [] => 0,
+        [player] => match player.parse::<usize>() {
+            Ok(player) if (1..=player_count).contains(&player) => player - 1,
+            _ => return Err(format!("There is no player \"{player}\"")),
+        },
+        _ => return Err("Only one player at a time".to_string()),
+    };
+    world.resource_mut::<Lives>().gain(player, player_count);
+    Ok(format!("A life for player {}", player + 1))
+}
+
+// Puts a player back at the top of the arena, standing on a temporary platform
+fn respawn_player(
+    commands: &mut Commands,
+    player: &Player,
+    transform: &mut Transform,
+    velocity: &mut Velocity,
+    grounded: &mut Grounded,
+) {
+    let position = RESPAWN_POSITION + Vec3::X * PLAYER_SPACING * player.index as f32;
+    transform.translation = position;
+    velocity.0 = Vec2::ZERO;
+    grounded.0 = None;
+
+    let platform_y = position.y - MARIO_SIZE.y / 2.0 - RESPAWN_PLATFORM_SIZE.y / 2.0;
+    commands.spawn((
+        Sprite {
+            color: RESPAWN_PLATFORM_COLOR,
+            ..default()
+        },
+        Transform {
+            translation: Vec3::new(position.x, platform_y, 0.0),
+            scale: RESPAWN_PLATFORM_SIZE.extend(1.0),
+            ..default()
+        },
+        RespawnPlatform(Timer::from_seconds(
+            RESPAWN_PLATFORM_SECONDS,
+            TimerMode::Once,
+        )),
+        Collider,
+        DespawnOnExit(GameState::Playing),
+    ));
+}
+
+fn move_mario_input(
+    (inputs, modifiers): (Res<StepInputs>, Res<Modifiers>),
+    jump_config: Res<JumpConfig>,
+    movement_config: Res<MovementConfig>,
+    ice_query: Query<(), With<Ice>>,
+    mut jumped_events: MessageWriter<PlayerJumped>,
+    mut landed_events: MessageWriter<PlayerLanded>,
+    mut query: Query<
+        (
+            &Player,
+            &Transform,
+            &mut Velocity,
+            &mut Grounded,
+            &mut Skidding,
+            &mut JumpState,
+            &mut GravityScale,
+            &StatusEffects,
+        ),
+        Without<Dying>,
+    >,
+) {
+    for (
+        player,
+        transform,
+        mut velocity,
+        mut grounded,
+        mut skidding,
+        mut jump,
+        mut gravity_scale,
+        effects,
+    ) in &mut query
+    {
+        // Staggered players don't get to act, as if nothing was pressed
+        let staggered = effects.has(StatusKind::Staggered);
+        let held = |action| !staggered && inputs.0[player.index].held(modifiers.remap(action));
+
+        let jump_down = held(Action::Jump);
+        if jump_down && !jump.jump_was_down {
+            jump.since_jump_pressed = 0.0;
+        } else {
+            jump.since_jump_pressed += TIME_STEP;
+        }
+        jump.jump_was_down = jump_down;
+
+        if grounded.0.is_some() {
+            if jump.since_grounded > LANDING_MIN_AIR_SECONDS {
+                landed_events.write(PlayerLanded {
+                    player: player.index,
+                    feet: transform.translation - Vec3::Y * transform.scale.y / 2.0,
+                });
+            }
+            jump.since_grounded = 0.0;
+        } else {
+            jump.since_grounded += TIME_STEP;
+        }
+
+        // A recent press counts if the player is on the ground or only just left it
+        if jump.since_jump_pressed <= jump_config.buffer_time
+            && jump.since_grounded <= jump_config.coyote_time
+        {
+            velocity.y = jump_config.speed;
+            grounded.0 = None;
+            jump.holding = true;
+            jumped_events.write(PlayerJumped {
+                player: player.index,
+                position: transform.translation,
+            });
+            // Use up both the press and the ground contact, so one press is one jump
+            jump.since_jump_pressed = f32::INFINITY;
+            jump.since_grounded = f32::INFINITY;
+        }
+
+        if jump.holding {
+            if velocity.y <= 0.0 {
+                // The top of the jump was reached with the key still held
+                jump.holding = false;
+            } else if !jump_down {
+                // Released early, so cut the jump short
+                velocity.y *= jump_config.release_velocity_scale;
+                jump.holding = false;
+            }
+        }
+        gravity_scale.0 = if jump.holding {
+            jump_config.held_gravity_scale
+        } else {
+            1.0
+        };
+
+        let direction = if held(Action::MoveLeft) {
+            -1.0
+        } else if held(Action::MoveRight) {
+            1.0
+        } else {
+            0.0
+        };
+        let surface = match grounded.0 {
+            Some(ground) if ice_query.contains(ground) => &movement_config.ice,
+            Some(_) => &movement_config.ground,
+            None => &movement_config.air,
+        };
+        let reversing = velocity.x * direction < 0.0;
+        let rate = if direction == 0.0 {
+            surface.deceleration
+        } else if reversing {
+            surface.turn_deceleration
+        } else {
+            surface.acceleration
+        };
+        velocity.x = move_towards(
+            velocity.x,
+            direction * movement_config.max_speed,
+            rate * TIME_STEP,
+        );
+        skidding.0 =
+            grounded.0.is_some() && reversing && velocity.x.abs() > movement_config.skid_speed;
+    }
+}
+
+// Moves `current` towards `target` by at most `max_delta`, without overshooting
+fn move_towards(current: f32, target: f32, max_delta: f32) -> f32 {
+    if (target - current).abs() <= max_delta {
+        target
+    } else {
+        current + (target - current).signum() * max_delta
+    }
+}
+
+fn update_mario_animation(
+    mut query: Query<
+        (
+            &Velocity,
+            &Grounded,
+            &Skidding,
+            &mut AnimationState,
+            Option<&Hammer>,
+        ),
+        With<Player>,
+    >,
+) {
+    for (velocity, grounded, skidding, mut animation, hammer) in &mut query {
+        if *animation == AnimationState::Death {
+            continue;
+        }
+
+        let next = if hammer.is_some_and(Hammer::swinging) {
+            AnimationState::Swing
+        } else if grounded.0.is_none() {
+            if velocity.y > 0.0 {
+                AnimationState::Jump
+            } else {
+                AnimationState::Fall
+            }
+        } else if skidding.0 {
+            AnimationState::Skid
+        } else if velocity.x.abs() > RUN_ANIMATION_MIN_SPEED {
+            AnimationState::Run
+        } else {
+            AnimationState::Idle
+        };
+        // Only write when it changes, so change detection stays meaningful
+        if *animation != next {
+            *animation = next;
+        }
+    }
+}
+
+fn update_facing(mut query: Query<(&Velocity, &mut Facing)>) {
+    for (velocity, mut facing) in &mut query {
+        let next = if velocity.x < 0.0 {
+            Facing::Left
+        } else if velocity.x > 0.0 {
+            Facing::Right
+        } else {
+            continue;
+        };
+        if *facing != next {
+            *facing = next;
+        }
+    }
+}
+
+// The sprites are drawn facing right, so flip them when facing left
+fn flip_sprites(
+    mut sprite_query: Query<(&Facing, &mut Sprite), Changed<Facing>>,
+    mut sheet_query: Query<(&Facing, &mut PaletteSprite), Changed<Facing>>,
+) {
+    for (facing, mut sprite) in &mut sprite_query {
+        sprite.flip_x = *facing == Facing::Left;
+    }
+    for (facing, mut sprite) in &mut sheet_query {
+        sprite.flip_x = *facing == Facing::Left;
+    }
+}
+
+fn animate_sprites(
+    time: Res<Time>,
+    mut query: Query<(&AnimationState, &mut AnimationTimer, &mut PaletteSprite)>,
+) {
+    for (animation, mut timer, mut sprite) in &mut query {
+        // Every animation starts from its first frame
+        if timer.playing != *animation {
+            timer.playing = *animation;
+            timer.frame = 0;
+            timer.timer.reset();
+        }
+
+        let (first, count) = animation.frames();
+        timer.timer.tick(time.delta());
+        if timer.timer.just_finished() {
+            timer.frame = (timer.frame + 1) % count;
+        }
+        sprite.index = first + timer.frame;
+    }
+}
+
+// Moves each player by their velocity, stopping them against whatever they run into. The rapier
+// backend has its own version.
+#[cfg_attr(feature = "rapier", allow(dead_code))]
+pub fn move_players(
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Velocity,
+            &mut Transform,
+            Option<&GravityScale>,
+            Option<&WrapsHorizontally>,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
+    spatial_hash: Res<SpatialHash>,
+    bounds: Res<ArenaBounds>,
+    collider_query: Query<(&Transform, Option<&OneWayPlatform>), (With<Collider>, Without<Player>)>,
+    mut collision_events: MessageWriter<CollisionEvent>,
+    modifiers: Res<Modifiers>,
+) {
+    for (entity, mut velocity, mut transform, gravity_scale, wraps) in &mut player_query {
+        let mut center = transform.translation.truncate();
+        let size = transform.scale.truncate();
+        let motion = velocity.0 * TIME_STEP;
+
+        // Everything near the whole path of this step
+        let colliders: Vec<_> = spatial_hash
+            .colliders_near(center + motion / 2.0, size + motion.abs())
+            .into_iter()
+            .filter_map(|entity| {
+                let (transform, one_way) = collider_query.get(entity).ok()?;
+                Some((entity, transform, one_way))
+            })
+            .collect();
+
+        // Players can still end up inside a collider that appears around them, like a platform
+        // that was just rebuilt. Push them out first, so they rest on top instead of sinking in.
+        // Being inside a one-way platform is fine, that's how they are jumped through.
+        for (_, collider, _) in colliders.iter().filter(|(_, _, one_way)| one_way.is_none()) {
+            if let Some(push) = penetration(center, size, collider) {
+                center += push;
+                velocity.0 = stop_against(velocity.0, push);
+            }
+        }
+
+        let motion = velocity.0 * TIME_STEP;
+        let (center, hits) = sweep(center, size, motion, &colliders);
+        transform.translation = center.extend(transform.translation.z);
+
+        // Mario doesn't bounce off walls, he just stops
+        if let Some(wall) = hits.x {
+            let side = if velocity.x > 0.0 {
+                Collision::Left
+            } else {
+                Collision::Right
+            };
+            collision_events.write(CollisionEvent::new(entity, wall, side, center, size));
+            velocity.x = 0.0;
+        }
+        // Jumping through a one-way platform still bumps it
+        if let Some(platform) = hits.through {
+            collision_events.write(CollisionEvent::new(
+                entity,
+                platform,
+                Collision::Bottom,
+                center,
+                size,
+            ));
+        }
+        if let Some(platform) = hits.y {
+            let side = if velocity.y > 0.0 {
+                Collision::Bottom
+            } else {
+                Collision::Top
+            };
+            collision_events.write(CollisionEvent::new(entity, platform, side, center, size));
+            velocity.y = 0.0;
+        }
+
+        if wraps.is_some() {
+            bounds.wrap(&mut transform.translation);
+        }
+        apply_gravity(&mut velocity, gravity_scale, &modifiers);
+    }
+}
+
+// In versus mode, bumping the platform under the other player knocks them off balance. Co-op
+// players can choose to play that way too.
+fn stagger_bumped_players(
+    (game_mode, coop_rules): (Res<GameMode>, Res<CoopRules>),
+    mut bump_events: MessageReader<PlatformBumped>,
+    platform_query: Query<&Transform, With<Collider>>,
+    mut player_query: Query<(&Player, &Transform, &mut Velocity, &mut StatusEffects)>,
+) {
+    if *game_mode != GameMode::Versus && !coop_rules.bump_partner {
+        return;
+    }
+
+    for bump in bump_events.read() {
+        let Ok(platform_transform) = platform_query.get(bump.platform) else {
+            continue;
+        };
+
+        for (player, transform, mut velocity, mut effects) in &mut player_query {
+            if player.index == bump.player || !hit_by_bump(bump, platform_transform, transform) {
+                continue;
+            }
+            velocity.x = 0.0;
+            velocity.y = STAGGER_BUMP_SPEED;
+            effects.apply(StatusKind::Staggered, STAGGER_SECONDS);
+        }
+    }
+}
+
+// An enemy kicked by one player knocks over the other if it flies into them, and is gone
+fn stagger_kicked_players(
+    mut commands: Commands,
+    kicked_query: Query<(Entity, &Transform, &KickedEnemy)>,
+    mut player_query: Query<
+        (&Player, &Transform, &mut Velocity, &mut StatusEffects),
+        Without<Dying>,
+    >,
+) {
+    for (kicked, kicked_transform, kicked_enemy) in &kicked_query {
+        for (player, transform, mut velocity, mut effects) in &mut player_query {
+            let hit = collide(
+                transform.translation,
+                transform.scale.truncate(),
+                kicked_transform.translation,
+                kicked_transform.scale.truncate(),
+            );
+            if player.index == kicked_enemy.by || hit.is_none() {
+                continue;
+            }
+            velocity.x = 0.0;
+            velocity.y = STAGGER_BUMP_SPEED;
+            effects.apply(StatusKind::Staggered, STAGGER_SECONDS);
+            commands.entity(kicked).despawn();
+            break;
+        }
+    }
+}
+
+// Players that collide and ended up overlapping after moving are pushed apart. Side by side they
+// each give way by half; one coming down on the other's head is put on top of it, where
+// `detect_ground` lets them stand.
+fn separate_players(
+    coop_rules: Res<CoopRules>,
+    mut query: Query<(&mut Transform, &mut Velocity), (With<Player>, Without<Dying>)>,
+) {
+    if !coop_rules.players_collide {
+        return;
+    }
+
+    let mut pairs = query.iter_combinations_mut();
+    while let Some([(mut first, mut first_velocity), (mut second, mut second_velocity)]) =
+        pairs.fetch_next()
+    {
+        let offset = (second.translation - first.translation).truncate();
+        let overlap = (first.scale.truncate() + second.scale.truncate()) / 2.0 - offset.abs();
+        if overlap.x <= 0.0 || overlap.y <= 0.0 {
+            continue;
+        }
+
+        if overlap.x < overlap.y {
+            let push = overlap.x / 2.0 * offset.x.signum();
+            first.translation.x -= push;
+            second.translation.x += push;
+            if first_velocity.x * push > 0.0 {
+                first_velocity.x = 0.0;
+            }
+            if second_velocity.x * push < 0.0 {
+                second_velocity.x = 0.0;
+            }
+        } else if offset.y > 0.0 {
+            second.translation.y += overlap.y;
+            second_velocity.y = second_velocity.y.max(0.0);
+        } else {
+            first.translation.y += overlap.y;
+            first_velocity.y = first_velocity.y.max(0.0);
+        }
+    }
+}
+
+// Kicks in quick succession are worth 1x, 2x, 3x then 4x the base points
+fn score_defeated_enemies(
+    mut defeated_events: MessageReader<EnemyDefeated>,
+    mut combo_tracker: ResMut<ComboTracker>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut popup_events: MessageWriter<ScorePopup>,
+    mut combo_events: MessageWriter<ComboExtended>,
+) {
+    for defeated in defeated_events.read() {
+        let combo = &mut combo_tracker.combos[defeated.player];
+        combo.extend();
+        combo_events.write(ComboExtended { chain: combo.chain });
+
+        let points = defeated.base_points * combo.chain + defeated.bonus;
+        scoreboard.add(defeated.player, points);
+        scoreboard.count_defeated(defeated.player);
+        popup_events.write(ScorePopup {
+            position: defeated.position,
+            points,
+        });
+    }
+}
+
+// Coins keep a chain going and count towards it, but are always worth their own points
+fn score_coins(
+    mut coin_events: MessageReader<CoinCollected>,
+    mut combo_tracker: ResMut<ComboTracker>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut popup_events: MessageWriter<ScorePopup>,
+    mut combo_events: MessageWriter<ComboExtended>,
+) {
+    for coin in coin_events.read() {
+        scoreboard.add(coin.player, coin.points);
+        popup_events.write(ScorePopup {
+            position: coin.position,
+            points: coin.points,
+        });
+        let combo = &mut combo_tracker.combos[coin.player];
+        combo.extend();
+        combo_events.write(ComboExtended { chain: combo.chain });
+    }
+}
+
+// Hazards and hits on the boss don't count towards a chain, and are always worth the same
+fn score_destroyed_hazards(
+    mut destroyed_events: MessageReader<HazardDestroyed>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut popup_events: MessageWriter<ScorePopup>,
+) {
+    for destroyed in destroyed_events.read() {
+        scoreboard.add(destroyed.player, destroyed.points);
+        popup_events.write(ScorePopup {
+            position: destroyed.position,
+            points: destroyed.points,
+        });
+    }
+}
+
+fn score_boss_hits(
+    mut hit_events: MessageReader<BossHit>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut popup_events: MessageWriter<ScorePopup>,
+) {
+    for hit in hit_events.read() {
+        scoreboard.add(hit.player, BOSS_HIT_POINTS);
+        popup_events.write(ScorePopup {
+            position: hit.position,
+            points: BOSS_HIT_POINTS,
+        });
+    }
+}
+
+// Every EXTRA_LIFE_POINTS points are worth a life
+fn award_extra_lives(
+    game_mode: Res<GameMode>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut lives: ResMut<Lives>,
+    mut extra_life_events: MessageWriter<ExtraLifeAwarded>,
+) {
+    for player in 0..MAX_PLAYERS {
+        // Borrowing it mutably marks it changed, and the HUD redraws the scores whenever it is
+        if !scoreboard.extra_life_due(player) {
+            continue;
+        }
+        for _ in 0..scoreboard.take_extra_lives(player) {
+            lives.gain(player, game_mode.player_count());
+            extra_life_events.write(ExtraLifeAwarded { player });
+        }
+    }
+}
+
+fn decay_combos(mut combo_tracker: ResMut<ComboTracker>) {
+    // Only a chain running out is a change the HUD needs to see, not every tick of its timer
+    let mut ended = false;
+    for combo in &mut combo_tracker.bypass_change_detection().combos {
+        if combo.chain == 0 {
+            continue;
+        }
+        combo.decay.tick(Duration::from_secs_f32(TIME_STEP));
+        if combo.decay.is_finished() {
+            combo.chain = 0;
+            ended = true;
+        }
+    }
+    if ended {
+        combo_tracker.set_changed();
+    }
+}
+
+// Touching an enemy that is still on its feet, or any hazard, costs a life
+fn check_for_enemy_contact(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    mut lives: ResMut<Lives>,
+    mut player_query: Query<
+        (
+            Entity,
+            &Player,
+            &Transform,
+            &mut Velocity,
+            &mut GravityScale,
+            &mut AnimationState,
+            &StatusEffects,
+        ),
+        Without<Dying>,
+    >,
+    enemy_query: Query<
+        (&Transform, Option<&StatusEffects>),
+        (
+            Or<((With<Enemy>, Without<Flipped>), With<Hazard>, With<Boss>)>,
+            Without<Player>,
+        ),
+    >,
+) {
+    for (entity, player, transform, mut velocity, mut gravity_scale, mut animation, effects) in
+        &mut player_query
+    {
+        // The game is already over, we are just waiting for the state to change
+        if lives.game_over(game_mode.player_count()) {
+            return;
+        }
+        if effects.invincible() {
+            continue;
+        }
+
+        // A stunned boss can be walked through
+        let touching_enemy = enemy_query.iter().any(|(enemy_transform, enemy_effects)| {
+            !enemy_effects.is_some_and(|effects| effects.has(StatusKind::Stunned))
+                && collide(
+                    transform.translation,
+                    transform.scale.truncate(),
+                    enemy_transform.translation,
+                    enemy_transform.scale.truncate(),
+                )
+                .is_some()
+        });
+        if !touching_enemy {
+            continue;
+        }
+
+        start_dying(
+            &mut commands,
+            &mut lives,
+            entity,
+            player,
+            &mut velocity,
+            &mut gravity_scale,
+            &mut animation,
+        );
+    }
+}
+
+// Getting squeezed by an elevator costs a life, even while invincible
+fn crush_players(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    mut lives: ResMut<Lives>,
+    mut crushed_events: MessageReader<Crushed>,
+    mut player_query: Query<
+        (
+            &Player,
+            &mut Velocity,
+            &mut GravityScale,
+            &mut AnimationState,
+        ),
+        Without<Dying>,
+    >,
+) {
+    for crushed in crushed_events.read() {
+        if lives.game_over(game_mode.player_count()) {
+            return;
+        }
+        let Ok((player, mut velocity, mut gravity_scale, mut animation)) =
+            player_query.get_mut(crushed.entity)
+        else {
+            continue;
+        };
+        start_dying(
+            &mut commands,
+            &mut lives,
+            crushed.entity,
+            player,
+            &mut velocity,
+            &mut gravity_scale,
+            &mut animation,
+        );
+    }
+}
+
+// Falling into a lava or water floor costs a life, even while invincible
+fn sink_players(
+    mut commands: Commands,
+    game_mode: Res<GameMode>,
+    mut lives: ResMut<Lives>,
+    mut enter_events: MessageReader<TriggerEnter>,
+    floor_query: Query<(), With<HazardFloor>>,
+    mut player_query: Query<
+        (
+            &Player,
+            &mut Velocity,
+            &mut GravityScale,
+            &mut AnimationState,
+        ),
+        Without<Dying>,
+    >,
+) {
+    for enter in enter_events.read() {
+        if lives.game_over(game_mode.player_count()) {
+            return;
+        }
+        if !floor_query.contains(enter.sensor) {
+            continue;
+        }
+        let Ok((player, mut velocity, mut gravity_scale, mut animation)) =
+            player_query.get_mut(enter.entity)
+        else {
+            continue;
+        };
+        start_dying(
+            &mut commands,
+            &mut lives,
+            enter.entity,
+            player,
+            &mut velocity,
+            &mut gravity_scale,
+            &mut animation,
+        );
+    }
+}
+
+// Takes a life, and sends the player popping up and falling off the screen
+fn start_dying(
+    commands: &mut Commands,
+    lives: &mut Lives,
+    entity: Entity,
+    player: &Player,
+    velocity: &mut Velocity,
+    gravity_scale: &mut GravityScale,
+    animation: &mut AnimationState,
+) {
+    lives.lose(player.index);
+    velocity.0 = Vec2::new(0.0, DEATH_POP_SPEED);
+    gravity_scale.0 = 1.0;
+    *animation = AnimationState::Death;
+    commands.entity(entity).insert(Dying);
+}
+
+// Deaths have a few different causes, which all end up in `start_dying`
+fn announce_deaths(
+    query: Query<(&Player, &Transform), Added<Dying>>,
+    mut died_events: MessageWriter<PlayerDied>,
+) {
+    for (player, transform) in &query {
+        died_events.write(PlayerDied {
+            player: player.index,
+            position: transform.translation,
+        });
+    }
+}
+
+// Once a dying player has fallen off the screen they respawn, or the game ends
+fn finish_dying(
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    game_mode: Res<GameMode>,
+    lives: Res<Lives>,
+    mut player_query: Query<
+        (
+            Entity,
+            &Player,
+            &mut Transform,
+            &mut Velocity,
+            &mut Grounded,
+            &mut AnimationState,
+            &mut StatusEffects,
+        ),
+        With<Dying>,
+    >,
+) {
+    for (entity, player, mut transform, mut velocity, mut grounded, mut animation, mut effects) in
+        &mut player_query
+    {
+        if transform.translation.y > DEATH_FALL_Y {
+            continue;
+        }
+
+        // In versus mode the round ends as soon as either player runs out of lives
+        // A frame can run more than one step before the game over screen takes over, and the
+        // later ones find the game just as over
+        if lives.game_over(game_mode.player_count()) {
+            change_state(&mut next_state, GameState::EnterInitials);
+            return;
+        }
+        // Kept just under the screen until the other player brings them back
+        if lives.waiting(player.index) {
+            transform.translation.y = DEATH_FALL_Y;
+            velocity.0 = Vec2::ZERO;
+            continue;
+        }
+
+        respawn_player(
+            &mut commands,
+            player,
+            &mut transform,
+            &mut velocity,
+            &mut grounded,
+        );
+        *animation = AnimationState::Idle;
+        // Losing a life cuts a star short
+        effects.end(StatusKind::Star);
+        effects.apply(StatusKind::Invincible, INVINCIBLE_SECONDS);
+        commands.entity(entity).remove::<Dying>();
+    }
+}
+
+// On separate lives, clearing a phase brings back whoever ran out, falling in like after any
+// other death
+fn bring_back_players(
+    mut cleared_events: MessageReader<PhaseCleared>,
+    game_mode: Res<GameMode>,
+    mut lives: ResMut<Lives>,
+) {
+    if cleared_events.read().count() > 0 {
+        lives.bring_back(game_mode.player_count());
+    }
+}
+
+// A restarted phase puts the players back where it started them. A player already losing a life
+// still falls off the screen and respawns the usual way, so a restart can't save a life.
+fn restart_players(
+    mut commands: Commands,
+    mut restart_events: MessageReader<RestartPhase>,
+    phase: Res<Phase>,
+    (levels, level_assets): (Res<Levels>, Res<Assets<LevelDef>>),
+    mut player_query: Query<
+        (
+            &Player,
+            &mut Transform,
+            &mut Velocity,
+            &mut Grounded,
+            &mut JumpState,
+            &mut AnimationState,
+            &mut StatusEffects,
+        ),
+        Without<Dying>,
+    >,
+    platform_query: Query<Entity, With<RespawnPlatform>>,
+) {
+    if restart_events.read().count() == 0 {
+        return;
+    }
+
+    let level = levels.for_phase(phase.0, &level_assets);
+    for (player, mut transform, mut velocity, mut grounded, mut jump, mut animation, mut effects) in
+        &mut player_query
+    {
+        transform.translation = level.player_spawn(player.index);
+        velocity.0 = Vec2::ZERO;
+        grounded.0 = None;
+        *jump = JumpState::default();
+        *animation = AnimationState::Idle;
+        // Ended rather than dropped, so they still expire and put the sprite back to normal
+        effects.end(StatusKind::Invincible);
+        effects.end(StatusKind::Star);
+        effects.end(StatusKind::Staggered);
+    }
+    for platform in &platform_query {
+        commands.entity(platform).despawn();
+    }
+}
+
+// A respawned player blinks, a player with a star cycles colors
+fn animate_status_effects(
+    mut ticked_events: MessageReader<StatusTicked>,
+    mut expired_events: MessageReader<StatusExpired>,
+    mut query: Query<(&Player, &mut Visibility, &mut PaletteSprite)>,
+) {
+    for ticked in ticked_events.read() {
+        let Ok((_, mut visibility, mut sprite)) = query.get_mut(ticked.entity) else {
+            continue;
+        };
+        match ticked.kind {
+            StatusKind::Invincible => {
+                *visibility = if ticked.ticks.is_multiple_of(2) {
+                    Visibility::Inherited
+                } else {
+                    Visibility::Hidden
+                };
+            }
+            StatusKind::Star => sprite.color = STAR_COLORS[ticked.ticks % STAR_COLORS.len()],
+            StatusKind::Staggered | StatusKind::Stunned => {}
+        }
+    }
+    for expired in expired_events.read() {
+        let Ok((_, mut visibility, mut sprite)) = query.get_mut(expired.entity) else {
+            continue;
+        };
+        match expired.kind {
+            StatusKind::Invincible => *visibility = Visibility::Inherited,
+            StatusKind::Star => sprite.color = Color::WHITE,
+            StatusKind::Staggered | StatusKind::Stunned => {}
+        }
+    }
+}
+
+fn expire_respawn_platforms(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut RespawnPlatform)>,
+) {
+    for (platform, mut respawn_platform) in &mut query {
+        respawn_platform.0.tick(Duration::from_secs_f32(TIME_STEP));
+        if respawn_platform.0.is_finished() {
+            commands.entity(platform).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_extra_life_below_the_first_threshold() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.add(0, EXTRA_LIFE_POINTS - 1);
+        assert_eq!(scoreboard.take_extra_lives(0), 0);
+    }
+
+    #[test]
+    fn crossing_several_thresholds_at_once_awards_each() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.add(0, EXTRA_LIFE_POINTS * 3 + 500);
+        assert_eq!(scoreboard.take_extra_lives(0), 3);
+        // Already awarded
+        assert_eq!(scoreboard.take_extra_lives(0), 0);
+        scoreboard.add(0, EXTRA_LIFE_POINTS - 500);
+        assert_eq!(scoreboard.take_extra_lives(0), 1);
+    }
+
+    #[test]
+    fn each_player_has_their_own_thresholds() {
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.add(0, EXTRA_LIFE_POINTS * 2);
+        assert_eq!(scoreboard.take_extra_lives(1), 0);
+        assert_eq!(scoreboard.take_extra_lives(0), 2);
+    }
+}
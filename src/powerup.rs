@@ -0,0 +1,355 @@
+//! Power-ups. Once in a while a kicked enemy leaves one behind as well as its coin. It slides
+//! along the platforms for a few seconds, waiting to be picked up:
+//!
+//! - A star makes the player invincible for a while: enemies and hazards they touch are defeated
+//!   instead of costing them a life.
+//! - A hammer gives the player a few swings of the attack button. A swing flips the enemies in
+//!   front of them on the spot, or knocks out the ones already flipped.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{
+    controls::{Action, StepInputs},
+    enemy::{
+        count_kicked_enemies, kick_flipped_enemies, Enemy, EnemyDefeated, EnemyKicked, Flipped,
+        Hazard, ScoreKind,
+    },
+    generator::Rng,
+    level::{collide, RestartPhase, Sensor, TriggerEnter, Velocity, WrapsHorizontally},
+    player::{Dying, MoveMarioInput, Player},
+    status::{StatusEffects, StatusKind},
+    GameState, PauseState, StepSet, BLOCK_SIZE, TIME_STEP,
+};
+
+const POWERUP_SIZE: Vec3 = Vec3::new(BLOCK_SIZE * 1.5, BLOCK_SIZE * 1.5, 0.0);
+const STAR_COLOR: Color = Color::srgb(1.0, 0.95, 0.3);
+const HAMMER_COLOR: Color = Color::srgb(0.75, 0.75, 0.8);
+// How long a star makes the player invincible
+const STAR_SECONDS: f32 = 8.0;
+const POWERUP_XSPEED: f32 = 150.0;
+const POWERUP_POP_SPEED: f32 = 700.0;
+// Left lying around for this long, it is gone
+const POWERUP_LIFETIME_SECONDS: f32 = 10.0;
+// Out of this many kicks, how many leave each power-up. The rest leave none.
+const DROP_ODDS: usize = 15;
+const DROP_TABLE: [(PowerupKind, usize); 2] = [(PowerupKind::Star, 1), (PowerupKind::Hammer, 2)];
+// Mixed into the number of every kick, to pick what it leaves
+const DROP_SEED: u64 = 0x57A2_D20B;
+const HAMMER_SWINGS: u32 = 6;
+// Two frames of the swing animation
+const HAMMER_SWING_SECONDS: f32 = 0.2;
+// What a swing hits, in front of the player
+const HAMMER_REACH: Vec2 = Vec2::new(BLOCK_SIZE * 3.0, BLOCK_SIZE * 3.0);
+
+pub struct PowerupPlugin;
+
+impl Plugin for PowerupPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PowerupDrops>()
+            .add_message::<HammerSwung>()
+            .add_systems(OnEnter(GameState::Playing), reset_drops)
+            .add_systems(Update, clear_powerups.run_if(in_state(PauseState::Running)))
+            .add_systems(
+                FixedUpdate,
+                swing_hammers.after(MoveMarioInput).in_set(StepSet::Input),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    drop_powerups.after(defeat_touched_enemies),
+                    collect_powerups,
+                    expire_powerups,
+                    defeat_touched_enemies
+                        .after(kick_flipped_enemies)
+                        .before(count_kicked_enemies),
+                )
+                    .in_set(StepSet::Gameplay),
+            );
+    }
+}
+
+// Everything of the power-ups' that the gameplay step changes, for an online game to put back
+// when it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<Powerup>()
+        .rollback_component_with_reflect::<Hammer>()
+        .rollback_resource_with_reflect::<PowerupDrops>();
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default)]
+enum PowerupKind {
+    #[default]
+    Star,
+    Hammer,
+}
+
+impl PowerupKind {
+    fn color(self) -> Color {
+        match self {
+            PowerupKind::Star => STAR_COLOR,
+            PowerupKind::Hammer => HAMMER_COLOR,
+        }
+    }
+}
+
+// A power-up lying around, waiting to be picked up
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+struct Powerup {
+    kind: PowerupKind,
+    lifetime: Timer,
+}
+
+// Held by a player who picked up a hammer, until the last swing is over
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Hammer {
+    swings_left: u32,
+    // Counts down while a swing is under way
+    swing_seconds: f32,
+    attack_was_down: bool,
+    // Which way the last swing went, kept from the step the player last moved
+    facing_left: bool,
+}
+
+impl Hammer {
+    pub fn swinging(&self) -> bool {
+        self.swing_seconds > 0.0
+    }
+}
+
+// Sent when a player swings a hammer, with the area in front of them it hits
+#[derive(Message)]
+pub struct HammerSwung {
+    pub player: usize,
+    pub center: Vec3,
+    pub size: Vec2,
+}
+
+impl HammerSwung {
+    pub fn hits(&self, transform: &Transform) -> bool {
+        collide(
+            self.center,
+            self.size,
+            transform.translation,
+            transform.scale.truncate(),
+        )
+        .is_some()
+    }
+}
+
+// How many enemies were kicked so far, to pick which ones leave a power-up
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+struct PowerupDrops {
+    kicks: usize,
+}
+
+fn reset_drops(mut drops: ResMut<PowerupDrops>) {
+    *drops = PowerupDrops::default();
+}
+
+// Only one power-up at a time, popping out the other way from the coin
+fn drop_powerups(
+    mut commands: Commands,
+    mut drops: ResMut<PowerupDrops>,
+    mut kick_events: MessageReader<EnemyKicked>,
+    powerup_query: Query<(), With<Powerup>>,
+) {
+    let mut dropped = !powerup_query.is_empty();
+    for kick in kick_events.read() {
+        let mut rng = Rng::new(DROP_SEED ^ drops.kicks as u64);
+        drops.kicks += 1;
+        if dropped {
+            continue;
+        }
+        let mut pick = rng.range(0, DROP_ODDS - 1);
+        let Some(kind) = DROP_TABLE.iter().find_map(|&(kind, odds)| {
+            let found = pick < odds;
+            pick = pick.saturating_sub(odds);
+            found.then_some(kind)
+        }) else {
+            continue;
+        };
+        dropped = true;
+        commands.spawn((
+            Sprite {
+                color: kind.color(),
+                ..default()
+            },
+            Transform::from_translation(kick.position).with_scale(POWERUP_SIZE),
+            Powerup {
+                kind,
+                lifetime: Timer::from_seconds(POWERUP_LIFETIME_SECONDS, TimerMode::Once),
+            },
+            Sensor::default(),
+            Velocity(Vec2::new(
+                -kick.direction * POWERUP_XSPEED,
+                POWERUP_POP_SPEED,
+            )),
+            WrapsHorizontally,
+            DespawnOnExit(GameState::Playing),
+        ));
+    }
+}
+
+fn collect_powerups(
+    mut commands: Commands,
+    mut trigger_events: MessageReader<TriggerEnter>,
+    mut player_query: Query<&mut StatusEffects, (With<Player>, Without<Dying>)>,
+    powerup_query: Query<&Powerup>,
+) {
+    let mut collected = Vec::new();
+    for trigger in trigger_events.read() {
+        let Ok(powerup) = powerup_query.get(trigger.sensor) else {
+            continue;
+        };
+        // Both players can reach a power-up in the same step, the first one gets it
+        if collected.contains(&trigger.sensor) {
+            continue;
+        }
+        let Ok(mut effects) = player_query.get_mut(trigger.entity) else {
+            continue;
+        };
+        collected.push(trigger.sensor);
+        match powerup.kind {
+            PowerupKind::Star => {
+                // Picking up a star while blinking puts a stop to the blinking
+                effects.end(StatusKind::Invincible);
+                effects.apply(StatusKind::Star, STAR_SECONDS);
+            }
+            PowerupKind::Hammer => {
+                commands.entity(trigger.entity).insert(Hammer {
+                    swings_left: HAMMER_SWINGS,
+                    ..default()
+                });
+            }
+        }
+        commands.entity(trigger.sensor).despawn();
+    }
+}
+
+fn expire_powerups(mut commands: Commands, mut query: Query<(Entity, &mut Powerup)>) {
+    for (entity, mut powerup) in &mut query {
+        powerup.lifetime.tick(Duration::from_secs_f32(TIME_STEP));
+        if powerup.lifetime.is_finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+// A restarted phase starts without power-ups, lying around or held
+fn clear_powerups(
+    mut commands: Commands,
+    mut restart_events: MessageReader<RestartPhase>,
+    powerup_query: Query<Entity, With<Powerup>>,
+    hammer_query: Query<Entity, With<Hammer>>,
+) {
+    if restart_events.read().count() == 0 {
+        return;
+    }
+    for powerup in &powerup_query {
+        commands.entity(powerup).despawn();
+    }
+    for player in &hammer_query {
+        commands.entity(player).remove::<Hammer>();
+    }
+}
+
+// A player with a star knocks out every enemy and hazard they touch. Enemies count as kicked,
+// and leave a coin; flipped ones are kicked the usual way.
+fn defeat_touched_enemies(
+    mut commands: Commands,
+    player_query: Query<(&Player, &Transform, &StatusEffects), Without<Dying>>,
+    enemy_query: Query<
+        (Entity, &Transform, &ScoreKind, Option<&Enemy>),
+        Or<((With<Enemy>, Without<Flipped>), With<Hazard>)>,
+    >,
+    mut defeated_events: MessageWriter<EnemyDefeated>,
+    mut kick_events: MessageWriter<EnemyKicked>,
+) {
+    for (enemy, transform, score_kind, is_enemy) in &enemy_query {
+        let toucher = player_query
+            .iter()
+            .filter(|(_, _, effects)| effects.has(StatusKind::Star))
+            .find(|(_, player_transform, _)| {
+                collide(
+                    player_transform.translation,
+                    player_transform.scale.truncate(),
+                    transform.translation,
+                    transform.scale.truncate(),
+                )
+                .is_some()
+            });
+        let Some((player, player_transform, _)) = toucher else {
+            continue;
+        };
+        defeated_events.write(EnemyDefeated {
+            player: player.index,
+            position: transform.translation,
+            base_points: score_kind.points(),
+            bonus: 0,
+        });
+        if is_enemy.is_some() {
+            kick_events.write(EnemyKicked {
+                position: transform.translation,
+                direction: (transform.translation.x - player_transform.translation.x).signum(),
+            });
+        }
+        commands.entity(enemy).despawn();
+    }
+}
+
+// A press of the attack button swings the hammer, one swing at a time. Losing a life loses the
+// hammer too.
+pub fn swing_hammers(
+    mut commands: Commands,
+    inputs: Res<StepInputs>,
+    mut query: Query<(
+        Entity,
+        &Player,
+        &Transform,
+        &Velocity,
+        &mut Hammer,
+        Option<&Dying>,
+    )>,
+    mut swung_events: MessageWriter<HammerSwung>,
+) {
+    for (entity, player, transform, velocity, mut hammer, dying) in &mut query {
+        if dying.is_some() {
+            commands.entity(entity).remove::<Hammer>();
+            continue;
+        }
+        if velocity.x != 0.0 {
+            hammer.facing_left = velocity.x < 0.0;
+        }
+        let attack_down = inputs.0[player.index].held(Action::Attack);
+        let pressed = attack_down && !hammer.attack_was_down;
+        hammer.attack_was_down = attack_down;
+
+        if hammer.swinging() {
+            hammer.swing_seconds -= TIME_STEP;
+            if !hammer.swinging() && hammer.swings_left == 0 {
+                commands.entity(entity).remove::<Hammer>();
+            }
+            continue;
+        }
+        if !pressed || hammer.swings_left == 0 {
+            continue;
+        }
+        hammer.swings_left -= 1;
+        hammer.swing_seconds = HAMMER_SWING_SECONDS;
+        let direction = if hammer.facing_left { -1.0 } else { 1.0 };
+        let offset = (transform.scale.x + HAMMER_REACH.x) / 2.0 * direction;
+        swung_events.write(HammerSwung {
+            player: player.index,
+            center: transform.translation + Vec3::X * offset,
+            size: HAMMER_REACH,
+        });
+    }
+}
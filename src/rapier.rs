@@ -0,0 +1,186 @@
+//! Moves the players with bevy_rapier2d's character controller instead of our own sweep.
+//! Everything else keeps going through `Velocity`, `Grounded` and our `Collider`, so the rest of
+//! the game plays the same with either backend.
+
+use bevy::prelude::*;
+use bevy_rapier2d::{
+    prelude::{
+        Collider as RapierCollider, NoUserData, QueryFilter, RapierConfiguration,
+        RapierPhysicsPlugin, ReadRapierContext,
+    },
+    rapier::{
+        control::{CharacterLength, KinematicCharacterController},
+        math::Pose,
+    },
+};
+
+use crate::{
+    level::{
+        apply_gravity, one_way_blocks, sweep, ArenaBounds, Collider, Collision, CollisionEvent,
+        GravityScale, OneWayPlatform, SpatialHash, Velocity, WrapsHorizontally,
+    },
+    mutators::Modifiers,
+    player::{Dying, MovePlayers, Player},
+    StepSet, BLOCK_SIZE, TIME_STEP,
+};
+
+// Gap the controller keeps between players and what they stand on or run into
+const CONTACT_OFFSET: f32 = 0.1;
+
+pub struct RapierBackendPlugin;
+
+impl Plugin for RapierBackendPlugin {
+    fn build(&self, app: &mut App) {
+        let physics = RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(BLOCK_SIZE);
+        app.add_plugins(physics)
+            .add_systems(Update, (configure_rapier, add_rapier_colliders))
+            .add_systems(
+                FixedUpdate,
+                move_players.in_set(MovePlayers).in_set(StepSet::Physics),
+            );
+    }
+}
+
+// Nothing is simulated; rapier only answers the controller's queries
+fn configure_rapier(mut query: Query<&mut RapierConfiguration, Added<RapierConfiguration>>) {
+    for mut configuration in &mut query {
+        configuration.gravity = Vec2::ZERO;
+        configuration.physics_pipeline_active = false;
+    }
+}
+
+// Every collider of ours gets a rapier one, sized by its transform's scale like its sprite
+fn add_rapier_colliders(mut commands: Commands, query: Query<Entity, Added<Collider>>) {
+    for entity in &query {
+        commands
+            .entity(entity)
+            .insert(RapierCollider::cuboid(0.5, 0.5));
+    }
+}
+
+// Moves each player by their velocity, stopping them against whatever they run into
+pub fn move_players(
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Velocity,
+            &mut Transform,
+            Option<&GravityScale>,
+            Option<&WrapsHorizontally>,
+        ),
+        (With<Player>, Without<Dying>),
+    >,
+    rapier_context: ReadRapierContext,
+    spatial_hash: Res<SpatialHash>,
+    bounds: Res<ArenaBounds>,
+    one_way_query: Query<(Entity, &Transform, Option<&OneWayPlatform>), Without<Player>>,
+    mut collision_events: MessageWriter<CollisionEvent>,
+    modifiers: Res<Modifiers>,
+) {
+    let Ok(rapier_context) = rapier_context.single() else {
+        return;
+    };
+    let controller = KinematicCharacterController {
+        offset: CharacterLength::Absolute(CONTACT_OFFSET),
+        slide: true,
+        autostep: None,
+        snap_to_ground: None,
+        ..default()
+    };
+
+    for (entity, mut velocity, mut transform, gravity_scale, wraps) in &mut player_query {
+        let center = transform.translation.truncate();
+        let size = transform.scale.truncate();
+        let motion = velocity.0 * TIME_STEP;
+
+        // Only the colliders near the path of this step, like the hand-rolled backend
+        let nearby = spatial_hash.colliders_near(center + motion / 2.0, size + motion.abs());
+        // One-way platforms are left out, unless the player is falling onto them
+        let one_ways: Vec<_> = one_way_query
+            .iter_many(&nearby)
+            .filter(|(_, _, one_way)| one_way.is_some())
+            .collect();
+        let passable: Vec<_> = one_ways
+            .iter()
+            .filter(|(_, platform, _)| !one_way_blocks(center, size, motion, platform))
+            .map(|(entity, _, _)| *entity)
+            .collect();
+        // Our own sweep finds out whether the player jumped into one from below
+        let (_, one_way_hits) = sweep(center, size, Vec2::new(0.0, motion.y), &one_ways);
+        let mut hit_wall = None;
+        let mut hit_ceiling = None;
+        let mut landed_on = None;
+        let shape = RapierCollider::cuboid(size.x / 2.0, size.y / 2.0);
+        let blocks = |entity| nearby.contains(&entity) && !passable.contains(&entity);
+        let filter = QueryFilter::default().predicate(&blocks);
+        let output = rapier_context.with_query_pipeline(filter, |pipeline| {
+            controller.move_shape(
+                TIME_STEP,
+                &pipeline.query_pipeline,
+                &*shape.raw,
+                &Pose::from_translation(center),
+                motion,
+                |collision| {
+                    let entity = pipeline.collider_entity(collision.handle);
+                    // The outward normal of the player's box where it touched the collider
+                    let normal = collision.hit.normal1;
+                    if normal.y > 0.5 {
+                        hit_ceiling = Some(entity);
+                    } else if normal.y < -0.5 {
+                        landed_on = Some(entity);
+                    } else {
+                        hit_wall = Some(entity);
+                    }
+                },
+            )
+        });
+        transform.translation += output.translation.extend(0.0);
+        let center = transform.translation.truncate();
+
+        // Mario doesn't bounce off walls, he just stops
+        if let Some(wall) = hit_wall {
+            let side = if velocity.x > 0.0 {
+                Collision::Left
+            } else {
+                Collision::Right
+            };
+            collision_events.write(CollisionEvent::new(entity, wall, side, center, size));
+            velocity.x = 0.0;
+        }
+        // Jumping through a one-way platform still bumps it
+        if let Some(platform) = one_way_hits.through {
+            collision_events.write(CollisionEvent::new(
+                entity,
+                platform,
+                Collision::Bottom,
+                center,
+                size,
+            ));
+        }
+        if let Some(platform) = hit_ceiling.filter(|_| velocity.y > 0.0) {
+            collision_events.write(CollisionEvent::new(
+                entity,
+                platform,
+                Collision::Bottom,
+                center,
+                size,
+            ));
+            velocity.y = 0.0;
+        }
+        if let Some(platform) = landed_on.filter(|_| velocity.y < 0.0) {
+            collision_events.write(CollisionEvent::new(
+                entity,
+                platform,
+                Collision::Top,
+                center,
+                size,
+            ));
+            velocity.y = 0.0;
+        }
+
+        if wraps.is_some() {
+            bounds.wrap(&mut transform.translation);
+        }
+        apply_gravity(&mut velocity, gravity_scale, &modifiers);
+    }
+}
@@ -0,0 +1,492 @@
+//! Replays of whole runs. Every run played on this machine is recorded: what it was started with,
+//! and what each player held during every gameplay step. "Watch replay" on the title menu plays
+//! the last one back through the same gameplay step, which plays out the same run again.
+//!
+//! A replay also keeps the scores its run ended with. Played back to the end with other ones, the
+//! game no longer plays the way it did when the replay was recorded, which makes replays worth
+//! keeping around for checking changes to the physics.
+//!
+//! When the title menu sits idle, it plays the demo replay that comes with the game instead, like
+//! an arcade cabinet's attract mode, until any key or button brings the menu back.
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_state,
+    console::ConsoleCommandRun,
+    controls::{PlayerInput, StepInputs},
+    level::{LevelChoice, LevelDef, Levels, RestartPhase, StartPhase},
+    mutators::{CoopRules, Mutators},
+    player::{MoveMarioInput, PlayerJoined, PlayerLeft, Scoreboard},
+    storage::{self, Location},
+    ui::TEXT_COLOR,
+    GameMode, GameState, PauseState, StepDriver, StepSet, MAX_PLAYERS,
+};
+
+// Only the last run is kept
+const REPLAY_FILE: &str = "replays/last.replay.ron";
+const REPLAY_FONT_SIZE: f32 = 40.0;
+const REPLAY_EXTENSIONS: &[&str] = &["replay.ron"];
+// Relative to the assets folder
+const DEMO_REPLAY: &str = "replays/demo.replay.ron";
+// How long the title menu waits for a key or button before playing the demo
+const ATTRACT_IDLE_SECONDS: f32 = 20.0;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Replay>()
+            .init_asset_loader::<ReplayLoader>()
+            .init_resource::<ReplayRecorder>()
+            .insert_resource(AttractTimer(Timer::from_seconds(
+                ATTRACT_IDLE_SECONDS,
+                TimerMode::Once,
+            )))
+            .add_message::<WatchReplay>()
+            .add_systems(Startup, load_demo)
+            .add_systems(
+                OnEnter(GameState::Menu),
+                (stop_playback, reset_attract_timer),
+            )
+            .add_systems(
+                Update,
+                (start_attract_mode.before(watch_replay), watch_replay)
+                    .run_if(in_state(GameState::Menu)),
+            )
+            // After the gameplay step, so leaving doesn't get in the way of a game over it saw
+            .add_systems(Last, leave_demo)
+            .add_systems(
+                OnEnter(GameState::Playing),
+                (start_recording, spawn_replay_text),
+            )
+            .add_systems(Update, stop_recording.run_if(in_state(PauseState::Running)))
+            .add_systems(OnExit(GameState::Playing), (save_replay, finish_playback))
+            .add_systems(
+                FixedUpdate,
+                (
+                    play_back_inputs.before(MoveMarioInput),
+                    record_inputs.after(play_back_inputs).before(MoveMarioInput),
+                )
+                    .in_set(StepSet::Input),
+            );
+    }
+}
+
+// Sent by the title menu to play a replay back
+#[derive(Message)]
+pub enum WatchReplay {
+    // The last run played on this machine
+    Last,
+    // The demo that comes with the game
+    Demo,
+}
+
+// One run of the game: what it was started with, and what the players held every gameplay step
+#[derive(Asset, TypePath, Clone, Serialize, Deserialize)]
+struct Replay {
+    mode: GameMode,
+    start_phase: usize,
+    level: ReplayLevel,
+    // By name. Replays from before there were mutators have none.
+    #[serde(default)]
+    mutators: Vec<String>,
+    #[serde(default)]
+    coop_rules: CoopRules,
+    // The bits of every player's `PlayerInput`, and for how many steps in a row they were held
+    inputs: Vec<(u32, [u8; MAX_PLAYERS])>,
+    // What the run ended with
+    scores: [usize; MAX_PLAYERS],
+}
+
+// Which layouts a run was played on
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ReplayLevel {
+    Regular,
+    // One of the player's own, by name
+    Custom(String),
+    Endless { seed: u64 },
+}
+
+impl ReplayLevel {
+    // Names the layouts and the phase a run starts from, for what is kept about each
+    pub fn key(&self, start_phase: usize) -> String {
+        match self {
+            ReplayLevel::Regular => format!("phase-{start_phase}"),
+            ReplayLevel::Custom(name) => format!("{name}-phase-{start_phase}"),
+            ReplayLevel::Endless { seed } => format!("endless-{seed}-phase-{start_phase}"),
+        }
+    }
+
+    // The layouts being played. Layouts of the editor being play-tested can't be played again.
+    pub fn of(levels: &Levels) -> Option<ReplayLevel> {
+        if let Some(seed) = levels.endless_seed() {
+            Some(ReplayLevel::Endless { seed })
+        } else if levels.custom.is_some() {
+            levels
+                .custom_name()
+                .map(|name| ReplayLevel::Custom(name.to_string()))
+        } else {
+            Some(ReplayLevel::Regular)
+        }
+    }
+}
+
+#[derive(Default, TypePath)]
+struct ReplayLoader;
+
+impl AssetLoader for ReplayLoader {
+    type Asset = Replay;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Replay, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let replay: Replay = ron::de::from_bytes(&bytes)?;
+        Ok(replay)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        REPLAY_EXTENSIONS
+    }
+}
+
+#[derive(Resource)]
+struct DemoReplay(Handle<Replay>);
+
+// Counts down while the title menu gets no keys or buttons
+#[derive(Resource)]
+struct AttractTimer(Timer);
+
+impl Replay {
+    fn load() -> Option<Replay> {
+        let contents = storage::read(Location::Data, REPLAY_FILE)?;
+        match ron::from_str(&contents) {
+            Ok(replay) => Some(replay),
+            Err(err) => {
+                warn!(
+                    "Could not read the replay {}: {err}",
+                    storage::describe(Location::Data, REPLAY_FILE)
+                );
+                None
+            }
+        }
+    }
+
+    fn save(&self) {
+        let result = ron::to_string(self)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Data, REPLAY_FILE, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the replay to {}: {err}",
+                storage::describe(Location::Data, REPLAY_FILE)
+            );
+        }
+    }
+}
+
+// The run being played, while it is recorded. Runs that can't be played again, like play-tests of
+// the level editor or online games, aren't.
+#[derive(Resource, Default)]
+struct ReplayRecorder(Option<Replay>);
+
+// A replay being watched, and how far it has got
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    // Index into `inputs`, and how many steps of that entry have been played
+    entry: usize,
+    played: u32,
+    finished: bool,
+    // Played by the title menu on its own, rather than asked for
+    demo: bool,
+}
+
+impl ReplayPlayback {
+    fn new(replay: Replay, demo: bool) -> ReplayPlayback {
+        ReplayPlayback {
+            replay,
+            entry: 0,
+            played: 0,
+            finished: false,
+            demo,
+        }
+    }
+
+    pub fn demo(&self) -> bool {
+        self.demo
+    }
+
+    fn next(&mut self) -> Option<[u8; MAX_PLAYERS]> {
+        let &(steps, inputs) = self.replay.inputs.get(self.entry)?;
+        self.played += 1;
+        if self.played >= steps {
+            self.entry += 1;
+            self.played = 0;
+        }
+        Some(inputs)
+    }
+
+    fn rewind(&mut self) {
+        self.entry = 0;
+        self.played = 0;
+        self.finished = false;
+    }
+
+    fn at_end(&self) -> bool {
+        self.entry >= self.replay.inputs.len()
+    }
+}
+
+// Sets the game up the way the replay's run was, and starts it
+fn watch_replay(
+    mut events: MessageReader<WatchReplay>,
+    mut commands: Commands,
+    mut next_state: ResMut<NextState<GameState>>,
+    (mut game_mode, mut start_phase, mut mutators, mut coop_rules): (
+        ResMut<GameMode>,
+        ResMut<StartPhase>,
+        ResMut<Mutators>,
+        ResMut<CoopRules>,
+    ),
+    mut levels: ResMut<Levels>,
+    (asset_server, level_assets): (Res<AssetServer>, Res<Assets<LevelDef>>),
+    (demo, replays): (Res<DemoReplay>, Res<Assets<Replay>>),
+) {
+    let Some(event) = events.read().last() else {
+        return;
+    };
+    let replay = match event {
+        WatchReplay::Last => Replay::load(),
+        WatchReplay::Demo => replays.get(&demo.0).cloned(),
+    };
+    let Some(replay) = replay else {
+        info!("There is no replay to watch yet");
+        return;
+    };
+
+    let choice = match &replay.level {
+        ReplayLevel::Regular => None,
+        ReplayLevel::Custom(name) => {
+            let choice = levels
+                .choices(&asset_server, &level_assets)
+                .into_iter()
+                .find(|choice| matches!(choice, LevelChoice::Custom { name: other, .. } if other == name));
+            if choice.is_none() {
+                warn!("The level {name} of the replay is gone");
+                return;
+            }
+            choice
+        }
+        ReplayLevel::Endless { seed } => Some(LevelChoice::Endless { seed: *seed }),
+    };
+    if let Some(choice) = choice {
+        levels.choose(&choice, &level_assets);
+    }
+    *game_mode = replay.mode;
+    start_phase.0 = replay.start_phase;
+    mutators.0.clone_from(&replay.mutators);
+    *coop_rules = replay.coop_rules;
+    commands.insert_resource(ReplayPlayback::new(
+        replay,
+        matches!(event, WatchReplay::Demo),
+    ));
+    change_state(&mut next_state, GameState::Playing);
+}
+
+fn stop_playback(mut commands: Commands) {
+    commands.remove_resource::<ReplayPlayback>();
+}
+
+fn load_demo(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(DemoReplay(asset_server.load(DEMO_REPLAY)));
+}
+
+fn reset_attract_timer(mut timer: ResMut<AttractTimer>) {
+    timer.0.reset();
+}
+
+// Whether any key or button was just pressed, keyboard or gamepad
+fn any_input(keyboard_input: &ButtonInput<KeyCode>, gamepads: &Query<&Gamepad>) -> bool {
+    keyboard_input.get_just_pressed().next().is_some()
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.get_just_pressed().next().is_some())
+}
+
+fn start_attract_mode(
+    time: Res<Time>,
+    mut timer: ResMut<AttractTimer>,
+    (keyboard_input, gamepads): (Res<ButtonInput<KeyCode>>, Query<&Gamepad>),
+    (asset_server, levels): (Res<AssetServer>, Res<Levels>),
+    (demo, replays): (Res<DemoReplay>, Res<Assets<Replay>>),
+    mut watch_replay: MessageWriter<WatchReplay>,
+) {
+    if any_input(&keyboard_input, &gamepads) {
+        timer.0.reset();
+        return;
+    }
+    // Waits for the arena layouts and the demo to load, however long that takes
+    timer.0.tick(time.delta());
+    if timer.0.is_finished() && levels.loaded(&asset_server) && replays.contains(&demo.0) {
+        watch_replay.write(WatchReplay::Demo);
+    }
+}
+
+fn leave_demo(
+    playback: Option<Res<ReplayPlayback>>,
+    (keyboard_input, gamepads): (Res<ButtonInput<KeyCode>>, Query<&Gamepad>),
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if playback.is_some_and(|playback| playback.demo) && any_input(&keyboard_input, &gamepads) {
+        // Unless the game already went somewhere else this frame
+        change_state(&mut next_state, GameState::Menu);
+    }
+}
+
+fn start_recording(
+    mut recorder: ResMut<ReplayRecorder>,
+    playback: Option<Res<ReplayPlayback>>,
+    game_mode: Res<GameMode>,
+    start_phase: Res<StartPhase>,
+    levels: Res<Levels>,
+    (mutators, coop_rules): (Res<Mutators>, Res<CoopRules>),
+) {
+    recorder.0 = ReplayLevel::of(&levels)
+        .filter(|_| playback.is_none())
+        .map(|level| Replay {
+            mode: *game_mode,
+            start_phase: start_phase.0,
+            level,
+            mutators: mutators.0.clone(),
+            coop_rules: *coop_rules,
+            inputs: Vec::new(),
+            scores: [0; MAX_PLAYERS],
+        });
+}
+
+// A restarted phase, a player dropping in or out or a console command isn't part of the inputs,
+// so a run with one can't be played again
+fn stop_recording(
+    mut restart_events: MessageReader<RestartPhase>,
+    mut joined_events: MessageReader<PlayerJoined>,
+    mut left_events: MessageReader<PlayerLeft>,
+    mut console_events: MessageReader<ConsoleCommandRun>,
+    mut recorder: ResMut<ReplayRecorder>,
+) {
+    let restarted = restart_events.read().count() > 0;
+    let joined = joined_events.read().count() > 0;
+    let left = left_events.read().count() > 0;
+    let commanded = console_events.read().count() > 0;
+    if restarted || joined || left || commanded {
+        recorder.0 = None;
+    }
+}
+
+fn play_back_inputs(
+    playback: Option<ResMut<ReplayPlayback>>,
+    mut inputs: ResMut<StepInputs>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(mut playback) = playback else {
+        return;
+    };
+    if let Some(recorded) = playback.next() {
+        inputs.0 = recorded.map(PlayerInput::from_bits);
+    } else if !playback.finished {
+        // The run was left before the game was over
+        playback.finished = true;
+        // Unless the last step already ended the game
+        change_state(&mut next_state, GameState::Menu);
+    }
+}
+
+fn record_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    inputs: Res<StepInputs>,
+    driver: Res<StepDriver>,
+) {
+    // The steps of an online game are played again every time a guess was wrong
+    if *driver == StepDriver::Session {
+        recorder.0 = None;
+    }
+    let Some(replay) = &mut recorder.0 else {
+        return;
+    };
+    let bits = inputs.0.map(PlayerInput::bits);
+    match replay.inputs.last_mut() {
+        Some((steps, last)) if *last == bits && *steps < u32::MAX => *steps += 1,
+        _ => replay.inputs.push((1, bits)),
+    }
+}
+
+fn save_replay(mut recorder: ResMut<ReplayRecorder>, scoreboard: Res<Scoreboard>) {
+    let Some(mut replay) = recorder.0.take() else {
+        return;
+    };
+    // Left before the first step
+    if replay.inputs.is_empty() {
+        return;
+    }
+    replay.scores = scoreboard.scores();
+    replay.save();
+}
+
+// Rewound, so playing again from the game over screen watches the replay again
+fn finish_playback(playback: Option<ResMut<ReplayPlayback>>, scoreboard: Res<Scoreboard>) {
+    let Some(mut playback) = playback else {
+        return;
+    };
+    // The demo was written by hand, without the scores it ends with
+    if playback.at_end() && !playback.demo && scoreboard.scores() != playback.replay.scores {
+        warn!(
+            "The replay ended with the scores {:?} instead of {:?}: the game plays differently \
+             from when it was recorded",
+            scoreboard.scores(),
+            playback.replay.scores
+        );
+    }
+    playback.rewind();
+}
+
+// Across the bottom of the screen, while a replay is being watched
+fn spawn_replay_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    let Some(playback) = playback else {
+        return;
+    };
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(REPLAY_FONT_SIZE / 2.0),
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        DespawnOnExit(GameState::Playing),
+        children![(
+            Text::new(if playback.demo {
+                "PRESS START"
+            } else {
+                "REPLAY"
+            }),
+            TextFont::from_font_size(REPLAY_FONT_SIZE)
+                .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+            TextColor(TEXT_COLOR),
+        )],
+    ));
+}
@@ -0,0 +1,118 @@
+//! Everything the player can set up, saved in the platform's config directory. Read before the
+//! window is created, so it opens the way it was left.
+
+use bevy::{
+    prelude::*,
+    window::{MonitorSelection, PresentMode, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::Channel,
+    controls::Bindings,
+    palette::Palette,
+    storage::{self, Location},
+    MAX_PLAYERS,
+};
+
+const FILE_NAME: &str = "settings.ron";
+
+// Window sizes to pick from on the options screen
+pub const RESOLUTIONS: [(f32, f32); 4] = [
+    (960.0, 720.0),
+    (1280.0, 720.0),
+    (1600.0, 900.0),
+    (1920.0, 1080.0),
+];
+
+// Settings missing from the file, like ones added since it was saved, keep their defaults
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // From 0 to 1
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub muted: bool,
+    pub bindings: Bindings,
+    pub fullscreen: bool,
+    // Size of the window when not fullscreen
+    pub resolution: (f32, f32),
+    pub vsync: bool,
+    // What each player looks like
+    pub player_palettes: [Palette; MAX_PLAYERS],
+    // The folder of the chosen pack in assets/skins, or None for the game's own pictures
+    pub skin: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            muted: false,
+            bindings: Bindings::default(),
+            fullscreen: false,
+            resolution: RESOLUTIONS[1],
+            vsync: true,
+            player_palettes: [Palette::Mario, Palette::Luigi],
+            skin: None,
+        }
+    }
+}
+
+impl Settings {
+    // A missing or unreadable file just means the defaults
+    pub fn load() -> Settings {
+        storage::read(Location::Config, FILE_NAME)
+            .and_then(|contents| ron::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Config, FILE_NAME, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the settings to {}: {err}",
+                storage::describe(Location::Config, FILE_NAME)
+            );
+        }
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.master_volume
+            * match channel {
+                Channel::Music => self.music_volume,
+                Channel::Sfx => self.sfx_volume,
+            }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen(MonitorSelection::Current)
+        } else {
+            WindowMode::Windowed
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        }
+    }
+
+    // Changes a window to match the display settings, before it opens or while it is open
+    pub fn apply_to(&self, window: &mut Window) {
+        window.mode = self.window_mode();
+        window.resolution.set(self.resolution.0, self.resolution.1);
+        window.present_mode = self.present_mode();
+    }
+}
@@ -0,0 +1,269 @@
+//! Skins: packs of pictures that replace the game's own. Each folder in assets/skins is a pack,
+//! picked on the options screen, with a pack.skin.ron listing the picture it has for each sprite:
+//!
+//! ```ron
+//! (
+//!     sprites: {
+//!         mario_sheet: "mario_sheet.png",
+//!         life_icon: "mario.png",
+//!     },
+//! )
+//! ```
+//!
+//! File names are relative to the pack's folder. Sprites a pack leaves out keep the game's own
+//! picture. A pack's pictures are cut up like the game's own, so a sprite sheet needs the same
+//! frames at the same size.
+//!
+//! Whatever draws one of these sprites gets its picture from `SkinImages` instead of loading a
+//! file itself. Picking another pack swaps the pictures of everything already on screen.
+
+use std::path::Path;
+
+use bevy::{
+    asset::{
+        io::{AssetSourceId, Reader},
+        AssetLoader, LoadContext,
+    },
+    platform::collections::HashMap,
+    prelude::*,
+    tasks::{block_on, futures_lite::StreamExt, poll_once, IoTaskPool, Task},
+};
+use serde::Deserialize;
+
+use crate::{palette::PaletteSwap, settings::Settings};
+
+const SKIN_FOLDER: &str = "skins";
+const SKIN_FILE: &str = "pack.skin.ron";
+const SKIN_EXTENSIONS: &[&str] = &["skin.ron"];
+
+pub struct SkinsPlugin;
+
+impl Plugin for SkinsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<SkinDef>()
+            .init_asset_loader::<SkinLoader>()
+            .init_resource::<SkinImages>()
+            .init_resource::<SkinPacks>()
+            .add_systems(Startup, find_skin_packs)
+            .add_systems(
+                Update,
+                (collect_skin_packs, apply_skin.after(collect_skin_packs)),
+            );
+    }
+}
+
+// Every sprite a pack can replace, by the name it has in pack.skin.ron
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SpriteId {
+    // Mario's frames, which Luigi and the ghost of the best run share
+    MarioSheet,
+    // The HUD's count of lives left
+    LifeIcon,
+    // The parallax layers behind the arena, far to near
+    Skyline,
+    Bricks,
+    Pipes,
+}
+
+impl SpriteId {
+    const ALL: [SpriteId; 5] = [
+        SpriteId::MarioSheet,
+        SpriteId::LifeIcon,
+        SpriteId::Skyline,
+        SpriteId::Bricks,
+        SpriteId::Pipes,
+    ];
+
+    // The game's own picture
+    fn default_path(self) -> &'static str {
+        match self {
+            SpriteId::MarioSheet => "mario_sheet.png",
+            SpriteId::LifeIcon => "mario.png",
+            SpriteId::Skyline => "backgrounds/skyline.png",
+            SpriteId::Bricks => "backgrounds/bricks.png",
+            SpriteId::Pipes => "backgrounds/pipes.png",
+        }
+    }
+}
+
+#[derive(Asset, TypePath, Deserialize)]
+pub struct SkinDef {
+    sprites: HashMap<SpriteId, String>,
+}
+
+#[derive(Default, TypePath)]
+struct SkinLoader;
+
+impl AssetLoader for SkinLoader {
+    type Asset = SkinDef;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<SkinDef, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let skin: SkinDef = ron::de::from_bytes(&bytes)?;
+        Ok(skin)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        SKIN_EXTENSIONS
+    }
+}
+
+// The packs in assets/skins by their folder's name, in alphabetical order. Empty until the folder
+// has been looked through.
+#[derive(Resource, Default)]
+pub struct SkinPacks(Vec<(String, Handle<SkinDef>)>);
+
+impl SkinPacks {
+    // The pack after or before the one given, None being the game's own pictures
+    pub fn next(&self, current: Option<&str>, forward: bool) -> Option<String> {
+        let names: Vec<Option<&str>> = std::iter::once(None)
+            .chain(self.0.iter().map(|(name, _)| Some(name.as_str())))
+            .collect();
+        let index = names.iter().position(|&name| name == current).unwrap_or(0);
+        let next = if forward {
+            (index + 1) % names.len()
+        } else {
+            (index + names.len() - 1) % names.len()
+        };
+        names[next].map(str::to_owned)
+    }
+
+    fn get(&self, name: &str) -> Option<&Handle<SkinDef>> {
+        self.0
+            .iter()
+            .find(|(pack, _)| pack == name)
+            .map(|(_, handle)| handle)
+    }
+}
+
+// The picture each sprite is drawn with, from the chosen pack
+#[derive(Resource)]
+pub struct SkinImages(HashMap<SpriteId, Handle<Image>>);
+
+impl SkinImages {
+    pub fn get(&self, sprite: SpriteId) -> Handle<Image> {
+        self.0[&sprite].clone()
+    }
+}
+
+impl FromWorld for SkinImages {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        SkinImages(
+            SpriteId::ALL
+                .into_iter()
+                .map(|sprite| (sprite, asset_server.load(sprite.default_path())))
+                .collect(),
+        )
+    }
+}
+
+// The names of the packs, while the folder is being listed
+#[derive(Resource)]
+struct SkinPackSearch(Task<Vec<String>>);
+
+// Without a skins folder, or where folders can't be listed like in the browser, there are only
+// the game's own pictures
+fn find_skin_packs(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let asset_server = asset_server.clone();
+    let search =
+        IoTaskPool::get().spawn(async move { pack_names(&asset_server).await.unwrap_or_default() });
+    commands.insert_resource(SkinPackSearch(search));
+}
+
+async fn pack_names(asset_server: &AssetServer) -> Result<Vec<String>, BevyError> {
+    let reader = asset_server.get_source(AssetSourceId::Default)?.reader();
+    let mut paths = reader.read_directory(Path::new(SKIN_FOLDER)).await?;
+    let mut names = Vec::new();
+    while let Some(path) = paths.next().await {
+        if !reader.is_directory(&path).await? {
+            continue;
+        }
+        if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+fn collect_skin_packs(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    search: Option<ResMut<SkinPackSearch>>,
+    mut packs: ResMut<SkinPacks>,
+) {
+    let Some(mut search) = search else {
+        return;
+    };
+    let Some(names) = block_on(poll_once(&mut search.0)) else {
+        return;
+    };
+    commands.remove_resource::<SkinPackSearch>();
+    packs.0 = names
+        .into_iter()
+        .map(|name| {
+            let handle = asset_server.load(format!("{SKIN_FOLDER}/{name}/{SKIN_FILE}"));
+            (name, handle)
+        })
+        .collect();
+}
+
+// Once the chosen pack is known, every sprite drawn with a picture it changes gets the new one.
+// Until its pack.skin.ron has loaded, or if it doesn't load, the game's own pictures are used.
+fn apply_skin(
+    (settings, asset_server): (Res<Settings>, Res<AssetServer>),
+    (packs, skins): (Res<SkinPacks>, Res<Assets<SkinDef>>),
+    mut skin_events: MessageReader<AssetEvent<SkinDef>>,
+    mut images: ResMut<SkinImages>,
+    mut sprite_query: Query<&mut Sprite>,
+    mut swap_query: Query<&mut PaletteSwap>,
+    mut ui_image_query: Query<&mut ImageNode>,
+) {
+    if skin_events.read().count() == 0 && !settings.is_changed() && !packs.is_changed() {
+        return;
+    }
+
+    let folder = settings.skin.as_deref();
+    let skin = folder
+        .and_then(|name| packs.get(name))
+        .and_then(|handle| skins.get(handle));
+    for sprite in SpriteId::ALL {
+        let image = match (folder, skin.and_then(|skin| skin.sprites.get(&sprite))) {
+            (Some(folder), Some(file)) => {
+                asset_server.load(format!("{SKIN_FOLDER}/{folder}/{file}"))
+            }
+            _ => asset_server.load(sprite.default_path()),
+        };
+        let old = images.get(sprite);
+        if image == old {
+            continue;
+        }
+
+        for mut sprite in &mut sprite_query {
+            if sprite.image == old {
+                sprite.image = image.clone();
+            }
+        }
+        for mut swap in &mut swap_query {
+            if swap.image == old {
+                swap.image = image.clone();
+            }
+        }
+        for mut ui_image in &mut ui_image_query {
+            if ui_image.image == old {
+                ui_image.image = image.clone();
+            }
+        }
+        images.0.insert(sprite, image);
+    }
+}
@@ -0,0 +1,192 @@
+//! Status effects: anything that lasts a while on a player, an enemy or a hazard, like the
+//! invincibility after respawning, a star, being staggered, or a stunned boss. They all live in one
+//! `StatusEffects` component, are counted down by the gameplay step, and announce themselves as
+//! they go, so whatever an effect does can follow its events instead of keeping its own timer:
+//!
+//! - `StatusTicked` every `StatusKind::tick_seconds` for the effects that do something over time.
+//! - `StatusExpired` once an effect runs out, or on the next step after being ended early.
+//!
+//! What applying an effect that is already on does is up to its `Stacking` rule.
+
+use bevy::prelude::*;
+#[cfg(feature = "online")]
+use bevy_ggrs::RollbackApp;
+
+use crate::{StepSet, TIME_STEP};
+
+// A respawned player blinks this often
+const INVINCIBLE_BLINK_SECONDS: f32 = 0.1;
+// A player with a star changes color this often
+const STAR_COLOR_SECONDS: f32 = 0.06;
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<StatusEffect>()
+            .add_message::<StatusTicked>()
+            .add_message::<StatusExpired>()
+            .add_systems(FixedUpdate, tick_status_effects.in_set(StepSet::Input));
+    }
+}
+
+// So the dev tools' inspector can show and change them
+#[cfg(feature = "dev-tools")]
+pub fn register_inspectable(app: &mut App) {
+    app.register_type::<StatusEffects>();
+}
+
+// Everything of the status effects that the gameplay step changes, for an online game to put
+// back when it rolls back
+#[cfg(feature = "online")]
+pub fn register_rollback(app: &mut App) {
+    app.rollback_component_with_reflect::<StatusEffects>();
+}
+
+#[derive(Reflect, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatusKind {
+    // Right after respawning, nothing can hurt the player
+    #[default]
+    Invincible,
+    // Nothing can hurt the player, and whatever they touch is defeated
+    Star,
+    // Knocked off balance, ignoring the controls
+    Staggered,
+    // A boss knocked down by a hit, harmless and out of reach of the next one
+    Stunned,
+}
+
+// What applying an effect that is already on does
+enum Stacking {
+    // Starts it over, for as long as the new one lasts if that is longer
+    Refresh,
+    // Adds the new duration to what is left
+    Extend,
+    // Leaves it be
+    Keep,
+}
+
+impl StatusKind {
+    fn stacking(self) -> Stacking {
+        match self {
+            StatusKind::Invincible => Stacking::Refresh,
+            // Another star on top of one keeps the music going
+            StatusKind::Star => Stacking::Extend,
+            // Bumping a staggered player again doesn't keep them down any longer
+            StatusKind::Staggered | StatusKind::Stunned => Stacking::Keep,
+        }
+    }
+
+    // How often the effect ticks, for the ones that do something over time
+    fn tick_seconds(self) -> Option<f32> {
+        match self {
+            StatusKind::Invincible => Some(INVINCIBLE_BLINK_SECONDS),
+            StatusKind::Star => Some(STAR_COLOR_SECONDS),
+            StatusKind::Staggered | StatusKind::Stunned => None,
+        }
+    }
+}
+
+#[derive(Reflect, Default)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    // Counts down to the effect running out
+    seconds_left: f32,
+    elapsed: f32,
+    ticks: usize,
+}
+
+// The effects on an entity, in the order they were applied
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct StatusEffects(Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub fn apply(&mut self, kind: StatusKind, seconds: f32) {
+        let Some(effect) = self.0.iter_mut().find(|effect| effect.kind == kind) else {
+            self.0.push(StatusEffect {
+                kind,
+                seconds_left: seconds,
+                ..default()
+            });
+            return;
+        };
+        match kind.stacking() {
+            Stacking::Refresh => {
+                *effect = StatusEffect {
+                    kind,
+                    seconds_left: effect.seconds_left.max(seconds),
+                    ..default()
+                };
+            }
+            Stacking::Extend => effect.seconds_left += seconds,
+            Stacking::Keep => {}
+        }
+    }
+
+    // Cuts an effect short. It still expires on the next step, like it ran out.
+    pub fn end(&mut self, kind: StatusKind) {
+        for effect in self.0.iter_mut().filter(|effect| effect.kind == kind) {
+            effect.seconds_left = 0.0;
+        }
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.0
+            .iter()
+            .any(|effect| effect.kind == kind && effect.seconds_left > 0.0)
+    }
+
+    // Whether enemies and hazards can't hurt whoever this is
+    pub fn invincible(&self) -> bool {
+        self.has(StatusKind::Invincible) || self.has(StatusKind::Star)
+    }
+}
+
+// Sent every `StatusKind::tick_seconds` while an effect lasts, counting the ticks from 1
+#[derive(Message)]
+pub struct StatusTicked {
+    pub entity: Entity,
+    pub kind: StatusKind,
+    pub ticks: usize,
+}
+
+// Sent when an effect runs out or was ended, after which the entity no longer has it
+#[derive(Message)]
+pub struct StatusExpired {
+    pub entity: Entity,
+    pub kind: StatusKind,
+}
+
+pub fn tick_status_effects(
+    mut query: Query<(Entity, &mut StatusEffects)>,
+    mut ticked_events: MessageWriter<StatusTicked>,
+    mut expired_events: MessageWriter<StatusExpired>,
+) {
+    for (entity, mut effects) in &mut query {
+        for effect in &mut effects.0 {
+            effect.seconds_left -= TIME_STEP;
+            effect.elapsed += TIME_STEP;
+            if effect.seconds_left <= 0.0 {
+                expired_events.write(StatusExpired {
+                    entity,
+                    kind: effect.kind,
+                });
+                continue;
+            }
+            let Some(tick_seconds) = effect.kind.tick_seconds() else {
+                continue;
+            };
+            let ticks = (effect.elapsed / tick_seconds) as usize;
+            if ticks > effect.ticks {
+                effect.ticks = ticks;
+                ticked_events.write(StatusTicked {
+                    entity,
+                    kind: effect.kind,
+                    ticks,
+                });
+            }
+        }
+        effects.0.retain(|effect| effect.seconds_left > 0.0);
+    }
+}
@@ -0,0 +1,111 @@
+//! Where the game keeps what it saves. On the desktop that is files in the platform's config and
+//! data directories. With the `wasm` feature, in the browser, it is localStorage instead, with the
+//! file names as keys.
+
+#[cfg(not(feature = "wasm"))]
+use std::{fs, path::PathBuf};
+
+#[cfg(not(feature = "wasm"))]
+use directories::ProjectDirs;
+
+// Settings and high scores go in the config directory, levels players keep in the data directory
+#[derive(Clone, Copy)]
+pub enum Location {
+    Config,
+    Data,
+}
+
+#[cfg(not(feature = "wasm"))]
+fn path(location: Location, name: &str) -> Option<PathBuf> {
+    let dirs = ProjectDirs::from("", "", "Mario-siblings")?;
+    let dir = match location {
+        Location::Config => dirs.config_dir(),
+        Location::Data => dirs.data_dir(),
+    };
+    Some(dir.join(name))
+}
+
+// Where something is saved, for messages about it
+#[cfg(not(feature = "wasm"))]
+pub fn describe(location: Location, name: &str) -> String {
+    path(location, name).map_or_else(|| name.to_string(), |path| path.display().to_string())
+}
+
+// Nothing saved yet, or something that can't be read, are both just nothing
+#[cfg(not(feature = "wasm"))]
+pub fn read(location: Location, name: &str) -> Option<String> {
+    fs::read_to_string(path(location, name)?).ok()
+}
+
+#[cfg(not(feature = "wasm"))]
+pub fn write(location: Location, name: &str, contents: &str) -> Result<(), String> {
+    let path = path(location, name).ok_or("there is no home directory")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    fs::write(&path, contents).map_err(|err| err.to_string())
+}
+
+// The names of everything saved in a folder, e.g. "levels/mine.level.ron"
+#[cfg(not(feature = "wasm"))]
+pub fn list(location: Location, folder: &str) -> Vec<String> {
+    path(location, folder)
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.ok()?.file_name();
+            Some(format!("{folder}/{}", file_name.to_str()?))
+        })
+        .collect()
+}
+
+#[cfg(feature = "wasm")]
+fn key(location: Location, name: &str) -> String {
+    let location = match location {
+        Location::Config => "config",
+        Location::Data => "data",
+    };
+    format!("Mario-siblings/{location}/{name}")
+}
+
+#[cfg(feature = "wasm")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(feature = "wasm")]
+pub fn describe(location: Location, name: &str) -> String {
+    format!("localStorage[\"{}\"]", key(location, name))
+}
+
+#[cfg(feature = "wasm")]
+pub fn read(location: Location, name: &str) -> Option<String> {
+    local_storage()?.get_item(&key(location, name)).ok()?
+}
+
+#[cfg(feature = "wasm")]
+pub fn write(location: Location, name: &str, contents: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or("localStorage is not available")?
+        .set_item(&key(location, name), contents)
+        .map_err(|err| format!("{err:?}"))
+}
+
+#[cfg(feature = "wasm")]
+pub fn list(location: Location, folder: &str) -> Vec<String> {
+    let Some(storage) = local_storage() else {
+        return Vec::new();
+    };
+    let prefix = key(location, "");
+    let folder = format!("{folder}/");
+    (0..storage.length().unwrap_or(0))
+        .filter_map(|index| storage.key(index).ok()?)
+        .filter_map(|key| key.strip_prefix(&prefix).map(str::to_owned))
+        // Only what is right in the folder, not in folders inside it
+        .filter(|name| {
+            name.strip_prefix(&folder)
+                .is_some_and(|file_name| !file_name.contains('/'))
+        })
+        .collect()
+}
@@ -0,0 +1,252 @@
+//! The survival mode: the arena never changes and the phase never ends. Enemies and hazards keep
+//! coming out of the pipes instead, in waves that come faster, go faster and mix in more kinds the
+//! longer the run lasts, until the last life is lost. The score is all there is to play for.
+//!
+//! How hard each wave is comes from assets/modes/survival.waves.ron, so it can be tuned without
+//! rebuilding the game.
+
+use std::time::Duration;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    director::{Curve, Spawn},
+    enemy::{Enemy, EnemyCount, Hazard},
+    generator::Rng,
+    level::{LevelDef, Levels, Phase, RestartPhase},
+    ui::{SCORE_COLOR, TEXT_COLOR},
+    GameMode, GameState, PauseState, StepSet, TIME_STEP,
+};
+
+const WAVES_FILE: &str = "modes/survival.waves.ron";
+const WAVES_EXTENSIONS: &[&str] = &["waves.ron"];
+// Mixed into the number of every spawn, to pick what comes out
+const SPAWN_SEED: u64 = 0x5EED_5A7E;
+const WAVE_FONT_SIZE: f32 = 30.0;
+// Under the middle column of the HUD
+const WAVE_TOP: Val = Val::Px(WAVE_FONT_SIZE * 3.0);
+
+pub struct SurvivalPlugin;
+
+impl Plugin for SurvivalPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<WaveDef>()
+            .init_asset_loader::<WaveLoader>()
+            .init_resource::<WaveScheduler>()
+            .add_systems(Startup, load_waves)
+            .add_systems(OnEnter(GameState::Playing), (reset_waves, spawn_wave_text))
+            .add_systems(
+                Update,
+                (update_wave_text, restart_waves).run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(FixedUpdate, schedule_waves.in_set(StepSet::Ai));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Mix {
+    first_wave: usize,
+    weights: Vec<(Spawn, u32)>,
+}
+
+// How hard each wave of the survival mode is
+#[derive(Asset, TypePath, Serialize, Deserialize)]
+struct WaveDef {
+    wave_seconds: f32,
+    spawn_seconds: Curve,
+    speed_scale: Curve,
+    max_alive: Curve,
+    mixes: Vec<Mix>,
+}
+
+impl WaveDef {
+    // The latest mix that has started by this wave
+    fn mix(&self, wave: usize) -> Option<&Mix> {
+        self.mixes.iter().rev().find(|mix| mix.first_wave <= wave)
+    }
+}
+
+#[derive(Default, TypePath)]
+struct WaveLoader;
+
+impl AssetLoader for WaveLoader {
+    type Asset = WaveDef;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<WaveDef, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let waves: WaveDef = ron::de::from_bytes(&bytes)?;
+        // A time to wait has to be one, or the first spawn of a run would panic
+        if !waves
+            .spawn_seconds
+            .values()
+            .all(|seconds| Duration::try_from_secs_f32(seconds).is_ok())
+        {
+            return Err(BevyError::from(
+                "spawn_seconds can only have numbers of seconds that aren't negative",
+            ));
+        }
+        Ok(waves)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        WAVES_EXTENSIONS
+    }
+}
+
+#[derive(Resource)]
+struct Waves(Handle<WaveDef>);
+
+// Where a survival run is at: which wave, and when the next spawn is due
+#[derive(Resource, Default)]
+struct WaveScheduler {
+    steps: u32,
+    wave: usize,
+    until_spawn: Duration,
+    spawned: usize,
+}
+
+fn load_waves(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Waves(asset_server.load(WAVES_FILE)));
+}
+
+fn reset_waves(mut scheduler: ResMut<WaveScheduler>) {
+    // The phase's own enemies make up the first wave
+    *scheduler = WaveScheduler {
+        wave: 1,
+        ..default()
+    };
+}
+
+// The survival mode never leaves its first phase, so restarting it starts the waves over
+fn restart_waves(
+    mut restart_events: MessageReader<RestartPhase>,
+    scheduler: ResMut<WaveScheduler>,
+) {
+    if restart_events.read().count() > 0 {
+        reset_waves(scheduler);
+    }
+}
+
+fn schedule_waves(
+    mut commands: Commands,
+    mut scheduler: ResMut<WaveScheduler>,
+    mut enemy_count: ResMut<EnemyCount>,
+    game_mode: Res<GameMode>,
+    (waves, wave_defs): (Res<Waves>, Res<Assets<WaveDef>>),
+    (phase, levels, level_assets): (Res<Phase>, Res<Levels>, Res<Assets<LevelDef>>),
+    alive_query: Query<(), Or<(With<Enemy>, With<Hazard>)>>,
+) {
+    if *game_mode != GameMode::Survival {
+        return;
+    }
+    let Some(def) = wave_defs.get(&waves.0) else {
+        return;
+    };
+
+    scheduler.steps += 1;
+    let seconds = scheduler.steps as f32 * TIME_STEP;
+    let wave = 1 + (seconds / def.wave_seconds) as usize;
+    scheduler.wave = wave;
+
+    scheduler.until_spawn = scheduler
+        .until_spawn
+        .saturating_sub(Duration::from_secs_f32(TIME_STEP));
+    if !scheduler.until_spawn.is_zero()
+        || alive_query.iter().count() as f32 >= def.max_alive.at(wave)
+    {
+        return;
+    }
+    let Some(mix) = def.mix(wave) else {
+        return;
+    };
+    let total: u32 = mix.weights.iter().map(|(_, weight)| weight).sum();
+    if total == 0 {
+        return;
+    }
+
+    // Every spawn picks on its own, so a replay of the run gets the same ones
+    let mut rng = Rng::new(SPAWN_SEED ^ scheduler.spawned as u64);
+    let mut pick = rng.range(0, total as usize - 1) as u32;
+    let spawn = mix
+        .weights
+        .iter()
+        .find(|(_, weight)| {
+            let found = pick < *weight;
+            pick = pick.saturating_sub(*weight);
+            found
+        })
+        .map_or(Spawn::Enemy, |(spawn, _)| *spawn);
+
+    let position = levels
+        .for_phase(phase.0, &level_assets)
+        .pipe(scheduler.spawned);
+    spawn.spawn(
+        &mut commands,
+        &mut enemy_count,
+        position,
+        def.speed_scale.at(wave),
+    );
+    scheduler.spawned += 1;
+    scheduler.until_spawn = Duration::from_secs_f32(def.spawn_seconds.at(wave));
+}
+
+#[derive(Component)]
+struct WaveText;
+
+fn spawn_wave_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_mode: Res<GameMode>,
+) {
+    if *game_mode != GameMode::Survival {
+        return;
+    }
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            top: WAVE_TOP,
+            width: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        DespawnOnExit(GameState::Playing),
+        children![(
+            Text::new("WAVE "),
+            TextFont::from_font_size(WAVE_FONT_SIZE)
+                .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+            TextColor(TEXT_COLOR),
+            WaveText,
+            children![(
+                TextSpan::default(),
+                TextFont::from_font_size(WAVE_FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+                TextColor(SCORE_COLOR),
+            )],
+        )],
+    ));
+}
+
+fn update_wave_text(
+    scheduler: Res<WaveScheduler>,
+    query: Query<Entity, With<WaveText>>,
+    mut writer: TextUiWriter,
+) {
+    for text in &query {
+        let wave = scheduler.wave.to_string();
+        if *writer.text(text, 1) != wave {
+            *writer.text(text, 1) = wave;
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Arenas authored in Tiled. Rectangles on the object layers become platforms and spawn points,
+//! and the tile layers become background sprites, giving the same `LevelDef` as the RON level
+//! files.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use roxmltree::{Document, Node};
+
+use crate::{
+    director::SpawnTable,
+    level::{BackgroundTile, Floor, LevelDef},
+};
+
+// The top bits of a tile id in a tile layer say how the tile is flipped, which we ignore
+const TILE_FLIP_FLAGS: u32 = 0xF000_0000;
+
+#[derive(Default, TypePath)]
+pub struct TiledLoader;
+
+impl AssetLoader for TiledLoader {
+    type Asset = LevelDef;
+    type Settings = ();
+    type Error = BevyError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<LevelDef, BevyError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let text = std::str::from_utf8(&bytes)?;
+        let document = Document::parse(text)?;
+        let map = document.root_element();
+        let map_folder = load_context
+            .path()
+            .path()
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_owned();
+
+        // Tilesets are either part of the map, or in .tsx files next to it
+        let mut tilesets = Vec::new();
+        for node in map.children().filter(|node| node.has_tag_name("tileset")) {
+            let first_id = attribute(node, "firstgid")?;
+            let tileset = match node.attribute("source") {
+                Some(source) => {
+                    let path = map_folder.join(source);
+                    let bytes = load_context.read_asset_bytes(path.clone()).await?;
+                    let text = String::from_utf8(bytes)?;
+                    let document = Document::parse(&text)?;
+                    read_tileset(
+                        document.root_element(),
+                        path.parent().unwrap_or(&map_folder),
+                    )?
+                }
+                None => read_tileset(node, &map_folder)?,
+            };
+
+            let image = load_context.load(tileset.image);
+            let layout = TextureAtlasLayout::from_grid(
+                tileset.tile_size,
+                tileset.columns,
+                tileset.rows,
+                Some(UVec2::splat(tileset.spacing)),
+                Some(UVec2::splat(tileset.margin)),
+            );
+            let layout = load_context.add_labeled_asset(format!("tileset{first_id}"), layout);
+            tilesets.push((first_id, image, layout));
+        }
+
+        read_map(map, &tilesets)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx"]
+    }
+}
+
+struct Tileset {
+    // Relative to the assets folder
+    image: PathBuf,
+    tile_size: UVec2,
+    columns: u32,
+    rows: u32,
+    spacing: u32,
+    margin: u32,
+}
+
+fn read_tileset(tileset: Node, folder: &Path) -> Result<Tileset, BevyError> {
+    let image = tileset
+        .children()
+        .find(|node| node.has_tag_name("image"))
+        .ok_or_else(|| BevyError::from("only tilesets made from a single image are supported"))?;
+    let columns: u32 = attribute(tileset, "columns")?;
+    let tile_count: u32 = attribute(tileset, "tilecount")?;
+    Ok(Tileset {
+        image: folder.join(image.attribute("source").unwrap_or_default()),
+        tile_size: UVec2::new(
+            attribute(tileset, "tilewidth")?,
+            attribute(tileset, "tileheight")?,
+        ),
+        columns,
+        rows: (tile_count + columns - 1) / columns.max(1),
+        spacing: optional_attribute(tileset, "spacing")?.unwrap_or(0),
+        margin: optional_attribute(tileset, "margin")?.unwrap_or(0),
+    })
+}
+
+fn read_map(
+    map: Node,
+    tilesets: &[(u32, Handle<Image>, Handle<TextureAtlasLayout>)],
+) -> Result<LevelDef, BevyError> {
+    let columns: usize = attribute(map, "width")?;
+    let rows: usize = attribute(map, "height")?;
+    let tile_size = Vec2::new(attribute(map, "tilewidth")?, attribute(map, "tileheight")?);
+    let origin = LevelDef::grid_origin(columns);
+    // From pixels down from the top left corner of the map to level coordinates
+    let to_level =
+        |point: Vec2| origin + Vec2::new(point.x, rows as f32 * tile_size.y - point.y) / tile_size;
+
+    let mut level = LevelDef {
+        first_phase: 1,
+        origin,
+        tiles: vec![".".repeat(columns); rows],
+        player_spawns: Vec::new(),
+        pipes: Vec::new(),
+        pow_block: None,
+        elevators: Vec::new(),
+        floor: Floor::Solid,
+        spawns: SpawnTable::default(),
+        background: Vec::new(),
+    };
+
+    let map_properties = map
+        .children()
+        .filter(|node| node.has_tag_name("properties"))
+        .flat_map(|node| node.children());
+    for property in map_properties {
+        match property.attribute("name") {
+            Some("first_phase") => level.first_phase = attribute(property, "value")?,
+            Some("floor") => {
+                level.floor = Floor::from_name(property.attribute("value").unwrap_or_default())
+            }
+            _ => {}
+        }
+    }
+
+    for node in map.descendants() {
+        match node.tag_name().name() {
+            "layer" => {
+                let data = node
+                    .children()
+                    .find(|child| child.has_tag_name("data"))
+                    .ok_or_else(|| BevyError::from("a tile layer has no data"))?;
+                if data.attribute("encoding") != Some("csv") {
+                    return Err(BevyError::from(
+                        "only tile layers saved as CSV are supported",
+                    ));
+                }
+                let ids = data
+                    .text()
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|id| id.trim().parse::<u32>());
+                for (cell, id) in ids.enumerate() {
+                    let id = id? & !TILE_FLIP_FLAGS;
+                    // Tileset ids go up, so the tile belongs to the last one starting at or before it
+                    let Some((first_id, image, layout)) =
+                        tilesets.iter().rev().find(|(first_id, ..)| *first_id <= id)
+                    else {
+                        continue;
+                    };
+                    let cell = Vec2::new((cell % columns) as f32, (cell / columns) as f32);
+                    level.background.push(BackgroundTile {
+                        image: image.clone(),
+                        layout: layout.clone(),
+                        index: (id - first_id) as usize,
+                        position: to_level((cell + 0.5) * tile_size),
+                    });
+                }
+            }
+            "object" => {
+                let corner = Vec2::new(attribute(node, "x")?, attribute(node, "y")?);
+                let size = Vec2::new(
+                    optional_attribute(node, "width")?.unwrap_or(0.0),
+                    optional_attribute(node, "height")?.unwrap_or(0.0),
+                );
+                // Tiled 1.9 saved the type of an object as its class
+                let kind = node
+                    .attribute("type")
+                    .or_else(|| node.attribute("class"))
+                    .unwrap_or_default();
+                let tile = match kind {
+                    "Player" => {
+                        level.player_spawns.push(to_level(corner + size / 2.0));
+                        continue;
+                    }
+                    // Enemies come out of the pipes
+                    "Pipe" | "Enemy" => {
+                        level.pipes.push(to_level(corner + size / 2.0));
+                        continue;
+                    }
+                    "Pow" => {
+                        level.pow_block = Some(to_level(corner + size / 2.0));
+                        continue;
+                    }
+                    "Ice" => '~',
+                    "OneWay" => '-',
+                    "ConveyorLeft" => '<',
+                    "ConveyorRight" => '>',
+                    "Crumbling" => '=',
+                    _ => '#',
+                };
+
+                // Walls cover every tile they overlap
+                let first = (corner / tile_size).floor().max(Vec2::ZERO);
+                let last = ((corner + size) / tile_size).ceil();
+                for line in level
+                    .tiles
+                    .iter_mut()
+                    .take(last.y as usize)
+                    .skip(first.y as usize)
+                {
+                    *line = line
+                        .chars()
+                        .enumerate()
+                        .map(|(column, character)| {
+                            if (first.x as usize..last.x as usize).contains(&column) {
+                                tile
+                            } else {
+                                character
+                            }
+                        })
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if level.player_spawns.is_empty() || level.pipes.is_empty() {
+        return Err(BevyError::from(
+            "the map needs at least one Player and one Pipe object",
+        ));
+    }
+    Ok(level)
+}
+
+fn attribute<T: std::str::FromStr>(node: Node, name: &str) -> Result<T, BevyError> {
+    optional_attribute(node, name)?
+        .ok_or_else(|| BevyError::from(format!("<{}> has no {name}", node.tag_name().name())))
+}
+
+fn optional_attribute<T: std::str::FromStr>(
+    node: Node,
+    name: &str,
+) -> Result<Option<T>, BevyError> {
+    node.attribute(name)
+        .map(|value| {
+            value.parse().map_err(|_| {
+                BevyError::from(format!(
+                    "<{}> has an invalid {name}",
+                    node.tag_name().name()
+                ))
+            })
+        })
+        .transpose()
+}
@@ -0,0 +1,274 @@
+//! The time attack mode: a single player clears a few phases in a row against the clock. The timer
+//! counts gameplay steps rather than the time on the clock, so it stops for the countdown that
+//! opens every phase and for the pause menu, and the same run always takes the same time.
+//!
+//! Clearing a phase is a split, shown next to the same split of the best run on the same layouts.
+//! The best run on each is saved with the high scores.
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    change_state,
+    level::{advance_phase, Levels, PhaseCleared, StartPhase},
+    replay::{ReplayLevel, ReplayPlayback},
+    storage::{self, Location},
+    ui::{SCORE_COLOR, SELECTED_TEXT_COLOR, TEXT_COLOR},
+    GameMode, GameState, PauseState, StepSet, TIME_STEP,
+};
+
+// Phases cleared to finish a run
+const TIME_ATTACK_PHASES: usize = 5;
+const TIMER_FONT_SIZE: f32 = 30.0;
+const TIMER_PADDING: Val = Val::Px(5.0);
+// On the right, under the top row of the HUD
+const TIMER_TOP: Val = Val::Px(TIMER_FONT_SIZE * 2.0);
+
+pub struct TimeAttackPlugin;
+
+impl Plugin for TimeAttackPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BestTimes::load())
+            .init_resource::<SpeedrunTimer>()
+            .add_systems(OnEnter(GameState::Playing), (start_timer, spawn_timer_text))
+            .add_systems(
+                Update,
+                (finish_run, update_timer_text).run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(
+                FixedUpdate,
+                tick_timer.after(advance_phase).in_set(StepSet::Gameplay),
+            );
+    }
+}
+
+// The time of a whole run and of each of its splits, in milliseconds from the start
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunTimes {
+    pub total: u64,
+    splits: Vec<u64>,
+}
+
+// The best run on each set of layouts, saved in the platform's config directory
+#[derive(Resource, Default, Serialize, Deserialize)]
+struct BestTimes {
+    runs: HashMap<String, RunTimes>,
+}
+
+impl BestTimes {
+    const FILE_NAME: &str = "best_times.ron";
+
+    // A missing or unreadable file just means there are no best times yet
+    fn load() -> BestTimes {
+        storage::read(Location::Config, Self::FILE_NAME)
+            .and_then(|contents| ron::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Config, Self::FILE_NAME, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the best times to {}: {err}",
+                storage::describe(Location::Config, Self::FILE_NAME)
+            );
+        }
+    }
+}
+
+// The clock of a time attack run
+#[derive(Resource, Default)]
+pub struct SpeedrunTimer {
+    // Where the best times of these layouts are kept. Layouts being play-tested don't keep any,
+    // and neither do replays.
+    key: Option<String>,
+    running: bool,
+    steps: u32,
+    // The steps taken by the end of each phase cleared so far
+    splits: Vec<u32>,
+    // The best run so far, to race against
+    best: Option<RunTimes>,
+    new_best: bool,
+}
+
+impl SpeedrunTimer {
+    pub fn finished(&self) -> bool {
+        self.splits.len() >= TIME_ATTACK_PHASES
+    }
+
+    pub fn times(&self) -> RunTimes {
+        RunTimes {
+            total: steps_to_millis(self.steps),
+            splits: self.splits.iter().copied().map(steps_to_millis).collect(),
+        }
+    }
+
+    // For the game over screen
+    pub fn summary(&self) -> String {
+        let times = self.times();
+        let mut text = if self.finished() {
+            format!("Time: {}", format_time(times.total))
+        } else {
+            format!(
+                "Did not finish ({} phases left)",
+                TIME_ATTACK_PHASES - self.splits.len()
+            )
+        };
+        if self.new_best {
+            text += "  NEW BEST!";
+        } else if let Some(best) = &self.best {
+            text += &format!("  Best: {}", format_time(best.total));
+        }
+        text + "\n"
+    }
+}
+
+fn steps_to_millis(steps: u32) -> u64 {
+    (f64::from(steps) * f64::from(TIME_STEP) * 1000.0).round() as u64
+}
+
+// Minutes, seconds and milliseconds, like 1:23.456
+fn format_time(millis: u64) -> String {
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// How far ahead (-) or behind (+) of the best run, like +1.250
+fn format_delta(millis: u64, best: u64) -> String {
+    let sign = if millis < best { '-' } else { '+' };
+    let delta = millis.abs_diff(best);
+    format!("{sign}{}.{:03}", delta / 1000, delta % 1000)
+}
+
+fn start_timer(
+    mut timer: ResMut<SpeedrunTimer>,
+    game_mode: Res<GameMode>,
+    (start_phase, levels): (Res<StartPhase>, Res<Levels>),
+    (best_times, playback): (Res<BestTimes>, Option<Res<ReplayPlayback>>),
+) {
+    let key = ReplayLevel::of(&levels)
+        .filter(|_| playback.is_none())
+        .map(|level| level.key(start_phase.0));
+    *timer = SpeedrunTimer {
+        best: key
+            .as_ref()
+            .and_then(|key| best_times.runs.get(key))
+            .cloned(),
+        key,
+        running: *game_mode == GameMode::TimeAttack,
+        ..default()
+    };
+}
+
+// A phase is cleared by the step that starts the next one
+fn tick_timer(mut timer: ResMut<SpeedrunTimer>, mut cleared_events: MessageReader<PhaseCleared>) {
+    if !timer.running {
+        cleared_events.clear();
+        return;
+    }
+    timer.steps += 1;
+    for _ in cleared_events.read() {
+        let steps = timer.steps;
+        timer.splits.push(steps);
+        timer.running = !timer.finished();
+    }
+}
+
+// Keeps the run if it is the best yet, and shows how it went
+fn finish_run(
+    mut timer: ResMut<SpeedrunTimer>,
+    mut best_times: ResMut<BestTimes>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if timer.running || !timer.finished() {
+        return;
+    }
+    let times = timer.times();
+    if let Some(key) = timer.key.clone() {
+        if timer
+            .best
+            .as_ref()
+            .is_none_or(|best| times.total < best.total)
+        {
+            best_times.runs.insert(key, times);
+            best_times.save();
+            timer.new_best = true;
+        }
+    }
+    // Straight to the game over screen: the high score table is for scores, not times
+    change_state(&mut next_state, GameState::GameOver);
+}
+
+#[derive(Component)]
+struct TimerText;
+
+fn spawn_timer_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_mode: Res<GameMode>,
+) {
+    if *game_mode != GameMode::TimeAttack {
+        return;
+    }
+    let mono = TextFont::from_font_size(TIMER_FONT_SIZE)
+        .with_font(asset_server.load("fonts/FiraMono-Medium.ttf"));
+    commands.spawn((
+        Text::new("TIME "),
+        TextFont::from_font_size(TIMER_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+        TextColor(TEXT_COLOR),
+        TextLayout::justify(Justify::Right),
+        Node {
+            position_type: PositionType::Absolute,
+            top: TIMER_TOP,
+            right: TIMER_PADDING,
+            ..default()
+        },
+        TimerText,
+        DespawnOnExit(GameState::Playing),
+        children![
+            (TextSpan::default(), mono.clone(), TextColor(SCORE_COLOR)),
+            // The splits, one per line
+            (
+                TextSpan::default(),
+                mono.with_font_size(TIMER_FONT_SIZE * 0.75),
+                TextColor(SELECTED_TEXT_COLOR),
+            ),
+        ],
+    ));
+}
+
+fn update_timer_text(
+    timer: Res<SpeedrunTimer>,
+    query: Query<Entity, With<TimerText>>,
+    mut writer: TextUiWriter,
+) {
+    if !timer.is_changed() {
+        return;
+    }
+
+    let times = timer.times();
+    let splits: String = times
+        .splits
+        .iter()
+        .enumerate()
+        .map(|(index, &split)| {
+            let delta = timer
+                .best
+                .as_ref()
+                .and_then(|best| best.splits.get(index))
+                .map_or_else(String::new, |&best| format_delta(split, best));
+            format!("\n{}  {} {delta}", index + 1, format_time(split))
+        })
+        .collect();
+    for text in &query {
+        *writer.text(text, 1) = format_time(times.total);
+        writer.text(text, 2).clone_from(&splits);
+    }
+}
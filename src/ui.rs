@@ -0,0 +1,1664 @@
+//! Menus, screens, score popups and the high score table.
+
+use bevy::{app::AppExit, ecs::system::SystemParam, prelude::*, window::PrimaryWindow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::Channel,
+    change_state,
+    controls::{Action, PlayerActions, ReservedKey},
+    daily::{DailyRun, DailyScores, PlayDaily},
+    level::{LevelChoice, LevelDef, Levels, Phase, RestartPhase, StartPhase},
+    player::{Lives, PlayerJoined, PlayerLeft, Scoreboard},
+    replay::{ReplayPlayback, WatchReplay},
+    settings::{Settings, RESOLUTIONS},
+    skins::SkinPacks,
+    storage::{self, Location},
+    time_attack::SpeedrunTimer,
+    GameMode, GameState, PauseState, StepDriver, MAX_PLAYERS, PLAYER_NAMES,
+};
+
+// Points earned float up from where they were earned and fade out
+const POPUP_FONT_SIZE: f32 = 20.0;
+const POPUP_SECONDS: f32 = 0.5;
+const POPUP_RISE_SPEED: f32 = 80.0;
+const TITLE_FONT_SIZE: f32 = 60.0;
+pub const MENU_FONT_SIZE: f32 = 40.0;
+// How many scores the high score table keeps
+const HIGH_SCORE_ENTRIES: usize = 10;
+const INITIALS_LENGTH: usize = 3;
+pub const TEXT_COLOR: Color = Color::srgb(0.5, 0.5, 1.0);
+pub const SCORE_COLOR: Color = Color::srgb(1.0, 0.5, 0.5);
+pub const SELECTED_TEXT_COLOR: Color = Color::srgb(1.0, 0.8, 0.1);
+// The tile grid of the highlighted level is previewed in small print
+const THUMBNAIL_FONT_SIZE: f32 = 10.0;
+pub const HINT_FONT_SIZE: f32 = 20.0;
+// How much Left or Right turns a volume down or up on the options screen
+const VOLUME_STEP: f32 = 0.1;
+// A new window mode or size goes back to the old one unless it is kept within this long, in case
+// the new one can't be seen
+const DISPLAY_CONFIRM_SECONDS: f32 = 10.0;
+// Every player's actions are listed on the controls screen, followed by Back
+const BINDING_ROWS: usize = MAX_PLAYERS * Action::ALL.len();
+// The keys that start a game on the level select screen
+const GAME_MODE_KEYS: [(KeyCode, GameMode); 5] = [
+    (KeyCode::Enter, GameMode::SinglePlayer),
+    (KeyCode::Digit2, GameMode::Coop),
+    (KeyCode::Digit3, GameMode::Versus),
+    (KeyCode::KeyT, GameMode::TimeAttack),
+    (KeyCode::KeyS, GameMode::Survival),
+];
+
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HighScores::load())
+            .add_message::<ScorePopup>()
+            .add_systems(OnEnter(GameState::Menu), spawn_menu_screen)
+            .add_systems(
+                Update,
+                (
+                    navigate_main_menu,
+                    highlight_main_menu.after(navigate_main_menu),
+                )
+                    .run_if(in_state(GameState::Menu)),
+            )
+            .add_systems(OnEnter(GameState::Options), spawn_options_screen)
+            .add_systems(
+                Update,
+                (
+                    navigate_options_menu,
+                    highlight_options_menu.after(navigate_options_menu),
+                    revert_display_settings.after(navigate_options_menu),
+                    update_display_confirmation_text.after(revert_display_settings),
+                )
+                    .run_if(in_state(GameState::Options)),
+            )
+            .add_systems(OnEnter(GameState::Controls), spawn_controls_screen)
+            .add_systems(
+                Update,
+                (rebind_controls, update_controls_text.after(rebind_controls))
+                    .run_if(in_state(GameState::Controls)),
+            )
+            .add_systems(
+                Update,
+                (pause_game, restart_hotkeys, spawn_score_popups, float_text)
+                    .run_if(in_state(PauseState::Running)),
+            )
+            .add_systems(OnEnter(PauseState::Paused), spawn_pause_screen)
+            .add_systems(
+                Update,
+                (
+                    navigate_pause_menu,
+                    highlight_pause_menu.after(navigate_pause_menu),
+                )
+                    .run_if(in_state(PauseState::Paused)),
+            )
+            .add_systems(OnEnter(GameState::HighScores), spawn_high_score_screen)
+            .add_systems(
+                Update,
+                leave_high_score_screen.run_if(in_state(GameState::HighScores)),
+            )
+            .add_systems(OnEnter(GameState::LevelSelect), spawn_level_select_screen)
+            .add_systems(
+                Update,
+                (
+                    navigate_level_select,
+                    highlight_level_select.after(navigate_level_select),
+                )
+                    .run_if(in_state(GameState::LevelSelect)),
+            )
+            .add_systems(OnEnter(GameState::EnterInitials), start_initials_entry)
+            .add_systems(
+                Update,
+                (enter_initials, update_initials_text.after(enter_initials))
+                    .run_if(in_state(GameState::EnterInitials)),
+            )
+            .add_systems(OnEnter(GameState::GameOver), spawn_game_over_screen)
+            .add_systems(
+                Update,
+                leave_game_over_screen.run_if(in_state(GameState::GameOver)),
+            );
+    }
+}
+
+// Asks to keep new display settings, while they wait to be confirmed
+#[derive(Component)]
+struct DisplayConfirmationText;
+
+// Entries of the title menu, in the order they are listed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum MainMenuAction {
+    Start,
+    Coop,
+    Versus,
+    TimeAttack,
+    Survival,
+    Daily,
+    CustomGame,
+    LevelSelect,
+    Replay,
+    Options,
+    HighScores,
+    Quit,
+    #[cfg(feature = "online")]
+    Online,
+}
+
+impl MainMenuAction {
+    const ALL: &'static [MainMenuAction] = &[
+        MainMenuAction::Start,
+        MainMenuAction::Coop,
+        MainMenuAction::Versus,
+        MainMenuAction::TimeAttack,
+        MainMenuAction::Survival,
+        MainMenuAction::Daily,
+        MainMenuAction::CustomGame,
+        #[cfg(feature = "online")]
+        MainMenuAction::Online,
+        MainMenuAction::LevelSelect,
+        MainMenuAction::Replay,
+        MainMenuAction::Options,
+        MainMenuAction::HighScores,
+        MainMenuAction::Quit,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            MainMenuAction::Start => "Start game",
+            MainMenuAction::Coop => "Co-op",
+            MainMenuAction::Versus => "Versus",
+            MainMenuAction::TimeAttack => "Time attack",
+            MainMenuAction::Survival => "Survival",
+            MainMenuAction::Daily => "Daily challenge",
+            MainMenuAction::CustomGame => "Custom game",
+            #[cfg(feature = "online")]
+            MainMenuAction::Online => "Online",
+            MainMenuAction::LevelSelect => "Level select",
+            MainMenuAction::Replay => "Watch replay",
+            MainMenuAction::Options => "Options",
+            MainMenuAction::HighScores => "High scores",
+            MainMenuAction::Quit => "Quit",
+        }
+    }
+
+    // Whether a game is played from where the entry leads
+    fn starts_game(&self) -> bool {
+        match self {
+            MainMenuAction::Start
+            | MainMenuAction::Coop
+            | MainMenuAction::Versus
+            | MainMenuAction::TimeAttack
+            | MainMenuAction::Survival
+            | MainMenuAction::Daily
+            | MainMenuAction::CustomGame
+            | MainMenuAction::LevelSelect
+            | MainMenuAction::Replay => true,
+            #[cfg(feature = "online")]
+            MainMenuAction::Online => true,
+            MainMenuAction::Options | MainMenuAction::HighScores | MainMenuAction::Quit => false,
+        }
+    }
+
+    // Picks the entry straight away, without moving the highlight to it first
+    fn shortcut(&self) -> Option<KeyCode> {
+        match self {
+            MainMenuAction::Start => None,
+            MainMenuAction::Coop => Some(KeyCode::Digit2),
+            MainMenuAction::Versus => Some(KeyCode::Digit3),
+            MainMenuAction::TimeAttack => Some(KeyCode::KeyT),
+            MainMenuAction::Survival => Some(KeyCode::KeyS),
+            MainMenuAction::Daily => Some(KeyCode::KeyD),
+            MainMenuAction::CustomGame => Some(KeyCode::KeyC),
+            #[cfg(feature = "online")]
+            MainMenuAction::Online => Some(KeyCode::KeyN),
+            MainMenuAction::LevelSelect => Some(KeyCode::KeyL),
+            MainMenuAction::Replay => Some(KeyCode::KeyR),
+            MainMenuAction::Options => Some(KeyCode::KeyO),
+            MainMenuAction::HighScores => Some(KeyCode::KeyH),
+            MainMenuAction::Quit => Some(KeyCode::Escape),
+        }
+    }
+}
+
+// Index into `MainMenuAction::ALL` of the highlighted entry
+#[derive(Resource, Default)]
+struct MainMenuSelection(usize);
+
+// Entries of the options menu, in the order they are listed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum OptionsMenuAction {
+    MasterVolume,
+    Volume(Channel),
+    Fullscreen,
+    Resolution,
+    Vsync,
+    Skin,
+    Controls,
+    Back,
+}
+
+impl OptionsMenuAction {
+    const ALL: [OptionsMenuAction; 9] = [
+        OptionsMenuAction::MasterVolume,
+        OptionsMenuAction::Volume(Channel::Music),
+        OptionsMenuAction::Volume(Channel::Sfx),
+        OptionsMenuAction::Fullscreen,
+        OptionsMenuAction::Resolution,
+        OptionsMenuAction::Vsync,
+        OptionsMenuAction::Skin,
+        OptionsMenuAction::Controls,
+        OptionsMenuAction::Back,
+    ];
+
+    fn label(&self, settings: &Settings) -> String {
+        let percent = |volume: f32| format!("< {:.0}% >", volume * 100.0);
+        let on_off = |on| if on { "On" } else { "Off" };
+        match self {
+            OptionsMenuAction::MasterVolume if settings.muted => {
+                format!("Volume: {} (muted)", percent(settings.master_volume))
+            }
+            OptionsMenuAction::MasterVolume => {
+                format!("Volume: {}", percent(settings.master_volume))
+            }
+            OptionsMenuAction::Volume(Channel::Music) => {
+                format!("Music: {}", percent(settings.music_volume))
+            }
+            OptionsMenuAction::Volume(Channel::Sfx) => {
+                format!("Sound effects: {}", percent(settings.sfx_volume))
+            }
+            OptionsMenuAction::Fullscreen => format!("Fullscreen: {}", on_off(settings.fullscreen)),
+            OptionsMenuAction::Resolution => {
+                let (width, height) = settings.resolution;
+                format!("Window size: < {width}x{height} >")
+            }
+            OptionsMenuAction::Vsync => format!("Vsync: {}", on_off(settings.vsync)),
+            OptionsMenuAction::Skin => {
+                format!(
+                    "Skin: < {} >",
+                    settings.skin.as_deref().unwrap_or("Original")
+                )
+            }
+            OptionsMenuAction::Controls => "Controls".to_string(),
+            OptionsMenuAction::Back => "Back".to_string(),
+        }
+    }
+
+    // The setting Left and Right turn down and up on this entry, if it's a volume
+    fn volume<'a>(&self, settings: &'a mut Settings) -> Option<&'a mut f32> {
+        match self {
+            OptionsMenuAction::MasterVolume => Some(&mut settings.master_volume),
+            OptionsMenuAction::Volume(Channel::Music) => Some(&mut settings.music_volume),
+            OptionsMenuAction::Volume(Channel::Sfx) => Some(&mut settings.sfx_volume),
+            _ => None,
+        }
+    }
+}
+
+// Index into `OptionsMenuAction::ALL` of the highlighted entry
+#[derive(Resource, Default)]
+struct OptionsMenuSelection(usize);
+
+// The window mode and size from before they were last changed, until the new ones are kept or
+// the timer runs out and they are put back
+#[derive(Resource, Default)]
+struct DisplayConfirmation(Option<PreviousDisplay>);
+
+struct PreviousDisplay {
+    fullscreen: bool,
+    resolution: (f32, f32),
+    timer: Timer,
+}
+
+impl PreviousDisplay {
+    fn restore(
+        &self,
+        settings: &mut Settings,
+        windows: &mut Query<&mut Window, With<PrimaryWindow>>,
+    ) {
+        settings.fullscreen = self.fullscreen;
+        settings.resolution = self.resolution;
+        if let Ok(mut window) = windows.single_mut() {
+            settings.apply_to(&mut window);
+        }
+    }
+}
+
+// The highlighted row of the controls screen, and whether its key or its gamepad button is
+// highlighted. While capturing, the next key or button pressed is bound to it.
+#[derive(Resource, Default)]
+struct ControlsSelection {
+    row: usize,
+    button: bool,
+    capturing: bool,
+    // Says what else was moved by the last rebinding
+    message: String,
+}
+
+impl ControlsSelection {
+    // None for the Back row
+    fn binding(&self) -> Option<(usize, Action)> {
+        (self.row < BINDING_ROWS).then(|| {
+            (
+                self.row / Action::ALL.len(),
+                Action::ALL[self.row % Action::ALL.len()],
+            )
+        })
+    }
+}
+
+// A row of the controls screen, `BINDING_ROWS` being Back
+#[derive(Component)]
+struct ControlsEntry(usize);
+
+#[derive(Component)]
+struct ControlsMessage;
+
+// Entries of the pause menu, in the order they are listed
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum PauseMenuAction {
+    Resume,
+    RestartPhase,
+    RestartRun,
+    // Only in a co-op game on this machine alone
+    DropOut,
+    Quit,
+}
+
+impl PauseMenuAction {
+    const ALL: [PauseMenuAction; 5] = [
+        PauseMenuAction::Resume,
+        PauseMenuAction::RestartPhase,
+        PauseMenuAction::RestartRun,
+        PauseMenuAction::DropOut,
+        PauseMenuAction::Quit,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            PauseMenuAction::Resume => "Resume",
+            PauseMenuAction::RestartPhase => "Restart phase (R)",
+            PauseMenuAction::RestartRun => "Restart run (Ctrl+R)",
+            PauseMenuAction::DropOut => "Player 2 drops out",
+            PauseMenuAction::Quit => "Quit to menu",
+        }
+    }
+}
+
+// The entries of the pause menu, and the index of the highlighted one
+#[derive(Resource)]
+struct PauseMenuSelection {
+    entries: Vec<PauseMenuAction>,
+    selected: usize,
+}
+
+// The levels listed on the level select screen, and the index of the highlighted one
+#[derive(Resource)]
+struct LevelSelection {
+    choices: Vec<LevelChoice>,
+    selected: usize,
+}
+
+// Marks the name of the `LevelSelection` choice with this index
+#[derive(Component)]
+struct LevelSelectEntry(usize);
+
+#[derive(Component)]
+struct LevelThumbnail;
+
+// World space text that rises and fades out until its timer runs out
+#[derive(Component)]
+struct FloatingText(Timer);
+
+// Sent when a player earns points, to show them where they were earned
+#[derive(Message)]
+pub struct ScorePopup {
+    pub position: Vec3,
+    pub points: usize,
+}
+
+// The best scores so far, best first, saved in the platform's config directory
+#[derive(Resource, Default, Serialize, Deserialize)]
+pub struct HighScores {
+    entries: Vec<HighScoreEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HighScoreEntry {
+    initials: String,
+    score: usize,
+}
+
+impl HighScores {
+    const FILE_NAME: &str = "high_scores.ron";
+
+    // A missing or unreadable file just means there are no high scores yet
+    fn load() -> HighScores {
+        storage::read(Location::Config, Self::FILE_NAME)
+            .and_then(|contents| ron::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, default())
+            .map_err(|err| err.to_string())
+            .and_then(|contents| storage::write(Location::Config, Self::FILE_NAME, &contents));
+        if let Err(err) = result {
+            warn!(
+                "Could not save the high scores to {}: {err}",
+                storage::describe(Location::Config, Self::FILE_NAME)
+            );
+        }
+    }
+
+    fn qualifies(&self, score: usize) -> bool {
+        score > 0
+            && (self.entries.len() < HIGH_SCORE_ENTRIES
+                || self.entries.iter().any(|entry| score > entry.score))
+    }
+
+    fn insert(&mut self, initials: String, score: usize) {
+        // Ties go below the scores that were there first
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| score > entry.score)
+            .unwrap_or(self.entries.len());
+        self.entries
+            .insert(rank, HighScoreEntry { initials, score });
+        self.entries.truncate(HIGH_SCORE_ENTRIES);
+    }
+
+    pub fn best(&self) -> Option<usize> {
+        self.entries.first().map(|entry| entry.score)
+    }
+
+    fn table(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("No high scores yet\n");
+        }
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(rank, entry)| {
+                format!("{:>2}. {} {:>7}\n", rank + 1, entry.initials, entry.score)
+            })
+            .collect()
+    }
+}
+
+// The initials being typed in, and which players still have to enter theirs
+#[derive(Resource)]
+struct InitialsEntry {
+    pending: Vec<usize>,
+    letters: [u8; INITIALS_LENGTH],
+    cursor: usize,
+}
+
+impl InitialsEntry {
+    fn new(pending: Vec<usize>) -> InitialsEntry {
+        InitialsEntry {
+            pending,
+            letters: [b'A'; INITIALS_LENGTH],
+            cursor: 0,
+        }
+    }
+}
+
+#[derive(Component)]
+struct InitialsText;
+
+// Full-screen UI root that centers whatever gets added to it
+pub fn centered_screen_node() -> Node {
+    Node {
+        width: Val::Percent(100.0),
+        height: Val::Percent(100.0),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    }
+}
+
+pub fn spawn_title_text(parent: &mut ChildSpawnerCommands, asset_server: &AssetServer, text: &str) {
+    parent.spawn((
+        Text::new(text),
+        TextFont::from_font_size(TITLE_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+        TextColor(TEXT_COLOR),
+        TextLayout::justify(Justify::Center),
+    ));
+}
+
+fn spawn_menu_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(MainMenuSelection::default());
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::Menu)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "MARIO BROS.");
+            for &action in MainMenuAction::ALL {
+                spawn_menu_entry(parent, &asset_server, action.label(), action);
+            }
+            spawn_hint_text(parent, &asset_server, "E: level editor");
+        });
+}
+
+fn spawn_options_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    commands.insert_resource(OptionsMenuSelection::default());
+    commands.insert_resource(DisplayConfirmation::default());
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::Options)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "OPTIONS");
+            for action in OptionsMenuAction::ALL {
+                spawn_menu_entry(parent, &asset_server, &action.label(&settings), action);
+            }
+            spawn_hint_text(parent, &asset_server, "M: mute");
+            parent.spawn((
+                Text::default(),
+                TextFont::from_font_size(HINT_FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+                TextColor(SELECTED_TEXT_COLOR),
+                DisplayConfirmationText,
+            ));
+        });
+}
+
+// A row per action of each player, with its key and its gamepad button
+fn spawn_controls_screen(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ControlsSelection::default());
+
+    let font = TextFont::from_font_size(HINT_FONT_SIZE)
+        .with_font(asset_server.load("fonts/FiraSans-Bold.ttf"));
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::Controls)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "CONTROLS");
+            // The key and the button spans are filled in by `update_controls_text`
+            for row in 0..BINDING_ROWS {
+                parent.spawn((
+                    Text::new(format!(
+                        "{} {}: ",
+                        PLAYER_NAMES[row / Action::ALL.len()],
+                        Action::ALL[row % Action::ALL.len()].label()
+                    )),
+                    font.clone(),
+                    TextColor(TEXT_COLOR),
+                    ControlsEntry(row),
+                    children![
+                        (TextSpan::default(), font.clone()),
+                        (TextSpan::new(" / "), font.clone(), TextColor(TEXT_COLOR)),
+                        (TextSpan::default(), font.clone()),
+                    ],
+                ));
+            }
+            spawn_menu_entry(parent, &asset_server, "Back", ControlsEntry(BINDING_ROWS));
+            parent.spawn((
+                Text::default(),
+                font.clone(),
+                TextColor(TEXT_COLOR),
+                Node {
+                    margin: UiRect::top(Val::Px(HINT_FONT_SIZE)),
+                    ..default()
+                },
+                ControlsMessage,
+            ));
+            spawn_hint_text(
+                parent,
+                &asset_server,
+                "Enter: rebind   Left/Right: key or gamepad button   Esc: back",
+            );
+        });
+}
+
+// One line of a menu, tagged with what picking it does
+pub fn spawn_menu_entry(
+    parent: &mut ChildSpawnerCommands,
+    asset_server: &AssetServer,
+    label: &str,
+    action: impl Component,
+) {
+    parent.spawn((
+        Text::new(label),
+        TextFont::from_font_size(MENU_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+        TextColor(TEXT_COLOR),
+        action,
+    ));
+}
+
+pub fn spawn_hint_text(parent: &mut ChildSpawnerCommands, asset_server: &AssetServer, text: &str) {
+    parent.spawn((
+        Text::new(text),
+        TextFont::from_font_size(HINT_FONT_SIZE)
+            .with_font(asset_server.load("fonts/FiraSans-Bold.ttf")),
+        TextColor(TEXT_COLOR),
+    ));
+}
+
+fn spawn_pause_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_mode: Res<GameMode>,
+    (playback, driver): (Option<Res<ReplayPlayback>>, Res<StepDriver>),
+) {
+    let can_drop_out =
+        *game_mode == GameMode::Coop && playback.is_none() && *driver == StepDriver::Clock;
+    let entries: Vec<PauseMenuAction> = PauseMenuAction::ALL
+        .into_iter()
+        .filter(|&action| action != PauseMenuAction::DropOut || can_drop_out)
+        .collect();
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+
+    commands
+        .spawn((
+            root,
+            // Darken the frozen game behind the menu
+            BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.5)),
+            DespawnOnExit(PauseState::Paused),
+        ))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "PAUSED");
+            for &action in &entries {
+                spawn_menu_entry(parent, &asset_server, action.label(), action);
+            }
+        });
+    commands.insert_resource(PauseMenuSelection {
+        entries,
+        selected: 0,
+    });
+}
+
+fn spawn_level_select_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    levels: Res<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+) {
+    let choices = levels.choices(&asset_server, &level_assets);
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::LevelSelect)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, "SELECT A LEVEL");
+            for (index, choice) in choices.iter().enumerate() {
+                spawn_menu_entry(
+                    parent,
+                    &asset_server,
+                    &choice.label(&level_assets),
+                    LevelSelectEntry(index),
+                );
+            }
+            parent.spawn((
+                Text::default(),
+                TextFont::from_font_size(THUMBNAIL_FONT_SIZE)
+                    .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+                TextColor(TEXT_COLOR),
+                LevelThumbnail,
+            ));
+            spawn_hint_text(
+                parent,
+                &asset_server,
+                "Enter: 1 player  2: co-op  3: versus  T: time attack  S: survival  Esc: back",
+            );
+        });
+
+    commands.insert_resource(LevelSelection {
+        choices,
+        selected: 0,
+    });
+}
+
+fn spawn_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_mode: Res<GameMode>,
+    (scoreboard, lives): (Res<Scoreboard>, Res<Lives>),
+    high_scores: Res<HighScores>,
+    (phase, start_phase): (Res<Phase>, Res<StartPhase>),
+    (timer, daily_run, daily_scores): (Res<SpeedrunTimer>, Res<DailyRun>, Res<DailyScores>),
+) {
+    let mut text = String::from(if timer.finished() {
+        "FINISHED\n"
+    } else {
+        "GAME OVER\n"
+    });
+    // A versus round is won by whoever still has lives left
+    if *game_mode == GameMode::Versus {
+        if let Some(winner) = lives.remaining[..MAX_PLAYERS]
+            .iter()
+            .position(|&left| left > 0)
+        {
+            text += &format!("{} wins!\n", PLAYER_NAMES[winner]);
+        }
+    }
+    for (player, name) in PLAYER_NAMES
+        .iter()
+        .enumerate()
+        .take(game_mode.player_count())
+    {
+        text += &format!(
+            "{name}: {} ({} defeated)\n",
+            scoreboard.score(player),
+            scoreboard.defeated(player)
+        );
+    }
+    // The phase the game ended on wasn't cleared
+    text += &format!("Phases cleared: {}\n", phase.0 - start_phase.0);
+    if *game_mode == GameMode::TimeAttack {
+        text += &timer.summary();
+    }
+    text += "\n";
+    // The daily challenge has a table of its own
+    if *game_mode == GameMode::Daily {
+        text += &daily_scores.summary(&daily_run);
+    } else {
+        text += &high_scores.table();
+    }
+    text += "\nEnter: play again   Esc: main menu";
+    commands
+        .spawn((centered_screen_node(), DespawnOnExit(GameState::GameOver)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, &text);
+        });
+}
+
+// Up and down move the highlight, and Enter or the gamepad's south button picks the entry
+fn navigate_main_menu(
+    mut controls: MenuControls,
+    mut selection: ResMut<MainMenuSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<GameMode>,
+    asset_server: Res<AssetServer>,
+    levels: Res<Levels>,
+    (mut exit, mut watch_replay, mut play_daily): (
+        MessageWriter<AppExit>,
+        MessageWriter<WatchReplay>,
+        MessageWriter<PlayDaily>,
+    ),
+) {
+    let entries = MainMenuAction::ALL.len();
+    if controls.pressed(MenuInput::Up) {
+        selection.0 = (selection.0 + entries - 1) % entries;
+    }
+    if controls.pressed(MenuInput::Down) {
+        selection.0 = (selection.0 + 1) % entries;
+    }
+
+    let action = if controls.pressed(MenuInput::Confirm) {
+        MainMenuAction::ALL[selection.0]
+    } else if let Some(&action) = MainMenuAction::ALL.iter().find(|action| {
+        action
+            .shortcut()
+            .is_some_and(|key| controls.keyboard_input.just_pressed(key))
+    }) {
+        action
+    } else {
+        return;
+    };
+
+    // The arena layouts are still loading
+    if action.starts_game() && !levels.loaded(&asset_server) {
+        return;
+    }
+    // Don't let the same press be seen again by the next state
+    controls.reset(MenuInput::Confirm);
+    if let Some(key) = action.shortcut() {
+        controls.keyboard_input.reset(key);
+    }
+
+    match action {
+        MainMenuAction::Start
+        | MainMenuAction::Coop
+        | MainMenuAction::Versus
+        | MainMenuAction::TimeAttack
+        | MainMenuAction::Survival => {
+            *game_mode = match action {
+                MainMenuAction::Coop => GameMode::Coop,
+                MainMenuAction::Versus => GameMode::Versus,
+                MainMenuAction::TimeAttack => GameMode::TimeAttack,
+                MainMenuAction::Survival => GameMode::Survival,
+                _ => GameMode::SinglePlayer,
+            };
+            change_state(&mut next_state, GameState::CharacterSelect);
+        }
+        #[cfg(feature = "online")]
+        MainMenuAction::Online => change_state(&mut next_state, GameState::Lobby),
+        MainMenuAction::Daily => {
+            play_daily.write(PlayDaily);
+        }
+        MainMenuAction::CustomGame => change_state(&mut next_state, GameState::CustomGame),
+        MainMenuAction::LevelSelect => change_state(&mut next_state, GameState::LevelSelect),
+        MainMenuAction::Replay => {
+            watch_replay.write(WatchReplay::Last);
+        }
+        MainMenuAction::Options => change_state(&mut next_state, GameState::Options),
+        MainMenuAction::HighScores => change_state(&mut next_state, GameState::HighScores),
+        MainMenuAction::Quit => {
+            exit.write(AppExit::Success);
+        }
+    }
+}
+
+fn navigate_options_menu(
+    mut controls: MenuControls,
+    mut selection: ResMut<OptionsMenuSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut settings: ResMut<Settings>,
+    mut confirmation: ResMut<DisplayConfirmation>,
+    packs: Res<SkinPacks>,
+) {
+    // Nothing else can be done until new display settings are kept or put back
+    if let Some(previous) = &confirmation.0 {
+        if controls.pressed(MenuInput::Confirm) {
+            controls.reset(MenuInput::Confirm);
+            confirmation.0 = None;
+            settings.save();
+        } else if controls.pressed(MenuInput::Back) {
+            controls.reset(MenuInput::Back);
+            previous.restore(&mut settings, &mut windows);
+            confirmation.0 = None;
+        }
+        return;
+    }
+
+    let entries = OptionsMenuAction::ALL.len();
+    if controls.pressed(MenuInput::Up) {
+        selection.0 = (selection.0 + entries - 1) % entries;
+    }
+    if controls.pressed(MenuInput::Down) {
+        selection.0 = (selection.0 + 1) % entries;
+    }
+    let step = if controls.pressed(MenuInput::Left) {
+        -VOLUME_STEP
+    } else if controls.pressed(MenuInput::Right) {
+        VOLUME_STEP
+    } else {
+        0.0
+    };
+    let selected = OptionsMenuAction::ALL[selection.0];
+    if step != 0.0 {
+        if let Some(volume) = selected.volume(&mut settings) {
+            *volume = (*volume + step).clamp(0.0, 1.0);
+            settings.save();
+        }
+    }
+    // Left and Right go through the window sizes, starting from the closest to a custom one
+    if step != 0.0 && selected == OptionsMenuAction::Resolution {
+        let current = RESOLUTIONS
+            .iter()
+            .position(|&resolution| resolution == settings.resolution)
+            .unwrap_or(0);
+        let next = if step < 0.0 {
+            (current + RESOLUTIONS.len() - 1) % RESOLUTIONS.len()
+        } else {
+            (current + 1) % RESOLUTIONS.len()
+        };
+        confirmation.0 = Some(previous_display(&settings));
+        settings.resolution = RESOLUTIONS[next];
+        if let Ok(mut window) = windows.single_mut() {
+            settings.apply_to(&mut window);
+        }
+        return;
+    }
+    // Left and Right go through the skin packs, the game's own pictures coming first
+    if step != 0.0 && selected == OptionsMenuAction::Skin {
+        settings.skin = packs.next(settings.skin.as_deref(), step > 0.0);
+        settings.save();
+        return;
+    }
+
+    let (input, action) = if controls.pressed(MenuInput::Back) {
+        (MenuInput::Back, OptionsMenuAction::Back)
+    } else if controls.pressed(MenuInput::Confirm) {
+        (MenuInput::Confirm, selected)
+    } else {
+        return;
+    };
+    controls.reset(input);
+
+    match action {
+        // Turned with Left and Right instead
+        OptionsMenuAction::MasterVolume
+        | OptionsMenuAction::Volume(_)
+        | OptionsMenuAction::Resolution
+        | OptionsMenuAction::Skin => return,
+        OptionsMenuAction::Fullscreen => {
+            confirmation.0 = Some(previous_display(&settings));
+            settings.fullscreen = !settings.fullscreen;
+        }
+        OptionsMenuAction::Vsync => settings.vsync = !settings.vsync,
+        OptionsMenuAction::Controls => {
+            change_state(&mut next_state, GameState::Controls);
+            return;
+        }
+        OptionsMenuAction::Back => {
+            change_state(&mut next_state, GameState::Menu);
+            return;
+        }
+    }
+
+    // Changes take effect straight away. They are kept for next time, unless they still need
+    // to be confirmed.
+    if let Ok(mut window) = windows.single_mut() {
+        settings.apply_to(&mut window);
+    }
+    if confirmation.0.is_none() {
+        settings.save();
+    }
+}
+
+fn previous_display(settings: &Settings) -> PreviousDisplay {
+    PreviousDisplay {
+        fullscreen: settings.fullscreen,
+        resolution: settings.resolution,
+        timer: Timer::from_seconds(DISPLAY_CONFIRM_SECONDS, TimerMode::Once),
+    }
+}
+
+fn revert_display_settings(
+    time: Res<Time>,
+    mut confirmation: ResMut<DisplayConfirmation>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    mut settings: ResMut<Settings>,
+) {
+    let Some(previous) = &mut confirmation.0 else {
+        return;
+    };
+    previous.timer.tick(time.delta());
+    if !previous.timer.is_finished() {
+        return;
+    }
+
+    previous.restore(&mut settings, &mut windows);
+    confirmation.0 = None;
+}
+
+fn update_display_confirmation_text(
+    confirmation: Res<DisplayConfirmation>,
+    mut query: Query<&mut Text, With<DisplayConfirmationText>>,
+) {
+    if !confirmation.is_changed() {
+        return;
+    }
+
+    let message = match &confirmation.0 {
+        Some(previous) => {
+            let seconds_left = previous.timer.duration() - previous.timer.elapsed();
+            format!(
+                "Keep these display settings? Enter: keep  Esc: undo ({})",
+                seconds_left.as_secs_f32().ceil()
+            )
+        }
+        None => String::new(),
+    };
+    for mut text in &mut query {
+        text.0 = message.clone();
+    }
+}
+
+// Picking a row waits for the key or button to bind to it; Esc cancels that. A key or button
+// that was already bound somewhere else swaps places with the old one.
+fn rebind_controls(
+    mut controls: MenuControls,
+    mut selection: ResMut<ControlsSelection>,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if selection.capturing {
+        let Some((player, action)) = selection.binding() else {
+            return;
+        };
+        if controls.keyboard_input.just_pressed(KeyCode::Escape) {
+            controls.reset(MenuInput::Back);
+            selection.capturing = false;
+            selection.message.clear();
+            return;
+        }
+
+        let message = if selection.button {
+            let Some(button) = controls.gamepads.iter_mut().find_map(|mut gamepad| {
+                let button = gamepad.get_just_pressed().next().copied()?;
+                gamepad.digital_mut().reset(button);
+                Some(button)
+            }) else {
+                return;
+            };
+            settings
+                .bindings
+                .bind_button(player, action, button)
+                .map(|other| {
+                    format!(
+                        "{} {} moved to {:?}",
+                        PLAYER_NAMES[player],
+                        other.label(),
+                        settings.bindings.0[player].button(other)
+                    )
+                })
+        } else {
+            let Some(key) = controls.keyboard_input.get_just_pressed().next().copied() else {
+                return;
+            };
+            controls.keyboard_input.reset(key);
+            match settings.bindings.bind_key(player, action, key) {
+                Ok(moved) => moved.map(|(other, other_action)| {
+                    format!(
+                        "{} {} moved to {:?}",
+                        PLAYER_NAMES[other],
+                        other_action.label(),
+                        settings.bindings.0[other].key(other_action)
+                    )
+                }),
+                Err(ReservedKey) => Some(format!("{key:?} is kept for a hotkey")),
+            }
+        };
+        settings.save();
+        selection.capturing = false;
+        selection.message = message.unwrap_or_default();
+        return;
+    }
+
+    let rows = BINDING_ROWS + 1;
+    if controls.pressed(MenuInput::Up) {
+        selection.row = (selection.row + rows - 1) % rows;
+    }
+    if controls.pressed(MenuInput::Down) {
+        selection.row = (selection.row + 1) % rows;
+    }
+    if controls.pressed(MenuInput::Left) || controls.pressed(MenuInput::Right) {
+        selection.button = !selection.button;
+    }
+
+    if controls.pressed(MenuInput::Back) {
+        controls.reset(MenuInput::Back);
+        change_state(&mut next_state, GameState::Options);
+    } else if controls.pressed(MenuInput::Confirm) {
+        controls.reset(MenuInput::Confirm);
+        if selection.binding().is_some() {
+            selection.capturing = true;
+            selection.message = if selection.button {
+                "Press a gamepad button, or Esc to cancel"
+            } else {
+                "Press a key, or Esc to cancel"
+            }
+            .to_string();
+        } else {
+            change_state(&mut next_state, GameState::Options);
+        }
+    }
+}
+
+fn pause_game(
+    mut actions: PlayerActions,
+    mut next_pause: ResMut<NextState<PauseState>>,
+    game_mode: Res<GameMode>,
+    (playback, driver): (Option<Res<ReplayPlayback>>, Res<StepDriver>),
+    mut joined_events: MessageWriter<PlayerJoined>,
+) {
+    // Any key leaves the demo instead
+    if playback.as_ref().is_some_and(|playback| playback.demo()) {
+        return;
+    }
+    for player in 0..game_mode.player_count() {
+        if actions.just_pressed(player, Action::Pause) {
+            // Unless the game ended on the same frame
+            change_state(&mut next_pause, PauseState::Paused);
+            // Don't let the same press be seen again by the next state
+            actions.reset(player, Action::Pause);
+            return;
+        }
+    }
+
+    // Anybody else drops in on a single-player game with their pause or jump button, which
+    // players sharing a pause key on the keyboard still have one of. Not while watching a replay
+    // or online, where every player's inputs have to come from where they were played.
+    if *game_mode != GameMode::SinglePlayer || playback.is_some() || *driver == StepDriver::Session
+    {
+        return;
+    }
+    for player in game_mode.player_count()..MAX_PLAYERS {
+        if actions.just_pressed(player, Action::Pause) || actions.just_pressed(player, Action::Jump)
+        {
+            actions.reset(player, Action::Pause);
+            actions.reset(player, Action::Jump);
+            joined_events.write(PlayerJoined(player));
+            return;
+        }
+    }
+}
+
+// R plays the phase again from the start, Ctrl+R the whole run. Neither does anything to a
+// replay being watched or an online game, which both have to play out the way they were played.
+fn restart_hotkeys(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut restart_events: MessageWriter<RestartPhase>,
+    playback: Option<Res<ReplayPlayback>>,
+    driver: Res<StepDriver>,
+) {
+    if playback.is_some()
+        || *driver == StepDriver::Session
+        || !keyboard_input.just_pressed(KeyCode::KeyR)
+    {
+        return;
+    }
+    keyboard_input.reset(KeyCode::KeyR);
+    if keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        // Entering `Playing` again exits it first, so the run is cleaned up before it starts over.
+        // Unless the pause button was pressed on the same frame.
+        change_state(&mut next_state, GameState::Playing);
+    } else {
+        restart_events.write(RestartPhase);
+    }
+}
+
+fn navigate_pause_menu(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut selection: ResMut<PauseMenuSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut next_pause: ResMut<NextState<PauseState>>,
+    mut restart_events: MessageWriter<RestartPhase>,
+    mut left_events: MessageWriter<PlayerLeft>,
+) {
+    let entries = selection.entries.len();
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        selection.selected = (selection.selected + entries - 1) % entries;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        selection.selected = (selection.selected + 1) % entries;
+    }
+
+    // Esc is a shortcut for resuming, just like the key that opened the menu
+    let action = if keyboard_input.just_pressed(KeyCode::Escape) {
+        keyboard_input.reset(KeyCode::Escape);
+        PauseMenuAction::Resume
+    } else if keyboard_input.just_pressed(KeyCode::Enter) {
+        keyboard_input.reset(KeyCode::Enter);
+        selection.entries[selection.selected]
+    } else {
+        return;
+    };
+
+    // An online game goes on while paused, and can end on the same frame, which goes first
+    match action {
+        PauseMenuAction::Resume => change_state(&mut next_pause, PauseState::Running),
+        // Picked up by the game once it is resumed
+        PauseMenuAction::RestartPhase => {
+            restart_events.write(RestartPhase);
+            change_state(&mut next_pause, PauseState::Running);
+        }
+        // Entering `Playing` again exits the paused game, so it is cleaned up before a new one is
+        // set up. The pause keeps its state through that, so it is resumed as well.
+        PauseMenuAction::RestartRun => {
+            change_state(&mut next_state, GameState::Playing);
+            change_state(&mut next_pause, PauseState::Running);
+        }
+        PauseMenuAction::DropOut => {
+            left_events.write(PlayerLeft(1));
+            change_state(&mut next_pause, PauseState::Running);
+        }
+        PauseMenuAction::Quit => change_state(&mut next_state, GameState::Menu),
+    }
+}
+
+fn navigate_level_select(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut selection: ResMut<LevelSelection>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut game_mode: ResMut<GameMode>,
+    mut start_phase: ResMut<StartPhase>,
+    mut levels: ResMut<Levels>,
+    level_assets: Res<Assets<LevelDef>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        change_state(&mut next_state, GameState::Menu);
+        // Esc on the menu quits the game
+        keyboard_input.reset(KeyCode::Escape);
+        return;
+    }
+
+    let entries = selection.choices.len();
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        selection.selected = (selection.selected + entries - 1) % entries;
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        selection.selected = (selection.selected + 1) % entries;
+    }
+
+    for (key, mode) in GAME_MODE_KEYS {
+        if keyboard_input.just_pressed(key) {
+            *game_mode = mode;
+            start_phase.0 = levels.choose(&selection.choices[selection.selected], &level_assets);
+            change_state(&mut next_state, GameState::CharacterSelect);
+            keyboard_input.reset(key);
+            return;
+        }
+    }
+}
+
+fn highlight_level_select(
+    selection: Res<LevelSelection>,
+    level_assets: Res<Assets<LevelDef>>,
+    mut entry_query: Query<(&LevelSelectEntry, &mut TextColor)>,
+    mut thumbnail_query: Query<&mut Text, With<LevelThumbnail>>,
+) {
+    if !selection.is_changed() {
+        return;
+    }
+
+    for (entry, mut color) in &mut entry_query {
+        color.0 = if entry.0 == selection.selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    }
+    let level = selection.choices[selection.selected].first_layout(&level_assets);
+    for mut text in &mut thumbnail_query {
+        text.0 = level.tiles.join("\n");
+    }
+}
+
+fn highlight_main_menu(
+    selection: Res<MainMenuSelection>,
+    mut query: Query<(&MainMenuAction, &mut TextColor)>,
+) {
+    let selected = MainMenuAction::ALL[selection.0];
+    for (action, mut color) in &mut query {
+        color.0 = if *action == selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    }
+}
+
+// Also keeps the volumes shown up to date
+fn highlight_options_menu(
+    selection: Res<OptionsMenuSelection>,
+    settings: Res<Settings>,
+    mut query: Query<(&OptionsMenuAction, &mut Text, &mut TextColor)>,
+) {
+    if !selection.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    let selected = OptionsMenuAction::ALL[selection.0];
+    for (action, mut text, mut color) in &mut query {
+        text.0 = action.label(&settings);
+        color.0 = if *action == selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    }
+}
+
+fn update_controls_text(
+    selection: Res<ControlsSelection>,
+    settings: Res<Settings>,
+    entry_query: Query<(Entity, &ControlsEntry)>,
+    mut message_query: Query<&mut Text, With<ControlsMessage>>,
+    mut writer: TextUiWriter,
+) {
+    if !selection.is_changed() && !settings.is_changed() {
+        return;
+    }
+
+    let color = |highlighted| {
+        if highlighted {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        }
+    };
+    for (entity, entry) in &entry_query {
+        let selected = entry.0 == selection.row;
+        *writer.color(entity, 0) = TextColor(color(selected));
+        if entry.0 == BINDING_ROWS {
+            continue;
+        }
+
+        let player = entry.0 / Action::ALL.len();
+        let action = Action::ALL[entry.0 % Action::ALL.len()];
+        let waiting = |button| selected && selection.capturing && selection.button == button;
+        *writer.text(entity, 1) = if waiting(false) {
+            "...".to_string()
+        } else {
+            format!("{:?}", settings.bindings.0[player].key(action))
+        };
+        *writer.color(entity, 1) = TextColor(color(selected && !selection.button));
+        *writer.text(entity, 3) = if waiting(true) {
+            "...".to_string()
+        } else {
+            format!("{:?}", settings.bindings.0[player].button(action))
+        };
+        *writer.color(entity, 3) = TextColor(color(selected && selection.button));
+    }
+    for mut text in &mut message_query {
+        text.0 = selection.message.clone();
+    }
+}
+
+fn highlight_pause_menu(
+    selection: Res<PauseMenuSelection>,
+    mut query: Query<(&PauseMenuAction, &mut TextColor)>,
+) {
+    let selected = selection.entries[selection.selected];
+    for (action, mut color) in &mut query {
+        color.0 = if *action == selected {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        };
+    }
+}
+
+// A new game in the same mode, or back to the menu
+fn leave_game_over_screen(
+    mut controls: MenuControls,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for (input, next) in [
+        (MenuInput::Confirm, GameState::Playing),
+        (MenuInput::Back, GameState::Menu),
+    ] {
+        if controls.pressed(input) {
+            change_state(&mut next_state, next);
+            controls.reset(input);
+            return;
+        }
+    }
+}
+
+fn spawn_high_score_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    high_scores: Res<HighScores>,
+) {
+    let text = format!(
+        "HIGH SCORES\n{}\nPress Enter to go back",
+        high_scores.table()
+    );
+    commands
+        .spawn((centered_screen_node(), DespawnOnExit(GameState::HighScores)))
+        .with_children(|parent| {
+            spawn_title_text(parent, &asset_server, &text);
+        });
+}
+
+fn leave_high_score_screen(
+    mut controls: MenuControls,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for input in [MenuInput::Confirm, MenuInput::Back] {
+        if controls.pressed(input) {
+            change_state(&mut next_state, GameState::Menu);
+            // Or the menu would pick its highlighted entry straight away
+            controls.reset(input);
+            return;
+        }
+    }
+}
+
+// Works out who made it into the high score table, skipping straight to the
+// game over screen if nobody did
+fn start_initials_entry(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut next_state: ResMut<NextState<GameState>>,
+    game_mode: Res<GameMode>,
+    scoreboard: Res<Scoreboard>,
+    high_scores: Res<HighScores>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    // The demo goes straight back to the title menu. Neither way out minds if another state was
+    // queued first.
+    if playback.as_ref().is_some_and(|playback| playback.demo()) {
+        change_state(&mut next_state, GameState::Menu);
+        return;
+    }
+    // Best score first, so it can't be pushed out of the table by a worse one. Replays were
+    // already scored when they were played, time attack runs are about times and the daily
+    // challenge has a table of its own.
+    let scored =
+        playback.is_none() && !matches!(*game_mode, GameMode::TimeAttack | GameMode::Daily);
+    let mut pending: Vec<usize> = (0..game_mode.player_count())
+        .filter(|&player| scored && high_scores.qualifies(scoreboard.score(player)))
+        .collect();
+    pending.sort_by_key(|&player| std::cmp::Reverse(scoreboard.score(player)));
+    let nobody_qualified = pending.is_empty();
+    commands.insert_resource(InitialsEntry::new(pending));
+    if nobody_qualified {
+        change_state(&mut next_state, GameState::GameOver);
+        return;
+    }
+
+    let mut root = centered_screen_node();
+    root.flex_direction = FlexDirection::Column;
+    commands
+        .spawn((root, DespawnOnExit(GameState::EnterInitials)))
+        .with_children(|parent| {
+            let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+            let style = TextFont::from_font_size(TITLE_FONT_SIZE).with_font(font);
+            // The title, one span per letter, then the instructions
+            parent
+                .spawn((
+                    Text::default(),
+                    style.clone(),
+                    TextColor(TEXT_COLOR),
+                    TextLayout::justify(Justify::Center),
+                    InitialsText,
+                ))
+                .with_children(|text| {
+                    for _ in 0..INITIALS_LENGTH {
+                        text.spawn((TextSpan::default(), style.clone()));
+                    }
+                    text.spawn((
+                        TextSpan::new("\nUp/Down: change letter\nLeft/Right: move\nEnter: done"),
+                        style.clone().with_font_size(MENU_FONT_SIZE),
+                        TextColor(TEXT_COLOR),
+                    ));
+                });
+        });
+}
+
+fn enter_initials(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    gamepads: Query<&Gamepad>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut entry: ResMut<InitialsEntry>,
+    mut high_scores: ResMut<HighScores>,
+    scoreboard: Res<Scoreboard>,
+) {
+    if entry.pending.is_empty() {
+        return;
+    }
+
+    let pressed = |key, button| {
+        keyboard_input.just_pressed(key) || gamepad_just_pressed(gamepads.iter(), button)
+    };
+    let up = pressed(KeyCode::ArrowUp, GamepadButton::DPadUp);
+    let down = pressed(KeyCode::ArrowDown, GamepadButton::DPadDown);
+    let left = pressed(KeyCode::ArrowLeft, GamepadButton::DPadLeft);
+    let right = pressed(KeyCode::ArrowRight, GamepadButton::DPadRight);
+    let confirm = pressed(KeyCode::Enter, GamepadButton::South);
+
+    let cursor = entry.cursor;
+    let letter = &mut entry.letters[cursor];
+    if up {
+        *letter = if *letter == b'Z' { b'A' } else { *letter + 1 };
+    }
+    if down {
+        *letter = if *letter == b'A' { b'Z' } else { *letter - 1 };
+    }
+    if left {
+        entry.cursor = entry.cursor.saturating_sub(1);
+    }
+    if right {
+        entry.cursor = (entry.cursor + 1).min(INITIALS_LENGTH - 1);
+    }
+    if !confirm {
+        return;
+    }
+    keyboard_input.reset(KeyCode::Enter);
+
+    let player = entry.pending.remove(0);
+    let initials = String::from_utf8_lossy(&entry.letters).into_owned();
+    high_scores.insert(initials, scoreboard.score(player));
+    high_scores.save();
+
+    // The next player gets a fresh set of initials
+    entry.letters = [b'A'; INITIALS_LENGTH];
+    entry.cursor = 0;
+    if entry.pending.is_empty() {
+        change_state(&mut next_state, GameState::GameOver);
+    }
+}
+
+fn update_initials_text(
+    entry: Res<InitialsEntry>,
+    scoreboard: Res<Scoreboard>,
+    query: Query<Entity, With<InitialsText>>,
+    mut writer: TextUiWriter,
+) {
+    let Some(&player) = entry.pending.first() else {
+        return;
+    };
+    let Ok(text) = query.single() else {
+        return;
+    };
+    *writer.text(text, 0) = format!(
+        "NEW HIGH SCORE!\n{}: {}\n",
+        PLAYER_NAMES[player],
+        scoreboard.score(player)
+    );
+    for (index, letter) in entry.letters.iter().enumerate() {
+        *writer.text(text, index + 1) = char::from(*letter).to_string();
+        *writer.color(text, index + 1) = TextColor(if index == entry.cursor {
+            SELECTED_TEXT_COLOR
+        } else {
+            TEXT_COLOR
+        });
+    }
+}
+
+// What menus are steered with, from the keyboard or any gamepad
+#[derive(Clone, Copy)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+impl MenuInput {
+    fn key(self) -> KeyCode {
+        match self {
+            MenuInput::Up => KeyCode::ArrowUp,
+            MenuInput::Down => KeyCode::ArrowDown,
+            MenuInput::Left => KeyCode::ArrowLeft,
+            MenuInput::Right => KeyCode::ArrowRight,
+            MenuInput::Confirm => KeyCode::Enter,
+            MenuInput::Back => KeyCode::Escape,
+        }
+    }
+
+    fn button(self) -> GamepadButton {
+        match self {
+            MenuInput::Up => GamepadButton::DPadUp,
+            MenuInput::Down => GamepadButton::DPadDown,
+            MenuInput::Left => GamepadButton::DPadLeft,
+            MenuInput::Right => GamepadButton::DPadRight,
+            MenuInput::Confirm => GamepadButton::South,
+            MenuInput::Back => GamepadButton::East,
+        }
+    }
+}
+
+// The keyboard and every connected gamepad, as far as menus are concerned
+#[derive(SystemParam)]
+pub struct MenuControls<'w, 's> {
+    pub keyboard_input: ResMut<'w, ButtonInput<KeyCode>>,
+    gamepads: Query<'w, 's, &'static mut Gamepad>,
+}
+
+impl MenuControls<'_, '_> {
+    pub fn pressed(&self, input: MenuInput) -> bool {
+        self.keyboard_input.just_pressed(input.key())
+            || gamepad_just_pressed(self.gamepads.iter(), input.button())
+    }
+
+    // Forgets a press, so it isn't seen again by the screen it leads to
+    pub fn reset(&mut self, input: MenuInput) {
+        self.keyboard_input.reset(input.key());
+        for mut gamepad in &mut self.gamepads {
+            gamepad.digital_mut().reset(input.button());
+        }
+    }
+}
+
+// Whether a button was just pressed on any of the connected gamepads
+fn gamepad_just_pressed<'a>(
+    mut gamepads: impl Iterator<Item = &'a Gamepad>,
+    button: GamepadButton,
+) -> bool {
+    gamepads.any(|gamepad| gamepad.just_pressed(button))
+}
+
+fn spawn_score_popups(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut popup_events: MessageReader<ScorePopup>,
+) {
+    for popup in popup_events.read() {
+        commands.spawn((
+            Text2d::new(popup.points.to_string()),
+            TextFont::from_font_size(POPUP_FONT_SIZE)
+                .with_font(asset_server.load("fonts/FiraMono-Medium.ttf")),
+            TextColor(SCORE_COLOR),
+            TextLayout::justify(Justify::Center),
+            // In front of everything else
+            Transform::from_translation(popup.position.truncate().extend(2.0)),
+            FloatingText(Timer::from_seconds(POPUP_SECONDS, TimerMode::Once)),
+            DespawnOnExit(GameState::Playing),
+        ));
+    }
+}
+
+fn float_text(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut FloatingText, &mut Transform, &mut TextColor)>,
+) {
+    for (entity, mut floating, mut transform, mut color) in &mut query {
+        floating.0.tick(time.delta());
+        if floating.0.is_finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        transform.translation.y += POPUP_RISE_SPEED * time.delta_secs();
+        let alpha = floating.0.fraction_remaining();
+        color.0.set_alpha(alpha);
+    }
+}
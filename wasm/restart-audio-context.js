@@ -0,0 +1,39 @@
+// Browsers create every AudioContext suspended until the page gets a key press, click or touch.
+// This keeps track of the ones the game creates and resumes them on the first of those.
+(function () {
+  const contexts = [];
+  const events = ["keydown", "mousedown", "touchstart", "pointerdown"];
+
+  for (const name of ["AudioContext", "webkitAudioContext"]) {
+    const Original = window[name];
+    if (!Original) {
+      continue;
+    }
+    window[name] = new Proxy(Original, {
+      construct(target, args) {
+        const context = new target(...args);
+        contexts.push(context);
+        return context;
+      },
+    });
+  }
+
+  function resume() {
+    let running = true;
+    for (const context of contexts) {
+      if (context.state !== "running") {
+        context.resume();
+        running = false;
+      }
+    }
+    if (running && contexts.length > 0) {
+      for (const name of events) {
+        document.removeEventListener(name, resume, true);
+      }
+    }
+  }
+
+  for (const name of events) {
+    document.addEventListener(name, resume, true);
+  }
+})();